@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS "mail_outbox";
+ALTER TABLE users DROP COLUMN IF EXISTS locale;
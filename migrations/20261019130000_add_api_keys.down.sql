@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS "api_keys";
+DROP TYPE IF EXISTS "api_key_scope";
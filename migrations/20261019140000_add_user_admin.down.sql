@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS "admin_audit_log";
+DROP TABLE IF EXISTS "user_suspensions";
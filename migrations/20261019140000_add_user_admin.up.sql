@@ -0,0 +1,26 @@
+-- Add up migration script here
+
+CREATE TABLE user_suspensions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    reason TEXT NOT NULL,
+    -- NULL means a permanent ban.
+    expires_at TIMESTAMP WITH TIME ZONE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    lifted_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+-- Target ids are kept without a foreign key, so entries outlive deleted users.
+CREATE TABLE admin_audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    admin_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    action VARCHAR(50) NOT NULL,
+    target_user_id UUID,
+    details TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX user_suspensions_user_id_idx ON user_suspensions (user_id);
+CREATE INDEX admin_audit_log_target_user_id_idx ON admin_audit_log (target_user_id);
+CREATE INDEX admin_audit_log_created_at_idx ON admin_audit_log (created_at);
@@ -0,0 +1,6 @@
+-- Add down migration script here
+
+ALTER TABLE books
+    DROP COLUMN IF EXISTS cover_variants,
+    DROP COLUMN IF EXISTS cover_blurhash,
+    DROP COLUMN IF EXISTS cover_color;
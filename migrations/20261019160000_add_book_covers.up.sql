@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+ALTER TABLE books
+    ADD COLUMN cover_variants JSONB NOT NULL DEFAULT '[]',
+    ADD COLUMN cover_blurhash VARCHAR(64),
+    ADD COLUMN cover_color VARCHAR(7);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS blob_references;
+DROP TABLE IF EXISTS blobs;
@@ -0,0 +1,25 @@
+-- Add up migration script here
+
+CREATE TABLE blobs (
+    hash VARCHAR(64) PRIMARY KEY NOT NULL,
+    storage_key VARCHAR(255) NOT NULL,
+    size BIGINT NOT NULL,
+    content_type VARCHAR(100) NOT NULL,
+    ref_count INTEGER NOT NULL DEFAULT 0 CHECK (ref_count >= 0),
+    last_used_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    verified_at TIMESTAMP WITH TIME ZONE,
+    corrupted_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX blobs_unreferenced_idx ON blobs (last_used_at) WHERE ref_count = 0;
+
+CREATE TABLE blob_references (
+    blob_hash VARCHAR(64) REFERENCES blobs(hash) NOT NULL,
+    owner_type VARCHAR(20) NOT NULL CHECK (owner_type IN ('avatar', 'cover')),
+    owner_id UUID NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (owner_type, owner_id, blob_hash)
+);
+
+CREATE INDEX blob_references_blob_hash_idx ON blob_references (blob_hash);
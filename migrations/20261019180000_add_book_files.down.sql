@@ -0,0 +1,12 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS book_entitlements;
+DROP TABLE IF EXISTS book_files;
+
+ALTER TABLE books DROP COLUMN IF EXISTS page_count;
+
+DELETE FROM blob_references WHERE owner_type = 'book_pdf';
+UPDATE blobs SET ref_count = (SELECT COUNT(*) FROM blob_references WHERE blob_hash = hash);
+ALTER TABLE blob_references DROP CONSTRAINT blob_references_owner_type_check;
+ALTER TABLE blob_references ADD CONSTRAINT blob_references_owner_type_check
+    CHECK (owner_type IN ('avatar', 'cover'));
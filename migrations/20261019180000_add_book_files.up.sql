@@ -0,0 +1,33 @@
+-- Add up migration script here
+
+ALTER TABLE blob_references DROP CONSTRAINT blob_references_owner_type_check;
+ALTER TABLE blob_references ADD CONSTRAINT blob_references_owner_type_check
+    CHECK (owner_type IN ('avatar', 'cover', 'book_pdf'));
+
+ALTER TABLE books ADD COLUMN page_count INTEGER;
+
+-- Downloadable editions of a book, one per format.
+CREATE TABLE book_files (
+    book_id UUID REFERENCES books(id) ON DELETE CASCADE NOT NULL,
+    format VARCHAR(10) NOT NULL CHECK (format IN ('pdf')),
+    blob_hash VARCHAR(64) REFERENCES blobs(hash) NOT NULL,
+    size BIGINT NOT NULL,
+    page_count INTEGER,
+    -- Metadata found in the file, not necessarily the same as the book's.
+    title TEXT,
+    author TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (book_id, format)
+);
+
+-- Users allowed to download the files of a book besides its author and staff.
+CREATE TABLE book_entitlements (
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    book_id UUID REFERENCES books(id) ON DELETE CASCADE NOT NULL,
+    granted_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (user_id, book_id)
+);
+
+CREATE INDEX book_entitlements_book_id_idx ON book_entitlements (book_id);
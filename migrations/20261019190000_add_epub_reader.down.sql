@@ -0,0 +1,18 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS book_resources;
+DROP TABLE IF EXISTS book_chapters;
+
+DELETE FROM book_files WHERE format = 'epub';
+ALTER TABLE book_files
+    DROP COLUMN IF EXISTS language,
+    DROP COLUMN IF EXISTS identifiers,
+    DROP COLUMN IF EXISTS toc;
+ALTER TABLE book_files DROP CONSTRAINT book_files_format_check;
+ALTER TABLE book_files ADD CONSTRAINT book_files_format_check CHECK (format IN ('pdf'));
+
+DELETE FROM blob_references WHERE owner_type = 'book_epub';
+UPDATE blobs SET ref_count = (SELECT COUNT(*) FROM blob_references WHERE blob_hash = hash);
+ALTER TABLE blob_references DROP CONSTRAINT blob_references_owner_type_check;
+ALTER TABLE blob_references ADD CONSTRAINT blob_references_owner_type_check
+    CHECK (owner_type IN ('avatar', 'cover', 'book_pdf'));
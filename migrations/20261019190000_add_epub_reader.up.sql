@@ -0,0 +1,32 @@
+-- Add up migration script here
+
+ALTER TABLE blob_references DROP CONSTRAINT blob_references_owner_type_check;
+ALTER TABLE blob_references ADD CONSTRAINT blob_references_owner_type_check
+    CHECK (owner_type IN ('avatar', 'cover', 'book_pdf', 'book_epub'));
+
+ALTER TABLE book_files DROP CONSTRAINT book_files_format_check;
+ALTER TABLE book_files ADD CONSTRAINT book_files_format_check CHECK (format IN ('pdf', 'epub'));
+ALTER TABLE book_files
+    ADD COLUMN language VARCHAR(35),
+    ADD COLUMN identifiers JSONB NOT NULL DEFAULT '[]',
+    -- Table of contents of an EPUB, see `EpubTocEntry`.
+    ADD COLUMN toc JSONB;
+
+-- Sanitized documents of the EPUB spine, in reading order from 1.
+CREATE TABLE book_chapters (
+    book_id UUID REFERENCES books(id) ON DELETE CASCADE NOT NULL,
+    position INTEGER NOT NULL,
+    path TEXT NOT NULL,
+    title TEXT,
+    content TEXT NOT NULL,
+    PRIMARY KEY (book_id, position)
+);
+
+-- Images, styles and fonts of the EPUB, by their path in the archive.
+CREATE TABLE book_resources (
+    book_id UUID REFERENCES books(id) ON DELETE CASCADE NOT NULL,
+    path TEXT NOT NULL,
+    media_type VARCHAR(100) NOT NULL,
+    blob_hash VARCHAR(64) REFERENCES blobs(hash) NOT NULL,
+    PRIMARY KEY (book_id, path)
+);
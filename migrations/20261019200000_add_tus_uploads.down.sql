@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS tus_upload_chunks;
+DROP TABLE IF EXISTS tus_uploads;
@@ -0,0 +1,28 @@
+-- Add up migration script here
+
+-- Resumable uploads of book files (tus protocol). Rows losing their user or
+-- book stay until the storage maintenance removes them with their chunks.
+CREATE TABLE tus_uploads (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    book_id UUID REFERENCES books(id) ON DELETE SET NULL,
+    format VARCHAR(10) NOT NULL CHECK (format IN ('pdf', 'epub')),
+    length BIGINT NOT NULL,
+    "offset" BIGINT NOT NULL DEFAULT 0,
+    -- `Upload-Metadata` sent on creation, decoded.
+    metadata JSONB NOT NULL DEFAULT '{}',
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX tus_uploads_expires_at_idx ON tus_uploads (expires_at);
+
+-- Received parts, each a separate object as storages can't append.
+CREATE TABLE tus_upload_chunks (
+    upload_id UUID REFERENCES tus_uploads(id) ON DELETE CASCADE NOT NULL,
+    "offset" BIGINT NOT NULL,
+    size BIGINT NOT NULL,
+    storage_key TEXT NOT NULL,
+    PRIMARY KEY (upload_id, "offset")
+);
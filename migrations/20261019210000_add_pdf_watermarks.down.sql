@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS pdf_watermarks;
+DROP TABLE IF EXISTS watermark_settings;
@@ -0,0 +1,27 @@
+-- Add up migration script here
+
+-- Stamp put on PDFs downloaded by buyers of the publisher's books.
+CREATE TABLE watermark_settings (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    -- May hold {email}, {name} and {user_id}.
+    text TEXT NOT NULL,
+    position VARCHAR(10) NOT NULL CHECK (position IN ('top', 'bottom', 'center', 'diagonal')),
+    opacity REAL NOT NULL CHECK (opacity > 0 AND opacity <= 1),
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+-- Stamped copies kept for the next download. `variant` hashes the original
+-- file and the stamp, a copy of another one is made again. Rows losing their
+-- user, book or entitlement stay until the storage maintenance removes them.
+CREATE TABLE pdf_watermarks (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    book_id UUID REFERENCES books(id) ON DELETE SET NULL,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    variant VARCHAR(64) NOT NULL,
+    storage_key TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    UNIQUE (book_id, user_id)
+);
@@ -0,0 +1,33 @@
+-- Add up migration script here
+
+-- Text extraction queued for every uploaded book file. The hash tells a
+-- finished run apart from one whose file was replaced in the meantime.
+CREATE TABLE book_text_jobs (
+    book_id UUID NOT NULL,
+    format VARCHAR(10) NOT NULL,
+    blob_hash VARCHAR(64) NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'done', 'failed')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+    PRIMARY KEY (book_id, format),
+    FOREIGN KEY (book_id, format) REFERENCES book_files (book_id, format) ON DELETE CASCADE
+);
+
+CREATE INDEX book_text_jobs_due_idx ON book_text_jobs (next_attempt_at) WHERE status = 'pending';
+
+-- Plain text of a PDF page or an EPUB chapter, by its number from 1.
+CREATE TABLE book_text_segments (
+    book_id UUID NOT NULL,
+    format VARCHAR(10) NOT NULL,
+    position INTEGER NOT NULL,
+    title TEXT,
+    content TEXT NOT NULL,
+    tsv TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', content)) STORED,
+    PRIMARY KEY (book_id, format, position),
+    FOREIGN KEY (book_id, format) REFERENCES book_files (book_id, format) ON DELETE CASCADE
+);
+
+CREATE INDEX book_text_segments_tsv_idx ON book_text_segments USING GIN (tsv);
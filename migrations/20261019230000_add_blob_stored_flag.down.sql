@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+ALTER TABLE blobs DROP COLUMN IF EXISTS stored;
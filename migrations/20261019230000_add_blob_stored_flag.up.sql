@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+-- Set once the file of the blob is written; rows claimed by an upload
+-- still writing it are stored again by the next upload of the content.
+ALTER TABLE blobs ADD COLUMN stored BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE blobs ALTER COLUMN stored SET DEFAULT FALSE;
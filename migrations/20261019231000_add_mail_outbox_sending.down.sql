@@ -0,0 +1,10 @@
+-- Add down migration script here
+
+UPDATE mail_outbox SET status = 'pending' WHERE status = 'sending';
+
+DROP INDEX mail_outbox_pending_idx;
+CREATE INDEX mail_outbox_pending_idx ON mail_outbox (next_attempt_at) WHERE status = 'pending';
+
+ALTER TABLE mail_outbox DROP CONSTRAINT mail_outbox_status_check;
+ALTER TABLE mail_outbox ADD CONSTRAINT mail_outbox_status_check
+    CHECK (status IN ('pending', 'sent', 'failed'));
@@ -0,0 +1,10 @@
+-- Add up migration script here
+
+-- Messages claimed by a worker, until they are sent or the claim expires.
+ALTER TABLE mail_outbox DROP CONSTRAINT mail_outbox_status_check;
+ALTER TABLE mail_outbox ADD CONSTRAINT mail_outbox_status_check
+    CHECK (status IN ('pending', 'sending', 'sent', 'failed'));
+
+DROP INDEX mail_outbox_pending_idx;
+CREATE INDEX mail_outbox_pending_idx ON mail_outbox (next_attempt_at)
+    WHERE status IN ('pending', 'sending');
@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+ALTER TABLE book_files DROP COLUMN IF EXISTS encrypted;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+-- Encrypted PDFs aren't watermarked, the stamped copy would lose the
+-- publisher's restrictions. Files uploaded before are flagged on their
+-- first stamped download.
+ALTER TABLE book_files ADD COLUMN encrypted BOOLEAN NOT NULL DEFAULT FALSE;
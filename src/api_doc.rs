@@ -1,4 +1,4 @@
-use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
 
 #[derive(utoipa::OpenApi)]
 #[openapi(
@@ -10,14 +10,90 @@ use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
     crate::books::book_handler::update_book,
     crate::books::book_handler::get_all_books,
     crate::books::book_handler::get_one_book,
+    crate::books::book_handler::upload_cover,
+    crate::books::book_handler::delete_cover,
+    crate::books::file_handler::upload_pdf,
+    crate::books::file_handler::delete_pdf,
+    crate::books::file_handler::download_pdf,
+    crate::books::file_handler::upload_epub,
+    crate::books::file_handler::delete_epub,
+    crate::books::file_handler::download_epub,
+    crate::books::reader_handler::get_toc,
+    crate::books::reader_handler::get_chapter,
+    crate::books::reader_handler::get_resource,
+    crate::books::signed_url_handler::create_signed_url,
+    crate::books::signed_url_handler::download_signed_file,
+    crate::books::signed_url_handler::download_signed_cover,
+    crate::books::tus_handler::tus_options,
+    crate::books::tus_handler::create_upload,
+    crate::books::tus_handler::upload_offset,
+    crate::books::tus_handler::upload_chunk,
+    crate::books::tus_handler::terminate_upload,
+    crate::books::watermark_handler::get_watermark,
+    crate::books::watermark_handler::set_watermark,
+    crate::books::watermark_handler::delete_watermark,
+    crate::books::search_handler::search_book_text,
     crate::users::handler::register_user_handler,
     crate::users::handler::login_user_handler,
     crate::users::handler::logout_user_handler,
+    crate::users::handler::csrf_token_handler,
+    crate::users::handler::unlock_login_handler,
+    crate::users::profile_handler::get_me_handler,
+    crate::users::profile_handler::update_me_handler,
+    crate::users::profile_handler::upload_avatar_handler,
+    crate::users::profile_handler::delete_avatar_handler,
+    crate::users::account_handler::delete_me_handler,
+    crate::users::account_handler::request_data_export_handler,
+    crate::users::account_handler::get_data_export_handler,
+    crate::users::account_handler::download_data_export_handler,
+    crate::users::password_handler::change_password_handler,
+    crate::users::password_handler::forgot_password_handler,
+    crate::users::password_handler::reset_password_handler,
+    crate::users::verification_handler::verify_email_handler,
+    crate::users::verification_handler::resend_verification_handler,
+    crate::users::two_factor_handler::two_factor_login_handler,
+    crate::users::two_factor_handler::setup_two_factor_handler,
+    crate::users::two_factor_handler::confirm_two_factor_handler,
+    crate::users::two_factor_handler::disable_two_factor_handler,
+    crate::users::two_factor_handler::regenerate_recovery_codes_handler,
+    crate::users::two_factor_handler::get_two_factor_policies,
+    crate::users::two_factor_handler::set_two_factor_policy,
+    crate::users::passkey_handler::start_passkey_registration_handler,
+    crate::users::passkey_handler::finish_passkey_registration_handler,
+    crate::users::passkey_handler::get_passkeys_handler,
+    crate::users::passkey_handler::delete_passkey_handler,
+    crate::users::passkey_handler::start_passkey_login_handler,
+    crate::users::passkey_handler::finish_passkey_login_handler,
+    crate::users::oidc_handler::get_oidc_providers_handler,
+    crate::users::oidc_handler::oidc_authorize_handler,
+    crate::users::oidc_handler::oidc_callback_handler,
+    crate::users::api_key_handler::create_api_key_handler,
+    crate::users::api_key_handler::get_api_keys_handler,
+    crate::users::api_key_handler::revoke_api_key_handler,
+    crate::users::admin_handler::list_users_handler,
+    crate::users::admin_handler::get_user_handler,
+    crate::users::admin_handler::change_user_role_handler,
+    crate::users::admin_handler::suspend_user_handler,
+    crate::users::admin_handler::lift_suspension_handler,
+    crate::users::admin_handler::verify_user_handler,
+    crate::users::admin_handler::delete_user_handler,
+    crate::users::admin_handler::get_audit_log_handler,
+    crate::users::admin_handler::impersonate_user_handler,
+    crate::users::admin_handler::stop_impersonation_handler,
+    crate::users::admin_handler::grant_book_access_handler,
+    crate::users::admin_handler::revoke_book_access_handler,
+    crate::users::admin_handler::get_storage_stats_handler,
+    crate::users::admin_handler::collect_storage_garbage_handler,
+    crate::users::admin_handler::verify_storage_handler,
     ),
     tags(
         (name = "Books", description = "API для работы с книгами"),
         (name = "Books genres", description = "API для работы с жанрами у книг"),
-        (name = "Users", description = "API для работы с пользователями")
+        (name = "Users", description = "API для работы с пользователями"),
+        (name = "Two-factor authentication", description = "API для двухфакторной аутентификации"),
+        (name = "Passkeys", description = "API для входа по ключам доступа (WebAuthn)"),
+        (name = "API keys", description = "API для персональных API ключей"),
+        (name = "User administration", description = "API для управления пользователями администраторами")
     ),
     modifiers(&SecurityAddon)
 )]
@@ -32,6 +108,10 @@ impl utoipa::Modify for SecurityAddon {
                 "Bearer",
                 SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
             );
+            components.add_security_scheme(
+                "ApiKey",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
+            );
         }
     }
 }
@@ -0,0 +1,121 @@
+use crate::AppState;
+use crate::books::model::Books;
+use crate::service::blob::BlobOwner;
+use crate::users::model::UserRole;
+use lopdf::{Document, decode_text_string};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Every PDF starts with this header.
+pub const PDF_MAGIC: &[u8] = b"%PDF-";
+
+/// Downloadable edition of a book, at most one per format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BookFormat {
+    Pdf,
+    Epub,
+}
+
+impl BookFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "pdf" => Some(BookFormat::Pdf),
+            "epub" => Some(BookFormat::Epub),
+            _ => None,
+        }
+    }
+
+    /// Name kept in `book_files.format`, also used as the file extension.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BookFormat::Pdf => "pdf",
+            BookFormat::Epub => "epub",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BookFormat::Pdf => "application/pdf",
+            BookFormat::Epub => "application/epub+zip",
+        }
+    }
+
+    pub fn blob_owner(self) -> BlobOwner {
+        match self {
+            BookFormat::Pdf => BlobOwner::BookPdf,
+            BookFormat::Epub => BlobOwner::BookEpub,
+        }
+    }
+}
+
+pub struct PdfInfo {
+    pub page_count: i32,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// The document subject, fills in a book without a description.
+    pub subject: Option<String>,
+    pub encrypted: bool,
+}
+
+/// Parses the whole document, so a file only starting like a PDF is
+/// rejected, and reads the page count and the document information.
+pub fn read_pdf_info(path: &Path) -> Result<PdfInfo, lopdf::Error> {
+    let mut document = Document::load(path)?;
+    let encrypted = document.is_encrypted();
+    if encrypted {
+        // Files only restricted by an owner password open with an empty
+        // user password; the others can't be read by anyone.
+        document.decrypt("")?;
+    }
+
+    let page_count = document.get_pages().len();
+    if page_count == 0 {
+        return Err(lopdf::Error::PageNumberNotFound(1));
+    }
+
+    let info = document
+        .trailer
+        .get(b"Info")
+        .and_then(|info| document.dereference(info))
+        .and_then(|(_, info)| info.as_dict())
+        .ok();
+    let text = |key: &[u8]| {
+        let value = info?.get(key).ok()?;
+        let (_, value) = document.dereference(value).ok()?;
+        let value = decode_text_string(value).ok()?;
+        let value = value.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    };
+
+    Ok(PdfInfo {
+        page_count: page_count as i32,
+        title: text(b"Title"),
+        author: text(b"Author"),
+        subject: text(b"Subject"),
+        encrypted,
+    })
+}
+
+/// Authors may download their own books and staff every book, other users
+/// the books granted to them.
+pub async fn has_book_access(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+    role: &UserRole,
+    book: &Books,
+) -> Result<bool, sqlx::Error> {
+    if book.author_id == user_id || matches!(role, UserRole::Worker | UserRole::Admin) {
+        return Ok(true);
+    }
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM book_entitlements WHERE user_id = $1 AND book_id = $2
+        ) as "exists!"
+        "#,
+        user_id,
+        book.id
+    )
+    .fetch_one(&data.db)
+    .await
+}
@@ -1,11 +1,17 @@
+use crate::books::cover::{cover_variant_response, remove_cover, render_cover, DEFAULT_COVER_FILE};
 use crate::books::model::Books;
 use crate::books::response::BookResponse;
 use crate::books::schema::{BookSchema, BookUpdateSchema};
 use crate::middleware::jwt_auth::JWTAuthMiddleware;
-use crate::service::get_or_set_cache;
+use crate::service::blob::{set_blob_references, store_blob_bytes, BlobOwner};
+use crate::service::image_processing::sniff_image_format;
 use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::service::upload::read_file_field;
+use crate::service::{delete_cache, get_or_set_cache};
+use crate::users::model::UserRole;
 use crate::AppState;
-use axum::extract::{Path, State};
+use axum::body::Bytes;
+use axum::extract::{Multipart, Path, State};
 use axum::http::StatusCode;
 use axum::{Extension, Json};
 use chrono::Datelike;
@@ -43,15 +49,14 @@ pub async fn create_book(
     let user_id = user.user.id;
     let publication_year = chrono::Utc::now().year() as i16;
     let query_result = sqlx::query(
-        r#"INSERT INTO books (title, description, author_id, genre_id, isbn, cover_image, price, discount, publication_year)
-VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id"#,
+        r#"INSERT INTO books (title, description, author_id, genre_id, isbn, price, discount, publication_year)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id"#,
     )
         .bind(body.title.to_owned())
         .bind(body.description.to_owned())
         .bind(user_id.to_owned())
         .bind(body.genre_id.to_owned())
         .bind(body.isbn.to_owned())
-        .bind(body.cover_image.to_owned())
         .bind(body.price)
         .bind(body.discount)
         .bind(publication_year)
@@ -99,24 +104,37 @@ pub async fn delete_book(
     State(data): State<Arc<AppState>>,
     Path(id): Path<uuid::Uuid>,
 ) -> APIResult<String> {
-    let query_result = sqlx::query!(r#"DELETE FROM books WHERE id = $1"#, id)
-        .execute(&data.db)
-        .await
-        .map_err(|e| {
-            let e = ErrorResponse {
-                error: format!("Database error: {}", e),
-                message: "Error when deleting".to_string(),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
-        })?;
+    let delete_error = |e: sqlx::Error| {
+        let e = ErrorResponse {
+            error: format!("Database error: {}", e),
+            message: "Error when deleting".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+    };
+
+    let mut tx = data.db.begin().await.map_err(delete_error)?;
+    let cover_image = sqlx::query_scalar!(
+        r#"DELETE FROM books WHERE id = $1 RETURNING cover_image"#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(delete_error)?;
+    for owner in [BlobOwner::Cover, BlobOwner::BookPdf, BlobOwner::BookEpub] {
+        set_blob_references(&mut tx, owner, id, &[])
+            .await
+            .map_err(delete_error)?;
+    }
+    tx.commit().await.map_err(delete_error)?;
 
-    if query_result.rows_affected() == 0 {
+    let Some(cover_image) = cover_image else {
         let e = ErrorResponse {
             error: "".to_string(),
             message: "Book not found".to_string(),
         };
         return Err((StatusCode::NOT_FOUND, Json(e)));
-    }
+    };
+    remove_cover(&data, id, &cover_image).await;
 
     let response = SuccessResponse {
         data: "Book deleted successfully".to_string(),
@@ -160,15 +178,13 @@ pub async fn update_book(
         SET
             title = COALESCE($1, title),
             description = COALESCE($2, description),
-            cover_image = COALESCE($3, cover_image),
-            price = COALESCE($4, price),
-            discount = COALESCE($5, discount)
-        WHERE id = $6
+            price = COALESCE($3, price),
+            discount = COALESCE($4, discount)
+        WHERE id = $5
         RETURNING *
         "#,
         body.title,
         body.description,
-        body.cover_image,
         body.price,
         body.discount,
         id
@@ -313,3 +329,249 @@ pub async fn get_one_book(
         }
     }
 }
+
+/// Authors may only change their own books, workers and admins any book.
+pub async fn fetch_editable_book(
+    data: &Arc<AppState>,
+    auth_guard: &JWTAuthMiddleware,
+    id: uuid::Uuid,
+) -> Result<Books, (StatusCode, Json<ErrorResponse>)> {
+    let book = sqlx::query_as!(Books, r#"SELECT * FROM books WHERE id = $1"#, id)
+        .fetch_optional(&data.db)
+        .await
+        .map_err(|e| {
+            let e = ErrorResponse {
+                error: format!("Database error: {}", e),
+                message: "Error when fetching book".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+        })?
+        .ok_or_else(|| {
+            let e = ErrorResponse {
+                error: "".to_string(),
+                message: "Book not found".to_string(),
+            };
+            (StatusCode::NOT_FOUND, Json(e))
+        })?;
+
+    if auth_guard.user.role == UserRole::Author && book.author_id != auth_guard.user.id {
+        let e = ErrorResponse {
+            error: "".to_string(),
+            message: "You can only change your own books".to_string(),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(e)));
+    }
+    Ok(book)
+}
+
+pub fn update_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let e = ErrorResponse {
+        error: format!("Database error: {}", e),
+        message: "Error when updating book in database".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+}
+
+pub async fn invalidate_book_cache(
+    data: &Arc<AppState>,
+    id: uuid::Uuid,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    for key in [format!("book-{}", id), "books-all".to_string()] {
+        delete_cache(&data.redis, &key).await.map_err(|e| {
+            let e = ErrorResponse {
+                error: format!("Redis error: {}", e),
+                message: "Redis error".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+        })?;
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/book/{id}/cover/",
+    request_body(content = String, content_type = "multipart/form-data", description = "Изображение JPEG, PNG или WebP в поле `file`"),
+    responses(
+        (status = 200, description = "Обложка загружена", body = BookResponse),
+        (status = 400, description = "Изображение не удалось прочитать", body = ErrorResponse),
+        (status = 403, description = "Книга другого автора", body = ErrorResponse),
+        (status = 404, description = "Ошибка такой id не найден", body = ErrorResponse),
+        (status = 413, description = "Файл слишком большой", body = ErrorResponse),
+        (status = 415, description = "Неподдерживаемый формат изображения", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn upload_cover(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    mut multipart: Multipart,
+) -> APIResult<BookResponse> {
+    let book = fetch_editable_book(&data, &auth_guard, id).await?;
+    let upload = read_file_field(&mut multipart, "file", data.env.cover_max_size).await?;
+
+    let updated_book = replace_cover(&data, &book, upload).await?;
+
+    let response = SuccessResponse {
+        data: BookResponse::from_book(updated_book),
+        message: "Cover uploaded successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// Processes an uploaded image into the cover variants of `book` and
+/// replaces its current cover.
+pub async fn replace_cover(
+    data: &Arc<AppState>,
+    book: &Books,
+    upload: Bytes,
+) -> Result<Books, (StatusCode, Json<ErrorResponse>)> {
+    let id = book.id;
+    let Some(format) = sniff_image_format(&upload) else {
+        let e = ErrorResponse {
+            error: "".to_string(),
+            message: "Unsupported image format, use JPEG, PNG or WebP".to_string(),
+        };
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(e)));
+    };
+
+    let widths = data.env.cover_widths.clone();
+    let cover = tokio::task::spawn_blocking(move || render_cover(&upload, format, &widths))
+        .await
+        .map_err(|e| {
+            let e = ErrorResponse {
+                error: format!("Task error: {}", e),
+                message: "Error when processing cover".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+        })?
+        .map_err(|e| {
+            let e = ErrorResponse {
+                error: e.to_string(),
+                message: "Image can't be decoded".to_string(),
+            };
+            (StatusCode::BAD_REQUEST, Json(e))
+        })?;
+
+    let mut hashes = Vec::new();
+    let mut variants = Vec::new();
+    for variant in &cover.variants {
+        let blob = store_blob_bytes(data, BlobOwner::Cover, &variant.bytes, variant.format)
+            .await
+            .map_err(|e| {
+                let e = ErrorResponse {
+                    error: format!("Storage error: {}", e),
+                    message: "Error when saving cover".to_string(),
+                };
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+            })?;
+        variants.push(cover_variant_response(variant, blob.path()));
+        hashes.push(blob.hash);
+    }
+
+    // The largest JPEG stays in `cover_image` for clients that only know it.
+    let cover_image = variants
+        .iter()
+        .filter(|variant| variant.format == "jpg")
+        .max_by_key(|variant| variant.width)
+        .map(|variant| variant.file.clone())
+        .unwrap_or_else(|| DEFAULT_COVER_FILE.to_string());
+
+    let mut tx = data.db.begin().await.map_err(update_error)?;
+    let updated_book = sqlx::query_as!(
+        Books,
+        r#"
+        UPDATE books
+        SET
+            cover_image = $1,
+            cover_variants = $2,
+            cover_blurhash = $3,
+            cover_color = $4,
+            updated_at = NOW()
+        WHERE id = $5
+        RETURNING *
+        "#,
+        cover_image,
+        serde_json::json!(variants),
+        cover.blurhash,
+        cover.color,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(update_error)?;
+    set_blob_references(&mut tx, BlobOwner::Cover, id, &hashes)
+        .await
+        .map_err(update_error)?;
+    tx.commit().await.map_err(update_error)?;
+
+    if let Some(old_cover) = &book.cover_image {
+        remove_cover(data, id, old_cover).await;
+    }
+    invalidate_book_cache(data, id).await?;
+
+    Ok(updated_book)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/book/{id}/cover/",
+    responses(
+        (status = 200, description = "Обложка удалена, установлена обложка по умолчанию", body = BookResponse),
+        (status = 403, description = "Книга другого автора", body = ErrorResponse),
+        (status = 404, description = "Ошибка такой id не найден", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn delete_cover(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<BookResponse> {
+    let book = fetch_editable_book(&data, &auth_guard, id).await?;
+
+    let mut tx = data.db.begin().await.map_err(update_error)?;
+    let updated_book = sqlx::query_as!(
+        Books,
+        r#"
+        UPDATE books
+        SET
+            cover_image = $1,
+            cover_variants = '[]',
+            cover_blurhash = NULL,
+            cover_color = NULL,
+            updated_at = NOW()
+        WHERE id = $2
+        RETURNING *
+        "#,
+        DEFAULT_COVER_FILE,
+        id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(update_error)?;
+    set_blob_references(&mut tx, BlobOwner::Cover, id, &[])
+        .await
+        .map_err(update_error)?;
+    tx.commit().await.map_err(update_error)?;
+
+    if let Some(old_cover) = &book.cover_image {
+        remove_cover(&data, id, old_cover).await;
+    }
+    invalidate_book_cache(&data, id).await?;
+
+    let response = SuccessResponse {
+        data: BookResponse::from_book(updated_book),
+        message: "Cover removed".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
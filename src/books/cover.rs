@@ -0,0 +1,116 @@
+use crate::AppState;
+use crate::books::response::CoverVariantResponse;
+use crate::service::image_processing::{decode_image, encode_jpeg, encode_webp};
+use crate::service::storage::version_prefix;
+use image::ImageFormat;
+use image::imageops::FilterType;
+use std::sync::Arc;
+use tracing::error;
+
+/// Cover of books without an upload. It's shared, so it's never removed.
+pub const DEFAULT_COVER_FILE: &str = "uploads/books/default.jpg";
+
+const COVER_DIR: &str = "books";
+const BLURHASH_COMPONENTS_X: u32 = 4;
+const BLURHASH_COMPONENTS_Y: u32 = 3;
+/// Blurhash only keeps a few components, a small copy is enough to compute it.
+const BLURHASH_SOURCE_SIZE: u32 = 64;
+
+pub struct CoverVariant {
+    pub width: u32,
+    pub height: u32,
+    pub format: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+pub struct RenderedCover {
+    pub variants: Vec<CoverVariant>,
+    pub blurhash: Option<String>,
+    pub color: String,
+}
+
+/// Scales the cover to every width, never above the original one, keeping
+/// the aspect ratio, and encodes each size as JPEG and WebP.
+pub fn render_cover(
+    bytes: &[u8],
+    format: ImageFormat,
+    widths: &[u32],
+) -> Result<RenderedCover, image::ImageError> {
+    let image = decode_image(bytes, format)?;
+
+    let mut widths: Vec<u32> = widths
+        .iter()
+        .map(|&width| width.min(image.width()))
+        .collect();
+    widths.sort_unstable();
+    widths.dedup();
+
+    let mut variants = Vec::new();
+    for width in widths {
+        let height =
+            (u64::from(image.height()) * u64::from(width) / u64::from(image.width())).max(1) as u32;
+        let resized = image.resize_exact(width, height, FilterType::Lanczos3);
+        variants.push(CoverVariant {
+            width,
+            height,
+            format: "jpg",
+            bytes: encode_jpeg(&resized)?,
+        });
+        variants.push(CoverVariant {
+            width,
+            height,
+            format: "webp",
+            bytes: encode_webp(&resized)?,
+        });
+    }
+
+    let small = image
+        .thumbnail(BLURHASH_SOURCE_SIZE, BLURHASH_SOURCE_SIZE)
+        .to_rgba8();
+    let blurhash = blurhash::encode(
+        BLURHASH_COMPONENTS_X,
+        BLURHASH_COMPONENTS_Y,
+        small.width(),
+        small.height(),
+        small.as_raw(),
+    )
+    .map_err(|e| error!("Failed to compute cover blurhash: {}", e))
+    .ok();
+
+    let average = image.resize_exact(1, 1, FilterType::Triangle).to_rgb8();
+    let [red, green, blue] = average.get_pixel(0, 0).0;
+
+    Ok(RenderedCover {
+        variants,
+        blurhash,
+        color: format!("#{:02x}{:02x}{:02x}", red, green, blue),
+    })
+}
+
+fn cover_dir(book_id: uuid::Uuid) -> String {
+    format!("{}/{}", COVER_DIR, book_id)
+}
+
+pub fn cover_variant_response(variant: &CoverVariant, file: String) -> CoverVariantResponse {
+    CoverVariantResponse {
+        width: variant.width,
+        height: variant.height,
+        format: variant.format.to_string(),
+        file,
+    }
+}
+
+/// Removes a cover uploaded before covers became blobs, when each upload had
+/// its own directory. Failures are only logged: the database no longer
+/// points at the files.
+pub async fn remove_cover(data: &Arc<AppState>, book_id: uuid::Uuid, file: &str) {
+    let Some(prefix) = version_prefix(file, &cover_dir(book_id)) else {
+        return;
+    };
+    if let Err(e) = data.storage.delete_prefix(&prefix).await {
+        error!(
+            "Failed to remove cover {} of book {}: {}",
+            prefix, book_id, e
+        );
+    }
+}
@@ -0,0 +1,559 @@
+use crate::books::response::EpubTocEntry;
+use ammonia::{Builder, UrlRelative, UrlRelativeEvaluate};
+use roxmltree::{Document, Node, ParsingOptions};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek};
+use zip::ZipArchive;
+
+pub type EpubError = Box<dyn std::error::Error + Send + Sync>;
+
+/// EPUBs are ZIP archives.
+pub const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+const EPUB_MIMETYPE: &str = "application/epub+zip";
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+const XHTML_MEDIA_TYPE: &str = "application/xhtml+xml";
+const NCX_MEDIA_TYPE: &str = "application/x-dtbncx+xml";
+const OPS_NAMESPACE: &str = "http://www.idpf.org/2007/ops";
+
+#[derive(Debug, Default)]
+pub struct EpubMetadata {
+    pub title: Option<String>,
+    pub creators: Vec<String>,
+    pub language: Option<String>,
+    pub identifiers: Vec<String>,
+    pub description: Option<String>,
+}
+
+impl EpubMetadata {
+    /// ISBN-13 of the book, when one of the identifiers is an ISBN.
+    pub fn isbn(&self) -> Option<String> {
+        self.identifiers.iter().find_map(|id| isbn13(id))
+    }
+}
+
+pub struct EpubChapter {
+    pub path: String,
+    pub title: Option<String>,
+    /// Sanitized HTML of the document body.
+    pub content: String,
+}
+
+pub struct EpubResource {
+    pub path: String,
+    pub media_type: String,
+    pub bytes: Vec<u8>,
+}
+
+pub struct Epub {
+    pub metadata: EpubMetadata,
+    pub chapters: Vec<EpubChapter>,
+    pub resources: Vec<EpubResource>,
+    /// Index of the cover image in `resources`.
+    pub cover: Option<usize>,
+    pub toc: Vec<EpubTocEntry>,
+}
+
+struct ManifestItem {
+    path: String,
+    media_type: String,
+    properties: String,
+}
+
+/// ISBN-13 from an identifier marked as an ISBN or looking like one.
+/// ISBN-10 are converted.
+fn isbn13(identifier: &str) -> Option<String> {
+    let identifier = identifier.trim();
+    let marked = identifier
+        .get(..9)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("urn:isbn:"))
+        .map(|_| &identifier[9..]);
+    let value = marked.unwrap_or(identifier);
+    if value
+        .chars()
+        .any(|c| !(c.is_ascii_digit() || matches!(c, '-' | ' ' | 'x' | 'X')))
+    {
+        return None;
+    }
+    let digits: String = value
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    match digits.len() {
+        13 if digits.bytes().all(|b| b.is_ascii_digit())
+            && (marked.is_some() || digits.starts_with("978") || digits.starts_with("979")) =>
+        {
+            Some(digits)
+        }
+        10 if marked.is_some() && digits[..9].bytes().all(|b| b.is_ascii_digit()) => {
+            let body = format!("978{}", &digits[..9]);
+            let sum: u32 = body
+                .bytes()
+                .enumerate()
+                .map(|(i, b)| u32::from(b - b'0') * if i % 2 == 0 { 1 } else { 3 })
+                .sum();
+            Some(format!("{}{}", body, (10 - sum % 10) % 10))
+        }
+        _ => None,
+    }
+}
+
+/// Normalizes a stored ISBN for comparison with [`EpubMetadata::isbn`].
+pub fn normalize_isbn(isbn: &str) -> String {
+    isbn13(&format!("urn:isbn:{}", isbn)).unwrap_or_else(|| isbn.to_string())
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = text.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Resolves a link found in the document at `base` to a path inside the
+/// archive and a fragment. Links leaving the archive give `None`.
+fn resolve_href(base: &str, href: &str) -> Option<(String, Option<String>)> {
+    let (path, fragment) = match href.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment.to_string())),
+        None => (href, None),
+    };
+    if path.contains(':') || path.starts_with('/') || path.contains('?') {
+        return None;
+    }
+    if path.is_empty() {
+        return Some((base.to_string(), fragment));
+    }
+
+    let mut parts: Vec<String> = match base.rsplit_once('/') {
+        Some((dir, _)) => dir.split('/').map(str::to_string).collect(),
+        None => Vec::new(),
+    };
+    for part in percent_decode(path)?.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part.to_string()),
+        }
+    }
+    Some((parts.join("/"), fragment))
+}
+
+fn parse_xml(text: &str) -> Result<Document<'_>, roxmltree::Error> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    Document::parse_with_options(text, options)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+fn text_of(node: Node) -> Option<String> {
+    let text: String = node
+        .descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// Reads entries without unpacking more than `budget` bytes in total,
+/// against archives that expand to gigabytes.
+struct Archive<R> {
+    zip: ZipArchive<R>,
+    budget: u64,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    fn read(&mut self, path: &str) -> Result<Vec<u8>, EpubError> {
+        let entry = self
+            .zip
+            .by_name(path)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        let mut bytes = Vec::new();
+        entry.take(self.budget + 1).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 > self.budget {
+            return Err("The EPUB unpacks to too much data".into());
+        }
+        self.budget -= bytes.len() as u64;
+        Ok(bytes)
+    }
+
+    fn read_text(&mut self, path: &str) -> Result<String, EpubError> {
+        let bytes = self.read(path)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+fn read_metadata(package: Node) -> EpubMetadata {
+    let mut metadata = EpubMetadata::default();
+    let Some(node) = child(package, "metadata") else {
+        return metadata;
+    };
+    for element in node.children().filter(|n| n.is_element()) {
+        let Some(text) = text_of(element) else {
+            continue;
+        };
+        match element.tag_name().name() {
+            "title" if metadata.title.is_none() => metadata.title = Some(text),
+            "creator" => metadata.creators.push(text),
+            "language" if metadata.language.is_none() => metadata.language = Some(text),
+            "description" if metadata.description.is_none() => metadata.description = Some(text),
+            "identifier" => {
+                // EPUB 2 marks the kind of identifier with `opf:scheme`.
+                let scheme = element
+                    .attributes()
+                    .find(|attribute| attribute.name() == "scheme")
+                    .map(|attribute| attribute.value());
+                if scheme.is_some_and(|scheme| scheme.eq_ignore_ascii_case("isbn"))
+                    && !text.to_ascii_lowercase().starts_with("urn:isbn:")
+                {
+                    metadata.identifiers.push(format!("urn:isbn:{}", text));
+                } else {
+                    metadata.identifiers.push(text);
+                }
+            }
+            _ => {}
+        }
+    }
+    metadata
+}
+
+/// Entries of an EPUB 3 navigation document, `nav` with `epub:type="toc"`.
+fn read_nav_list(list: Node, resolve: &dyn Fn(&str, &str) -> EpubTocEntry) -> Vec<EpubTocEntry> {
+    let mut entries = Vec::new();
+    for item in list.children().filter(|n| n.tag_name().name() == "li") {
+        let label = item
+            .children()
+            .find(|n| matches!(n.tag_name().name(), "a" | "span"));
+        let Some(title) = label.and_then(text_of) else {
+            continue;
+        };
+        let href = label.and_then(|n| n.attribute("href")).unwrap_or("");
+        let mut entry = resolve(&title, href);
+        if let Some(children) = child(item, "ol") {
+            entry.children = read_nav_list(children, resolve);
+        }
+        entries.push(entry);
+    }
+    entries
+}
+
+fn read_nav(
+    document: &Document,
+    resolve: &dyn Fn(&str, &str) -> EpubTocEntry,
+) -> Vec<EpubTocEntry> {
+    let navs: Vec<Node> = document
+        .descendants()
+        .filter(|n| n.tag_name().name() == "nav")
+        .collect();
+    let toc = navs
+        .iter()
+        .find(|nav| nav.attribute((OPS_NAMESPACE, "type")) == Some("toc"))
+        .or(navs.first());
+    toc.and_then(|nav| child(*nav, "ol"))
+        .map(|list| read_nav_list(list, resolve))
+        .unwrap_or_default()
+}
+
+/// Entries of an EPUB 2 NCX file.
+fn read_ncx_points(
+    parent: Node,
+    resolve: &dyn Fn(&str, &str) -> EpubTocEntry,
+) -> Vec<EpubTocEntry> {
+    let mut entries = Vec::new();
+    for point in parent
+        .children()
+        .filter(|n| n.tag_name().name() == "navPoint")
+    {
+        let Some(title) = child(point, "navLabel").and_then(text_of) else {
+            continue;
+        };
+        let href = child(point, "content")
+            .and_then(|n| n.attribute("src"))
+            .unwrap_or("");
+        let mut entry = resolve(&title, href);
+        entry.children = read_ncx_points(point, resolve);
+        entries.push(entry);
+    }
+    entries
+}
+
+fn chapter_url(book_id: uuid::Uuid, position: usize) -> String {
+    format!("/api/v1/book/{}/epub/chapters/{}/", book_id, position)
+}
+
+/// URL of an embedded resource served by the reader API.
+pub fn resource_url(book_id: uuid::Uuid, path: &str) -> String {
+    format!("/api/v1/book/{}/epub/resources/{}", book_id, path)
+}
+
+/// Content of the `body` element, the sanitizer would keep the text of the
+/// `head` otherwise.
+fn body_of(html: &str) -> &str {
+    let lower = html.to_ascii_lowercase();
+    let start = lower
+        .find("<body")
+        .and_then(|start| lower[start..].find('>').map(|end| start + end + 1));
+    match (start, lower.rfind("</body>")) {
+        (Some(start), Some(end)) if start <= end => &html[start..end],
+        _ => html,
+    }
+}
+
+/// Points relative links of a chapter at the reader API.
+struct LinkRewriter<'a> {
+    base: &'a str,
+    book_id: uuid::Uuid,
+    chapters: &'a HashMap<String, usize>,
+    resources: &'a HashSet<String>,
+}
+
+impl<'a> UrlRelativeEvaluate<'a> for LinkRewriter<'a> {
+    fn evaluate<'url>(&self, url: &'url str) -> Option<Cow<'url, str>> {
+        if url.starts_with('#') {
+            return Some(Cow::Borrowed(url));
+        }
+        let (target, fragment) = resolve_href(self.base, url)?;
+        if let Some(&position) = self.chapters.get(&target) {
+            let fragment = fragment.map(|f| format!("#{}", f)).unwrap_or_default();
+            let url = format!("{}{}", chapter_url(self.book_id, position), fragment);
+            return Some(Cow::Owned(url));
+        }
+        self.resources
+            .contains(&target)
+            .then(|| Cow::Owned(resource_url(self.book_id, &target)))
+    }
+}
+
+/// Removes scripts, styles, event handlers and unknown elements. Links to
+/// other chapters and resources point at the reader API, relative links
+/// that can't be resolved inside the book are dropped.
+fn sanitize_chapter(html: &str, rewriter: LinkRewriter) -> String {
+    Builder::default()
+        .add_tags(["section", "main"])
+        .add_generic_attributes(["id", "class", "dir"])
+        .url_relative(UrlRelative::Custom(Box::new(rewriter)))
+        .clean(body_of(html))
+        .to_string()
+        .trim()
+        .to_string()
+}
+
+/// Reads the package of an EPUB: its metadata, the spine documents as
+/// sanitized chapters, the embedded resources and the table of contents.
+/// At most `max_unpacked` bytes are unpacked.
+pub fn read_epub<R: Read + Seek>(
+    reader: R,
+    book_id: uuid::Uuid,
+    max_unpacked: u64,
+) -> Result<Epub, EpubError> {
+    let mut archive = Archive {
+        zip: ZipArchive::new(reader)?,
+        budget: max_unpacked,
+    };
+    if archive.read_text("mimetype")?.trim() != EPUB_MIMETYPE {
+        return Err("The archive is not an EPUB".into());
+    }
+
+    let container = archive.read_text(CONTAINER_PATH)?;
+    let container = parse_xml(&container)?;
+    let opf_path = container
+        .descendants()
+        .find(|n| n.tag_name().name() == "rootfile")
+        .and_then(|n| n.attribute("full-path"))
+        .ok_or("The container has no package document")?
+        .to_string();
+
+    let opf = archive.read_text(&opf_path)?;
+    let opf = parse_xml(&opf)?;
+    let package = opf.root_element();
+    let metadata = read_metadata(package);
+
+    let mut manifest = HashMap::new();
+    if let Some(node) = child(package, "manifest") {
+        for item in node.children().filter(|n| n.tag_name().name() == "item") {
+            let (Some(id), Some(href)) = (item.attribute("id"), item.attribute("href")) else {
+                continue;
+            };
+            let Some((path, _)) = resolve_href(&opf_path, href) else {
+                continue;
+            };
+            manifest.insert(
+                id.to_string(),
+                ManifestItem {
+                    path,
+                    media_type: item.attribute("media-type").unwrap_or("").to_string(),
+                    properties: item.attribute("properties").unwrap_or("").to_string(),
+                },
+            );
+        }
+    }
+
+    let spine = child(package, "spine").ok_or("The package has no spine")?;
+    let mut spine_paths = Vec::new();
+    for itemref in spine
+        .children()
+        .filter(|n| n.tag_name().name() == "itemref")
+    {
+        if itemref.attribute("linear") == Some("no") {
+            continue;
+        }
+        let Some(item) = itemref.attribute("idref").and_then(|id| manifest.get(id)) else {
+            continue;
+        };
+        if matches!(item.media_type.as_str(), XHTML_MEDIA_TYPE | "text/html")
+            && !spine_paths.contains(&item.path)
+        {
+            spine_paths.push(item.path.clone());
+        }
+    }
+    if spine_paths.is_empty() {
+        return Err("The EPUB has no readable documents".into());
+    }
+    let positions: HashMap<String, usize> = spine_paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| (path.clone(), i + 1))
+        .collect();
+
+    let resolve_entry = |base: &str| {
+        let positions = &positions;
+        let base = base.to_string();
+        move |title: &str, href: &str| {
+            let target = resolve_href(&base, href);
+            EpubTocEntry {
+                title: title.to_string(),
+                chapter: target
+                    .as_ref()
+                    .and_then(|(path, _)| positions.get(path))
+                    .map(|&position| position as i32),
+                fragment: target.and_then(|(_, fragment)| fragment),
+                children: Vec::new(),
+            }
+        }
+    };
+
+    let nav = manifest
+        .values()
+        .find(|item| item.properties.split_whitespace().any(|p| p == "nav"));
+    let ncx = spine
+        .attribute("toc")
+        .and_then(|id| manifest.get(id))
+        .or_else(|| {
+            manifest
+                .values()
+                .find(|item| item.media_type == NCX_MEDIA_TYPE)
+        });
+    let mut toc = Vec::new();
+    if let Some(nav) = nav {
+        // A navigation document the parser can't read falls back to the NCX.
+        let text = archive.read_text(&nav.path)?;
+        if let Ok(document) = parse_xml(&text) {
+            toc = read_nav(&document, &resolve_entry(&nav.path));
+        }
+    }
+    if let Some(ncx) = ncx.filter(|_| toc.is_empty()) {
+        let text = archive.read_text(&ncx.path)?;
+        let document = parse_xml(&text)?;
+        if let Some(map) = document
+            .descendants()
+            .find(|n| n.tag_name().name() == "navMap")
+        {
+            toc = read_ncx_points(map, &resolve_entry(&ncx.path));
+        }
+    }
+
+    let mut resources = Vec::new();
+    let mut resource_paths = HashSet::new();
+    for item in manifest.values() {
+        let document = matches!(
+            item.media_type.as_str(),
+            XHTML_MEDIA_TYPE | "text/html" | NCX_MEDIA_TYPE
+        );
+        if document || item.media_type.is_empty() || resource_paths.contains(&item.path) {
+            continue;
+        }
+        resources.push(EpubResource {
+            path: item.path.clone(),
+            media_type: item.media_type.clone(),
+            bytes: archive.read(&item.path)?,
+        });
+        resource_paths.insert(item.path.clone());
+    }
+    resources.sort_by(|a, b| a.path.cmp(&b.path));
+
+    // EPUB 3 marks the cover in the manifest, EPUB 2 with a `meta` element.
+    let cover_path = manifest
+        .values()
+        .find(|item| {
+            item.properties
+                .split_whitespace()
+                .any(|p| p == "cover-image")
+        })
+        .or_else(|| {
+            let metadata = child(package, "metadata")?;
+            let id = metadata
+                .children()
+                .find(|n| n.tag_name().name() == "meta" && n.attribute("name") == Some("cover"))?
+                .attribute("content")?;
+            manifest.get(id)
+        })
+        .map(|item| item.path.clone());
+    let cover = cover_path.and_then(|path| resources.iter().position(|r| r.path == path));
+
+    let mut titles = HashMap::new();
+    fn collect_titles(entries: &[EpubTocEntry], titles: &mut HashMap<i32, String>) {
+        for entry in entries {
+            if let Some(chapter) = entry.chapter {
+                titles.entry(chapter).or_insert_with(|| entry.title.clone());
+            }
+            collect_titles(&entry.children, titles);
+        }
+    }
+    collect_titles(&toc, &mut titles);
+
+    let mut chapters = Vec::new();
+    for (i, path) in spine_paths.iter().enumerate() {
+        let html = archive.read_text(path)?;
+        chapters.push(EpubChapter {
+            path: path.clone(),
+            title: titles.remove(&(i as i32 + 1)),
+            content: sanitize_chapter(
+                &html,
+                LinkRewriter {
+                    base: path,
+                    book_id,
+                    chapters: &positions,
+                    resources: &resource_paths,
+                },
+            ),
+        });
+    }
+
+    Ok(Epub {
+        metadata,
+        chapters,
+        resources,
+        cover,
+        toc,
+    })
+}
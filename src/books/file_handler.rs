@@ -0,0 +1,798 @@
+use crate::AppState;
+use crate::books::book_file::{BookFormat, PDF_MAGIC, has_book_access, read_pdf_info};
+use crate::books::book_handler::{
+    fetch_editable_book, invalidate_book_cache, replace_cover, update_error,
+};
+use crate::books::cover::DEFAULT_COVER_FILE;
+use crate::books::epub::{ZIP_MAGIC, normalize_isbn, read_epub};
+use crate::books::model::{BookFile, Books};
+use crate::books::response::BookFileResponse;
+use crate::books::text_index::queue_text_extraction;
+use crate::books::watermark::stamped_pdf;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::blob::{BlobError, set_blob_references, store_blob, store_blob_bytes};
+use crate::service::download::{
+    RangeRequest, attachment_disposition, content_etag, etag_matches, requested_range,
+};
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::service::upload::{SpooledFile, spool_file_field};
+use crate::users::model::UserRole;
+use axum::body::{Body, Bytes};
+use axum::extract::{Multipart, Path, State};
+use axum::http::header::{
+    ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use std::sync::Arc;
+use tracing::warn;
+
+pub fn fetch_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let e = ErrorResponse {
+        error: format!("Database error: {}", e),
+        message: "Error when fetching book".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+}
+
+pub fn file_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    let e = ErrorResponse {
+        error: "".to_string(),
+        message: "Book file not found".to_string(),
+    };
+    (StatusCode::NOT_FOUND, Json(e))
+}
+
+pub async fn fetch_book(
+    data: &Arc<AppState>,
+    id: uuid::Uuid,
+) -> Result<Books, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as!(Books, "SELECT * FROM books WHERE id = $1", id)
+        .fetch_optional(&data.db)
+        .await
+        .map_err(fetch_error)?
+        .ok_or_else(|| {
+            let e = ErrorResponse {
+                error: "".to_string(),
+                message: "Book not found".to_string(),
+            };
+            (StatusCode::NOT_FOUND, Json(e))
+        })
+}
+
+/// Book `id` if the user may read it.
+pub async fn fetch_readable_book(
+    data: &Arc<AppState>,
+    auth_guard: &JWTAuthMiddleware,
+    id: uuid::Uuid,
+) -> Result<Books, (StatusCode, Json<ErrorResponse>)> {
+    let book = fetch_book(data, id).await?;
+    let allowed = has_book_access(data, auth_guard.user.id, &auth_guard.user.role, &book)
+        .await
+        .map_err(fetch_error)?;
+    if !allowed {
+        let e = ErrorResponse {
+            error: "".to_string(),
+            message: "You don't have access to this book".to_string(),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(e)));
+    }
+
+    Ok(book)
+}
+
+async fn delete_book_file(
+    data: &Arc<AppState>,
+    auth_guard: &JWTAuthMiddleware,
+    id: uuid::Uuid,
+    format: BookFormat,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    fetch_editable_book(data, auth_guard, id).await?;
+
+    let mut tx = data.db.begin().await.map_err(update_error)?;
+    let deleted = sqlx::query!(
+        "DELETE FROM book_files WHERE book_id = $1 AND format = $2",
+        id,
+        format.as_str()
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(update_error)?;
+    if deleted.rows_affected() == 0 {
+        return Err(file_not_found());
+    }
+    match format {
+        BookFormat::Pdf => {
+            sqlx::query!(
+                "UPDATE books SET page_count = NULL, updated_at = NOW() WHERE id = $1",
+                id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(update_error)?;
+        }
+        BookFormat::Epub => {
+            sqlx::query!("DELETE FROM book_chapters WHERE book_id = $1", id)
+                .execute(&mut *tx)
+                .await
+                .map_err(update_error)?;
+            sqlx::query!("DELETE FROM book_resources WHERE book_id = $1", id)
+                .execute(&mut *tx)
+                .await
+                .map_err(update_error)?;
+        }
+    }
+    set_blob_references(&mut tx, format.blob_owner(), id, &[])
+        .await
+        .map_err(update_error)?;
+    tx.commit().await.map_err(update_error)?;
+    invalidate_book_cache(data, id).await?;
+
+    Ok(())
+}
+
+async fn download_book_file(
+    data: &Arc<AppState>,
+    auth_guard: &JWTAuthMiddleware,
+    id: uuid::Uuid,
+    format: BookFormat,
+    headers: &HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let book = fetch_readable_book(data, auth_guard, id).await?;
+    let user = &auth_guard.user;
+    send_book_file(data, &book, user.id, &user.role, format, headers).await
+}
+
+/// Streams the file to the user with support for resuming: `Range` with
+/// `If-Range` sends a part of it, `If-None-Match` spares sending it again.
+/// Buyers get PDFs stamped if the publisher asks for it. Access must be
+/// checked by the caller.
+pub async fn send_book_file(
+    data: &Arc<AppState>,
+    book: &Books,
+    user_id: uuid::Uuid,
+    role: &UserRole,
+    format: BookFormat,
+    headers: &HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let id = book.id;
+    let stamped = match format {
+        BookFormat::Pdf => stamped_pdf(data, book, user_id, role).await.map_err(|e| {
+            let e = ErrorResponse {
+                error: format!("Watermark error: {}", e),
+                message: "Error when stamping PDF".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+        })?,
+        BookFormat::Epub => None,
+    };
+    let (storage_key, hash, size) = match stamped {
+        Some(stamped) => (stamped.storage_key, stamped.variant, stamped.size),
+        None => {
+            let file = sqlx::query!(
+                r#"
+                SELECT blobs.storage_key, book_files.blob_hash, book_files.size
+                FROM book_files
+                JOIN blobs ON blobs.hash = book_files.blob_hash
+                WHERE book_files.book_id = $1 AND book_files.format = $2
+                "#,
+                id,
+                format.as_str()
+            )
+            .fetch_optional(&data.db)
+            .await
+            .map_err(fetch_error)?
+            .ok_or_else(file_not_found)?;
+            (file.storage_key, file.blob_hash, file.size as u64)
+        }
+    };
+
+    let etag = content_etag(&hash);
+    if etag_matches(headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+
+    let range = match requested_range(headers, &etag, size) {
+        RangeRequest::Full => None,
+        RangeRequest::Partial(range) => Some(range),
+        RangeRequest::Unsatisfiable => {
+            let e = ErrorResponse {
+                error: "".to_string(),
+                message: "Requested range not satisfiable".to_string(),
+            };
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{}", size))],
+                Json(e),
+            )
+                .into_response());
+        }
+    };
+
+    let object = match range {
+        Some(range) => {
+            data.storage
+                .get_range(&storage_key, range.start, range.length())
+                .await
+        }
+        None => data.storage.get(&storage_key).await,
+    }
+    .map_err(|e| {
+        let e = ErrorResponse {
+            error: format!("Storage error: {}", e),
+            message: "Error when reading book file".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+    })?;
+
+    let mut response = (
+        [
+            (CONTENT_TYPE, format.content_type().to_string()),
+            (CONTENT_LENGTH, object.length.to_string()),
+            (
+                CONTENT_DISPOSITION,
+                attachment_disposition(&book.title, &id.to_string(), format.as_str()),
+            ),
+            (ACCEPT_RANGES, "bytes".to_string()),
+            (ETAG, etag),
+            // Clients keep the file, but check the tag before using it again.
+            (CACHE_CONTROL, "private, no-cache".to_string()),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(object.body),
+    )
+        .into_response();
+    if let Some(range) = range {
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        let content_range = HeaderValue::from_str(&range.content_range(size)).unwrap();
+        response.headers_mut().insert(CONTENT_RANGE, content_range);
+    }
+    Ok(response)
+}
+
+/// Checks and parses a PDF, then makes it the PDF of the book. An empty
+/// description of the book is filled in from the document subject.
+pub async fn attach_pdf(
+    data: &Arc<AppState>,
+    book: &Books,
+    upload: &SpooledFile,
+) -> Result<BookFile, (StatusCode, Json<ErrorResponse>)> {
+    let id = book.id;
+    if !upload.starts_with(PDF_MAGIC) {
+        let e = ErrorResponse {
+            error: "".to_string(),
+            message: "Unsupported file format, use PDF".to_string(),
+        };
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(e)));
+    }
+
+    let path = upload.path().to_path_buf();
+    let info = tokio::task::spawn_blocking(move || read_pdf_info(&path))
+        .await
+        .map_err(|e| {
+            let e = ErrorResponse {
+                error: format!("Task error: {}", e),
+                message: "Error when processing PDF".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+        })?
+        .map_err(|e| {
+            let e = ErrorResponse {
+                error: e.to_string(),
+                message: "PDF can't be read".to_string(),
+            };
+            (StatusCode::BAD_REQUEST, Json(e))
+        })?;
+
+    // A stamped copy would drop the encryption, so buyers would get the file
+    // without the publisher's watermark.
+    if info.encrypted {
+        let stamped = sqlx::query_scalar!(
+            "SELECT enabled FROM watermark_settings WHERE user_id = $1",
+            book.author_id
+        )
+        .fetch_optional(&data.db)
+        .await
+        .map_err(fetch_error)?
+        .unwrap_or(false);
+        if stamped {
+            let e = ErrorResponse {
+                error: "".to_string(),
+                message: "Encrypted PDFs can't be watermarked, upload the file without encryption or turn the watermark off".to_string(),
+            };
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(e)));
+        }
+    }
+
+    let storage_error = |e: BlobError| {
+        let e = ErrorResponse {
+            error: format!("Storage error: {}", e),
+            message: "Error when saving PDF".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+    };
+    let format = BookFormat::Pdf;
+    let hash = upload.hash.clone();
+    let size = upload.size;
+    let body = upload.stream().await.map_err(|e| storage_error(e.into()))?;
+    store_blob(
+        data,
+        format.blob_owner(),
+        &hash,
+        format.as_str(),
+        body,
+        size,
+    )
+    .await
+    .map_err(storage_error)?;
+
+    let mut tx = data.db.begin().await.map_err(update_error)?;
+    let file = sqlx::query_as!(
+        BookFile,
+        r#"
+        INSERT INTO book_files (book_id, format, blob_hash, size, page_count, title, author, encrypted)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (book_id, format) DO UPDATE
+        SET
+            blob_hash = EXCLUDED.blob_hash,
+            size = EXCLUDED.size,
+            page_count = EXCLUDED.page_count,
+            title = EXCLUDED.title,
+            author = EXCLUDED.author,
+            encrypted = EXCLUDED.encrypted,
+            updated_at = NOW()
+        RETURNING *
+        "#,
+        id,
+        format.as_str(),
+        hash,
+        size as i64,
+        info.page_count,
+        info.title,
+        info.author,
+        info.encrypted
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(update_error)?;
+    // The title is required when the book is created, so only a missing
+    // description is taken from the document.
+    sqlx::query!(
+        r#"
+        UPDATE books
+        SET page_count = $1,
+            description = CASE
+                WHEN COALESCE(btrim(description), '') = '' THEN COALESCE($2, description)
+                ELSE description
+            END,
+            updated_at = NOW()
+        WHERE id = $3
+        "#,
+        info.page_count,
+        info.subject,
+        id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(update_error)?;
+    queue_text_extraction(&mut tx, id, format, &hash)
+        .await
+        .map_err(update_error)?;
+    set_blob_references(&mut tx, format.blob_owner(), id, &[hash])
+        .await
+        .map_err(update_error)?;
+    tx.commit().await.map_err(update_error)?;
+    invalidate_book_cache(data, id).await?;
+
+    Ok(file)
+}
+
+/// Checks and unpacks an EPUB, then makes it the EPUB of the book with its
+/// chapters and resources.
+pub async fn attach_epub(
+    data: &Arc<AppState>,
+    book: &Books,
+    upload: &SpooledFile,
+) -> Result<BookFile, (StatusCode, Json<ErrorResponse>)> {
+    let id = book.id;
+    if !upload.starts_with(ZIP_MAGIC) {
+        let e = ErrorResponse {
+            error: "".to_string(),
+            message: "Unsupported file format, use EPUB".to_string(),
+        };
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(e)));
+    }
+
+    // Compressed text rarely grows more than a few times, anything beyond
+    // is treated as a zip bomb. Files sent over tus may be larger than the
+    // multipart limit.
+    let max_unpacked = upload.size.max(data.env.book_file_max_size as u64) * 4;
+    let path = upload.path().to_path_buf();
+    let epub = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(path)?;
+        read_epub(file, id, max_unpacked)
+    })
+    .await
+    .map_err(|e| {
+        let e = ErrorResponse {
+            error: format!("Task error: {}", e),
+            message: "Error when processing EPUB".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+    })?
+    .map_err(|e| {
+        let e = ErrorResponse {
+            error: e.to_string(),
+            message: "EPUB can't be read".to_string(),
+        };
+        (StatusCode::BAD_REQUEST, Json(e))
+    })?;
+
+    let isbn = epub.metadata.isbn();
+    if let Some(isbn) = isbn.filter(|isbn| *isbn != normalize_isbn(&book.isbn)) {
+        let e = ErrorResponse {
+            error: format!("The file has ISBN {}, the book {}", isbn, book.isbn),
+            message: "The EPUB belongs to another book".to_string(),
+        };
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(e)));
+    }
+
+    let storage_error = |e: BlobError| {
+        let e = ErrorResponse {
+            error: format!("Storage error: {}", e),
+            message: "Error when saving EPUB".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+    };
+    let format = BookFormat::Epub;
+    let hash = upload.hash.clone();
+    let size = upload.size;
+    let body = upload.stream().await.map_err(|e| storage_error(e.into()))?;
+    store_blob(
+        data,
+        format.blob_owner(),
+        &hash,
+        format.as_str(),
+        body,
+        size,
+    )
+    .await
+    .map_err(storage_error)?;
+
+    let mut hashes = vec![hash.clone()];
+    let mut resource_paths = Vec::new();
+    let mut resource_types = Vec::new();
+    let mut resource_hashes = Vec::new();
+    for resource in &epub.resources {
+        let extension = resource_extension(&resource.path);
+        let blob = store_blob_bytes(data, format.blob_owner(), &resource.bytes, &extension)
+            .await
+            .map_err(storage_error)?;
+        resource_paths.push(resource.path.clone());
+        resource_types.push(resource.media_type.clone());
+        resource_hashes.push(blob.hash.clone());
+        hashes.push(blob.hash);
+    }
+
+    let metadata = &epub.metadata;
+    let author = (!metadata.creators.is_empty()).then(|| metadata.creators.join(", "));
+    let positions: Vec<i32> = (1..=epub.chapters.len() as i32).collect();
+    let chapter_paths: Vec<String> = epub.chapters.iter().map(|c| c.path.clone()).collect();
+    let chapter_titles: Vec<Option<String>> =
+        epub.chapters.iter().map(|c| c.title.clone()).collect();
+    let chapter_contents: Vec<String> = epub.chapters.iter().map(|c| c.content.clone()).collect();
+
+    let mut tx = data.db.begin().await.map_err(update_error)?;
+    let file = sqlx::query_as!(
+        BookFile,
+        r#"
+        INSERT INTO book_files
+            (book_id, format, blob_hash, size, title, author, language, identifiers, toc)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (book_id, format) DO UPDATE
+        SET
+            blob_hash = EXCLUDED.blob_hash,
+            size = EXCLUDED.size,
+            title = EXCLUDED.title,
+            author = EXCLUDED.author,
+            language = EXCLUDED.language,
+            identifiers = EXCLUDED.identifiers,
+            toc = EXCLUDED.toc,
+            updated_at = NOW()
+        RETURNING *
+        "#,
+        id,
+        format.as_str(),
+        hash,
+        size as i64,
+        metadata.title,
+        author,
+        metadata.language,
+        serde_json::json!(metadata.identifiers),
+        serde_json::json!(epub.toc)
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(update_error)?;
+
+    sqlx::query!("DELETE FROM book_chapters WHERE book_id = $1", id)
+        .execute(&mut *tx)
+        .await
+        .map_err(update_error)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO book_chapters (book_id, position, path, title, content)
+        SELECT $1, * FROM UNNEST($2::int[], $3::text[], $4::text[], $5::text[])
+        "#,
+        id,
+        &positions,
+        &chapter_paths,
+        &chapter_titles as &[Option<String>],
+        &chapter_contents
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(update_error)?;
+
+    sqlx::query!("DELETE FROM book_resources WHERE book_id = $1", id)
+        .execute(&mut *tx)
+        .await
+        .map_err(update_error)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO book_resources (book_id, path, media_type, blob_hash)
+        SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::text[])
+        "#,
+        id,
+        &resource_paths,
+        &resource_types,
+        &resource_hashes
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(update_error)?;
+
+    // The description written by the author wins over the one in the file.
+    sqlx::query!(
+        r#"
+        UPDATE books
+        SET description = COALESCE(NULLIF(description, ''), $1), updated_at = NOW()
+        WHERE id = $2
+        "#,
+        metadata.description,
+        id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(update_error)?;
+    queue_text_extraction(&mut tx, id, format, &hash)
+        .await
+        .map_err(update_error)?;
+    set_blob_references(&mut tx, format.blob_owner(), id, &hashes)
+        .await
+        .map_err(update_error)?;
+    tx.commit().await.map_err(update_error)?;
+
+    let default_cover = book
+        .cover_image
+        .as_deref()
+        .is_none_or(|cover| cover == DEFAULT_COVER_FILE);
+    if let Some(cover) = epub.cover.filter(|_| default_cover) {
+        let upload = Bytes::copy_from_slice(&epub.resources[cover].bytes);
+        // A cover the image pipeline can't handle leaves the default one.
+        if let Err((_, Json(e))) = replace_cover(data, book, upload).await {
+            warn!("Cover of EPUB for book {} not used: {}", id, e.message);
+        }
+    }
+    invalidate_book_cache(data, id).await?;
+
+    Ok(file)
+}
+
+pub async fn attach_book_file(
+    data: &Arc<AppState>,
+    book: &Books,
+    format: BookFormat,
+    upload: &SpooledFile,
+) -> Result<BookFile, (StatusCode, Json<ErrorResponse>)> {
+    match format {
+        BookFormat::Pdf => attach_pdf(data, book, upload).await,
+        BookFormat::Epub => attach_epub(data, book, upload).await,
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/book/{id}/pdf/",
+    request_body(content = String, content_type = "multipart/form-data", description = "PDF файл в поле `file`"),
+    responses(
+        (status = 200, description = "Файл загружен, метаданные прочитаны. Пустое описание книги заполняется темой документа, название и автор из файла хранятся только в метаданных файла", body = BookFileResponse),
+        (status = 400, description = "PDF не удалось прочитать", body = ErrorResponse),
+        (status = 403, description = "Книга другого автора", body = ErrorResponse),
+        (status = 404, description = "Ошибка такой id не найден", body = ErrorResponse),
+        (status = 413, description = "Файл слишком большой", body = ErrorResponse),
+        (status = 415, description = "Файл не PDF", body = ErrorResponse),
+        (status = 422, description = "PDF зашифрован, а у издателя включён водяной знак", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn upload_pdf(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    mut multipart: Multipart,
+) -> APIResult<BookFileResponse> {
+    let book = fetch_editable_book(&data, &auth_guard, id).await?;
+    let upload = spool_file_field(&mut multipart, "file", data.env.book_file_max_size).await?;
+
+    let file = attach_pdf(&data, &book, &upload).await?;
+
+    let response = SuccessResponse {
+        data: BookFileResponse::from_file(file),
+        message: "PDF uploaded successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/book/{id}/pdf/",
+    responses(
+        (status = 200, description = "Файл удалён", body = String),
+        (status = 403, description = "Книга другого автора", body = ErrorResponse),
+        (status = 404, description = "Книга или файл не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn delete_pdf(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<String> {
+    delete_book_file(&data, &auth_guard, id, BookFormat::Pdf).await?;
+
+    let response = SuccessResponse {
+        data: "PDF deleted successfully".to_string(),
+        message: "Success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/{id}/pdf/",
+    responses(
+        (status = 200, description = "PDF файл книги", content_type = "application/pdf", body = Vec<u8>),
+        (status = 206, description = "Запрошенная часть файла (Range)", content_type = "application/pdf", body = Vec<u8>),
+        (status = 304, description = "Файл не изменился (If-None-Match)"),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Нет доступа к книге", body = ErrorResponse),
+        (status = 404, description = "Книга или файл не найдены", body = ErrorResponse),
+        (status = 416, description = "Диапазон за пределами файла", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = [])
+    ),
+    tag = "Books"
+)]
+pub async fn download_pdf(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    download_book_file(&data, &auth_guard, id, BookFormat::Pdf, &headers).await
+}
+
+/// Extension a resource is stored under, taken from its path in the archive.
+fn resource_extension(path: &str) -> String {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .filter(|extension| extension.bytes().all(|b| b.is_ascii_alphanumeric()))
+        .map(|extension| extension.to_ascii_lowercase())
+        .unwrap_or_else(|| "bin".to_string())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/book/{id}/epub/",
+    request_body(content = String, content_type = "multipart/form-data", description = "EPUB файл в поле `file`"),
+    responses(
+        (status = 200, description = "Файл загружен, главы и оглавление прочитаны", body = BookFileResponse),
+        (status = 400, description = "EPUB не удалось прочитать", body = ErrorResponse),
+        (status = 403, description = "Книга другого автора", body = ErrorResponse),
+        (status = 404, description = "Ошибка такой id не найден", body = ErrorResponse),
+        (status = 413, description = "Файл слишком большой", body = ErrorResponse),
+        (status = 415, description = "Файл не EPUB", body = ErrorResponse),
+        (status = 422, description = "ISBN файла не совпадает с ISBN книги", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn upload_epub(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    mut multipart: Multipart,
+) -> APIResult<BookFileResponse> {
+    let book = fetch_editable_book(&data, &auth_guard, id).await?;
+    let upload = spool_file_field(&mut multipart, "file", data.env.book_file_max_size).await?;
+
+    let file = attach_epub(&data, &book, &upload).await?;
+
+    let response = SuccessResponse {
+        data: BookFileResponse::from_file(file),
+        message: "EPUB uploaded successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/book/{id}/epub/",
+    responses(
+        (status = 200, description = "Файл и главы удалены", body = String),
+        (status = 403, description = "Книга другого автора", body = ErrorResponse),
+        (status = 404, description = "Книга или файл не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn delete_epub(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<String> {
+    delete_book_file(&data, &auth_guard, id, BookFormat::Epub).await?;
+
+    let response = SuccessResponse {
+        data: "EPUB deleted successfully".to_string(),
+        message: "Success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/{id}/epub/",
+    responses(
+        (status = 200, description = "EPUB файл книги", content_type = "application/epub+zip", body = Vec<u8>),
+        (status = 206, description = "Запрошенная часть файла (Range)", content_type = "application/epub+zip", body = Vec<u8>),
+        (status = 304, description = "Файл не изменился (If-None-Match)"),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Нет доступа к книге", body = ErrorResponse),
+        (status = 404, description = "Книга или файл не найдены", body = ErrorResponse),
+        (status = 416, description = "Диапазон за пределами файла", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = [])
+    ),
+    tag = "Books"
+)]
+pub async fn download_epub(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    download_book_file(&data, &auth_guard, id, BookFormat::Epub, &headers).await
+}
@@ -1,6 +1,17 @@
+mod book_file;
 pub mod book_handler;
+mod cover;
+mod epub;
+pub mod file_handler;
 pub mod genres_handler;
-mod model;
-mod response;
+pub mod model;
+pub mod reader_handler;
+pub mod response;
 pub mod route;
 mod schema;
+pub mod search_handler;
+pub mod signed_url_handler;
+pub mod text_index;
+pub mod tus_handler;
+pub mod watermark;
+pub mod watermark_handler;
@@ -26,4 +26,53 @@ pub struct Books {
     pub discount: Decimal,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// Resized copies of the uploaded cover, see `CoverVariantResponse`.
+    pub cover_variants: serde_json::Value,
+    pub cover_blurhash: Option<String>,
+    pub cover_color: Option<String>,
+    /// Taken from the uploaded PDF.
+    pub page_count: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema, Clone)]
+pub struct BookFile {
+    pub book_id: uuid::Uuid,
+    pub format: String,
+    pub blob_hash: String,
+    pub size: i64,
+    pub page_count: Option<i32>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub language: Option<String>,
+    /// Identifiers found in the file metadata, ISBNs as `urn:isbn:`.
+    pub identifiers: serde_json::Value,
+    /// Table of contents of an EPUB, see `EpubTocEntry`.
+    pub toc: Option<serde_json::Value>,
+    /// The PDF is encrypted, buyers get it without a watermark.
+    pub encrypted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema, Clone)]
+pub struct BookChapter {
+    pub book_id: uuid::Uuid,
+    pub position: i32,
+    pub path: String,
+    pub title: Option<String>,
+    pub content: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema, Clone)]
+pub struct TusUpload {
+    pub id: uuid::Uuid,
+    pub user_id: Option<uuid::Uuid>,
+    pub book_id: Option<uuid::Uuid>,
+    pub format: String,
+    pub length: i64,
+    pub offset: i64,
+    pub metadata: serde_json::Value,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
 }
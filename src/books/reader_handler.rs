@@ -0,0 +1,187 @@
+use crate::AppState;
+use crate::books::file_handler::{fetch_error, fetch_readable_book, file_not_found};
+use crate::books::model::BookChapter;
+use crate::books::response::{
+    ChapterResponse, ChapterSummaryResponse, EpubTocEntry, EpubTocResponse,
+};
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::http::header::{
+    CACHE_CONTROL, CONTENT_LENGTH, CONTENT_SECURITY_POLICY, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use std::sync::Arc;
+
+/// Resources may be SVG or HTML-like, they must not run anything when
+/// opened directly.
+const RESOURCE_CSP: &str = "default-src 'none'; img-src 'self'; style-src 'unsafe-inline'; sandbox";
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/{id}/epub/toc/",
+    responses(
+        (status = 200, description = "Оглавление и список глав", body = EpubTocResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Нет доступа к книге", body = ErrorResponse),
+        (status = 404, description = "Книга или файл не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = [])
+    ),
+    tag = "Books"
+)]
+pub async fn get_toc(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<EpubTocResponse> {
+    fetch_readable_book(&data, &auth_guard, id).await?;
+
+    let file = sqlx::query!(
+        "SELECT title, author, language, toc FROM book_files WHERE book_id = $1 AND format = 'epub'",
+        id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(fetch_error)?
+    .ok_or_else(file_not_found)?;
+    let chapters = sqlx::query_as!(
+        ChapterSummaryResponse,
+        "SELECT position, title FROM book_chapters WHERE book_id = $1 ORDER BY position",
+        id
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(fetch_error)?;
+
+    let toc: Vec<EpubTocEntry> = file
+        .toc
+        .and_then(|toc| serde_json::from_value(toc).ok())
+        .unwrap_or_default();
+    let response = SuccessResponse {
+        data: EpubTocResponse {
+            title: file.title,
+            author: file.author,
+            language: file.language,
+            chapters,
+            toc,
+        },
+        message: "Success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/{id}/epub/chapters/{position}/",
+    responses(
+        (status = 200, description = "Глава книги в очищенном HTML", body = ChapterResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Нет доступа к книге", body = ErrorResponse),
+        (status = 404, description = "Книга или глава не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = [])
+    ),
+    tag = "Books"
+)]
+pub async fn get_chapter(
+    Path((id, position)): Path<(uuid::Uuid, i32)>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<ChapterResponse> {
+    fetch_readable_book(&data, &auth_guard, id).await?;
+
+    let chapter = sqlx::query_as!(
+        BookChapter,
+        "SELECT * FROM book_chapters WHERE book_id = $1 AND position = $2",
+        id,
+        position
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(fetch_error)?
+    .ok_or_else(|| {
+        let e = ErrorResponse {
+            error: "".to_string(),
+            message: "Chapter not found".to_string(),
+        };
+        (StatusCode::NOT_FOUND, Json(e))
+    })?;
+    let chapter_count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM book_chapters WHERE book_id = $1"#,
+        id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(fetch_error)?;
+
+    let response = SuccessResponse {
+        data: ChapterResponse::from_chapter(chapter, chapter_count as i32),
+        message: "Success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/{id}/epub/resources/{path}",
+    responses(
+        (status = 200, description = "Изображение, стиль или шрифт из EPUB", body = Vec<u8>),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Нет доступа к книге", body = ErrorResponse),
+        (status = 404, description = "Книга или ресурс не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = [])
+    ),
+    tag = "Books"
+)]
+pub async fn get_resource(
+    Path((id, path)): Path<(uuid::Uuid, String)>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    fetch_readable_book(&data, &auth_guard, id).await?;
+
+    let resource = sqlx::query!(
+        r#"
+        SELECT book_resources.media_type, blobs.storage_key
+        FROM book_resources
+        JOIN blobs ON blobs.hash = book_resources.blob_hash
+        WHERE book_resources.book_id = $1 AND book_resources.path = $2
+        "#,
+        id,
+        path
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(fetch_error)?
+    .ok_or_else(file_not_found)?;
+
+    let object = data.storage.get(&resource.storage_key).await.map_err(|e| {
+        let e = ErrorResponse {
+            error: format!("Storage error: {}", e),
+            message: "Error when reading book file".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+    })?;
+
+    Ok((
+        [
+            (CONTENT_TYPE, resource.media_type),
+            (CONTENT_LENGTH, object.length.to_string()),
+            (CACHE_CONTROL, "private, max-age=3600".to_string()),
+            (CONTENT_SECURITY_POLICY, RESOURCE_CSP.to_string()),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(object.body),
+    ))
+}
@@ -1,4 +1,4 @@
-use crate::books::model::Books;
+use crate::books::model::{BookChapter, BookFile, Books};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
@@ -16,10 +16,25 @@ pub struct BookResponse {
     pub price: Decimal,
     pub discount: Decimal,
     pub discounted_price: Decimal,
+    pub cover_variants: Vec<CoverVariantResponse>,
+    /// Placeholder to show while the cover loads.
+    pub cover_blurhash: Option<String>,
+    /// Average colour of the cover, `#rrggbb`.
+    pub cover_color: Option<String>,
+    pub page_count: Option<i32>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct CoverVariantResponse {
+    pub width: u32,
+    pub height: u32,
+    /// `jpg` or `webp`.
+    pub format: String,
+    pub file: String,
+}
+
 impl BookResponse {
     pub fn from_book(book: Books) -> Self {
         let discounted_price = book.price * (Decimal::ONE - book.discount / Decimal::from(100));
@@ -36,8 +51,134 @@ impl BookResponse {
             price: book.price,
             discount: book.discount,
             discounted_price,
+            cover_variants: serde_json::from_value(book.cover_variants).unwrap_or_default(),
+            cover_blurhash: book.cover_blurhash,
+            cover_color: book.cover_color,
+            page_count: book.page_count,
             created_at: book.created_at,
             updated_at: book.updated_at,
         }
     }
 }
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct BookFileResponse {
+    /// `pdf` or `epub`.
+    pub format: String,
+    pub size: i64,
+    pub page_count: Option<i32>,
+    /// Title found in the file metadata.
+    pub title: Option<String>,
+    /// Author found in the file metadata.
+    pub author: Option<String>,
+    pub language: Option<String>,
+    /// Identifiers found in the file metadata, ISBNs as `urn:isbn:`.
+    pub identifiers: Vec<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl BookFileResponse {
+    pub fn from_file(file: BookFile) -> Self {
+        Self {
+            format: file.format,
+            size: file.size,
+            page_count: file.page_count,
+            title: file.title,
+            author: file.author,
+            language: file.language,
+            identifiers: serde_json::from_value(file.identifiers).unwrap_or_default(),
+            created_at: file.created_at,
+            updated_at: file.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+#[schema(no_recursion)]
+pub struct EpubTocEntry {
+    pub title: String,
+    /// Position of the chapter the entry points to.
+    pub chapter: Option<i32>,
+    /// Anchor inside the chapter.
+    pub fragment: Option<String>,
+    pub children: Vec<EpubTocEntry>,
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct ChapterSummaryResponse {
+    pub position: i32,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct EpubTocResponse {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub language: Option<String>,
+    /// Every chapter in reading order.
+    pub chapters: Vec<ChapterSummaryResponse>,
+    /// Table of contents of the book, may skip chapters.
+    pub toc: Vec<EpubTocEntry>,
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct ChapterResponse {
+    pub position: i32,
+    pub title: Option<String>,
+    /// Sanitized HTML, links point at the reader API.
+    pub content: String,
+    pub previous: Option<i32>,
+    pub next: Option<i32>,
+}
+
+impl ChapterResponse {
+    pub fn from_chapter(chapter: BookChapter, chapter_count: i32) -> Self {
+        Self {
+            position: chapter.position,
+            title: chapter.title,
+            content: chapter.content,
+            previous: (chapter.position > 1).then(|| chapter.position - 1),
+            next: (chapter.position < chapter_count).then(|| chapter.position + 1),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct SignedUrlResponse {
+    pub url: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct WatermarkSettingsResponse {
+    pub enabled: bool,
+    pub text: String,
+    pub position: String,
+    pub opacity: f32,
+    /// Books with an encrypted PDF, buyers get them without the watermark.
+    pub unstamped_books: Vec<uuid::Uuid>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct BookSearchHit {
+    /// `pdf` or `epub`.
+    pub format: String,
+    /// Page of the PDF or chapter of the EPUB, from 1.
+    pub position: i32,
+    /// Title of the EPUB chapter.
+    pub title: Option<String>,
+    /// Text around the match as HTML, the found words are in `<mark>`.
+    pub snippet: String,
+    /// Whole text of the page or chapter, only for users who own the book.
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema, Clone, Deserialize)]
+pub struct BookSearchResponse {
+    /// Some file of the book is still waiting for its text to be extracted.
+    pub pending: bool,
+    /// Best matches first.
+    pub hits: Vec<BookSearchHit>,
+}
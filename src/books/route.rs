@@ -1,10 +1,24 @@
 use crate::AppState;
 use crate::books::book_handler::{
-    create_book, delete_book, get_all_books, get_one_book, update_book,
+    create_book, delete_book, delete_cover, get_all_books, get_one_book, update_book, upload_cover,
+};
+use crate::books::file_handler::{
+    delete_epub, delete_pdf, download_epub, download_pdf, upload_epub, upload_pdf,
 };
 use crate::books::genres_handler::{create_genres, get_all_genres};
-use crate::middleware::jwt_auth::{auth_admin, auth_author_worker_admin};
-use axum::routing::{delete, get, patch, post};
+use crate::books::reader_handler::{get_chapter, get_resource, get_toc};
+use crate::books::search_handler::search_book_text;
+use crate::books::signed_url_handler::{
+    create_signed_url, download_signed_cover, download_signed_file,
+};
+use crate::books::tus_handler::{
+    create_upload, terminate_upload, tus_options, tus_resumable, upload_chunk, upload_offset,
+};
+use crate::books::watermark_handler::{delete_watermark, get_watermark, set_watermark};
+use crate::middleware::jwt_auth::{auth, auth_admin, auth_author_worker_admin, require_verified};
+use crate::service::upload::MULTIPART_OVERHEAD;
+use axum::extract::DefaultBodyLimit;
+use axum::routing::{delete, get, head, options, patch, post, put};
 use axum::{Router, middleware};
 use std::sync::Arc;
 
@@ -21,31 +35,177 @@ pub fn genre_routers(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
         .with_state(app_state)
 }
 
+pub fn tus_routers(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route(
+            "/",
+            post(create_upload)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
+        )
+        .route("/", options(tus_options))
+        .route(
+            "/{id}",
+            head(upload_offset)
+                .patch(upload_chunk)
+                .delete(terminate_upload)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
+        )
+        .layer(middleware::from_fn(tus_resumable))
+        .with_state(app_state)
+}
+
 pub fn books_routers(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .nest("/genres", genre_routers(app_state.clone()))
+        .nest("/uploads", tus_routers(app_state.clone()))
         .route("/", get(get_all_books))
         .route("/{id}", get(get_one_book))
         .route(
             "/create/",
-            post(create_book).route_layer(middleware::from_fn_with_state(
-                app_state.clone(),
-                auth_author_worker_admin,
-            )),
+            post(create_book)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
         )
         .route(
             "/delete/{id}/",
-            delete(delete_book).route_layer(middleware::from_fn_with_state(
-                app_state.clone(),
-                auth_author_worker_admin,
-            )),
+            delete(delete_book)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
+        )
+        .route(
+            "/{id}/cover/",
+            put(upload_cover)
+                .delete(delete_cover)
+                .layer(DefaultBodyLimit::max(
+                    app_state.env.cover_max_size + MULTIPART_OVERHEAD,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
+        )
+        .route(
+            "/{id}/pdf/",
+            put(upload_pdf)
+                .delete(delete_pdf)
+                .layer(DefaultBodyLimit::max(
+                    app_state.env.book_file_max_size + MULTIPART_OVERHEAD,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
+        )
+        .route(
+            "/{id}/pdf/",
+            get(download_pdf).route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{id}/epub/",
+            put(upload_epub)
+                .delete(delete_epub)
+                .layer(DefaultBodyLimit::max(
+                    app_state.env.book_file_max_size + MULTIPART_OVERHEAD,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
+        )
+        .route(
+            "/{id}/epub/",
+            get(download_epub).route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{id}/epub/toc/",
+            get(get_toc).route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{id}/epub/chapters/{position}/",
+            get(get_chapter).route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{id}/epub/resources/{*path}",
+            get(get_resource).route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/{id}/search/",
+            get(search_book_text)
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/watermark/",
+            get(get_watermark)
+                .put(set_watermark)
+                .delete(delete_watermark)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
+        )
+        .route(
+            "/{id}/signed-url/",
+            post(create_signed_url)
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route("/{id}/signed/{format}/", get(download_signed_file))
+        .route(
+            "/{id}/signed/cover/{width}/{format}/",
+            get(download_signed_cover),
         )
         .route(
             "/update/{id}/",
-            patch(update_book).route_layer(middleware::from_fn_with_state(
-                app_state.clone(),
-                auth_author_worker_admin,
-            )),
+            patch(update_book)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_verified,
+                ))
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_author_worker_admin,
+                )),
         )
         .with_state(app_state)
 }
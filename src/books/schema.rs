@@ -1,6 +1,6 @@
 use rust_decimal::Decimal;
 use serde::Deserialize;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 #[derive(Debug, Validate, Deserialize, ToSchema)]
@@ -21,7 +21,6 @@ pub struct BookSchema {
     pub isbn: String,
     pub discount: Option<Decimal>,
     pub genre_id: uuid::Uuid,
-    pub cover_image: String,
 }
 
 #[derive(Debug, Validate, Deserialize, ToSchema)]
@@ -29,7 +28,46 @@ pub struct BookUpdateSchema {
     #[validate(length(min = 1))]
     pub title: Option<String>,
     pub description: Option<String>,
-    pub cover_image: Option<String>,
     pub price: Option<Decimal>,
     pub discount: Option<Decimal>,
 }
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SignedUrlSchema {
+    /// `pdf`, `epub` or `cover`.
+    pub target: String,
+    /// Width of the cover variant.
+    pub width: Option<u32>,
+    /// `jpg` or `webp`, format of the cover variant.
+    pub format: Option<String>,
+    /// Lifetime in seconds, at most `SIGNED_URL_MAX_AGE`.
+    pub expires_in: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SignedUrlQuery {
+    pub user: uuid::Uuid,
+    /// Unix timestamp.
+    pub expires: i64,
+    pub signature: String,
+}
+
+#[derive(Debug, Validate, Deserialize, ToSchema)]
+pub struct WatermarkSettingsSchema {
+    /// Stamping is on when omitted.
+    pub enabled: Option<bool>,
+    /// May hold `{email}`, `{name}` and `{user_id}` of the buyer.
+    #[validate(length(min = 1, max = 200))]
+    pub text: String,
+    /// `top`, `bottom`, `center` or `diagonal`.
+    pub position: String,
+    #[validate(range(min = 0.05, max = 1.0))]
+    pub opacity: f32,
+}
+
+#[derive(Debug, Validate, Deserialize, IntoParams)]
+pub struct BookSearchQuery {
+    /// Words to find, `"quoted phrases"`, `or` and `-excluded` words work.
+    #[validate(length(min = 1, max = 200))]
+    pub q: String,
+}
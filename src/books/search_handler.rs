@@ -0,0 +1,174 @@
+use crate::AppState;
+use crate::books::book_file::has_book_access;
+use crate::books::file_handler::{fetch_book, fetch_error};
+use crate::books::response::{BookSearchHit, BookSearchResponse};
+use crate::books::schema::BookSearchQuery;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::users::session::{redis_connection, redis_error};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use std::sync::Arc;
+use validator::Validate;
+
+const SEARCH_HIT_LIMIT: i64 = 20;
+/// Period of `SEARCH_PREVIEW_QUOTA`, a day.
+const SEARCH_PREVIEW_WINDOW: i64 = 86400;
+/// Marks around the found words in `ts_headline`. Control characters never
+/// get into the indexed text, so they can't be confused with it.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// Snippet as HTML: the text is escaped, the found words are put in `<mark>`.
+fn snippet_html(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+fn preview_quota_key(user_id: uuid::Uuid, book_id: uuid::Uuid) -> String {
+    format!("search-preview-{}-{}", user_id, book_id)
+}
+
+/// Counts a search by a user who doesn't own the book. Each one returns a
+/// few snippets, so without a limit enough of them would give the book away.
+async fn use_preview_quota(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+    book_id: uuid::Uuid,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let key = preview_quota_key(user_id, book_id);
+    let mut redis_client = redis_connection(data).await?;
+    // The counter is created with its expiry in the same transaction, so it
+    // can't be left without one.
+    let (searches, retry_after): (i64, i64) = redis::pipe()
+        .atomic()
+        .cmd("SET")
+        .arg(&key)
+        .arg(0)
+        .arg("EX")
+        .arg(SEARCH_PREVIEW_WINDOW)
+        .arg("NX")
+        .ignore()
+        .incr(&key, 1)
+        .ttl(&key)
+        .query_async(&mut redis_client)
+        .await
+        .map_err(redis_error)?;
+    if searches > data.env.search_preview_quota {
+        let e = ErrorResponse {
+            error: format!("Retry after {} seconds", retry_after),
+            message: "Too many searches in this book, try again later".to_string(),
+        };
+        return Err((StatusCode::TOO_MANY_REQUESTS, Json(e)));
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/{id}/search/",
+    params(BookSearchQuery),
+    responses(
+        (status = 200, description = "Страницы PDF и главы EPUB с найденными словами. Текст целиком видят только владельцы книги, остальные получают короткие отрывки", body = BookSearchResponse),
+        (status = 400, description = "Пустой или слишком длинный запрос", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 404, description = "Книга не найдена", body = ErrorResponse),
+        (status = 429, description = "Исчерпан дневной лимит поисков SEARCH_PREVIEW_QUOTA для книги, которой нет у пользователя", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = [])
+    ),
+    tag = "Books"
+)]
+pub async fn search_book_text(
+    Path(id): Path<uuid::Uuid>,
+    Query(query): Query<BookSearchQuery>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<BookSearchResponse> {
+    if query.validate().is_err() {
+        let e = ErrorResponse {
+            error: "Invalid".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(e)));
+    }
+
+    let book = fetch_book(&data, id).await?;
+    let owner = has_book_access(&data, auth_guard.user.id, &auth_guard.user.role, &book)
+        .await
+        .map_err(fetch_error)?;
+    if !owner {
+        use_preview_quota(&data, auth_guard.user.id, id).await?;
+    }
+
+    let pending = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM book_text_jobs WHERE book_id = $1 AND status = 'pending'
+        ) as "pending!"
+        "#,
+        id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(fetch_error)?;
+
+    // Snippets are kept short, so the text of a book can't be read through
+    // search without owning it.
+    let options = format!(
+        "StartSel={}, StopSel={}, MaxWords=30, MinWords=10, MaxFragments=1",
+        MATCH_START, MATCH_END
+    );
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            format,
+            position,
+            title,
+            ts_headline('simple', content, query, $3) as "snippet!",
+            CASE WHEN $4 THEN content END as content
+        FROM book_text_segments, websearch_to_tsquery('simple', $2) query
+        WHERE book_id = $1 AND tsv @@ query
+        ORDER BY ts_rank(tsv, query) DESC, format, position
+        LIMIT $5
+        "#,
+        id,
+        query.q,
+        options,
+        owner,
+        SEARCH_HIT_LIMIT
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(fetch_error)?;
+
+    let hits = rows
+        .into_iter()
+        .map(|row| BookSearchHit {
+            format: row.format,
+            position: row.position,
+            title: row.title,
+            snippet: snippet_html(&row.snippet),
+            content: row.content,
+        })
+        .collect();
+    let response = SuccessResponse {
+        data: BookSearchResponse { pending, hits },
+        message: "Success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
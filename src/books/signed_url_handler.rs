@@ -0,0 +1,271 @@
+use crate::AppState;
+use crate::books::book_file::{BookFormat, has_book_access};
+use crate::books::file_handler::{
+    fetch_book, fetch_error, fetch_readable_book, file_not_found, send_book_file,
+};
+use crate::books::model::Books;
+use crate::books::response::{CoverVariantResponse, SignedUrlResponse};
+use crate::books::schema::{SignedUrlQuery, SignedUrlSchema};
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::service::signed_url::{sign_url, verify_url};
+use crate::service::storage::storage_key;
+use crate::users::admin_handler::check_not_suspended;
+use crate::users::model::UserRole;
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use std::sync::Arc;
+
+/// What a signed link opens.
+enum SignedTarget {
+    File(BookFormat),
+    Cover { width: u32, format: String },
+}
+
+impl SignedTarget {
+    fn path(&self, book_id: uuid::Uuid) -> String {
+        match self {
+            SignedTarget::File(format) => {
+                format!("/api/v1/book/{}/signed/{}/", book_id, format.as_str())
+            }
+            SignedTarget::Cover { width, format } => {
+                format!(
+                    "/api/v1/book/{}/signed/cover/{}/{}/",
+                    book_id, width, format
+                )
+            }
+        }
+    }
+}
+
+fn bad_request(message: &str) -> (StatusCode, Json<ErrorResponse>) {
+    let e = ErrorResponse {
+        error: "".to_string(),
+        message: message.to_string(),
+    };
+    (StatusCode::BAD_REQUEST, Json(e))
+}
+
+fn forbidden(message: &str) -> (StatusCode, Json<ErrorResponse>) {
+    let e = ErrorResponse {
+        error: "".to_string(),
+        message: message.to_string(),
+    };
+    (StatusCode::FORBIDDEN, Json(e))
+}
+
+fn cover_variant(book: &Books, width: u32, format: &str) -> Option<CoverVariantResponse> {
+    let variants: Vec<CoverVariantResponse> =
+        serde_json::from_value(book.cover_variants.clone()).unwrap_or_default();
+    variants
+        .into_iter()
+        .find(|variant| variant.width == width && variant.format == format)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/book/{id}/signed-url/",
+    request_body = SignedUrlSchema,
+    responses(
+        (status = 200, description = "Подписанная ссылка на файл или обложку, действует до expires_at", body = SignedUrlResponse),
+        (status = 400, description = "Неизвестный файл или размер обложки", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Нет доступа к книге", body = ErrorResponse),
+        (status = 404, description = "Книга, файл или обложка не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = [])
+    ),
+    tag = "Books"
+)]
+pub async fn create_signed_url(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<SignedUrlSchema>,
+) -> APIResult<SignedUrlResponse> {
+    let target = match body.target.as_str() {
+        "cover" => {
+            let width = body
+                .width
+                .ok_or_else(|| bad_request("Cover width is required"))?;
+            let format = body.format.unwrap_or_else(|| "jpg".to_string());
+            let book = fetch_book(&data, id).await?;
+            cover_variant(&book, width, &format).ok_or_else(|| {
+                let e = ErrorResponse {
+                    error: "".to_string(),
+                    message: "Cover variant not found".to_string(),
+                };
+                (StatusCode::NOT_FOUND, Json(e))
+            })?;
+            SignedTarget::Cover { width, format }
+        }
+        target => {
+            let format =
+                BookFormat::parse(target).ok_or_else(|| bad_request("Unknown link target"))?;
+            fetch_readable_book(&data, &auth_guard, id).await?;
+            let exists = sqlx::query_scalar!(
+                r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM book_files WHERE book_id = $1 AND format = $2
+                ) as "exists!"
+                "#,
+                id,
+                format.as_str()
+            )
+            .fetch_one(&data.db)
+            .await
+            .map_err(fetch_error)?;
+            if !exists {
+                return Err(file_not_found());
+            }
+            SignedTarget::File(format)
+        }
+    };
+
+    let max_age = data.env.signed_url_max_age;
+    let expires_in = body.expires_in.unwrap_or(max_age).clamp(1, max_age);
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(expires_in);
+    let path = target.path(id);
+    let query = sign_url(
+        &data.env.secret_key,
+        &path,
+        auth_guard.user.id,
+        expires_at.timestamp(),
+    );
+
+    let response = SuccessResponse {
+        data: SignedUrlResponse {
+            url: format!("{}{}?{}", data.env.app_url, path, query),
+            expires_at,
+        },
+        message: "Success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// Checks the signature of the link to `target` of book `id`.
+fn verify_signed_query(
+    data: &Arc<AppState>,
+    id: uuid::Uuid,
+    target: &SignedTarget,
+    query: &SignedUrlQuery,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    verify_url(
+        &data.env.secret_key,
+        &target.path(id),
+        query.user,
+        query.expires,
+        &query.signature,
+    )
+    .map_err(|e| forbidden(&e.to_string()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/{id}/signed/{format}/",
+    params(SignedUrlQuery),
+    responses(
+        (status = 200, description = "Файл книги по подписанной ссылке, без токена. Поддерживает Range и ETag как обычное скачивание"),
+        (status = 206, description = "Запрошенная часть файла"),
+        (status = 304, description = "Файл не изменился"),
+        (status = 403, description = "Подпись неверна, ссылка устарела, доступ к книге отозван или пользователь заблокирован", body = ErrorResponse),
+        (status = 404, description = "Книга или файл не найдены", body = ErrorResponse),
+        (status = 416, description = "Диапазон за пределами файла", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    tag = "Books"
+)]
+pub async fn download_signed_file(
+    Path((id, format)): Path<(uuid::Uuid, String)>,
+    Query(query): Query<SignedUrlQuery>,
+    State(data): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let format = BookFormat::parse(&format).ok_or_else(file_not_found)?;
+    verify_signed_query(&data, id, &SignedTarget::File(format), &query)?;
+
+    // The access is checked again, so links stop working as soon as the
+    // book is taken away from the user or the user is suspended.
+    check_not_suspended(&data, query.user).await?;
+    let role = sqlx::query_scalar!(
+        r#"SELECT role as "role: UserRole" FROM users WHERE id = $1"#,
+        query.user
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(fetch_error)?
+    .ok_or_else(|| forbidden("You don't have access to this book"))?;
+    let book = fetch_book(&data, id).await?;
+    let allowed = has_book_access(&data, query.user, &role, &book)
+        .await
+        .map_err(fetch_error)?;
+    if !allowed {
+        return Err(forbidden("You don't have access to this book"));
+    }
+
+    send_book_file(&data, &book, query.user, &role, format, &headers).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/{id}/signed/cover/{width}/{format}/",
+    params(SignedUrlQuery),
+    responses(
+        (status = 200, description = "Обложка нужного размера по подписанной ссылке"),
+        (status = 403, description = "Подпись неверна или ссылка устарела", body = ErrorResponse),
+        (status = 404, description = "Книга или обложка не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    tag = "Books"
+)]
+pub async fn download_signed_cover(
+    Path((id, width, format)): Path<(uuid::Uuid, u32, String)>,
+    Query(query): Query<SignedUrlQuery>,
+    State(data): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let target = SignedTarget::Cover {
+        width,
+        format: format.clone(),
+    };
+    verify_signed_query(&data, id, &target, &query)?;
+
+    let book = fetch_book(&data, id).await?;
+    let key = cover_variant(&book, width, &format)
+        .and_then(|variant| storage_key(&variant.file).map(str::to_string))
+        .ok_or_else(|| {
+            let e = ErrorResponse {
+                error: "".to_string(),
+                message: "Cover variant not found".to_string(),
+            };
+            (StatusCode::NOT_FOUND, Json(e))
+        })?;
+    let object = data.storage.get(&key).await.map_err(|e| {
+        let e = ErrorResponse {
+            error: format!("Storage error: {}", e),
+            message: "Error when reading cover".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+    })?;
+
+    let content_type = match format.as_str() {
+        "webp" => "image/webp",
+        _ => "image/jpeg",
+    };
+    // Covers are public, a cache may keep them until the link expires.
+    let max_age = (query.expires - chrono::Utc::now().timestamp()).max(0);
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (CONTENT_LENGTH, object.length.to_string()),
+            (CACHE_CONTROL, format!("public, max-age={}", max_age)),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(object.body),
+    ))
+}
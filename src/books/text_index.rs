@@ -0,0 +1,336 @@
+use crate::AppState;
+use crate::books::book_file::BookFormat;
+use futures_util::TryStreamExt;
+use lopdf::Document;
+use sqlx::PgConnection;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+pub type TextIndexError = Box<dyn std::error::Error + Send + Sync>;
+
+const BATCH_SIZE: i64 = 5;
+const MAX_ATTEMPTS: i32 = 5;
+/// How long a job is kept from other workers once taken.
+const LEASE_SECONDS: f64 = 600.0;
+
+/// Text of a PDF page or an EPUB chapter.
+struct TextSegment {
+    position: i32,
+    title: Option<String>,
+    content: String,
+}
+
+struct TextJob {
+    book_id: uuid::Uuid,
+    format: String,
+    blob_hash: String,
+    attempts: i32,
+}
+
+/// Queues the text of the file `blob_hash` for extraction. The text of the
+/// previous file is dropped right away, so search never points at its pages.
+pub async fn queue_text_extraction(
+    conn: &mut PgConnection,
+    book_id: uuid::Uuid,
+    format: BookFormat,
+    blob_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO book_text_jobs (book_id, format, blob_hash)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (book_id, format) DO UPDATE
+        SET
+            blob_hash = EXCLUDED.blob_hash,
+            status = 'pending',
+            attempts = 0,
+            last_error = NULL,
+            next_attempt_at = NOW(),
+            updated_at = NOW()
+        "#,
+        book_id,
+        format.as_str(),
+        blob_hash
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM book_text_segments WHERE book_id = $1 AND format = $2",
+        book_id,
+        format.as_str()
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Collapses whitespace and drops control characters, which also keeps the
+/// snippet markers out of the indexed text.
+fn plain_text(text: &str) -> String {
+    text.split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = name.strip_prefix('#')?;
+            let value = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(value)
+        }
+    }
+}
+
+/// Text of a sanitized chapter. Every tag separates words, scripts and
+/// styles are already gone.
+fn html_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(['<', '&']) {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with('<') {
+            let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+            text.push(' ');
+            rest = &rest[end..];
+            continue;
+        }
+        let entity = rest
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end]).map(|c| (end, c)));
+        match entity {
+            Some((end, c)) => {
+                text.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    plain_text(&text)
+}
+
+/// Text of every PDF page that has any. Pages lopdf can't decode, e.g.
+/// with fonts it doesn't know, are skipped instead of failing the book.
+fn pdf_pages(bytes: &[u8]) -> Result<Vec<TextSegment>, lopdf::Error> {
+    let document = Document::load_mem(bytes)?;
+    let segments = document
+        .get_pages()
+        .keys()
+        .filter_map(|&number| {
+            let text = document
+                .extract_text(&[number])
+                .map_err(|e| warn!("Text of PDF page {} can't be read: {}", number, e))
+                .ok()?;
+            let content = plain_text(&text);
+            (!content.is_empty()).then_some(TextSegment {
+                position: number as i32,
+                title: None,
+                content,
+            })
+        })
+        .collect();
+    Ok(segments)
+}
+
+async fn extract_segments(
+    data: &Arc<AppState>,
+    job: &TextJob,
+) -> Result<Vec<TextSegment>, TextIndexError> {
+    match BookFormat::parse(&job.format) {
+        Some(BookFormat::Pdf) => {
+            let storage_key = sqlx::query_scalar!(
+                "SELECT storage_key FROM blobs WHERE hash = $1",
+                job.blob_hash
+            )
+            .fetch_one(&data.db)
+            .await?;
+            let mut bytes = Vec::new();
+            let mut body = data.storage.get(&storage_key).await?.body;
+            while let Some(part) = body.try_next().await? {
+                bytes.extend_from_slice(&part);
+            }
+            Ok(tokio::task::spawn_blocking(move || pdf_pages(&bytes)).await??)
+        }
+        Some(BookFormat::Epub) => {
+            let chapters = sqlx::query!(
+                "SELECT position, title, content FROM book_chapters WHERE book_id = $1",
+                job.book_id
+            )
+            .fetch_all(&data.db)
+            .await?;
+            Ok(chapters
+                .into_iter()
+                .map(|chapter| TextSegment {
+                    position: chapter.position,
+                    title: chapter.title,
+                    content: html_text(&chapter.content),
+                })
+                .filter(|segment| !segment.content.is_empty())
+                .collect())
+        }
+        None => Err(format!("Unknown book format {}", job.format).into()),
+    }
+}
+
+/// Replaces the indexed text of the book file, unless the file was replaced
+/// while its text was being extracted.
+async fn save_segments(
+    data: &Arc<AppState>,
+    job: &TextJob,
+    segments: Vec<TextSegment>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = data.db.begin().await?;
+    let current = sqlx::query!(
+        r#"
+        UPDATE book_text_jobs
+        SET status = 'done', last_error = NULL, updated_at = NOW()
+        WHERE book_id = $1 AND format = $2 AND blob_hash = $3 AND attempts = $4
+            AND status = 'pending'
+        "#,
+        job.book_id,
+        job.format,
+        job.blob_hash,
+        job.attempts
+    )
+    .execute(&mut *tx)
+    .await?;
+    if current.rows_affected() == 0 {
+        return Ok(());
+    }
+
+    let positions: Vec<i32> = segments.iter().map(|s| s.position).collect();
+    let titles: Vec<Option<String>> = segments.iter().map(|s| s.title.clone()).collect();
+    let contents: Vec<String> = segments.into_iter().map(|s| s.content).collect();
+    sqlx::query!(
+        "DELETE FROM book_text_segments WHERE book_id = $1 AND format = $2",
+        job.book_id,
+        job.format
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO book_text_segments (book_id, format, position, title, content)
+        SELECT $1, $2, * FROM UNNEST($3::int[], $4::text[], $5::text[])
+        "#,
+        job.book_id,
+        job.format,
+        &positions,
+        &titles as &[Option<String>],
+        &contents
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+async fn fail_job(data: &Arc<AppState>, job: &TextJob, e: &str) -> Result<(), sqlx::Error> {
+    let status = if job.attempts >= MAX_ATTEMPTS {
+        error!(
+            "Giving up on the text of {} of book {} after {} attempts: {}",
+            job.format, job.book_id, job.attempts, e
+        );
+        "failed"
+    } else {
+        warn!(
+            "Text extraction of {} of book {} failed, will retry: {}",
+            job.format, job.book_id, e
+        );
+        "pending"
+    };
+    let backoff_seconds = 30_f64 * 2_f64.powi(job.attempts.min(10));
+
+    sqlx::query!(
+        r#"
+        UPDATE book_text_jobs
+        SET status = $1,
+            last_error = $2,
+            next_attempt_at = NOW() + make_interval(secs => $3),
+            updated_at = NOW()
+        WHERE book_id = $4 AND format = $5 AND blob_hash = $6 AND attempts = $7
+        "#,
+        status,
+        e,
+        backoff_seconds,
+        job.book_id,
+        job.format,
+        job.blob_hash,
+        job.attempts
+    )
+    .execute(&data.db)
+    .await?;
+    Ok(())
+}
+
+/// Extracts the text of due book files into `book_text_segments`. Failed
+/// jobs are retried with exponential backoff until `MAX_ATTEMPTS`.
+pub async fn process_text_jobs(data: &Arc<AppState>) -> Result<usize, sqlx::Error> {
+    // Jobs are leased instead of staying locked while the text is
+    // extracted, so a new upload of the file doesn't wait for the old one.
+    let jobs = sqlx::query_as!(
+        TextJob,
+        r#"
+        UPDATE book_text_jobs
+        SET attempts = attempts + 1,
+            next_attempt_at = NOW() + make_interval(secs => $1),
+            updated_at = NOW()
+        WHERE (book_id, format) IN (
+            SELECT book_id, format FROM book_text_jobs
+            WHERE status = 'pending' AND next_attempt_at <= NOW()
+            ORDER BY next_attempt_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING book_id, format, blob_hash, attempts
+        "#,
+        LEASE_SECONDS,
+        BATCH_SIZE
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let processed = jobs.len();
+    for job in jobs {
+        let result = match extract_segments(data, &job).await {
+            Ok(segments) => save_segments(data, &job, segments)
+                .await
+                .map_err(TextIndexError::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            fail_job(data, &job, &e.to_string()).await?;
+        }
+    }
+    Ok(processed)
+}
+
+pub async fn run_text_worker(data: Arc<AppState>, poll_interval: Duration) {
+    info!("🔎 Text extraction worker started");
+    loop {
+        match process_text_jobs(&data).await {
+            Ok(processed) if processed > 0 => {
+                info!("Text worker processed {} book files", processed)
+            }
+            Ok(_) => {}
+            Err(e) => error!("Text worker error: {:?}", e),
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
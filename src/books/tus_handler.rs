@@ -0,0 +1,432 @@
+use crate::AppState;
+use crate::books::book_file::BookFormat;
+use crate::books::book_handler::{fetch_editable_book, update_error};
+use crate::books::file_handler::{attach_book_file, fetch_error};
+use crate::books::model::TusUpload;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::response_server::ErrorResponse;
+use crate::service::tus::{
+    TUS_EXTENSIONS, TUS_VERSION, TusError, chunk_prefix, http_date, parse_metadata, remove_upload,
+    spool_upload,
+};
+use axum::body::Body;
+use axum::extract::{Path, Request, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use futures_util::TryStreamExt;
+use std::sync::Arc;
+use tracing::error;
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+
+/// Content type of the body of a `PATCH`.
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+type TusResult = Result<Response, (StatusCode, Json<ErrorResponse>)>;
+
+fn tus_error(status: StatusCode, message: &str) -> (StatusCode, Json<ErrorResponse>) {
+    let e = ErrorResponse {
+        error: "".to_string(),
+        message: message.to_string(),
+    };
+    (status, Json(e))
+}
+
+fn header_u64(headers: &HeaderMap, name: HeaderName) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Rejects requests for another protocol version and marks every response
+/// with the version spoken.
+pub async fn tus_resumable(req: Request, next: Next) -> Response {
+    let supported = req.headers().get(TUS_RESUMABLE).map(|v| v.as_bytes());
+    let mut response = if req.method() != Method::OPTIONS && supported != Some(b"1.0.0") {
+        let e = tus_error(StatusCode::PRECONDITION_FAILED, "Unsupported tus version");
+        ([(TUS_VERSION_HEADER, TUS_VERSION)], e).into_response()
+    } else {
+        next.run(req).await
+    };
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+/// Upload `id` of the user. Expired uploads are gone even if the storage
+/// maintenance didn't remove them yet.
+async fn fetch_upload(
+    data: &Arc<AppState>,
+    auth_guard: &JWTAuthMiddleware,
+    id: uuid::Uuid,
+) -> Result<TusUpload, (StatusCode, Json<ErrorResponse>)> {
+    let upload = sqlx::query_as!(
+        TusUpload,
+        "SELECT * FROM tus_uploads WHERE id = $1 AND user_id = $2 AND book_id IS NOT NULL",
+        id,
+        auth_guard.user.id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(fetch_error)?
+    .ok_or_else(|| tus_error(StatusCode::NOT_FOUND, "Upload not found"))?;
+    if upload.expires_at < chrono::Utc::now() {
+        return Err(tus_error(StatusCode::GONE, "Upload expired"));
+    }
+    Ok(upload)
+}
+
+#[utoipa::path(
+    options,
+    path = "/api/v1/book/uploads/",
+    responses(
+        (status = 204, description = "Поддерживаемая версия tus, расширения и максимальный размер"),
+    ),
+    tag = "Books"
+)]
+pub async fn tus_options(State(data): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_VERSION_HEADER, TUS_VERSION.to_string()),
+            (TUS_EXTENSION, TUS_EXTENSIONS.to_string()),
+            (TUS_MAX_SIZE, data.env.tus_max_size.to_string()),
+        ],
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/book/uploads/",
+    params(
+        ("Upload-Length" = u64, Header, description = "Размер файла"),
+        ("Upload-Metadata" = String, Header, description = "`book_id` и `format` (`pdf` или `epub`) в Base64, как в протоколе tus"),
+    ),
+    responses(
+        (status = 201, description = "Загрузка создана, адрес в Location"),
+        (status = 400, description = "Нет размера или метаданных", body = ErrorResponse),
+        (status = 403, description = "Книга другого автора", body = ErrorResponse),
+        (status = 404, description = "Книга не найдена", body = ErrorResponse),
+        (status = 412, description = "Неподдерживаемая версия tus", body = ErrorResponse),
+        (status = 413, description = "Файл больше Tus-Max-Size", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn create_upload(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    headers: HeaderMap,
+) -> TusResult {
+    let length = header_u64(&headers, UPLOAD_LENGTH)
+        .filter(|length| *length > 0)
+        .ok_or_else(|| tus_error(StatusCode::BAD_REQUEST, "Upload-Length is required"))?;
+    if length > data.env.tus_max_size {
+        return Err(tus_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "The file is larger than Tus-Max-Size",
+        ));
+    }
+
+    let metadata = headers
+        .get(UPLOAD_METADATA)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_metadata)
+        .ok_or_else(|| tus_error(StatusCode::BAD_REQUEST, "Invalid Upload-Metadata"))?;
+    let book_id = metadata
+        .get("book_id")
+        .and_then(|id| id.parse::<uuid::Uuid>().ok())
+        .ok_or_else(|| tus_error(StatusCode::BAD_REQUEST, "book_id metadata is required"))?;
+    let format = metadata
+        .get("format")
+        .and_then(|format| BookFormat::parse(format))
+        .ok_or_else(|| {
+            tus_error(
+                StatusCode::BAD_REQUEST,
+                "format metadata must be pdf or epub",
+            )
+        })?;
+    fetch_editable_book(&data, &auth_guard, book_id).await?;
+
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(data.env.tus_upload_expiration);
+    let upload_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO tus_uploads (user_id, book_id, format, length, metadata, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+        auth_guard.user.id,
+        book_id,
+        format.as_str(),
+        length as i64,
+        serde_json::json!(metadata),
+        expires_at
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(update_error)?;
+
+    Ok((
+        StatusCode::CREATED,
+        [
+            (LOCATION, format!("/api/v1/book/uploads/{}", upload_id)),
+            (UPLOAD_EXPIRES, http_date(expires_at)),
+        ],
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    head,
+    path = "/api/v1/book/uploads/{id}",
+    responses(
+        (status = 200, description = "Сколько байт получено, в Upload-Offset"),
+        (status = 404, description = "Загрузка не найдена", body = ErrorResponse),
+        (status = 410, description = "Загрузка просрочена", body = ErrorResponse),
+        (status = 412, description = "Неподдерживаемая версия tus", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn upload_offset(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> TusResult {
+    let upload = fetch_upload(&data, &auth_guard, id).await?;
+
+    Ok((
+        [
+            (UPLOAD_OFFSET, upload.offset.to_string()),
+            (UPLOAD_LENGTH, upload.length.to_string()),
+            (UPLOAD_EXPIRES, http_date(upload.expires_at)),
+            (CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Body::empty(),
+    )
+        .into_response())
+}
+
+/// Stores the body as the chunk at `offset`. A chunk cut off by a
+/// disconnect is dropped: the client asks for the offset and sends it again.
+async fn store_chunk(
+    data: &Arc<AppState>,
+    upload: &TusUpload,
+    body: Body,
+    length: u64,
+) -> Result<(i64, chrono::DateTime<chrono::Utc>), (StatusCode, Json<ErrorResponse>)> {
+    let key = format!(
+        "{}/{}-{}",
+        chunk_prefix(upload.id),
+        upload.offset,
+        uuid::Uuid::new_v4().simple()
+    );
+    let stream = body.into_data_stream().map_err(std::io::Error::other);
+    data.storage
+        .put(&key, Box::pin(stream), length)
+        .await
+        .map_err(|e| {
+            let e = ErrorResponse {
+                error: format!("Storage error: {}", e),
+                message: "Error when saving the chunk".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+        })?;
+
+    // Another request may have sent the same chunk meanwhile.
+    let mut tx = data.db.begin().await.map_err(update_error)?;
+    let updated = sqlx::query!(
+        r#"
+        UPDATE tus_uploads
+        SET "offset" = "offset" + $1, expires_at = $2, updated_at = NOW()
+        WHERE id = $3 AND "offset" = $4
+        RETURNING "offset", expires_at
+        "#,
+        length as i64,
+        chrono::Utc::now() + chrono::Duration::seconds(data.env.tus_upload_expiration),
+        upload.id,
+        upload.offset
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(update_error)?;
+    let Some(updated) = updated else {
+        drop(tx);
+        data.storage.delete(&key).await.ok();
+        return Err(tus_error(
+            StatusCode::CONFLICT,
+            "Upload-Offset doesn't match",
+        ));
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO tus_upload_chunks (upload_id, "offset", size, storage_key)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        upload.id,
+        upload.offset,
+        length as i64,
+        key
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(update_error)?;
+    tx.commit().await.map_err(update_error)?;
+    Ok((updated.offset, updated.expires_at))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/book/uploads/{id}",
+    params(
+        ("Upload-Offset" = u64, Header, description = "Смещение, с которого идут байты тела"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/offset+octet-stream"),
+    responses(
+        (status = 204, description = "Часть сохранена, новое смещение в Upload-Offset. Последняя часть прикрепляет файл к книге"),
+        (status = 400, description = "Файл не удалось прочитать или часть длиннее файла", body = ErrorResponse),
+        (status = 404, description = "Загрузка не найдена", body = ErrorResponse),
+        (status = 409, description = "Upload-Offset не совпадает", body = ErrorResponse),
+        (status = 410, description = "Загрузка просрочена", body = ErrorResponse),
+        (status = 412, description = "Неподдерживаемая версия tus", body = ErrorResponse),
+        (status = 415, description = "Неверный Content-Type или формат файла", body = ErrorResponse),
+        (status = 422, description = "EPUB другой книги или зашифрованный PDF при включённом водяном знаке", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn upload_chunk(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    headers: HeaderMap,
+    body: Body,
+) -> TusResult {
+    if headers.get(CONTENT_TYPE).map(|v| v.as_bytes()) != Some(OFFSET_CONTENT_TYPE.as_bytes()) {
+        return Err(tus_error(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/offset+octet-stream",
+        ));
+    }
+    let upload = fetch_upload(&data, &auth_guard, id).await?;
+    if header_u64(&headers, UPLOAD_OFFSET) != Some(upload.offset as u64) {
+        return Err(tus_error(
+            StatusCode::CONFLICT,
+            "Upload-Offset doesn't match",
+        ));
+    }
+    let length = header_u64(&headers, CONTENT_LENGTH)
+        .ok_or_else(|| tus_error(StatusCode::LENGTH_REQUIRED, "Content-Length is required"))?;
+    if upload.offset as u64 + length > upload.length as u64 {
+        return Err(tus_error(
+            StatusCode::BAD_REQUEST,
+            "The chunk goes past Upload-Length",
+        ));
+    }
+
+    let (offset, expires_at) = match length {
+        0 => (upload.offset, upload.expires_at),
+        _ => store_chunk(&data, &upload, body, length).await?,
+    };
+    if offset == upload.length {
+        complete_upload(&data, &auth_guard, &upload).await?;
+    }
+
+    Ok((
+        StatusCode::NO_CONTENT,
+        [
+            (UPLOAD_OFFSET, offset.to_string()),
+            (UPLOAD_EXPIRES, http_date(expires_at)),
+        ],
+    )
+        .into_response())
+}
+
+/// Attaches the finished file to its book, the same way as a multipart
+/// upload. The upload is removed either way: a file that can't be attached
+/// won't get better when sent again.
+async fn complete_upload(
+    data: &Arc<AppState>,
+    auth_guard: &JWTAuthMiddleware,
+    upload: &TusUpload,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let attached = async {
+        let book_id = upload
+            .book_id
+            .ok_or_else(|| tus_error(StatusCode::NOT_FOUND, "Book not found"))?;
+        let format = BookFormat::parse(&upload.format)
+            .ok_or_else(|| tus_error(StatusCode::BAD_REQUEST, "Unknown file format"))?;
+        // Uploads created before the limit was lowered may be larger.
+        if upload.length as u64 > data.env.tus_max_size {
+            return Err(tus_error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "The file is larger than Tus-Max-Size",
+            ));
+        }
+        let book = fetch_editable_book(data, auth_guard, book_id).await?;
+        let storage_error = |e: TusError| {
+            let e = ErrorResponse {
+                error: format!("Storage error: {}", e),
+                message: "Error when reading the upload".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+        };
+        let file = spool_upload(data, upload.id).await.map_err(storage_error)?;
+        attach_book_file(data, &book, format, &file).await
+    }
+    .await;
+
+    if let Err(e) = remove_upload(data, upload.id).await {
+        error!("Failed to remove upload {}: {}", upload.id, e);
+    }
+    attached.map(|_| ())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/book/uploads/{id}",
+    responses(
+        (status = 204, description = "Загрузка отменена, полученные части удалены"),
+        (status = 404, description = "Загрузка не найдена", body = ErrorResponse),
+        (status = 410, description = "Загрузка просрочена", body = ErrorResponse),
+        (status = 412, description = "Неподдерживаемая версия tus", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn terminate_upload(
+    Path(id): Path<uuid::Uuid>,
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> TusResult {
+    let upload = fetch_upload(&data, &auth_guard, id).await?;
+    remove_upload(&data, upload.id).await.map_err(|e| {
+        let e = ErrorResponse {
+            error: format!("Storage error: {}", e),
+            message: "Error when removing the upload".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(e))
+    })?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
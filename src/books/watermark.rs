@@ -0,0 +1,472 @@
+use crate::AppState;
+use crate::books::model::Books;
+use crate::service::blob::sha256_hex;
+use crate::service::download::transliterate;
+use crate::service::storage::PRIVATE_PREFIX;
+use crate::users::model::UserRole;
+use futures_util::TryStreamExt;
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, dictionary, text_string};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+pub type WatermarkError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Page tree levels searched for inherited attributes.
+const MAX_PAGE_TREE_DEPTH: usize = 32;
+/// Average Helvetica glyph width, in text size units.
+const GLYPH_WIDTH: f32 = 0.55;
+/// Key of the document information holding the stamp.
+const INFO_KEY: &str = "Watermark";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatermarkPosition {
+    Top,
+    Bottom,
+    Center,
+    Diagonal,
+}
+
+impl WatermarkPosition {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "top" => Some(WatermarkPosition::Top),
+            "bottom" => Some(WatermarkPosition::Bottom),
+            "center" => Some(WatermarkPosition::Center),
+            "diagonal" => Some(WatermarkPosition::Diagonal),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WatermarkPosition::Top => "top",
+            WatermarkPosition::Bottom => "bottom",
+            WatermarkPosition::Center => "center",
+            WatermarkPosition::Diagonal => "diagonal",
+        }
+    }
+}
+
+/// Stamp text for a buyer, `{email}`, `{name}` and `{user_id}` filled in.
+pub fn watermark_text(template: &str, email: &str, name: &str, user_id: uuid::Uuid) -> String {
+    template
+        .replace("{email}", email)
+        .replace("{name}", name)
+        .replace("{user_id}", &user_id.to_string())
+}
+
+/// The standard Helvetica only has WinAnsi glyphs: Cyrillic is
+/// transliterated and whatever is left outside ASCII becomes `?`.
+fn latin_text(text: &str) -> String {
+    transliterate(text)
+        .chars()
+        .map(|c| match c {
+            ' '..='~' => c,
+            _ => '?',
+        })
+        .collect()
+}
+
+/// Attribute of a page, looked up through the page tree when the page
+/// inherits it.
+fn inherited<'a>(document: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node = document.get_dictionary(page_id).ok()?;
+    for _ in 0..MAX_PAGE_TREE_DEPTH {
+        if let Ok(value) = node.get(key) {
+            return document.dereference(value).ok().map(|(_, value)| value);
+        }
+        let parent = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = document.get_dictionary(parent).ok()?;
+    }
+    None
+}
+
+/// Visible area of a page as `[left, bottom, right, top]`.
+fn page_box(document: &Document, page_id: ObjectId) -> [f32; 4] {
+    let read = |key: &[u8]| {
+        let values = inherited(document, page_id, key)?.as_array().ok()?;
+        let values: Vec<f32> = values
+            .iter()
+            .filter_map(|value| document.dereference(value).ok()?.1.as_float().ok())
+            .collect();
+        let [x0, y0, x1, y1] = <[f32; 4]>::try_from(values).ok()?;
+        Some([x0.min(x1), y0.min(y1), x0.max(x1), y0.max(y1)])
+    };
+    read(b"CropBox")
+        .or_else(|| read(b"MediaBox"))
+        .unwrap_or([0.0, 0.0, 612.0, 792.0])
+}
+
+/// Text size fitting `length` characters in `width`, at most `max`.
+fn fit_size(width: f32, length: usize, max: f32) -> f32 {
+    (width / (length.max(1) as f32 * GLYPH_WIDTH)).min(max)
+}
+
+/// Text matrix drawing the text at `position` of the box.
+fn text_matrix(bounds: [f32; 4], length: usize, position: WatermarkPosition) -> (f32, [f32; 6]) {
+    let [x0, y0, x1, y1] = bounds;
+    let (width, height) = (x1 - x0, y1 - y0);
+    let centered = |size: f32| x0 + (width - length as f32 * GLYPH_WIDTH * size) / 2.0;
+    match position {
+        WatermarkPosition::Top => {
+            let size = fit_size(width * 0.9, length, 10.0);
+            (size, [1.0, 0.0, 0.0, 1.0, centered(size), y1 - size * 2.0])
+        }
+        WatermarkPosition::Bottom => {
+            let size = fit_size(width * 0.9, length, 10.0);
+            (size, [1.0, 0.0, 0.0, 1.0, centered(size), y0 + size])
+        }
+        WatermarkPosition::Center => {
+            let size = fit_size(width * 0.9, length, 36.0);
+            let y = y0 + (height - size) / 2.0;
+            (size, [1.0, 0.0, 0.0, 1.0, centered(size), y])
+        }
+        WatermarkPosition::Diagonal => {
+            let diagonal = width.hypot(height);
+            let size = fit_size(diagonal * 0.8, length, 72.0);
+            let (sin, cos) = (height / diagonal, width / diagonal);
+            // The baseline starts half the text before the centre, along
+            // the diagonal, and half a line below it.
+            let half = length as f32 * GLYPH_WIDTH * size / 2.0;
+            let x = x0 + width / 2.0 - cos * half + sin * size / 2.0;
+            let y = y0 + height / 2.0 - sin * half - cos * size / 2.0;
+            (size, [cos, sin, -sin, cos, x, y])
+        }
+    }
+}
+
+/// Resources of the page as a dictionary of its own, so adding to them
+/// changes neither the parent nor pages sharing them.
+fn own_resources(document: &Document, page_id: ObjectId) -> Dictionary {
+    let mut resources = inherited(document, page_id, b"Resources")
+        .and_then(|resources| resources.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    let xobjects = resources
+        .get(b"XObject")
+        .ok()
+        .and_then(|xobjects| document.dereference(xobjects).ok())
+        .and_then(|(_, xobjects)| xobjects.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    resources.set("XObject", xobjects);
+    resources
+}
+
+/// Content streams of the page, in drawing order.
+fn page_contents(document: &Document, page_id: ObjectId) -> Vec<Object> {
+    let Some(contents) = document
+        .get_dictionary(page_id)
+        .ok()
+        .and_then(|page| page.get(b"Contents").ok())
+    else {
+        return Vec::new();
+    };
+    match contents {
+        Object::Reference(id) => match document.get_object(*id) {
+            Ok(Object::Array(streams)) => streams.clone(),
+            _ => vec![contents.clone()],
+        },
+        Object::Array(streams) => streams.clone(),
+        _ => Vec::new(),
+    }
+}
+
+/// Stamps every page with the text over the existing content and records
+/// it in the document information. `None` for encrypted files: lopdf can't
+/// encrypt the copy again, and saving it decrypted would drop the
+/// restrictions of the owner password.
+pub fn watermark_pdf(
+    bytes: &[u8],
+    text: &str,
+    position: WatermarkPosition,
+    opacity: f32,
+) -> Result<Option<Vec<u8>>, lopdf::Error> {
+    let mut document = Document::load_mem(bytes)?;
+    if document.is_encrypted() {
+        return Ok(None);
+    }
+
+    let stamp = latin_text(text);
+    let font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+        "Encoding" => "WinAnsiEncoding",
+    });
+    let state_id = document.add_object(dictionary! {
+        "Type" => "ExtGState",
+        "ca" => opacity,
+        "CA" => opacity,
+    });
+    // Saves the state before the page draws, so whatever it leaves set
+    // doesn't move the stamp.
+    let save_id = document.add_object(Stream::new(dictionary! {}, b"q\n".to_vec()));
+
+    for page_id in document.get_pages().into_values() {
+        let bounds = page_box(&document, page_id);
+        let (size, matrix) = text_matrix(bounds, stamp.len(), position);
+        let content = Content {
+            operations: vec![
+                Operation::new("q", vec![]),
+                Operation::new("gs", vec!["WatermarkState".into()]),
+                Operation::new("g", vec![0.5.into()]),
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["WatermarkFont".into(), size.into()]),
+                Operation::new("Tm", matrix.iter().map(|&v| v.into()).collect()),
+                Operation::new("Tj", vec![Object::string_literal(stamp.as_str())]),
+                Operation::new("ET", vec![]),
+                Operation::new("Q", vec![]),
+            ],
+        };
+        let form_id = document.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => bounds.iter().map(|&v| v.into()).collect::<Vec<Object>>(),
+                "Resources" => dictionary! {
+                    "Font" => dictionary! { "WatermarkFont" => font_id },
+                    "ExtGState" => dictionary! { "WatermarkState" => state_id },
+                },
+            },
+            content.encode()?,
+        ));
+
+        let mut resources = own_resources(&document, page_id);
+        let mut name = "Watermark".to_string();
+        if let Ok(xobjects) = resources.get_mut(b"XObject").and_then(Object::as_dict_mut) {
+            while xobjects.has(name.as_bytes()) {
+                name.push('_');
+            }
+            xobjects.set(name.as_bytes(), form_id);
+        }
+        // Streams are joined as they are, the page may end without a space.
+        let draw = format!("\nQ\n/{} Do\n", name).into_bytes();
+        let draw_id = document.add_object(Stream::new(dictionary! {}, draw));
+
+        let mut contents = vec![Object::Reference(save_id)];
+        contents.extend(page_contents(&document, page_id));
+        contents.push(Object::Reference(draw_id));
+        let page = document.get_dictionary_mut(page_id)?;
+        page.set("Resources", resources);
+        page.set("Contents", contents);
+    }
+
+    let info_id = match document.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) => id,
+        Err(_) => {
+            let id = document.add_object(Dictionary::new());
+            document.trailer.set("Info", id);
+            id
+        }
+    };
+    document
+        .get_dictionary_mut(info_id)?
+        .set(INFO_KEY, text_string(text));
+
+    let mut stamped = Vec::new();
+    document.save_to(&mut stamped)?;
+    Ok(Some(stamped))
+}
+
+fn watermark_prefix(book_id: uuid::Uuid, user_id: uuid::Uuid) -> String {
+    format!("{}watermarks/{}/{}/", PRIVATE_PREFIX, book_id, user_id)
+}
+
+/// Copy of a book PDF stamped for one buyer.
+pub struct StampedPdf {
+    pub storage_key: String,
+    /// Hash of the original file and the stamp, tags the copy.
+    pub variant: String,
+    pub size: u64,
+}
+
+/// PDF of the book stamped for the user, made on the first download and
+/// kept until the file or the stamp changes. `None` for the author and
+/// staff, for encrypted files, and when the publisher doesn't stamp PDFs.
+/// Encrypted files are refused at upload while stamping is on and listed in
+/// the watermark settings otherwise.
+pub async fn stamped_pdf(
+    data: &Arc<AppState>,
+    book: &Books,
+    user_id: uuid::Uuid,
+    role: &UserRole,
+) -> Result<Option<StampedPdf>, WatermarkError> {
+    if book.author_id == user_id || matches!(role, UserRole::Worker | UserRole::Admin) {
+        return Ok(None);
+    }
+    let Some(settings) = sqlx::query!(
+        "SELECT text, position, opacity FROM watermark_settings WHERE user_id = $1 AND enabled",
+        book.author_id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let Some(file) = sqlx::query!(
+        r#"
+        SELECT blobs.storage_key, book_files.blob_hash
+        FROM book_files
+        JOIN blobs ON blobs.hash = book_files.blob_hash
+        WHERE book_files.book_id = $1 AND book_files.format = 'pdf'
+            AND NOT book_files.encrypted
+        "#,
+        book.id
+    )
+    .fetch_optional(&data.db)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let user = sqlx::query!(
+        "SELECT email, first_name, last_name FROM users WHERE id = $1",
+        user_id
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    let name = format!("{} {}", user.first_name, user.last_name);
+    let text = watermark_text(&settings.text, &user.email, name.trim(), user_id);
+    let position =
+        WatermarkPosition::parse(&settings.position).unwrap_or(WatermarkPosition::Bottom);
+    let variant = sha256_hex(
+        format!(
+            "{}\n{}\n{}\n{}",
+            file.blob_hash,
+            text,
+            position.as_str(),
+            settings.opacity
+        )
+        .as_bytes(),
+    );
+
+    let current = sqlx::query!(
+        "SELECT storage_key, variant, size FROM pdf_watermarks WHERE book_id = $1 AND user_id = $2",
+        book.id,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await?;
+    if let Some(current) = current
+        .as_ref()
+        .filter(|current| current.variant == variant)
+    {
+        return Ok(Some(StampedPdf {
+            storage_key: current.storage_key.clone(),
+            variant,
+            size: current.size as u64,
+        }));
+    }
+
+    let mut original = Vec::new();
+    let mut body = data.storage.get(&file.storage_key).await?.body;
+    while let Some(part) = body.try_next().await? {
+        original.extend_from_slice(&part);
+    }
+    let opacity = settings.opacity;
+    let stamped =
+        tokio::task::spawn_blocking(move || watermark_pdf(&original, &text, position, opacity))
+            .await??;
+    let Some(stamped) = stamped else {
+        // Uploaded before encrypted files were flagged.
+        sqlx::query!(
+            r#"
+            UPDATE book_files SET encrypted = TRUE
+            WHERE book_id = $1 AND format = 'pdf' AND blob_hash = $2
+            "#,
+            book.id,
+            file.blob_hash
+        )
+        .execute(&data.db)
+        .await?;
+        warn!(
+            "PDF of book {} is encrypted, served without the watermark",
+            book.id
+        );
+        return Ok(None);
+    };
+
+    let storage_key = format!("{}{}.pdf", watermark_prefix(book.id, user_id), variant);
+    data.storage.put_bytes(&storage_key, &stamped).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO pdf_watermarks (book_id, user_id, variant, storage_key, size)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (book_id, user_id) DO UPDATE
+        SET
+            variant = EXCLUDED.variant,
+            storage_key = EXCLUDED.storage_key,
+            size = EXCLUDED.size,
+            created_at = NOW()
+        "#,
+        book.id,
+        user_id,
+        variant,
+        storage_key,
+        stamped.len() as i64
+    )
+    .execute(&data.db)
+    .await?;
+    if let Some(current) = current.filter(|current| current.storage_key != storage_key) {
+        data.storage
+            .delete(&current.storage_key)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to remove watermarked copy {}: {}",
+                    current.storage_key, e
+                )
+            })
+            .ok();
+    }
+
+    Ok(Some(StampedPdf {
+        storage_key,
+        variant,
+        size: stamped.len() as u64,
+    }))
+}
+
+/// Removes stamped copies whose buyer no longer has the book, whose
+/// publisher stopped stamping, or whose user or book was deleted.
+pub async fn remove_stale_watermarks(data: &Arc<AppState>) -> Result<u64, WatermarkError> {
+    let stale = sqlx::query!(
+        r#"
+        SELECT id, storage_key FROM pdf_watermarks
+        WHERE book_id IS NULL OR user_id IS NULL
+            OR NOT EXISTS (
+                SELECT 1 FROM book_entitlements
+                WHERE book_entitlements.user_id = pdf_watermarks.user_id
+                    AND book_entitlements.book_id = pdf_watermarks.book_id
+            )
+            OR NOT EXISTS (
+                SELECT 1 FROM books
+                JOIN watermark_settings ON watermark_settings.user_id = books.author_id
+                WHERE books.id = pdf_watermarks.book_id AND watermark_settings.enabled
+            )
+        "#
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let mut removed = 0;
+    for copy in stale {
+        if let Err(e) = data.storage.delete(&copy.storage_key).await {
+            error!(
+                "Failed to remove watermarked copy {}: {}",
+                copy.storage_key, e
+            );
+            continue;
+        }
+        sqlx::query!("DELETE FROM pdf_watermarks WHERE id = $1", copy.id)
+            .execute(&data.db)
+            .await?;
+        removed += 1;
+    }
+    if removed > 0 {
+        info!("Removed {} stale watermarked copies", removed);
+    }
+    Ok(removed)
+}
@@ -0,0 +1,177 @@
+use crate::AppState;
+use crate::books::book_handler::update_error;
+use crate::books::file_handler::fetch_error;
+use crate::books::response::WatermarkSettingsResponse;
+use crate::books::schema::WatermarkSettingsSchema;
+use crate::books::watermark::WatermarkPosition;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use std::sync::Arc;
+use validator::Validate;
+
+fn settings_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    let e = ErrorResponse {
+        error: "".to_string(),
+        message: "Watermark isn't set up".to_string(),
+    };
+    (StatusCode::NOT_FOUND, Json(e))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/book/watermark/",
+    responses(
+        (status = 200, description = "Водяной знак на PDF книг издателя для покупателей", body = WatermarkSettingsResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Водяной знак не настроен", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn get_watermark(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<WatermarkSettingsResponse> {
+    let settings = sqlx::query_as!(
+        WatermarkSettingsResponse,
+        r#"
+        SELECT enabled, text, position, opacity, updated_at,
+            ARRAY(
+                SELECT books.id FROM books
+                JOIN book_files ON book_files.book_id = books.id
+                WHERE books.author_id = watermark_settings.user_id
+                    AND book_files.format = 'pdf' AND book_files.encrypted
+                ORDER BY books.id
+            ) AS "unstamped_books!"
+        FROM watermark_settings WHERE user_id = $1
+        "#,
+        auth_guard.user.id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(fetch_error)?
+    .ok_or_else(settings_not_found)?;
+
+    let response = SuccessResponse {
+        data: settings,
+        message: "Success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/book/watermark/",
+    request_body = WatermarkSettingsSchema,
+    responses(
+        (status = 200, description = "Водяной знак сохранён, копии с прежним знаком будут сделаны заново. Зашифрованные PDF из `unstamped_books` отдаются без знака, об этом предупреждает `message`", body = WatermarkSettingsResponse),
+        (status = 400, description = "Неверный текст, положение или прозрачность", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn set_watermark(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<WatermarkSettingsSchema>,
+) -> APIResult<WatermarkSettingsResponse> {
+    let position = WatermarkPosition::parse(&body.position);
+    if body.validate().is_err() || position.is_none() {
+        let e = ErrorResponse {
+            error: "Invalid".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(e)));
+    }
+
+    let settings = sqlx::query_as!(
+        WatermarkSettingsResponse,
+        r#"
+        INSERT INTO watermark_settings (user_id, enabled, text, position, opacity)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id) DO UPDATE
+        SET
+            enabled = EXCLUDED.enabled,
+            text = EXCLUDED.text,
+            position = EXCLUDED.position,
+            opacity = EXCLUDED.opacity,
+            updated_at = NOW()
+        RETURNING enabled, text, position, opacity, updated_at,
+            ARRAY(
+                SELECT books.id FROM books
+                JOIN book_files ON book_files.book_id = books.id
+                WHERE books.author_id = watermark_settings.user_id
+                    AND book_files.format = 'pdf' AND book_files.encrypted
+                ORDER BY books.id
+            ) AS "unstamped_books!"
+        "#,
+        auth_guard.user.id,
+        body.enabled.unwrap_or(true),
+        body.text,
+        position.map(WatermarkPosition::as_str),
+        body.opacity
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(update_error)?;
+
+    let message = if settings.enabled && !settings.unstamped_books.is_empty() {
+        "Saved, but encrypted PDFs are served without the watermark, see unstamped_books"
+    } else {
+        "Success"
+    };
+    let response = SuccessResponse {
+        data: settings,
+        message: message.to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/book/watermark/",
+    responses(
+        (status = 200, description = "Водяной знак удалён, PDF отдаются без него", body = String),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Водяной знак не настроен", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["author","worker","admin"])
+    ),
+    tag = "Books"
+)]
+pub async fn delete_watermark(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<String> {
+    let deleted = sqlx::query!(
+        "DELETE FROM watermark_settings WHERE user_id = $1",
+        auth_guard.user.id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(update_error)?;
+    if deleted.rows_affected() == 0 {
+        return Err(settings_not_found());
+    }
+
+    let response = SuccessResponse {
+        data: "Watermark removed".to_string(),
+        message: "Success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
@@ -1,15 +1,27 @@
-use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, COOKIE};
-use axum::http::{HeaderValue, Method};
+use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, COOKIE, LOCATION};
+use axum::http::{HeaderName, HeaderValue, Method};
 use dotenv::dotenv;
 use redis::Client;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Pool, Postgres};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tower_http::cors::CorsLayer;
 use tracing::{error, info};
 mod settings;
+use crate::books::text_index::run_text_worker;
 use crate::route::init_router;
-pub use settings::Settings;
+use crate::service::blob::run_blob_maintenance;
+use crate::service::mail_queue::run_mail_worker;
+use crate::service::mailer::{LogMailer, build_mail_transport};
+use crate::service::storage::build_storage;
+pub use books::text_index::process_text_jobs;
+pub use service::mail_queue::{OutboxMailer, process_mail_queue};
+pub use service::mailer::{FileMailer, MailMessage, Mailer, MemoryMailer};
+pub use service::storage::{ByteStream, LocalStorage, S3Storage, Storage, StoredObject};
+pub use settings::{OidcProvider, Settings};
+pub use users::data_export::{remove_expired_data_exports, resume_data_exports};
 
 mod api_doc;
 mod books;
@@ -23,12 +35,34 @@ pub struct AppState {
     db: Pool<Postgres>,
     env: Settings,
     redis: Client,
+    mailer: Arc<dyn Mailer>,
+    storage: Arc<dyn Storage>,
+    /// Shared so connections to identity providers are reused.
+    http: reqwest::Client,
 }
 
 impl AppState {
     pub fn new(db: Pool<Postgres>, env: Settings, redis: Client) -> Self {
-        AppState { db, env, redis }
+        AppState {
+            db,
+            storage: build_storage(&env),
+            env,
+            redis,
+            mailer: Arc::new(LogMailer),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = storage;
+        self
     }
+
     pub fn db(&self) -> &Pool<Postgres> {
         &self.db
     }
@@ -40,6 +74,14 @@ impl AppState {
     pub fn redis(&self) -> &Client {
         &self.redis
     }
+
+    pub fn mailer(&self) -> &Arc<dyn Mailer> {
+        &self.mailer
+    }
+
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
 }
 
 pub async fn start_server() {
@@ -72,18 +114,60 @@ pub async fn start_server() {
             Method::PATCH,
             Method::DELETE,
             Method::PUT,
+            Method::HEAD,
         ])
         .allow_credentials(true)
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE, COOKIE]);
+        .allow_headers([
+            AUTHORIZATION,
+            ACCEPT,
+            CONTENT_TYPE,
+            COOKIE,
+            HeaderName::from_static("tus-resumable"),
+            HeaderName::from_static("upload-length"),
+            HeaderName::from_static("upload-offset"),
+            HeaderName::from_static("upload-metadata"),
+        ])
+        .expose_headers([
+            LOCATION,
+            HeaderName::from_static("tus-resumable"),
+            HeaderName::from_static("tus-version"),
+            HeaderName::from_static("tus-extension"),
+            HeaderName::from_static("tus-max-size"),
+            HeaderName::from_static("upload-offset"),
+            HeaderName::from_static("upload-length"),
+            HeaderName::from_static("upload-expires"),
+        ]);
+
+    let mail_transport = build_mail_transport(&settings);
+    let mailer: Arc<dyn Mailer> = if settings.mail_queue {
+        tokio::spawn(run_mail_worker(
+            pool.clone(),
+            mail_transport,
+            settings.mail_max_attempts,
+            Duration::from_secs(10),
+        ));
+        Arc::new(OutboxMailer::new(pool.clone()))
+    } else {
+        mail_transport
+    };
+
+    let app_state = Arc::new(
+        AppState::new(pool.clone(), settings.clone(), redis_client.clone()).with_mailer(mailer),
+    );
+    if let Err(e) = resume_data_exports(&app_state).await {
+        error!("Failed to resume data exports: {}", e);
+    }
+    tokio::spawn(run_blob_maintenance(app_state.clone()));
+    tokio::spawn(run_text_worker(app_state.clone(), Duration::from_secs(10)));
 
-    let app = init_router(Arc::new(AppState {
-        db: pool.clone(),
-        env: settings.clone(),
-        redis: redis_client.clone(),
-    }))
-    .layer(cors);
+    let app = init_router(app_state).layer(cors);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
     info!("🚀 Server started at {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
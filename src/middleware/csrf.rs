@@ -0,0 +1,81 @@
+use crate::AppState;
+use crate::service::response_server::ErrorResponse;
+use crate::users::token::{generate_signed_token, verify_signed_token};
+use axum::Json;
+use axum::body::Body;
+use axum::extract::OriginalUri;
+use axum::http::{Method, Request, StatusCode};
+use std::sync::Arc;
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+const CSRF_TOKEN_PURPOSE: &str = "csrf";
+
+/// Issues a CSRF token bound to the session of the access token, so it
+/// stops working on logout.
+pub fn generate_csrf_token(
+    data: &Arc<AppState>,
+    access_token_uuid: uuid::Uuid,
+) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    generate_signed_token(
+        &access_token_uuid.to_string(),
+        CSRF_TOKEN_PURPOSE,
+        chrono::Duration::days(data.env.access_token_max_age),
+        &data.env.secret_key,
+    )
+    .map_err(|e| {
+        let error_response = ErrorResponse {
+            error: format!("Error generating CSRF token: {}", e),
+            message: "Error generating CSRF token".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })
+}
+
+fn csrf_exempt(data: &Arc<AppState>, req: &Request<Body>) -> bool {
+    if matches!(
+        *req.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    ) {
+        return true;
+    }
+
+    // Route layers of nested routers see the path without the nest prefix.
+    let path = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path())
+        .unwrap_or_else(|| req.uri().path());
+    data.env
+        .csrf_exempt_routes
+        .iter()
+        .any(|route| path.starts_with(route.as_str()))
+}
+
+/// Checks the `X-CSRF-Token` header of a request authenticated with the
+/// `access_token` cookie. Browsers attach the cookie to cross-site requests
+/// too, but only same-origin scripts can read the token and set the header.
+pub fn verify_csrf(
+    data: &Arc<AppState>,
+    req: &Request<Body>,
+    access_token_uuid: uuid::Uuid,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if csrf_exempt(data, req) {
+        return Ok(());
+    }
+
+    let valid = req
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|token| verify_signed_token(token, CSRF_TOKEN_PURPOSE, &data.env.secret_key).ok())
+        .is_some_and(|sub| sub == access_token_uuid.to_string());
+
+    if !valid {
+        let json_error = ErrorResponse {
+            error: "".to_string(),
+            message: "CSRF token is missing or invalid".to_string(),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+    Ok(())
+}
@@ -1,11 +1,14 @@
 use crate::AppState;
+use crate::middleware::csrf::verify_csrf;
 use crate::service::response_server::ErrorResponse;
-use crate::users::model::{User, UserRole};
-use crate::users::token::verify_jwt_token;
+use crate::users::admin_handler::{check_not_suspended, record_admin_action};
+use crate::users::model::{ApiKeyScope, AuditAction, User, UserRole};
+use crate::users::token::{hash_token, verify_jwt_token};
+use crate::users::two_factor_handler::two_factor_status;
 use axum::Json;
 use axum::body::Body;
-use axum::extract::State;
-use axum::http::{Request, StatusCode, header};
+use axum::extract::{OriginalUri, State};
+use axum::http::{Method, Request, StatusCode, header};
 use axum::middleware::Next;
 use axum::response::IntoResponse;
 use axum_extra::extract::CookieJar;
@@ -17,22 +20,207 @@ use std::sync::Arc;
 pub struct JWTAuthMiddleware {
     pub user: User,
     pub accesses_token_uuid: uuid::Uuid,
+    /// Set when the request was authenticated with a personal API key.
+    pub api_key_id: Option<uuid::Uuid>,
+    /// Admin acting as `user` with an impersonation token.
+    pub impersonator_id: Option<uuid::Uuid>,
 }
+
+async fn find_user(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<User, (StatusCode, Json<ErrorResponse>)> {
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+        id,
+        first_name,
+        last_name,
+        middle_name,
+        age,
+        email,
+        password,
+        biography,
+        file,
+        verified,
+        role as "role: UserRole",
+        locale,
+        balance,
+        rating,
+        created_at,
+        updated_at
+        FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(|e| {
+        let json_error = ErrorResponse {
+            error: format!("Error fetching user from database: {}", e),
+            message: "Error database fetching user".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(json_error))
+    })?;
+
+    let user = user.ok_or_else(|| {
+        let json_error = ErrorResponse {
+            error: "".to_string(),
+            message: "The user belonging to this token no longer exists".to_string(),
+        };
+        (StatusCode::UNAUTHORIZED, Json(json_error))
+    })?;
+    check_not_suspended(data, user.id).await?;
+    Ok(user)
+}
+
+fn request_api_key(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            req.headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|auth_header| auth_header.to_str().ok())
+                .and_then(|auth_value| auth_value.strip_prefix("ApiKey "))
+        })
+        .map(|key| key.trim().to_owned())
+}
+
+/// Authenticates a request by personal API key. Safe methods need the
+/// `read` scope, everything else `write`.
+async fn examination_api_key(
+    data: &Arc<AppState>,
+    mut req: Request<Body>,
+    api_key: &str,
+) -> Result<Request<Body>, (StatusCode, Json<ErrorResponse>)> {
+    let key = sqlx::query!(
+        r#"
+        SELECT id, user_id, scopes as "scopes: Vec<ApiKeyScope>"
+        FROM api_keys
+        WHERE key_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+        "#,
+        hash_token(api_key)
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(|e| {
+        let json_error = ErrorResponse {
+            error: format!("Error fetching API key from database: {}", e),
+            message: "Error database fetching API key".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(json_error))
+    })?
+    .ok_or_else(|| {
+        let json_error = ErrorResponse {
+            error: "".to_string(),
+            message: "API key is invalid, expired or revoked".to_string(),
+        };
+        (StatusCode::UNAUTHORIZED, Json(json_error))
+    })?;
+
+    let required_scope = match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => ApiKeyScope::Read,
+        _ => ApiKeyScope::Write,
+    };
+    if !key.scopes.contains(&required_scope) {
+        let json_error = ErrorResponse {
+            error: "".to_string(),
+            message: format!("API key does not have the {} scope", required_scope),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+
+    let user = find_user(data, key.user_id).await?;
+
+    // Written at most once a minute to keep hot keys from updating on every request.
+    sqlx::query!(
+        r#"
+        UPDATE api_keys SET last_used_at = NOW()
+        WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')
+        "#,
+        key.id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(|e| {
+        let json_error = ErrorResponse {
+            error: format!("Database error: {}", e),
+            message: "Request failed".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(json_error))
+    })?;
+
+    req.extensions_mut().insert(JWTAuthMiddleware {
+        user,
+        accesses_token_uuid: key.id,
+        api_key_id: Some(key.id),
+        impersonator_id: None,
+    });
+    Ok(req)
+}
+
+fn request_line(req: &Request<Body>) -> String {
+    // Route layers of nested routers see the path without the nest prefix.
+    let path = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.path())
+        .unwrap_or_else(|| req.uri().path());
+    format!("{} {}", req.method(), path)
+}
+
+/// Re-checks that the impersonating admin still holds the role, and records
+/// every request made on the user's behalf.
+async fn examination_impersonation(
+    data: &Arc<AppState>,
+    impersonator_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+    request_line: String,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let impersonator = find_user(data, impersonator_id).await?;
+    if impersonator.role != UserRole::Admin {
+        let json_error = ErrorResponse {
+            error: "".to_string(),
+            message: "Impersonation is no longer allowed".to_string(),
+        };
+        return Err((StatusCode::UNAUTHORIZED, Json(json_error)));
+    }
+
+    record_admin_action(
+        data,
+        impersonator_id,
+        AuditAction::ImpersonatedRequest,
+        Some(user_id),
+        Some(request_line),
+    )
+    .await
+}
+
 pub async fn examination_auth(
     cookie_jar: CookieJar,
     State(data): State<Arc<AppState>>,
     mut req: Request<Body>,
 ) -> Result<Request<Body>, (StatusCode, Json<ErrorResponse>)> {
-    let access_token = cookie_jar
-        .get("access_token")
-        .map(|cookie| cookie.value().to_string())
-        .or_else(|| {
-            req.headers()
-                .get(header::AUTHORIZATION)
-                .and_then(|auth_header| auth_header.to_str().ok())
-                .and_then(|auth_value| auth_value.strip_prefix("Bearer "))
-                .map(|token| token.to_owned())
-        });
+    if let Some(api_key) = request_api_key(&req) {
+        return examination_api_key(&data, req, &api_key).await;
+    }
+
+    // An explicit Authorization header wins over the cookie: cross-site
+    // requests can't set it, so only cookie sessions need the CSRF check.
+    let bearer_token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|auth_header| auth_header.to_str().ok())
+        .and_then(|auth_value| auth_value.strip_prefix("Bearer "))
+        .map(|token| token.to_owned());
+    let cookie_auth = bearer_token.is_none();
+    let access_token = bearer_token.or_else(|| {
+        cookie_jar
+            .get("access_token")
+            .map(|cookie| cookie.value().to_string())
+    });
 
     let access_token = access_token.ok_or_else(|| {
         let json_error = ErrorResponse {
@@ -93,49 +281,21 @@ pub async fn examination_auth(
         };
         (StatusCode::UNAUTHORIZED, Json(json_error))
     })?;
-    let user = sqlx::query_as!(
-        User,
-        r#"
-        SELECT
-        id,
-        first_name,
-        last_name,
-        middle_name,
-        age,
-        email,
-        password,
-        biography,
-        file,
-        verified,
-        role as "role: UserRole",
-        balance,
-        rating,
-        created_at,
-        updated_at
-        FROM users WHERE id = $1
-        "#,
-        user_id
-    )
-    .fetch_optional(&data.db)
-    .await
-    .map_err(|e| {
-        let json_error = ErrorResponse {
-            error: format!("Error fetching user from database: {}", e),
-            message: "Error database fetching user".to_string(),
-        };
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(json_error))
-    })?;
+    let user = find_user(&data, user_id).await?;
+    if cookie_auth {
+        verify_csrf(&data, &req, access_token_uuid)?;
+    }
+
+    let impersonator_id = access_token_details.impersonator_id;
+    if let Some(impersonator_id) = impersonator_id {
+        examination_impersonation(&data, impersonator_id, user.id, request_line(&req)).await?;
+    }
 
-    let user = user.ok_or_else(|| {
-        let json_error = ErrorResponse {
-            error: "".to_string(),
-            message: "The user belonging to this token no longer exists".to_string(),
-        };
-        (StatusCode::UNAUTHORIZED, Json(json_error))
-    })?;
     req.extensions_mut().insert(JWTAuthMiddleware {
         user,
         accesses_token_uuid: access_token_uuid,
+        api_key_id: None,
+        impersonator_id,
     });
     Ok(req)
 }
@@ -160,12 +320,21 @@ pub async fn auth_roles(
     next: Next,
     allowed_roles: Vec<UserRole>,
 ) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
-    let auth_result = examination_auth(cookie_jar, State(data), req).await;
+    let auth_result = examination_auth(cookie_jar, State(data.clone()), req).await;
 
     match auth_result {
         Ok(req) => {
             if let Some(auth_middleware) = req.extensions().get::<JWTAuthMiddleware>().cloned() {
                 if allowed_roles.contains(&auth_middleware.user.role) {
+                    let two_factor = two_factor_status(&data, &auth_middleware.user).await?;
+                    if two_factor.required && !two_factor.enabled {
+                        let json_error = ErrorResponse {
+                            error: "".to_string(),
+                            message: "Two-factor authentication is required for your role"
+                                .to_string(),
+                        };
+                        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+                    }
                     Ok(next.run(req).await)
                 } else {
                     let json_error = ErrorResponse {
@@ -210,3 +379,73 @@ pub async fn auth_author_worker_admin(
     )
     .await
 }
+
+/// Rejects users who haven't confirmed their email when
+/// `REQUIRE_VERIFIED_EMAIL` is enabled. Guards the routes writing books;
+/// account routes stay open so the email can still be confirmed. Must be
+/// layered after an auth middleware.
+pub async fn require_verified(
+    State(data): State<Arc<AppState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    if data.env.require_verified_email {
+        let verified = req
+            .extensions()
+            .get::<JWTAuthMiddleware>()
+            .map(|auth_middleware| auth_middleware.user.verified)
+            .unwrap_or(false);
+
+        if !verified {
+            let json_error = ErrorResponse {
+                error: "".to_string(),
+                message: "Please verify your email to access this resource".to_string(),
+            };
+            return Err((StatusCode::FORBIDDEN, Json(json_error)));
+        }
+    }
+    Ok(next.run(req).await)
+}
+
+/// Limits account security endpoints to password, passkey or OIDC sessions,
+/// so a leaked API key can't be turned into full account access. Must be
+/// layered after an auth middleware.
+pub async fn deny_api_key(
+    req: Request<Body>,
+    next: Next,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let api_key_auth = req
+        .extensions()
+        .get::<JWTAuthMiddleware>()
+        .is_some_and(|auth_middleware| auth_middleware.api_key_id.is_some());
+
+    if api_key_auth {
+        let json_error = ErrorResponse {
+            error: "".to_string(),
+            message: "This action is not available with an API key".to_string(),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+    Ok(next.run(req).await)
+}
+
+/// Blocks actions support staff must not take on a user's behalf, like
+/// changing credentials. Must be layered after an auth middleware.
+pub async fn deny_impersonation(
+    req: Request<Body>,
+    next: Next,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let impersonated = req
+        .extensions()
+        .get::<JWTAuthMiddleware>()
+        .is_some_and(|auth_middleware| auth_middleware.impersonator_id.is_some());
+
+    if impersonated {
+        let json_error = ErrorResponse {
+            error: "".to_string(),
+            message: "This action is not available while impersonating a user".to_string(),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(json_error)));
+    }
+    Ok(next.run(req).await)
+}
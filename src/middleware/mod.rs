@@ -1 +1,2 @@
+pub mod csrf;
 pub mod jwt_auth;
@@ -0,0 +1,420 @@
+use crate::AppState;
+use crate::books::watermark::remove_stale_watermarks;
+use crate::service::storage::{ByteStream, PRIVATE_PREFIX, public_path};
+use crate::service::tus::remove_expired_uploads;
+use crate::users::data_export::remove_expired_data_exports;
+use axum::body::Bytes;
+use futures_util::{TryStreamExt, stream};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgConnection;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+pub type BlobError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Kind of row using a blob. Each row keeps one set of references, replaced
+/// whenever its files change.
+#[derive(Debug, Clone, Copy)]
+pub enum BlobOwner {
+    Avatar,
+    Cover,
+    BookPdf,
+    /// The EPUB and the resources unpacked from it.
+    BookEpub,
+}
+
+impl BlobOwner {
+    fn as_str(self) -> &'static str {
+        match self {
+            BlobOwner::Avatar => "avatar",
+            BlobOwner::Cover => "cover",
+            BlobOwner::BookPdf => "book_pdf",
+            BlobOwner::BookEpub => "book_epub",
+        }
+    }
+
+    /// Private blobs are kept out of `/uploads/` and only served by handlers
+    /// checking access.
+    fn is_private(self) -> bool {
+        matches!(self, BlobOwner::BookPdf | BlobOwner::BookEpub)
+    }
+}
+
+/// Content stored once under its SHA-256 hash.
+#[derive(Debug, Clone)]
+pub struct StoredBlob {
+    pub hash: String,
+    pub key: String,
+}
+
+impl StoredBlob {
+    /// Path kept in the database and served from `/uploads/`.
+    pub fn path(&self) -> String {
+        public_path(&self.key)
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BlobGcReport {
+    pub removed: i64,
+    pub freed_bytes: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BlobVerifyReport {
+    pub checked: i64,
+    /// Hashes of the blobs that are missing or don't match their hash.
+    pub corrupted: Vec<String>,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Advisory lock taken while a blob row is claimed or removed.
+fn blob_lock_key(hash: &str) -> i64 {
+    i64::from_str_radix(&hash[..15], 16).unwrap_or_default()
+}
+
+/// Blobs are spread over 256 directories by the first byte of the hash.
+fn blob_key(owner: BlobOwner, hash: &str, extension: &str) -> String {
+    let key = format!("blobs/{}/{}.{}", &hash[..2], hash, extension);
+    if owner.is_private() {
+        format!("{}{}", PRIVATE_PREFIX, key)
+    } else {
+        key
+    }
+}
+
+/// Stores the content unless a blob with the same hash exists already.
+/// `hash` must be the SHA-256 of the `length` bytes of `body`. A blob
+/// marked as corrupted is written again.
+///
+/// The owner only decides where a new blob goes. Public uploads are
+/// re-encoded images, so private files share content with them only when
+/// they embed an image published already.
+///
+/// A blob claimed by another upload still writing it is written again, the
+/// content is the same, so the caller never gets a blob without its file.
+pub async fn store_blob(
+    data: &Arc<AppState>,
+    owner: BlobOwner,
+    hash: &str,
+    extension: &str,
+    body: ByteStream,
+    length: u64,
+) -> Result<StoredBlob, BlobError> {
+    let content_type = mime_guess::from_ext(extension).first_or_octet_stream();
+    // The shared lock waits for a garbage collection removing the same
+    // content to finish with the file before the row is claimed again.
+    let mut tx = data.db.begin().await?;
+    sqlx::query!(
+        "SELECT pg_advisory_xact_lock_shared($1)",
+        blob_lock_key(hash)
+    )
+    .execute(&mut *tx)
+    .await?;
+    let blob = sqlx::query!(
+        r#"
+        INSERT INTO blobs (hash, storage_key, size, content_type)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (hash) DO UPDATE SET last_used_at = NOW()
+        RETURNING storage_key, stored, corrupted_at
+        "#,
+        hash,
+        blob_key(owner, hash, extension),
+        length as i64,
+        content_type.essence_str()
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let stored = StoredBlob {
+        hash: hash.to_string(),
+        key: blob.storage_key,
+    };
+    if blob.stored && blob.corrupted_at.is_none() {
+        return Ok(stored);
+    }
+
+    // A blob that failed to store stays unstored and unreferenced, the
+    // garbage collection removes it.
+    data.storage.put(&stored.key, body, length).await?;
+    sqlx::query!(
+        "UPDATE blobs SET stored = TRUE WHERE hash = $1 AND NOT stored",
+        hash
+    )
+    .execute(&data.db)
+    .await?;
+    if blob.corrupted_at.is_some() {
+        info!("Blob {} was uploaded again and is repaired", hash);
+        sqlx::query!(
+            "UPDATE blobs SET corrupted_at = NULL, verified_at = NOW() WHERE hash = $1",
+            hash
+        )
+        .execute(&data.db)
+        .await?;
+    }
+    Ok(stored)
+}
+
+pub async fn store_blob_bytes(
+    data: &Arc<AppState>,
+    owner: BlobOwner,
+    bytes: &[u8],
+    extension: &str,
+) -> Result<StoredBlob, BlobError> {
+    let hash = sha256_hex(bytes);
+    let length = bytes.len() as u64;
+    let bytes = Bytes::copy_from_slice(bytes);
+    let body = Box::pin(stream::once(async move { Ok(bytes) }));
+    store_blob(data, owner, &hash, extension, body, length).await
+}
+
+/// Replaces the blobs used by the row. Run it in the transaction updating
+/// the row, so the reference counts always match the stored paths.
+pub async fn set_blob_references(
+    conn: &mut PgConnection,
+    owner: BlobOwner,
+    owner_id: uuid::Uuid,
+    hashes: &[String],
+) -> Result<(), sqlx::Error> {
+    // Transactions changing references to the same blobs wait for each
+    // other here, so the counts below see the rows of the one before.
+    sqlx::query!(
+        r#"
+        SELECT hash FROM blobs
+        WHERE hash = ANY($1) OR hash IN (
+            SELECT blob_hash FROM blob_references WHERE owner_type = $2 AND owner_id = $3
+        )
+        ORDER BY hash
+        FOR UPDATE
+        "#,
+        hashes,
+        owner.as_str(),
+        owner_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut affected = sqlx::query_scalar!(
+        r#"
+        DELETE FROM blob_references
+        WHERE owner_type = $1 AND owner_id = $2
+        RETURNING blob_hash
+        "#,
+        owner.as_str(),
+        owner_id
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO blob_references (blob_hash, owner_type, owner_id)
+        SELECT hash, $2, $3 FROM UNNEST($1::varchar[]) as hash
+        ON CONFLICT DO NOTHING
+        "#,
+        hashes,
+        owner.as_str(),
+        owner_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    affected.extend_from_slice(hashes);
+    sqlx::query!(
+        r#"
+        UPDATE blobs
+        SET ref_count = (SELECT COUNT(*) FROM blob_references WHERE blob_hash = hash),
+            last_used_at = NOW()
+        WHERE hash = ANY($1)
+        "#,
+        &affected
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Removes blobs nobody has used for `grace_period`. The period leaves time
+/// to an upload between storing its blobs and referencing them.
+pub async fn collect_garbage(
+    data: &Arc<AppState>,
+    grace_period: Duration,
+) -> Result<BlobGcReport, BlobError> {
+    let grace_seconds = grace_period.as_secs_f64();
+    let candidates = sqlx::query_scalar!(
+        r#"
+        SELECT hash FROM blobs
+        WHERE ref_count = 0 AND last_used_at < NOW() - make_interval(secs => $1)
+        "#,
+        grace_seconds
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let mut report = BlobGcReport {
+        removed: 0,
+        freed_bytes: 0,
+    };
+    for hash in candidates {
+        // Uploads of the same content wait for this lock, so they can't
+        // store the file again between removing the row and the file.
+        let mut lock = data.db.begin().await?;
+        let locked = sqlx::query_scalar!(
+            r#"SELECT pg_try_advisory_xact_lock($1) as "locked!""#,
+            blob_lock_key(&hash)
+        )
+        .fetch_one(&mut *lock)
+        .await?;
+        if !locked {
+            continue;
+        }
+
+        // The reference count is only a hint, the referencing rows decide.
+        // Rows being added hold a lock on the blob and are skipped.
+        let mut tx = data.db.begin().await?;
+        let blob = sqlx::query!(
+            r#"
+            SELECT storage_key, size FROM blobs
+            WHERE hash = $1
+                AND ref_count = 0
+                AND last_used_at < NOW() - make_interval(secs => $2)
+                AND NOT EXISTS (SELECT 1 FROM blob_references WHERE blob_hash = blobs.hash)
+                AND NOT EXISTS (SELECT 1 FROM book_files WHERE blob_hash = blobs.hash)
+                AND NOT EXISTS (SELECT 1 FROM book_resources WHERE blob_hash = blobs.hash)
+            FOR UPDATE SKIP LOCKED
+            "#,
+            hash,
+            grace_seconds
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(blob) = blob else {
+            continue;
+        };
+        sqlx::query!("DELETE FROM blobs WHERE hash = $1", hash)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        // Once the row is gone a failure only leaves a stray file behind.
+        if let Err(e) = data.storage.delete(&blob.storage_key).await {
+            error!("Failed to remove file of blob {}: {}", hash, e);
+        }
+        lock.commit().await?;
+
+        report.removed += 1;
+        report.freed_bytes += blob.size;
+    }
+
+    if report.removed > 0 {
+        info!(
+            "Removed {} unused blobs, {} bytes",
+            report.removed, report.freed_bytes
+        );
+    }
+    Ok(report)
+}
+
+/// Hash and size of a stored file, read as a stream.
+async fn hash_stored(data: &Arc<AppState>, key: &str) -> std::io::Result<(String, i64)> {
+    let object = data.storage.get(key).await?;
+    let mut body = object.body;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(chunk) = body.try_next().await? {
+        hasher.update(&chunk);
+        size += chunk.len() as i64;
+    }
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+/// Re-hashes the `limit` blobs checked the longest time ago and marks those
+/// whose content no longer matches as corrupted.
+pub async fn verify_blobs(data: &Arc<AppState>, limit: i64) -> Result<BlobVerifyReport, BlobError> {
+    let blobs = sqlx::query!(
+        r#"
+        SELECT hash, storage_key, size FROM blobs
+        WHERE stored
+        ORDER BY verified_at NULLS FIRST, created_at
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let mut report = BlobVerifyReport {
+        checked: 0,
+        corrupted: Vec::new(),
+    };
+    for blob in blobs {
+        let intact = match hash_stored(data, &blob.storage_key).await {
+            Ok((hash, size)) => hash == blob.hash && size == blob.size,
+            Err(e) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => {
+                // The storage is unreachable, it says nothing about the blob.
+                warn!("Failed to read blob {}: {}", blob.hash, e);
+                continue;
+            }
+        };
+        report.checked += 1;
+
+        if intact {
+            sqlx::query!(
+                "UPDATE blobs SET verified_at = NOW(), corrupted_at = NULL WHERE hash = $1",
+                blob.hash
+            )
+            .execute(&data.db)
+            .await?;
+        } else {
+            error!("Blob {} is missing or corrupted", blob.hash);
+            sqlx::query!(
+                r#"
+                UPDATE blobs
+                SET verified_at = NOW(), corrupted_at = COALESCE(corrupted_at, NOW())
+                WHERE hash = $1
+                "#,
+                blob.hash
+            )
+            .execute(&data.db)
+            .await?;
+            report.corrupted.push(blob.hash);
+        }
+    }
+    Ok(report)
+}
+
+/// Collects unused blobs, verifies a batch of stored ones and removes
+/// expired resumable uploads and stale watermarked copies every
+/// `blob_gc_interval` seconds.
+pub async fn run_blob_maintenance(data: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(data.env.blob_gc_interval));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let grace_period = Duration::from_secs(data.env.blob_gc_grace_period);
+        if let Err(e) = collect_garbage(&data, grace_period).await {
+            error!("Blob garbage collection failed: {}", e);
+        }
+        if let Err(e) = verify_blobs(&data, data.env.blob_verify_batch_size).await {
+            error!("Blob verification failed: {}", e);
+        }
+        if let Err(e) = remove_expired_uploads(&data).await {
+            error!("Removing expired uploads failed: {}", e);
+        }
+        if let Err(e) = remove_stale_watermarks(&data).await {
+            error!("Removing stale watermarked copies failed: {}", e);
+        }
+        if let Err(e) = remove_expired_data_exports(&data).await {
+            error!("Removing expired data exports failed: {}", e);
+        }
+    }
+}
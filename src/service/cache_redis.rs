@@ -29,3 +29,8 @@ where
         Ok(value)
     }
 }
+
+pub async fn delete_cache(redis: &redis::Client, key: &str) -> Result<(), RedisError> {
+    let mut conn = redis.get_multiplexed_async_connection().await?;
+    conn.del::<_, ()>(key).await
+}
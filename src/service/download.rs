@@ -0,0 +1,220 @@
+use axum::http::HeaderMap;
+use axum::http::header::{IF_NONE_MATCH, IF_RANGE, RANGE};
+
+/// Inclusive byte range of a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value of `Content-Range` for a file of `size` bytes.
+    pub fn content_range(self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    Full,
+    Partial(ByteRange),
+    Unsatisfiable,
+}
+
+/// Strong entity tag of stored content, from its SHA-256.
+pub fn content_etag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// Whether `If-None-Match` lists the tag. Weak tags match too, as the
+/// header uses the weak comparison.
+pub fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(value) = headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    value
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Parses a single `bytes=` range. Malformed values and lists of ranges
+/// give `Full`, servers may ignore them.
+fn parse_range(value: &str, size: u64) -> RangeRequest {
+    let Some(spec) = value
+        .get(..6)
+        .filter(|unit| unit.eq_ignore_ascii_case("bytes="))
+        .map(|_| value[6..].trim())
+    else {
+        return RangeRequest::Full;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // Suffix range: the last `end` bytes.
+        return match end.parse::<u64>() {
+            Ok(0) => RangeRequest::Unsatisfiable,
+            Ok(_) if size == 0 => RangeRequest::Unsatisfiable,
+            Ok(suffix) => RangeRequest::Partial(ByteRange {
+                start: size.saturating_sub(suffix),
+                end: size - 1,
+            }),
+            Err(_) => RangeRequest::Full,
+        };
+    }
+
+    let Ok(start) = start.parse::<u64>() else {
+        return RangeRequest::Full;
+    };
+    let end = match end {
+        "" => None,
+        end => match end.parse::<u64>() {
+            Ok(end) if end >= start => Some(end),
+            _ => return RangeRequest::Full,
+        },
+    };
+    if start >= size {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial(ByteRange {
+        start,
+        end: end.map_or(size - 1, |end| end.min(size - 1)),
+    })
+}
+
+/// Range of the file to send. `If-Range` must repeat the current tag,
+/// otherwise the file changed since the client started and it gets the
+/// whole new one.
+pub fn requested_range(headers: &HeaderMap, etag: &str, size: u64) -> RangeRequest {
+    let Some(range) = headers.get(RANGE).and_then(|v| v.to_str().ok()) else {
+        return RangeRequest::Full;
+    };
+    let if_range = headers
+        .get(IF_RANGE)
+        .map(|v| v.to_str().ok().map(str::trim));
+    if if_range.is_some_and(|tag| tag != Some(etag)) {
+        return RangeRequest::Full;
+    }
+    parse_range(range, size)
+}
+
+fn transliterate_char(c: char) -> Option<&'static str> {
+    let latin = match c.to_lowercase().next()? {
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' => "g",
+        'д' => "d",
+        'е' => "e",
+        'ё' => "yo",
+        'ж' => "zh",
+        'з' => "z",
+        'и' => "i",
+        'й' => "y",
+        'к' => "k",
+        'л' => "l",
+        'м' => "m",
+        'н' => "n",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'у' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ъ' | 'ь' => "",
+        'ы' => "y",
+        'э' => "e",
+        'ю' => "yu",
+        'я' => "ya",
+        _ => return None,
+    };
+    Some(latin)
+}
+
+/// Appends the Latin spelling of a Cyrillic letter, `false` for any other
+/// character.
+fn push_transliterated(text: &mut String, c: char) -> bool {
+    let Some(latin) = transliterate_char(c) else {
+        return false;
+    };
+    // Capitals keep a capital first letter: Щ gives Shch.
+    let mut letters = latin.chars();
+    if let Some(first) = letters.next() {
+        if c.is_uppercase() {
+            text.push(first.to_ascii_uppercase());
+        } else {
+            text.push(first);
+        }
+        text.extend(letters);
+    }
+    true
+}
+
+/// Text with Cyrillic transliterated, other characters are kept.
+pub fn transliterate(text: &str) -> String {
+    let mut latin = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !push_transliterated(&mut latin, c) {
+            latin.push(c);
+        }
+    }
+    latin
+}
+
+/// ASCII file name for a title: Cyrillic is transliterated, anything else
+/// that isn't a letter or digit becomes `_`.
+pub fn transliterate_filename(title: &str) -> String {
+    let mut name = String::with_capacity(title.len());
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !push_transliterated(&mut name, c) && !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    name.trim_matches('_').to_string()
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// `Content-Disposition` of a download named after the title. Clients
+/// reading RFC 6266 get the original title from `filename*`, the others
+/// the transliterated one. `fallback` names titles with nothing left.
+pub fn attachment_disposition(title: &str, fallback: &str, extension: &str) -> String {
+    let name = transliterate_filename(title);
+    if name.is_empty() {
+        return format!("attachment; filename=\"{}.{}\"", fallback, extension);
+    }
+    format!(
+        "attachment; filename=\"{}.{}\"; filename*=UTF-8''{}.{}",
+        name,
+        extension,
+        percent_encode(title.trim()),
+        extension
+    )
+}
@@ -0,0 +1,41 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+
+/// Larger images are rejected before decoding, so a small file can't expand
+/// into gigabytes of pixels.
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+const JPEG_QUALITY: u8 = 85;
+
+/// Detects the format from the magic bytes; the content type sent by the
+/// client is not trusted.
+pub fn sniff_image_format(bytes: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(bytes) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => Some(format),
+        _ => None,
+    }
+}
+
+pub fn decode_image(bytes: &[u8], format: ImageFormat) -> Result<DynamicImage, image::ImageError> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    reader.decode()
+}
+
+pub fn encode_jpeg(image: &DynamicImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY))?;
+    Ok(jpeg)
+}
+
+pub fn encode_webp(image: &DynamicImage) -> Result<Vec<u8>, image::ImageError> {
+    let mut webp = Vec::new();
+    DynamicImage::ImageRgba8(image.to_rgba8())
+        .write_with_encoder(WebPEncoder::new_lossless(&mut webp))?;
+    Ok(webp)
+}
@@ -0,0 +1,170 @@
+use crate::service::mailer::{MailMessage, Mailer};
+use async_trait::async_trait;
+use sqlx::{FromRow, Pool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const BATCH_SIZE: i64 = 20;
+/// How long a claimed message is kept from other workers.
+const SEND_LEASE_SECONDS: f64 = 300.0;
+
+/// Puts messages into the `mail_outbox` table instead of sending them, so
+/// they survive restarts and are retried by [`run_mail_worker`].
+#[derive(Debug, Clone)]
+pub struct OutboxMailer {
+    db: Pool<Postgres>,
+}
+
+impl OutboxMailer {
+    pub fn new(db: Pool<Postgres>) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, message: MailMessage) -> Result<(), String> {
+        sqlx::query!(
+            r#"INSERT INTO mail_outbox (recipient, subject, body) VALUES ($1, $2, $3)"#,
+            message.to,
+            message.subject,
+            message.body
+        )
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Database error: {}", e))
+    }
+}
+
+#[derive(Debug, FromRow)]
+struct OutboxRow {
+    id: uuid::Uuid,
+    recipient: String,
+    subject: String,
+    body: String,
+    attempts: i32,
+}
+
+/// Records the outcome of one delivery.
+async fn finish_delivery(
+    db: &Pool<Postgres>,
+    row: &OutboxRow,
+    result: Result<(), String>,
+    max_attempts: i32,
+) -> Result<(), sqlx::Error> {
+    match result {
+        Ok(()) => {
+            sqlx::query!(
+                r#"
+                UPDATE mail_outbox
+                SET status = 'sent', last_error = NULL, sent_at = NOW(), updated_at = NOW()
+                WHERE id = $1
+                "#,
+                row.id
+            )
+            .execute(db)
+            .await?;
+        }
+        Err(e) => {
+            let status = if row.attempts >= max_attempts {
+                error!(
+                    "Giving up on mail {} after {} attempts: {}",
+                    row.id, row.attempts, e
+                );
+                "failed"
+            } else {
+                warn!("Mail {} delivery failed, will retry: {}", row.id, e);
+                "pending"
+            };
+            let backoff_seconds = 30_f64 * 2_f64.powi(row.attempts.min(10));
+
+            sqlx::query!(
+                r#"
+                UPDATE mail_outbox
+                SET status = $1,
+                    last_error = $2,
+                    next_attempt_at = NOW() + make_interval(secs => $3),
+                    updated_at = NOW()
+                WHERE id = $4
+                "#,
+                status,
+                e,
+                backoff_seconds,
+                row.id
+            )
+            .execute(db)
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Delivers due messages from the outbox through `transport`. Failed
+/// deliveries are rescheduled with exponential backoff until `max_attempts`.
+///
+/// Messages are claimed in a short transaction and sent outside of it, so
+/// the outbox isn't locked while the transport works and each outcome is
+/// kept on its own. A claim lasts `SEND_LEASE_SECONDS`: messages of a
+/// worker that died while sending are picked up again after it.
+pub async fn process_mail_queue(
+    db: &Pool<Postgres>,
+    transport: &Arc<dyn Mailer>,
+    max_attempts: i32,
+) -> Result<usize, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        OutboxRow,
+        r#"
+        UPDATE mail_outbox
+        SET status = 'sending',
+            attempts = attempts + 1,
+            next_attempt_at = NOW() + make_interval(secs => $1),
+            updated_at = NOW()
+        WHERE id IN (
+            SELECT id FROM mail_outbox
+            WHERE status IN ('pending', 'sending') AND next_attempt_at <= NOW()
+            ORDER BY next_attempt_at
+            LIMIT $2
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, recipient, subject, body, attempts
+        "#,
+        SEND_LEASE_SECONDS,
+        BATCH_SIZE
+    )
+    .fetch_all(db)
+    .await?;
+
+    let processed = rows.len();
+    for row in rows {
+        let message = MailMessage {
+            to: row.recipient.clone(),
+            subject: row.subject.clone(),
+            body: row.body.clone(),
+        };
+        let result = transport.send(message).await;
+        // The message may be sent already, the others still go out.
+        if let Err(e) = finish_delivery(db, &row, result, max_attempts).await {
+            error!("Failed to record delivery of mail {}: {}", row.id, e);
+        }
+    }
+    Ok(processed)
+}
+
+pub async fn run_mail_worker(
+    db: Pool<Postgres>,
+    transport: Arc<dyn Mailer>,
+    max_attempts: i32,
+    poll_interval: Duration,
+) {
+    info!("📬 Mail worker started");
+    loop {
+        match process_mail_queue(&db, &transport, max_attempts).await {
+            Ok(processed) if processed > 0 => info!("Mail worker processed {} messages", processed),
+            Ok(_) => {}
+            Err(e) => error!("Mail worker error: {:?}", e),
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
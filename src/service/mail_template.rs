@@ -0,0 +1,74 @@
+use crate::service::mailer::MailMessage;
+use strum::{Display, EnumString};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    Ru,
+    En,
+}
+
+/// Emails sent by the application. Each variant carries the values
+/// substituted into its Russian and English texts.
+#[derive(Debug, Clone)]
+pub enum MailTemplate {
+    PasswordReset { link: String, valid_minutes: i64 },
+    EmailVerification { link: String, valid_hours: i64 },
+}
+
+impl MailTemplate {
+    /// Renders the message in the user's locale, falling back to the
+    /// default one when the locale is unknown.
+    pub fn render(&self, to: &str, locale: &str) -> MailMessage {
+        let locale = locale.parse::<Locale>().unwrap_or_default();
+        let (subject, body) = match (self, locale) {
+            (
+                MailTemplate::PasswordReset {
+                    link,
+                    valid_minutes,
+                },
+                Locale::Ru,
+            ) => (
+                "Восстановление пароля".to_string(),
+                format!(
+                    "Чтобы сбросить пароль, перейдите по ссылке: {}\nСсылка действительна {} мин.\nЕсли вы не запрашивали сброс, просто проигнорируйте это письмо.",
+                    link, valid_minutes
+                ),
+            ),
+            (
+                MailTemplate::PasswordReset {
+                    link,
+                    valid_minutes,
+                },
+                Locale::En,
+            ) => (
+                "Password reset".to_string(),
+                format!(
+                    "To reset your password follow the link: {}\nThe link is valid for {} minutes.\nIf you didn't request a reset, just ignore this email.",
+                    link, valid_minutes
+                ),
+            ),
+            (MailTemplate::EmailVerification { link, valid_hours }, Locale::Ru) => (
+                "Подтверждение email".to_string(),
+                format!(
+                    "Чтобы подтвердить email, перейдите по ссылке: {}\nСсылка действительна {} ч.",
+                    link, valid_hours
+                ),
+            ),
+            (MailTemplate::EmailVerification { link, valid_hours }, Locale::En) => (
+                "Email verification".to_string(),
+                format!(
+                    "To confirm your email follow the link: {}\nThe link is valid for {} hours.",
+                    link, valid_hours
+                ),
+            ),
+        };
+
+        MailMessage {
+            to: to.to_string(),
+            subject,
+            body,
+        }
+    }
+}
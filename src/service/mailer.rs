@@ -0,0 +1,185 @@
+use crate::Settings;
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Transport used to deliver outgoing emails.
+#[async_trait]
+pub trait Mailer: Send + Sync + std::fmt::Debug {
+    async fn send(&self, message: MailMessage) -> Result<(), String>;
+}
+
+/// Writes messages to the application log instead of delivering them.
+#[derive(Debug, Default)]
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, message: MailMessage) -> Result<(), String> {
+        info!(
+            "📧 Mail to {}: {}\n{}",
+            message.to, message.subject, message.body
+        );
+        Ok(())
+    }
+}
+
+/// Keeps sent messages in memory so tests can inspect them.
+#[derive(Debug, Default)]
+pub struct MemoryMailer {
+    messages: Mutex<Vec<MailMessage>>,
+}
+
+impl MemoryMailer {
+    pub fn messages(&self) -> Vec<MailMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    pub fn last_to(&self, to: &str) -> Option<MailMessage> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|message| message.to == to)
+            .cloned()
+    }
+
+    pub fn clear(&self) {
+        self.messages.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, message: MailMessage) -> Result<(), String> {
+        self.messages.lock().unwrap().push(message);
+        Ok(())
+    }
+}
+
+/// Saves every message as a text file in a local outbox directory, for
+/// development without an SMTP server.
+#[derive(Debug)]
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, message: MailMessage) -> Result<(), String> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| format!("Failed to create outbox directory: {}", e))?;
+
+        let file_name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S%3f"),
+            uuid::Uuid::new_v4()
+        );
+        let content = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            message.to, message.subject, message.body
+        );
+        tokio::fs::write(self.dir.join(file_name), content)
+            .await
+            .map_err(|e| format!("Failed to write message to outbox: {}", e))
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl std::fmt::Debug for SmtpMailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SmtpMailer")
+            .field("from", &self.from.to_string())
+            .finish()
+    }
+}
+
+impl SmtpMailer {
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, String)>,
+        from: &str,
+    ) -> Result<Self, String> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+            .map_err(|e| format!("Invalid SMTP relay: {}", e))?
+            .port(port);
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: from
+                .parse()
+                .map_err(|e| format!("Invalid sender address: {}", e))?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, message: MailMessage) -> Result<(), String> {
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(message
+                .to
+                .parse()
+                .map_err(|e| format!("Invalid recipient address: {}", e))?)
+            .subject(message.subject)
+            .body(message.body)
+            .map_err(|e| format!("Failed to build message: {}", e))?;
+
+        self.transport
+            .send(email)
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("SMTP error: {}", e))
+    }
+}
+
+/// Creates the transport selected by `MAIL_TRANSPORT`.
+pub fn build_mail_transport(settings: &Settings) -> Arc<dyn Mailer> {
+    match settings.mail_transport.as_str() {
+        "smtp" => {
+            let host = settings
+                .smtp_host
+                .as_deref()
+                .expect("SMTP_HOST must be set when MAIL_TRANSPORT=smtp");
+            let credentials = settings
+                .smtp_username
+                .clone()
+                .zip(settings.smtp_password.clone());
+            let mailer =
+                SmtpMailer::new(host, settings.smtp_port, credentials, &settings.mail_from)
+                    .unwrap();
+            Arc::new(mailer)
+        }
+        "file" => Arc::new(FileMailer::new(&settings.mail_outbox_dir)),
+        _ => Arc::new(LogMailer),
+    }
+}
@@ -1,4 +1,14 @@
+pub mod blob;
+pub mod download;
+pub mod image_processing;
+pub mod mail_queue;
+pub mod mail_template;
+pub mod mailer;
 pub mod response_server;
+pub mod signed_url;
+pub mod storage;
+pub mod tus;
+pub mod upload;
 mod cache_redis;
 
-pub use cache_redis::get_or_set_cache;
\ No newline at end of file
+pub use cache_redis::{delete_cache, get_or_set_cache};
\ No newline at end of file
@@ -0,0 +1,462 @@
+use crate::Settings;
+use async_trait::async_trait;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::{
+    BehaviorVersion, Credentials, Region, RequestChecksumCalculation, ResponseChecksumValidation,
+};
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use axum::body::Bytes;
+use futures_util::{TryStreamExt, stream};
+use http_body::{Frame, SizeHint};
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+/// Prefix of stored file paths kept in the database. Files are served from
+/// `/uploads/`, so a stored path is also its URL path.
+pub const UPLOADS_PREFIX: &str = "uploads/";
+
+/// Keys under this prefix are never served from `/uploads/`.
+pub const PRIVATE_PREFIX: &str = "private/";
+
+/// Path of the stored file as kept in the database.
+pub fn public_path(key: &str) -> String {
+    format!("{}{}", UPLOADS_PREFIX, key)
+}
+
+/// Storage key of a path from the database.
+pub fn storage_key(path: &str) -> Option<&str> {
+    path.strip_prefix(UPLOADS_PREFIX)
+}
+
+/// Prefix `<dir>/<version>` holding every variant of an upload, when the
+/// stored path lies inside `dir`. Other paths, like the shared defaults,
+/// are not ours to remove.
+pub fn version_prefix(path: &str, dir: &str) -> Option<String> {
+    let version = storage_key(path)?
+        .strip_prefix(dir)?
+        .strip_prefix('/')?
+        .split_once('/')?
+        .0;
+    if version.is_empty() {
+        return None;
+    }
+    Some(format!("{}/{}", dir, version))
+}
+
+/// File contents read or written chunk by chunk.
+pub type ByteStream = Pin<Box<dyn futures_util::Stream<Item = std::io::Result<Bytes>> + Send>>;
+
+/// A stored file opened for reading.
+pub struct StoredObject {
+    pub length: u64,
+    pub body: ByteStream,
+}
+
+/// Where uploaded files are kept.
+///
+/// Keys are relative paths such as `photo-user/<user id>/<version>/256.jpg`.
+/// Reading a missing key fails with `ErrorKind::NotFound`.
+#[async_trait]
+pub trait Storage: Send + Sync + std::fmt::Debug {
+    /// Stores `length` bytes read from `body` under the key, replacing the
+    /// previous file. Readers never see a partly written file.
+    async fn put(&self, key: &str, body: ByteStream, length: u64) -> std::io::Result<()>;
+
+    async fn get(&self, key: &str) -> std::io::Result<StoredObject>;
+
+    /// Reads `length` bytes starting at `start`, which the caller keeps
+    /// inside the file.
+    async fn get_range(&self, key: &str, start: u64, length: u64) -> std::io::Result<StoredObject>;
+
+    /// Deletes the file; a missing file is not an error.
+    async fn delete(&self, key: &str) -> std::io::Result<()>;
+
+    /// Deletes every file under `<prefix>/`.
+    async fn delete_prefix(&self, prefix: &str) -> std::io::Result<()>;
+
+    async fn put_bytes(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let length = bytes.len() as u64;
+        let bytes = Bytes::copy_from_slice(bytes);
+        let body = stream::once(async move { Ok(bytes) });
+        self.put(key, Box::pin(body), length).await
+    }
+
+    /// Reads the whole file, for files small enough to keep in memory.
+    async fn get_bytes(&self, key: &str) -> std::io::Result<Vec<u8>> {
+        let object = self.get(key).await?;
+        let mut bytes = Vec::with_capacity(object.length as usize);
+        let mut body = object.body;
+        while let Some(chunk) = body.try_next().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+}
+
+fn invalid_key(key: &str) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("invalid storage key {:?}", key),
+    )
+}
+
+/// Uploaded files on the local disk, under `upload_dir`.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> std::io::Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(invalid_key(key));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    /// Writes the file next to its final place first, then renames it.
+    async fn put(&self, key: &str, mut body: ByteStream, length: u64) -> std::io::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4().simple()));
+
+        let written = async {
+            let mut file = tokio::fs::File::create(&tmp_path).await?;
+            let mut written = 0;
+            while let Some(chunk) = body.try_next().await? {
+                written += chunk.len() as u64;
+                file.write_all(&chunk).await?;
+            }
+            if written != length {
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("expected {} bytes, got {}", length, written),
+                ));
+            }
+            file.sync_all().await
+        }
+        .await;
+
+        match written {
+            Ok(()) => tokio::fs::rename(&tmp_path, &path).await,
+            Err(e) => {
+                tokio::fs::remove_file(&tmp_path).await.ok();
+                Err(e)
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<StoredObject> {
+        let file = tokio::fs::File::open(self.path(key)?).await?;
+        let metadata = file.metadata().await?;
+        if !metadata.is_file() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(StoredObject {
+            length: metadata.len(),
+            body: Box::pin(ReaderStream::new(file)),
+        })
+    }
+
+    async fn get_range(&self, key: &str, start: u64, length: u64) -> std::io::Result<StoredObject> {
+        let mut file = tokio::fs::File::open(self.path(key)?).await?;
+        if !file.metadata().await?.is_file() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        file.seek(SeekFrom::Start(start)).await?;
+        Ok(StoredObject {
+            length,
+            body: Box::pin(ReaderStream::new(file.take(length))),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> std::io::Result<()> {
+        match tokio::fs::remove_dir_all(self.path(prefix)?).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Upload body handed to the S3 client, which wants it `Sync`. The stream
+/// is only polled through `&mut`, so the mutex is never locked.
+struct UploadBody {
+    stream: std::sync::Mutex<ByteStream>,
+    length: u64,
+}
+
+impl http_body::Body for UploadBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::io::Result<Frame<Bytes>>>> {
+        let stream = match self.get_mut().stream.get_mut() {
+            Ok(stream) => stream,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        stream
+            .as_mut()
+            .poll_next(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map(Frame::data)))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.length)
+    }
+}
+
+/// Reads an object body chunk by chunk.
+fn object_body(body: aws_sdk_s3::primitives::ByteStream) -> ByteStream {
+    Box::pin(stream::try_unfold(body, |mut body| async move {
+        let chunk = body.try_next().await.map_err(std::io::Error::other)?;
+        Ok(chunk.map(|chunk| (chunk, body)))
+    }))
+}
+
+/// Missing objects become `ErrorKind::NotFound`, other failures keep the
+/// error code of the service.
+fn s3_error<E>(e: SdkError<E, HttpResponse>, key: &str) -> std::io::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    let status = e.raw_response().map(|response| response.status().as_u16());
+    if status == Some(404) {
+        return std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} not found in the bucket", key),
+        );
+    }
+    let message = match (status, e.code()) {
+        (Some(status), Some(code)) => format!("{}: {}", status, code),
+        (Some(status), None) => format!("{}: no error code", status),
+        _ => DisplayErrorContext(&e).to_string(),
+    };
+    std::io::Error::other(format!("S3 request for {} failed with {}", key, message))
+}
+
+/// Uploaded files in a bucket of an S3-compatible service (AWS S3, MinIO,
+/// Ceph...). Objects are addressed path-style: `<endpoint>/<bucket>/<key>`.
+pub struct S3Storage {
+    client: aws_sdk_s3::Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+}
+
+impl std::fmt::Debug for S3Storage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Storage")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .finish_non_exhaustive()
+    }
+}
+
+impl S3Storage {
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> Result<Self, String> {
+        let endpoint = endpoint.trim_end_matches('/');
+        reqwest::Url::parse(endpoint).map_err(|e| format!("Invalid S3 endpoint: {}", e))?;
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .endpoint_url(endpoint)
+            .region(Region::new(region.to_string()))
+            .credentials_provider(Credentials::new(
+                access_key, secret_key, None, None, "settings",
+            ))
+            .force_path_style(true)
+            // Checksums would be sent as trailers of a chunked body, which not
+            // every S3-compatible service accepts.
+            .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+            .response_checksum_validation(ResponseChecksumValidation::WhenRequired)
+            .build();
+        Ok(Self {
+            client: aws_sdk_s3::Client::from_conf(config),
+            endpoint: endpoint.to_string(),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+        })
+    }
+
+    fn check_key(key: &str) -> std::io::Result<()> {
+        if key.is_empty()
+            || key
+                .split('/')
+                .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            return Err(invalid_key(key));
+        }
+        Ok(())
+    }
+
+    /// Keys under `<prefix>/`, following the listing pages.
+    async fn list(&self, prefix: &str) -> std::io::Result<Vec<String>> {
+        let prefix = format!("{}/", prefix);
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&prefix)
+            .into_paginator()
+            .send();
+        let mut keys = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| s3_error(e, &prefix))?;
+            keys.extend(
+                page.contents()
+                    .iter()
+                    .filter_map(|object| object.key().map(String::from)),
+            );
+        }
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    /// S3 only makes an object visible once its upload completes.
+    async fn put(&self, key: &str, body: ByteStream, length: u64) -> std::io::Result<()> {
+        Self::check_key(key)?;
+        let body = UploadBody {
+            stream: std::sync::Mutex::new(body),
+            length,
+        };
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_length(length as i64)
+            .body(aws_sdk_s3::primitives::ByteStream::from_body_1_x(body))
+            .send()
+            .await
+            .map_err(|e| s3_error(e, key))?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> std::io::Result<StoredObject> {
+        Self::check_key(key)?;
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| s3_error(e, key))?;
+        Ok(StoredObject {
+            length: object.content_length().unwrap_or(0) as u64,
+            body: object_body(object.body),
+        })
+    }
+
+    async fn get_range(&self, key: &str, start: u64, length: u64) -> std::io::Result<StoredObject> {
+        Self::check_key(key)?;
+        // A `Range` header can't ask for nothing.
+        if length == 0 {
+            return Ok(StoredObject {
+                length,
+                body: Box::pin(stream::empty()),
+            });
+        }
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .range(format!("bytes={}-{}", start, start + length - 1))
+            .send()
+            .await
+            .map_err(|e| s3_error(e, key))?;
+        Ok(StoredObject {
+            length: object.content_length().unwrap_or(0) as u64,
+            body: object_body(object.body),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> std::io::Result<()> {
+        Self::check_key(key)?;
+        match self
+            .client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Err(e) => match s3_error(e, key) {
+                e if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+            Ok(_) => Ok(()),
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> std::io::Result<()> {
+        Self::check_key(prefix)?;
+        for key in self.list(prefix).await? {
+            self.delete(&key).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Picks the storage backend configured with `STORAGE_BACKEND`.
+pub fn build_storage(settings: &Settings) -> Arc<dyn Storage> {
+    match settings.storage_backend.as_str() {
+        "s3" => {
+            let endpoint = settings
+                .s3_endpoint
+                .as_deref()
+                .expect("S3_ENDPOINT must be set when STORAGE_BACKEND=s3");
+            let bucket = settings
+                .s3_bucket
+                .as_deref()
+                .expect("S3_BUCKET must be set when STORAGE_BACKEND=s3");
+            let storage = S3Storage::new(
+                endpoint,
+                bucket,
+                &settings.s3_region,
+                settings.s3_access_key.as_deref().unwrap_or(""),
+                settings.s3_secret_key.as_deref().unwrap_or(""),
+            )
+            .unwrap();
+            Arc::new(storage)
+        }
+        _ => Arc::new(LocalStorage::new(&settings.upload_dir)),
+    }
+}
@@ -0,0 +1,116 @@
+use crate::AppState;
+use crate::service::storage::{ByteStream, PRIVATE_PREFIX};
+use crate::service::upload::{Spool, SpooledFile};
+use base64::Engine;
+use base64::engine::general_purpose;
+use futures_util::{StreamExt, TryStreamExt, stream};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{error, info};
+
+pub type TusError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Version of the tus protocol spoken, sent in `Tus-Resumable`.
+pub const TUS_VERSION: &str = "1.0.0";
+
+pub const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+
+/// Parses `Upload-Metadata`: comma separated pairs of a key and its
+/// Base64 encoded value, the value may be left out. `None` when malformed.
+pub fn parse_metadata(header: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in header.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut parts = pair.split(' ');
+        let key = parts.next()?;
+        let value = match parts.next() {
+            Some(value) => {
+                let bytes = general_purpose::STANDARD.decode(value).ok()?;
+                String::from_utf8(bytes).ok()?
+            }
+            None => String::new(),
+        };
+        if parts.next().is_some() || metadata.insert(key.to_string(), value).is_some() {
+            return None;
+        }
+    }
+    Some(metadata)
+}
+
+/// `Upload-Expires` value, an HTTP date.
+pub fn http_date(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Storage prefix of the chunks of an upload.
+pub fn chunk_prefix(upload_id: uuid::Uuid) -> String {
+    format!("{}tus/{}", PRIVATE_PREFIX, upload_id)
+}
+
+/// The received chunks one after another, each read when reached.
+fn chunk_stream(data: &Arc<AppState>, keys: Vec<String>) -> ByteStream {
+    let data = data.clone();
+    let chunks = stream::iter(keys).then(move |key| {
+        let data = data.clone();
+        async move { data.storage.get(&key).await.map(|object| object.body) }
+    });
+    Box::pin(chunks.try_flatten())
+}
+
+/// Joins the received chunks into a temporary file, streamed so the
+/// upload is never held in memory.
+pub async fn spool_upload(
+    data: &Arc<AppState>,
+    upload_id: uuid::Uuid,
+) -> Result<SpooledFile, TusError> {
+    let keys = sqlx::query_scalar!(
+        r#"
+        SELECT storage_key FROM tus_upload_chunks
+        WHERE upload_id = $1
+        ORDER BY "offset"
+        "#,
+        upload_id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let mut spool = Spool::new().await?;
+    let mut body = chunk_stream(data, keys);
+    while let Some(part) = body.try_next().await? {
+        spool.write(&part).await?;
+    }
+    Ok(spool.finish().await?)
+}
+
+/// Deletes the upload and its stored chunks.
+pub async fn remove_upload(data: &Arc<AppState>, upload_id: uuid::Uuid) -> Result<(), TusError> {
+    sqlx::query!("DELETE FROM tus_uploads WHERE id = $1", upload_id)
+        .execute(&data.db)
+        .await?;
+    data.storage.delete_prefix(&chunk_prefix(upload_id)).await?;
+    Ok(())
+}
+
+/// Removes uploads not continued before they expired, and those whose
+/// user or book was deleted meanwhile.
+pub async fn remove_expired_uploads(data: &Arc<AppState>) -> Result<u64, TusError> {
+    let expired = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM tus_uploads
+        WHERE expires_at < NOW() OR user_id IS NULL OR book_id IS NULL
+        "#
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let mut removed = 0;
+    for upload_id in expired {
+        match remove_upload(data, upload_id).await {
+            Ok(()) => removed += 1,
+            Err(e) => error!("Failed to remove upload {}: {}", upload_id, e),
+        }
+    }
+    if removed > 0 {
+        info!("Removed {} expired uploads", removed);
+    }
+    Ok(removed)
+}
@@ -0,0 +1,223 @@
+use crate::AppState;
+use crate::service::response_server::ErrorResponse;
+use crate::service::storage::{ByteStream, PRIVATE_PREFIX};
+use axum::Json;
+use axum::body::{Body, Bytes};
+use axum::extract::multipart::MultipartError;
+use axum::extract::{Multipart, Path, State};
+use axum::http::StatusCode;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS};
+use axum::response::IntoResponse;
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use tracing::error;
+
+/// Room for the multipart framing around the file, added to the body limit
+/// of upload routes.
+pub const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
+fn multipart_error(e: MultipartError) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: e.body_text(),
+        message: "Invalid upload".to_string(),
+    };
+    (e.status(), Json(error_response))
+}
+
+fn too_large(max_size: usize) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("The limit is {} bytes", max_size),
+        message: "File is too large".to_string(),
+    };
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(error_response))
+}
+
+fn missing_field(name: &str) -> (StatusCode, Json<ErrorResponse>) {
+    let error = ErrorResponse {
+        error: format!("The `{}` field is missing", name),
+        message: "Invalid input data".to_string(),
+    };
+    (StatusCode::BAD_REQUEST, Json(error))
+}
+
+/// Reads the file sent in the `name` field of a multipart form, for files
+/// small enough to keep in memory. Stops as soon as it's over `max_size`.
+pub async fn read_file_field(
+    multipart: &mut Multipart,
+    name: &str,
+    max_size: usize,
+) -> Result<Bytes, (StatusCode, Json<ErrorResponse>)> {
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        if field.name() != Some(name) {
+            continue;
+        }
+        let mut upload = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+            if upload.len() + chunk.len() > max_size {
+                return Err(too_large(max_size));
+            }
+            upload.extend_from_slice(&chunk);
+        }
+        return Ok(Bytes::from(upload));
+    }
+    Err(missing_field(name))
+}
+
+/// Writes the file sent in the `name` field of a multipart form to a
+/// temporary file as it arrives. Stops as soon as it's over `max_size`.
+pub async fn spool_file_field(
+    multipart: &mut Multipart,
+    name: &str,
+    max_size: usize,
+) -> Result<SpooledFile, (StatusCode, Json<ErrorResponse>)> {
+    let spool_error = |e: std::io::Error| {
+        let error_response = ErrorResponse {
+            error: format!("IO error: {}", e),
+            message: "Error when receiving file".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    };
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        if field.name() != Some(name) {
+            continue;
+        }
+        let mut spool = Spool::new().await.map_err(spool_error)?;
+        while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+            if spool.size() + chunk.len() as u64 > max_size as u64 {
+                return Err(too_large(max_size));
+            }
+            spool.write(&chunk).await.map_err(spool_error)?;
+        }
+        return spool.finish().await.map_err(spool_error);
+    }
+    Err(missing_field(name))
+}
+
+/// An upload kept in a temporary file, with its SHA-256. The file is
+/// removed when this is dropped.
+#[derive(Debug)]
+pub struct SpooledFile {
+    path: PathBuf,
+    head: Vec<u8>,
+    pub size: u64,
+    pub hash: String,
+}
+
+impl SpooledFile {
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    pub fn starts_with(&self, magic: &[u8]) -> bool {
+        self.head.starts_with(magic)
+    }
+
+    /// Reads the file from the start.
+    pub async fn stream(&self) -> std::io::Result<ByteStream> {
+        let file = tokio::fs::File::open(&self.path).await?;
+        Ok(Box::pin(ReaderStream::new(file)))
+    }
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            error!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Bytes kept for checking the format of a spooled file.
+const HEAD_SIZE: usize = 16;
+
+/// A [`SpooledFile`] being written.
+pub struct Spool {
+    file: tokio::fs::File,
+    hasher: Sha256,
+    spooled: SpooledFile,
+}
+
+impl Spool {
+    pub async fn new() -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("books-upload-{}", uuid::Uuid::new_v4()));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Self {
+            file,
+            hasher: Sha256::new(),
+            spooled: SpooledFile {
+                path,
+                head: Vec::with_capacity(HEAD_SIZE),
+                size: 0,
+                hash: String::new(),
+            },
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.spooled.size
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        let head = &mut self.spooled.head;
+        let missing = HEAD_SIZE - head.len();
+        head.extend_from_slice(&chunk[..missing.min(chunk.len())]);
+        self.hasher.update(chunk);
+        self.file.write_all(chunk).await?;
+        self.spooled.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    pub async fn finish(self) -> std::io::Result<SpooledFile> {
+        let Self {
+            mut file,
+            hasher,
+            mut spooled,
+        } = self;
+        file.flush().await?;
+        spooled.hash = format!("{:x}", hasher.finalize());
+        Ok(spooled)
+    }
+}
+
+fn file_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: "".to_string(),
+        message: "File not found".to_string(),
+    };
+    (StatusCode::NOT_FOUND, Json(error_response))
+}
+
+/// Serves `/uploads/<key>` from the storage backend, streaming the file.
+/// Private files look missing.
+pub async fn serve_upload(
+    State(data): State<Arc<AppState>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    if key.starts_with(PRIVATE_PREFIX) {
+        return Err(file_not_found());
+    }
+    let object = data.storage.get(&key).await.map_err(|e| {
+        if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::InvalidInput) {
+            return file_not_found();
+        }
+        let error_response = ErrorResponse {
+            error: format!("Storage error: {}", e),
+            message: "Error when reading file".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    let content_type = mime_guess::from_path(&key).first_or_octet_stream();
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (CONTENT_LENGTH, object.length.to_string()),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(object.body),
+    ))
+}
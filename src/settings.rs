@@ -1,3 +1,32 @@
+/// External OpenID Connect provider, configured with `OIDC_<NAME>_*` variables.
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub scopes: String,
+}
+
+impl OidcProvider {
+    pub fn from_env(name: &str) -> Self {
+        let prefix = format!("OIDC_{}", name.to_uppercase().replace('-', "_"));
+        let var = |key: &str| std::env::var(format!("{}_{}", prefix, key));
+
+        Self {
+            name: name.to_string(),
+            issuer: var("ISSUER")
+                .unwrap_or_else(|_| panic!("{}_ISSUER must be set", prefix))
+                .trim_end_matches('/')
+                .to_string(),
+            client_id: var("CLIENT_ID")
+                .unwrap_or_else(|_| panic!("{}_CLIENT_ID must be set", prefix)),
+            client_secret: var("CLIENT_SECRET").ok(),
+            scopes: var("SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Settings {
     pub database_url: String,
@@ -10,6 +39,75 @@ pub struct Settings {
     pub refresh_token_private_key: String,
     pub refresh_token_public_key: String,
     pub refresh_token_max_age: i64,
+
+    pub app_url: String,
+    pub secret_key: String,
+    pub password_reset_token_max_age: i64,
+
+    pub email_verification_token_max_age: i64,
+    pub email_verification_resend_interval: i64,
+    pub require_verified_email: bool,
+
+    pub mail_transport: String,
+    pub mail_from: String,
+    pub mail_outbox_dir: String,
+    pub mail_queue: bool,
+    pub mail_max_attempts: i32,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+
+    pub webauthn_rp_id: String,
+    pub webauthn_rp_name: String,
+    pub webauthn_origin: String,
+
+    pub oidc_providers: Vec<OidcProvider>,
+
+    pub login_max_failed_attempts: i64,
+    pub login_max_failed_attempts_per_ip: i64,
+    pub login_failure_window: i64,
+    pub login_lockout_duration: i64,
+    pub trust_proxy_headers: bool,
+
+    pub csrf_exempt_routes: Vec<String>,
+
+    pub impersonation_token_max_age: i64,
+
+    pub data_export_dir: String,
+    pub data_export_max_age: i64,
+
+    pub storage_backend: String,
+    pub upload_dir: String,
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    pub blob_gc_interval: u64,
+    pub blob_gc_grace_period: u64,
+    pub blob_verify_batch_size: i64,
+    pub avatar_max_size: usize,
+    pub avatar_sizes: Vec<u32>,
+    pub cover_max_size: usize,
+    pub cover_widths: Vec<u32>,
+    pub book_file_max_size: usize,
+    pub tus_max_size: u64,
+    pub tus_upload_expiration: i64,
+    pub signed_url_max_age: i64,
+    pub search_preview_quota: i64,
+
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    pub password_require_uppercase: bool,
+    pub password_require_lowercase: bool,
+    pub password_require_digit: bool,
+    pub password_require_symbol: bool,
+    pub breached_passwords_dir: Option<String>,
+
+    pub argon2_memory_cost: u32,
+    pub argon2_time_cost: u32,
+    pub argon2_parallelism: u32,
 }
 
 impl Settings {
@@ -25,6 +123,125 @@ impl Settings {
         let refresh_token_public_key = std::env::var("REFRESH_TOKEN_PUBLIC_KEY").unwrap();
         let refresh_token_max_age = std::env::var("REFRESH_TOKEN_MAXAGE").unwrap();
 
+        let app_url =
+            std::env::var("APP_URL").unwrap_or_else(|_| "http://localhost:8000".to_string());
+        // An empty key would make every signature easy to forge.
+        let secret_key = std::env::var("SECRET_KEY")
+            .ok()
+            .filter(|key| !key.trim().is_empty())
+            .expect("SECRET_KEY must be set to a long random string, see .env.example");
+        let signed_url_max_age =
+            std::env::var("SIGNED_URL_MAX_AGE").unwrap_or_else(|_| "3600".to_string());
+        // Link lifetimes are clamped to 1..=SIGNED_URL_MAX_AGE.
+        if signed_url_max_age.parse::<i64>().is_ok_and(|age| age < 1) {
+            panic!("SIGNED_URL_MAX_AGE must be at least 1 second");
+        }
+        let password_reset_token_max_age =
+            std::env::var("PASSWORD_RESET_TOKEN_MAXAGE").unwrap_or_else(|_| "30".to_string());
+
+        let email_verification_token_max_age =
+            std::env::var("EMAIL_VERIFICATION_TOKEN_MAXAGE").unwrap_or_else(|_| "24".to_string());
+        let email_verification_resend_interval =
+            std::env::var("EMAIL_VERIFICATION_RESEND_INTERVAL")
+                .unwrap_or_else(|_| "60".to_string());
+        let require_verified_email =
+            std::env::var("REQUIRE_VERIFIED_EMAIL").unwrap_or_else(|_| "false".to_string());
+
+        let mail_transport = std::env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "log".to_string());
+        let mail_from =
+            std::env::var("MAIL_FROM").unwrap_or_else(|_| "Books <noreply@localhost>".to_string());
+        let mail_outbox_dir =
+            std::env::var("MAIL_OUTBOX_DIR").unwrap_or_else(|_| "outbox".to_string());
+        let mail_queue = std::env::var("MAIL_QUEUE").unwrap_or_else(|_| "false".to_string());
+        let mail_max_attempts =
+            std::env::var("MAIL_MAX_ATTEMPTS").unwrap_or_else(|_| "5".to_string());
+        let smtp_host = std::env::var("SMTP_HOST").ok();
+        let smtp_port = std::env::var("SMTP_PORT").unwrap_or_else(|_| "587".to_string());
+        let smtp_username = std::env::var("SMTP_USERNAME").ok();
+        let smtp_password = std::env::var("SMTP_PASSWORD").ok();
+
+        let webauthn_rp_id =
+            std::env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string());
+        let webauthn_rp_name =
+            std::env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "Books".to_string());
+        let webauthn_origin = std::env::var("WEBAUTHN_ORIGIN").unwrap_or_else(|_| app_url.clone());
+
+        let oidc_providers = std::env::var("OIDC_PROVIDERS").unwrap_or_default();
+
+        let login_max_failed_attempts =
+            std::env::var("LOGIN_MAX_FAILED_ATTEMPTS").unwrap_or_else(|_| "5".to_string());
+        let login_max_failed_attempts_per_ip =
+            std::env::var("LOGIN_MAX_FAILED_ATTEMPTS_PER_IP").unwrap_or_else(|_| "50".to_string());
+        let login_failure_window =
+            std::env::var("LOGIN_FAILURE_WINDOW").unwrap_or_else(|_| "900".to_string());
+        let login_lockout_duration =
+            std::env::var("LOGIN_LOCKOUT_DURATION").unwrap_or_else(|_| "900".to_string());
+        let trust_proxy_headers =
+            std::env::var("TRUST_PROXY_HEADERS").unwrap_or_else(|_| "false".to_string());
+
+        let csrf_exempt_routes = std::env::var("CSRF_EXEMPT_ROUTES").unwrap_or_default();
+
+        let impersonation_token_max_age =
+            std::env::var("IMPERSONATION_TOKEN_MAXAGE").unwrap_or_else(|_| "900".to_string());
+
+        let data_export_dir =
+            std::env::var("DATA_EXPORT_DIR").unwrap_or_else(|_| "exports".to_string());
+        let data_export_max_age =
+            std::env::var("DATA_EXPORT_MAXAGE").unwrap_or_else(|_| "24".to_string());
+
+        let storage_backend =
+            std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string());
+        let upload_dir = std::env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string());
+        let s3_endpoint = std::env::var("S3_ENDPOINT").ok();
+        let s3_bucket = std::env::var("S3_BUCKET").ok();
+        let s3_region = std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let s3_access_key = std::env::var("S3_ACCESS_KEY").ok();
+        let s3_secret_key = std::env::var("S3_SECRET_KEY").ok();
+        let blob_gc_interval =
+            std::env::var("BLOB_GC_INTERVAL").unwrap_or_else(|_| "3600".to_string());
+        let blob_gc_grace_period =
+            std::env::var("BLOB_GC_GRACE_PERIOD").unwrap_or_else(|_| "3600".to_string());
+        let blob_verify_batch_size =
+            std::env::var("BLOB_VERIFY_BATCH_SIZE").unwrap_or_else(|_| "100".to_string());
+        let avatar_max_size =
+            std::env::var("AVATAR_MAX_SIZE").unwrap_or_else(|_| "5242880".to_string());
+        let avatar_sizes =
+            std::env::var("AVATAR_SIZES").unwrap_or_else(|_| "64,128,256".to_string());
+        let cover_max_size =
+            std::env::var("COVER_MAX_SIZE").unwrap_or_else(|_| "10485760".to_string());
+        let cover_widths =
+            std::env::var("COVER_WIDTHS").unwrap_or_else(|_| "160,320,640".to_string());
+        let book_file_max_size =
+            std::env::var("BOOK_FILE_MAX_SIZE").unwrap_or_else(|_| "104857600".to_string());
+        let tus_max_size =
+            std::env::var("TUS_MAX_SIZE").unwrap_or_else(|_| "524288000".to_string());
+        let tus_upload_expiration =
+            std::env::var("TUS_UPLOAD_EXPIRATION").unwrap_or_else(|_| "86400".to_string());
+        let search_preview_quota =
+            std::env::var("SEARCH_PREVIEW_QUOTA").unwrap_or_else(|_| "30".to_string());
+
+        let password_min_length =
+            std::env::var("PASSWORD_MIN_LENGTH").unwrap_or_else(|_| "8".to_string());
+        let password_max_length =
+            std::env::var("PASSWORD_MAX_LENGTH").unwrap_or_else(|_| "128".to_string());
+        let password_require_uppercase =
+            std::env::var("PASSWORD_REQUIRE_UPPERCASE").unwrap_or_else(|_| "false".to_string());
+        let password_require_lowercase =
+            std::env::var("PASSWORD_REQUIRE_LOWERCASE").unwrap_or_else(|_| "false".to_string());
+        let password_require_digit =
+            std::env::var("PASSWORD_REQUIRE_DIGIT").unwrap_or_else(|_| "false".to_string());
+        let password_require_symbol =
+            std::env::var("PASSWORD_REQUIRE_SYMBOL").unwrap_or_else(|_| "false".to_string());
+        let breached_passwords_dir = std::env::var("BREACHED_PASSWORDS_DIR").ok();
+
+        // Defaults are the OWASP recommendation for Argon2id (19 MiB, 2 passes).
+        let argon2_memory_cost =
+            std::env::var("ARGON2_MEMORY_COST").unwrap_or_else(|_| "19456".to_string());
+        let argon2_time_cost =
+            std::env::var("ARGON2_TIME_COST").unwrap_or_else(|_| "2".to_string());
+        let argon2_parallelism =
+            std::env::var("ARGON2_PARALLELISM").unwrap_or_else(|_| "1".to_string());
+
         Self {
             database_url,
             redis_url,
@@ -34,6 +251,89 @@ impl Settings {
             refresh_token_public_key,
             access_token_max_age: access_token_max_age.parse::<i64>().unwrap(),
             refresh_token_max_age: refresh_token_max_age.parse::<i64>().unwrap(),
+            app_url,
+            secret_key,
+            password_reset_token_max_age: password_reset_token_max_age.parse::<i64>().unwrap(),
+            email_verification_token_max_age: email_verification_token_max_age
+                .parse::<i64>()
+                .unwrap(),
+            email_verification_resend_interval: email_verification_resend_interval
+                .parse::<i64>()
+                .unwrap(),
+            require_verified_email: require_verified_email.parse::<bool>().unwrap(),
+            mail_transport,
+            mail_from,
+            mail_outbox_dir,
+            mail_queue: mail_queue.parse::<bool>().unwrap(),
+            mail_max_attempts: mail_max_attempts.parse::<i32>().unwrap(),
+            smtp_host,
+            smtp_port: smtp_port.parse::<u16>().unwrap(),
+            smtp_username,
+            smtp_password,
+            webauthn_rp_id,
+            webauthn_rp_name,
+            webauthn_origin,
+            oidc_providers: oidc_providers
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(OidcProvider::from_env)
+                .collect(),
+            login_max_failed_attempts: login_max_failed_attempts.parse::<i64>().unwrap(),
+            login_max_failed_attempts_per_ip: login_max_failed_attempts_per_ip
+                .parse::<i64>()
+                .unwrap(),
+            login_failure_window: login_failure_window.parse::<i64>().unwrap(),
+            login_lockout_duration: login_lockout_duration.parse::<i64>().unwrap(),
+            trust_proxy_headers: trust_proxy_headers.parse::<bool>().unwrap(),
+            csrf_exempt_routes: csrf_exempt_routes
+                .split(',')
+                .map(str::trim)
+                .filter(|route| !route.is_empty())
+                .map(str::to_string)
+                .collect(),
+            impersonation_token_max_age: impersonation_token_max_age.parse::<i64>().unwrap(),
+            data_export_dir,
+            data_export_max_age: data_export_max_age.parse::<i64>().unwrap(),
+            storage_backend,
+            upload_dir,
+            s3_endpoint,
+            s3_bucket,
+            s3_region,
+            s3_access_key,
+            s3_secret_key,
+            blob_gc_interval: blob_gc_interval.parse::<u64>().unwrap(),
+            blob_gc_grace_period: blob_gc_grace_period.parse::<u64>().unwrap(),
+            blob_verify_batch_size: blob_verify_batch_size.parse::<i64>().unwrap(),
+            avatar_max_size: avatar_max_size.parse::<usize>().unwrap(),
+            avatar_sizes: avatar_sizes
+                .split(',')
+                .map(str::trim)
+                .filter(|size| !size.is_empty())
+                .map(|size| size.parse::<u32>().unwrap())
+                .collect(),
+            cover_max_size: cover_max_size.parse::<usize>().unwrap(),
+            cover_widths: cover_widths
+                .split(',')
+                .map(str::trim)
+                .filter(|width| !width.is_empty())
+                .map(|width| width.parse::<u32>().unwrap())
+                .collect(),
+            book_file_max_size: book_file_max_size.parse::<usize>().unwrap(),
+            tus_max_size: tus_max_size.parse::<u64>().unwrap(),
+            tus_upload_expiration: tus_upload_expiration.parse::<i64>().unwrap(),
+            signed_url_max_age: signed_url_max_age.parse::<i64>().unwrap(),
+            search_preview_quota: search_preview_quota.parse::<i64>().unwrap(),
+            password_min_length: password_min_length.parse::<usize>().unwrap(),
+            password_max_length: password_max_length.parse::<usize>().unwrap(),
+            password_require_uppercase: password_require_uppercase.parse::<bool>().unwrap(),
+            password_require_lowercase: password_require_lowercase.parse::<bool>().unwrap(),
+            password_require_digit: password_require_digit.parse::<bool>().unwrap(),
+            password_require_symbol: password_require_symbol.parse::<bool>().unwrap(),
+            breached_passwords_dir,
+            argon2_memory_cost: argon2_memory_cost.parse::<u32>().unwrap(),
+            argon2_time_cost: argon2_time_cost.parse::<u32>().unwrap(),
+            argon2_parallelism: argon2_parallelism.parse::<u32>().unwrap(),
         }
     }
 }
@@ -0,0 +1,354 @@
+use crate::AppState;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::blob::{BlobOwner, set_blob_references};
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::users::avatar::{DEFAULT_USER_FILE, remove_avatar};
+use crate::users::data_export::{process_data_export, remove_data_exports};
+use crate::users::password::verify_password;
+use crate::users::response::DataExportResponse;
+use crate::users::schema::DeleteAccountSchema;
+use crate::users::session::revoke_user_sessions;
+use axum::body::Body;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use std::sync::Arc;
+use tokio_util::io::ReaderStream;
+use validator::Validate;
+
+fn database_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Database error: {}", e),
+        message: "Request failed".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn export_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: "".to_string(),
+        message: "Data export not found".to_string(),
+    };
+    (StatusCode::NOT_FOUND, Json(error_response))
+}
+
+async fn fetch_data_export(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+    id: uuid::Uuid,
+) -> Result<(DataExportResponse, Option<String>), (StatusCode, Json<ErrorResponse>)> {
+    let export = sqlx::query!(
+        r#"
+        SELECT id, status, file_path, completed_at, expires_at, created_at
+        FROM data_exports
+        WHERE id = $1 AND user_id = $2
+        "#,
+        id,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(export_not_found)?;
+
+    let response = DataExportResponse {
+        id: export.id,
+        status: export.status,
+        completed_at: export.completed_at,
+        expires_at: export.expires_at,
+        created_at: export.created_at,
+    };
+    Ok((response, export.file_path))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/me/exports/",
+    responses(
+        (status = 202, description = "Выгрузка данных поставлена в очередь", body = DataExportResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Запрос выполнен с API ключом или от имени пользователя", body = ErrorResponse),
+        (status = 409, description = "Выгрузка уже готовится", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn request_data_export_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<DataExportResponse> {
+    let user_id = auth_guard.user.id;
+
+    let in_progress = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM data_exports
+            WHERE user_id = $1 AND status IN ('pending', 'processing')
+        ) as "exists!"
+        "#,
+        user_id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    if in_progress {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "A data export is already in progress".to_string(),
+        };
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+
+    // Only the latest archive is kept.
+    remove_data_exports(&data, user_id)
+        .await
+        .map_err(database_error)?;
+
+    let export = sqlx::query_as!(
+        DataExportResponse,
+        r#"
+        INSERT INTO data_exports (user_id)
+        VALUES ($1)
+        RETURNING id, status, completed_at, expires_at, created_at
+        "#,
+        user_id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    tokio::spawn(process_data_export(data.clone(), export.id, user_id));
+
+    let response = SuccessResponse {
+        data: export,
+        message: "Data export started".to_string(),
+    };
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/me/exports/{id}/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID выгрузки")
+    ),
+    responses(
+        (status = 200, description = "Состояние выгрузки данных", body = DataExportResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Запрос выполнен с API ключом или от имени пользователя", body = ErrorResponse),
+        (status = 404, description = "Выгрузка не найдена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn get_data_export_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+) -> APIResult<DataExportResponse> {
+    let (export, _) = fetch_data_export(&data, auth_guard.user.id, id).await?;
+
+    let response = SuccessResponse {
+        data: export,
+        message: "Data export fetched successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/me/exports/{id}/download/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID выгрузки")
+    ),
+    responses(
+        (status = 200, description = "ZIP архив с данными пользователя", content_type = "application/zip", body = Vec<u8>),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Запрос выполнен с API ключом или от имени пользователя", body = ErrorResponse),
+        (status = 404, description = "Выгрузка не найдена", body = ErrorResponse),
+        (status = 409, description = "Выгрузка еще не готова", body = ErrorResponse),
+        (status = 410, description = "Срок хранения выгрузки истек", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn download_data_export_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+) -> Result<impl IntoResponse, (StatusCode, Json<ErrorResponse>)> {
+    let (export, file_path) = fetch_data_export(&data, auth_guard.user.id, id).await?;
+
+    let Some(file_path) = file_path.filter(|_| export.status == "ready") else {
+        let error_response = ErrorResponse {
+            error: export.status,
+            message: "Data export is not ready".to_string(),
+        };
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    };
+
+    if export
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= chrono::Utc::now())
+    {
+        remove_data_exports(&data, auth_guard.user.id)
+            .await
+            .map_err(database_error)?;
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Data export has expired, request a new one".to_string(),
+        };
+        return Err((StatusCode::GONE, Json(error_response)));
+    }
+
+    let read_error = |e: std::io::Error| {
+        let error_response = ErrorResponse {
+            error: format!("IO error: {}", e),
+            message: "Error when reading data export".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    };
+    let archive = tokio::fs::File::open(&file_path)
+        .await
+        .map_err(read_error)?;
+    let size = archive.metadata().await.map_err(read_error)?.len();
+
+    let file_name = format!(
+        "attachment; filename=\"data-export-{}.zip\"",
+        export.created_at.format("%Y%m%d")
+    );
+    Ok((
+        [
+            (CONTENT_TYPE, "application/zip".to_string()),
+            (CONTENT_LENGTH, size.to_string()),
+            (CONTENT_DISPOSITION, file_name),
+        ],
+        Body::from_stream(ReaderStream::new(archive)),
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/me/",
+    request_body = DeleteAccountSchema,
+    responses(
+        (status = 200, description = "Аккаунт удален", body = String),
+        (status = 400, description = "Неверный пароль", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Запрос выполнен с API ключом или от имени пользователя", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn delete_me_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<DeleteAccountSchema>,
+) -> APIResult<String> {
+    if body.validate().is_err() {
+        let error = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error)));
+    }
+
+    let user = auth_guard.user;
+    if !verify_password(&body.password, &user.password) {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid password".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    remove_data_exports(&data, user.id)
+        .await
+        .map_err(database_error)?;
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+
+    let has_books = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM books WHERE author_id = $1) as "exists!""#,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(database_error)?;
+
+    if has_books {
+        // Books keep pointing at the account, so it stays as an empty shell
+        // that nobody can log into: the password is not a valid hash and
+        // every other way in is removed.
+        sqlx::query!(
+            r#"
+            UPDATE users
+            SET first_name = 'Deleted',
+                last_name = 'User',
+                middle_name = NULL,
+                age = 0,
+                email = $1,
+                password = '!',
+                biography = NULL,
+                file = $2,
+                verified = FALSE,
+                updated_at = NOW()
+            WHERE id = $3
+            "#,
+            format!("deleted-{}@deleted.invalid", user.id),
+            DEFAULT_USER_FILE,
+            user.id
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(database_error)?;
+
+        for query in [
+            sqlx::query!("DELETE FROM user_identities WHERE user_id = $1", user.id),
+            sqlx::query!("DELETE FROM user_passkeys WHERE user_id = $1", user.id),
+            sqlx::query!("DELETE FROM api_keys WHERE user_id = $1", user.id),
+            sqlx::query!("DELETE FROM user_two_factor WHERE user_id = $1", user.id),
+            sqlx::query!(
+                "DELETE FROM user_recovery_codes WHERE user_id = $1",
+                user.id
+            ),
+            sqlx::query!("DELETE FROM book_entitlements WHERE user_id = $1", user.id),
+        ] {
+            query.execute(&mut *tx).await.map_err(database_error)?;
+        }
+    } else {
+        sqlx::query!("DELETE FROM users WHERE id = $1", user.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(database_error)?;
+    }
+    set_blob_references(&mut tx, BlobOwner::Avatar, user.id, &[])
+        .await
+        .map_err(database_error)?;
+
+    tx.commit().await.map_err(database_error)?;
+
+    revoke_user_sessions(&data, user.id).await?;
+    remove_avatar(&data, user.id, &user.file).await;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Account deleted".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
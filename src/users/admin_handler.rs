@@ -0,0 +1,1032 @@
+use crate::AppState;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::blob::{
+    BlobError, BlobGcReport, BlobOwner, BlobVerifyReport, collect_garbage, set_blob_references,
+    verify_blobs,
+};
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::users::avatar::remove_avatar;
+use crate::users::model::{AuditAction, User, UserRole};
+use crate::users::response::{
+    AdminUserResponse, AuditLogEntryResponse, AuditLogResponse, BlobStatsResponse,
+    ImpersonationResponse, SuspensionResponse, UserListResponse, UserResponse,
+};
+use crate::users::schema::{
+    AuditLogQuery, ChangeRoleSchema, ImpersonateSchema, SuspendUserSchema, UserListQuery,
+};
+use crate::users::session::{remove_sessions, revoke_user_sessions, save_token_data_to_redis};
+use crate::users::token::generate_impersonation_jwt_token;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use std::sync::Arc;
+use std::time::Duration;
+use validator::Validate;
+
+const DEFAULT_PER_PAGE: i64 = 20;
+
+fn database_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Database error: {}", e),
+        message: "Request failed".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn invalid_input() -> (StatusCode, Json<ErrorResponse>) {
+    let error = ErrorResponse {
+        error: "".to_string(),
+        message: "Invalid input data".to_string(),
+    };
+    (StatusCode::BAD_REQUEST, Json(error))
+}
+
+fn user_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: "".to_string(),
+        message: "User not found".to_string(),
+    };
+    (StatusCode::NOT_FOUND, Json(error_response))
+}
+
+/// Admins can't demote, suspend or delete themselves, so the last admin
+/// can't lock everyone out by accident.
+fn deny_self(
+    auth_guard: &JWTAuthMiddleware,
+    user_id: uuid::Uuid,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if auth_guard.user.id == user_id {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "This action can't be applied to your own account".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+    Ok(())
+}
+
+/// Escapes `LIKE` wildcards in user input.
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .trim()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+pub async fn record_admin_action(
+    data: &Arc<AppState>,
+    admin_id: uuid::Uuid,
+    action: AuditAction,
+    target_user_id: Option<uuid::Uuid>,
+    details: Option<String>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query!(
+        r#"
+        INSERT INTO admin_audit_log (admin_id, action, target_user_id, details)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        admin_id,
+        action.to_string(),
+        target_user_id,
+        details
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+    Ok(())
+}
+
+async fn active_suspension(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<Option<SuspensionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as!(
+        SuspensionResponse,
+        r#"
+        SELECT id, reason, expires_at, created_by, created_at
+        FROM user_suspensions
+        WHERE user_id = $1 AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())
+        ORDER BY expires_at DESC NULLS FIRST
+        LIMIT 1
+        "#,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)
+}
+
+/// Rejects suspended and banned users. Checked on every authenticated
+/// request and before tokens are issued.
+pub async fn check_not_suspended(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(suspension) = active_suspension(data, user_id).await? else {
+        return Ok(());
+    };
+
+    let message = match suspension.expires_at {
+        Some(expires_at) => format!("Your account is suspended until {}", expires_at),
+        None => "Your account is banned".to_string(),
+    };
+    let json_error = ErrorResponse {
+        error: suspension.reason,
+        message,
+    };
+    Err((StatusCode::FORBIDDEN, Json(json_error)))
+}
+
+async fn fetch_user(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<User, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+        id,
+        first_name,
+        last_name,
+        middle_name,
+        age,
+        email,
+        password,
+        biography,
+        file,
+        verified,
+        role as "role: UserRole",
+        locale,
+        balance,
+        rating,
+        created_at,
+        updated_at
+        FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(user_not_found)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/admin/users/",
+    params(UserListQuery),
+    responses(
+        (status = 200, description = "Список пользователей", body = UserListResponse),
+        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn list_users_handler(
+    State(data): State<Arc<AppState>>,
+    Query(query): Query<UserListQuery>,
+) -> APIResult<UserListResponse> {
+    if query.validate().is_err() {
+        return Err(invalid_input());
+    }
+
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    let search = query.search.as_deref().map(like_pattern);
+    let role = query.role;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM users
+        WHERE ($1::text IS NULL OR email ILIKE $1 OR first_name ILIKE $1 OR last_name ILIKE $1)
+        AND ($2::user_role IS NULL OR role = $2)
+        "#,
+        search,
+        role.clone() as Option<UserRole>
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let users = sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+        id,
+        first_name,
+        last_name,
+        middle_name,
+        age,
+        email,
+        password,
+        biography,
+        file,
+        verified,
+        role as "role: UserRole",
+        locale,
+        balance,
+        rating,
+        created_at,
+        updated_at
+        FROM users
+        WHERE ($1::text IS NULL OR email ILIKE $1 OR first_name ILIKE $1 OR last_name ILIKE $1)
+        AND ($2::user_role IS NULL OR role = $2)
+        ORDER BY created_at DESC, id
+        LIMIT $3 OFFSET $4
+        "#,
+        search,
+        role as Option<UserRole>,
+        per_page,
+        (page - 1) * per_page
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: UserListResponse {
+            users: users.iter().map(UserResponse::new).collect(),
+            total,
+            page,
+            per_page,
+        },
+        message: "success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/admin/users/{id}/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя")
+    ),
+    responses(
+        (status = 200, description = "Пользователь и его текущая блокировка", body = AdminUserResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Пользователь не найден", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn get_user_handler(
+    State(data): State<Arc<AppState>>,
+    Path(id): Path<uuid::Uuid>,
+) -> APIResult<AdminUserResponse> {
+    let user = fetch_user(&data, id).await?;
+    let suspension = active_suspension(&data, id).await?;
+
+    let response = SuccessResponse {
+        data: AdminUserResponse {
+            user: UserResponse::new(&user),
+            suspension,
+        },
+        message: "success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/user/admin/users/{id}/role/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя")
+    ),
+    request_body = ChangeRoleSchema,
+    responses(
+        (status = 200, description = "Роль изменена", body = UserResponse),
+        (status = 400, description = "Нельзя изменить собственную роль", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Пользователь не найден", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn change_user_role_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+    Json(body): Json<ChangeRoleSchema>,
+) -> APIResult<UserResponse> {
+    deny_self(&auth_guard, id)?;
+    let previous_role = fetch_user(&data, id).await?.role;
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        UPDATE users SET role = $1, updated_at = NOW()
+        WHERE id = $2
+        RETURNING id, first_name, last_name, middle_name, age, email, password, biography, file, verified, role as "role: UserRole", locale, balance, rating, created_at, updated_at
+        "#,
+        body.role.clone() as UserRole,
+        id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(user_not_found)?;
+
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::ChangeRole,
+        Some(id),
+        Some(format!("{} -> {}", previous_role, body.role)),
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: UserResponse::new(&user),
+        message: "Role changed".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/admin/users/{id}/suspend/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя")
+    ),
+    request_body = SuspendUserSchema,
+    responses(
+        (status = 201, description = "Пользователь заблокирован", body = SuspensionResponse),
+        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Пользователь не найден", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn suspend_user_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+    Json(body): Json<SuspendUserSchema>,
+) -> APIResult<SuspensionResponse> {
+    if body.validate().is_err()
+        || body
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= chrono::Utc::now())
+    {
+        return Err(invalid_input());
+    }
+    deny_self(&auth_guard, id)?;
+    fetch_user(&data, id).await?;
+
+    let suspension = sqlx::query_as!(
+        SuspensionResponse,
+        r#"
+        INSERT INTO user_suspensions (user_id, reason, expires_at, created_by)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, reason, expires_at, created_by, created_at
+        "#,
+        id,
+        body.reason,
+        body.expires_at,
+        auth_guard.user.id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    // Existing sessions end now; API keys are rejected by the auth check.
+    revoke_user_sessions(&data, id).await?;
+    let details = match suspension.expires_at {
+        Some(expires_at) => format!("until {}: {}", expires_at, suspension.reason),
+        None => format!("permanent: {}", suspension.reason),
+    };
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::Suspend,
+        Some(id),
+        Some(details),
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: suspension,
+        message: "User suspended".to_string(),
+    };
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/admin/users/{id}/suspend/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя")
+    ),
+    responses(
+        (status = 200, description = "Блокировка снята", body = String),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Пользователь или активная блокировка не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn lift_suspension_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+) -> APIResult<String> {
+    // An unknown user is told apart from one who isn't suspended.
+    fetch_user(&data, id).await?;
+    let query_result = sqlx::query!(
+        r#"
+        UPDATE user_suspensions SET lifted_at = NOW()
+        WHERE user_id = $1 AND lifted_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())
+        "#,
+        id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    if query_result.rows_affected() == 0 {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "User is not suspended".to_string(),
+        };
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    }
+
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::LiftSuspension,
+        Some(id),
+        None,
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Suspension lifted".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/admin/users/{id}/verify/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя")
+    ),
+    responses(
+        (status = 200, description = "Email отмечен подтверждённым", body = UserResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Пользователь не найден", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn verify_user_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+) -> APIResult<UserResponse> {
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        UPDATE users SET verified = TRUE, updated_at = NOW()
+        WHERE id = $1
+        RETURNING id, first_name, last_name, middle_name, age, email, password, biography, file, verified, role as "role: UserRole", locale, balance, rating, created_at, updated_at
+        "#,
+        id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(user_not_found)?;
+
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::Verify,
+        Some(id),
+        None,
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: UserResponse::new(&user),
+        message: "User verified".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/admin/users/{id}/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя")
+    ),
+    responses(
+        (status = 200, description = "Пользователь удалён", body = String),
+        (status = 400, description = "Нельзя удалить собственный аккаунт", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Пользователь не найден", body = ErrorResponse),
+        (status = 409, description = "У пользователя есть книги", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn delete_user_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+) -> APIResult<String> {
+    deny_self(&auth_guard, id)?;
+    let user = fetch_user(&data, id).await?;
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    sqlx::query!(r#"DELETE FROM users WHERE id = $1"#, id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_error) if db_error.is_foreign_key_violation() => {
+                let error_response = ErrorResponse {
+                    error: "".to_string(),
+                    message: "User still has books, delete or reassign them first".to_string(),
+                };
+                (StatusCode::CONFLICT, Json(error_response))
+            }
+            e => database_error(e),
+        })?;
+    set_blob_references(&mut tx, BlobOwner::Avatar, id, &[])
+        .await
+        .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    revoke_user_sessions(&data, id).await?;
+    remove_avatar(&data, id, &user.file).await;
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::Delete,
+        Some(id),
+        Some(user.email),
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "User deleted".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/admin/audit-log/",
+    params(AuditLogQuery),
+    responses(
+        (status = 200, description = "Журнал действий администраторов", body = AuditLogResponse),
+        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn get_audit_log_handler(
+    State(data): State<Arc<AppState>>,
+    Query(query): Query<AuditLogQuery>,
+) -> APIResult<AuditLogResponse> {
+    if query.validate().is_err() {
+        return Err(invalid_input());
+    }
+
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM admin_audit_log
+        WHERE ($1::uuid IS NULL OR target_user_id = $1)
+        "#,
+        query.target_user_id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let entries = sqlx::query_as!(
+        AuditLogEntryResponse,
+        r#"
+        SELECT id, admin_id, action, target_user_id, details, created_at
+        FROM admin_audit_log
+        WHERE ($1::uuid IS NULL OR target_user_id = $1)
+        ORDER BY created_at DESC, id
+        LIMIT $2 OFFSET $3
+        "#,
+        query.target_user_id,
+        per_page,
+        (page - 1) * per_page
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: AuditLogResponse {
+            entries,
+            total,
+            page,
+            per_page,
+        },
+        message: "success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/admin/users/{id}/impersonate/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя")
+    ),
+    request_body = ImpersonateSchema,
+    responses(
+        (status = 201, description = "Токен для работы от имени пользователя", body = ImpersonationResponse),
+        (status = 400, description = "Ошибка валидации данных или попытка войти от имени себя", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав или пользователь администратор", body = ErrorResponse),
+        (status = 404, description = "Пользователь не найден", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn impersonate_user_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+    Json(body): Json<ImpersonateSchema>,
+) -> APIResult<ImpersonationResponse> {
+    if body.validate().is_err() {
+        return Err(invalid_input());
+    }
+    deny_self(&auth_guard, id)?;
+    let user = fetch_user(&data, id).await?;
+
+    // Acting as another admin would hand out their privileges.
+    if user.role == UserRole::Admin {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Administrators can't be impersonated".to_string(),
+        };
+        return Err((StatusCode::FORBIDDEN, Json(error_response)));
+    }
+
+    let max_age = data.env.impersonation_token_max_age;
+    let token_details = generate_impersonation_jwt_token(
+        user.id,
+        auth_guard.user.id,
+        chrono::Duration::seconds(max_age),
+        data.env.access_token_private_key.to_owned(),
+    )
+    .map_err(|e| {
+        let error_response = ErrorResponse {
+            error: format!("error generating token: {}", e),
+            message: "Error token".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+    save_token_data_to_redis(&data, &token_details, max_age).await?;
+
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::StartImpersonation,
+        Some(user.id),
+        Some(body.reason),
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: ImpersonationResponse {
+            access_token: token_details.token.unwrap_or_default(),
+            token_type: "Bearer".to_string(),
+            user_id: user.id,
+            impersonator_id: auth_guard.user.id,
+            expires_at: chrono::Utc::now() + chrono::Duration::seconds(max_age),
+        },
+        message: "Impersonation started".to_string(),
+    };
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/impersonation/",
+    responses(
+        (status = 200, description = "Сессия от имени пользователя завершена", body = String),
+        (status = 400, description = "Токен не является токеном имперсонации", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "User administration"
+)]
+pub async fn stop_impersonation_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<String> {
+    let Some(impersonator_id) = auth_guard.impersonator_id else {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "This session is not an impersonation".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    };
+
+    remove_sessions(
+        &data,
+        auth_guard.user.id,
+        &[auth_guard.accesses_token_uuid.to_string()],
+    )
+    .await?;
+    record_admin_action(
+        &data,
+        impersonator_id,
+        AuditAction::StopImpersonation,
+        Some(auth_guard.user.id),
+        None,
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Impersonation ended".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/user/admin/users/{id}/books/{book_id}/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя"),
+        ("book_id" = uuid::Uuid, Path, description = "ID книги")
+    ),
+    responses(
+        (status = 200, description = "Доступ к файлам книги выдан", body = String),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "Пользователь или книга не найдены", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn grant_book_access_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path((id, book_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> APIResult<String> {
+    fetch_user(&data, id).await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO book_entitlements (user_id, book_id, granted_by)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, book_id) DO NOTHING
+        "#,
+        id,
+        book_id,
+        auth_guard.user.id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db_error) if db_error.is_foreign_key_violation() => {
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "Book not found".to_string(),
+            };
+            (StatusCode::NOT_FOUND, Json(error_response))
+        }
+        e => database_error(e),
+    })?;
+
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::GrantBookAccess,
+        Some(id),
+        Some(book_id.to_string()),
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Book access granted".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/admin/users/{id}/books/{book_id}/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID пользователя"),
+        ("book_id" = uuid::Uuid, Path, description = "ID книги")
+    ),
+    responses(
+        (status = 200, description = "Доступ к файлам книги отозван", body = String),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 404, description = "У пользователя нет доступа к книге", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn revoke_book_access_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path((id, book_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> APIResult<String> {
+    let deleted = sqlx::query!(
+        "DELETE FROM book_entitlements WHERE user_id = $1 AND book_id = $2",
+        id,
+        book_id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    if deleted.rows_affected() == 0 {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "The user has no access to this book".to_string(),
+        };
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    }
+
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::RevokeBookAccess,
+        Some(id),
+        Some(book_id.to_string()),
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Book access revoked".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+fn blob_job_error(e: BlobError) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: e.to_string(),
+        message: "Storage maintenance failed".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/admin/storage/",
+    responses(
+        (status = 200, description = "Статистика хранилища файлов", body = BlobStatsResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn get_storage_stats_handler(
+    State(data): State<Arc<AppState>>,
+) -> APIResult<BlobStatsResponse> {
+    let stats = sqlx::query_as!(
+        BlobStatsResponse,
+        r#"
+        SELECT
+            COUNT(*) as "blobs!",
+            COALESCE(SUM(size), 0)::bigint as "total_size!",
+            COUNT(*) FILTER (WHERE ref_count = 0) as "unreferenced!",
+            COUNT(*) FILTER (WHERE corrupted_at IS NOT NULL) as "corrupted!"
+        FROM blobs
+        "#
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: stats,
+        message: "Storage stats fetched successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/admin/storage/gc/",
+    responses(
+        (status = 200, description = "Неиспользуемые файлы удалены", body = BlobGcReport),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn collect_storage_garbage_handler(
+    State(data): State<Arc<AppState>>,
+) -> APIResult<BlobGcReport> {
+    let grace_period = Duration::from_secs(data.env.blob_gc_grace_period);
+    let report = collect_garbage(&data, grace_period)
+        .await
+        .map_err(blob_job_error)?;
+
+    let response = SuccessResponse {
+        data: report,
+        message: "Unused files removed".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/admin/storage/verify/",
+    responses(
+        (status = 200, description = "Проверка целостности файлов выполнена", body = BlobVerifyReport),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "User administration"
+)]
+pub async fn verify_storage_handler(
+    State(data): State<Arc<AppState>>,
+) -> APIResult<BlobVerifyReport> {
+    let report = verify_blobs(&data, data.env.blob_verify_batch_size)
+        .await
+        .map_err(blob_job_error)?;
+
+    let response = SuccessResponse {
+        data: report,
+        message: "Files verified".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
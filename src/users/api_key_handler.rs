@@ -0,0 +1,173 @@
+use crate::AppState;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::users::model::ApiKeyScope;
+use crate::users::response::{ApiKeyResponse, CreatedApiKeyResponse};
+use crate::users::schema::CreateApiKeySchema;
+use crate::users::token::{generate_random_token, hash_token};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use std::sync::Arc;
+use validator::Validate;
+
+const API_KEY_PREFIX: &str = "bk_";
+const API_KEY_DEFAULT_TTL_DAYS: i64 = 90;
+
+fn database_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Database error: {}", e),
+        message: "Request failed".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/api-keys/",
+    request_body = CreateApiKeySchema,
+    responses(
+        (status = 201, description = "Ключ создан, значение показывается один раз", body = CreatedApiKeyResponse),
+        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Запрос выполнен с API ключом", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "API keys"
+)]
+pub async fn create_api_key_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<CreateApiKeySchema>,
+) -> APIResult<CreatedApiKeyResponse> {
+    if body.validate().is_err() {
+        let error = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error)));
+    }
+
+    let scopes: Vec<ApiKeyScope> = [ApiKeyScope::Read, ApiKeyScope::Write]
+        .into_iter()
+        .filter(|scope| body.scopes.contains(scope))
+        .collect();
+    let key = format!("{}{}", API_KEY_PREFIX, generate_random_token());
+    let expires_at = chrono::Utc::now()
+        + chrono::Duration::days(body.expires_in_days.unwrap_or(API_KEY_DEFAULT_TTL_DAYS));
+
+    let api_key = sqlx::query_as!(
+        ApiKeyResponse,
+        r#"
+        INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, name, prefix, scopes as "scopes: Vec<ApiKeyScope>", expires_at, last_used_at, revoked_at, created_at
+        "#,
+        auth_guard.user.id,
+        body.name,
+        &key[..API_KEY_PREFIX.len() + 8],
+        hash_token(&key),
+        &scopes as &[ApiKeyScope],
+        expires_at
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: CreatedApiKeyResponse { key, api_key },
+        message: "API key created, store it now: it won't be shown again".to_string(),
+    };
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/api-keys/",
+    responses(
+        (status = 200, description = "API ключи пользователя", body = [ApiKeyResponse]),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "API keys"
+)]
+pub async fn get_api_keys_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<Vec<ApiKeyResponse>> {
+    let api_keys = sqlx::query_as!(
+        ApiKeyResponse,
+        r#"
+        SELECT id, name, prefix, scopes as "scopes: Vec<ApiKeyScope>", expires_at, last_used_at, revoked_at, created_at
+        FROM api_keys
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        auth_guard.user.id
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: api_keys,
+        message: "success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/api-keys/{id}/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID API ключа")
+    ),
+    responses(
+        (status = 200, description = "Ключ отозван", body = String),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Запрос выполнен с API ключом", body = ErrorResponse),
+        (status = 404, description = "Ключ не найден или уже отозван", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "API keys"
+)]
+pub async fn revoke_api_key_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+) -> APIResult<String> {
+    let query_result = sqlx::query!(
+        r#"
+        UPDATE api_keys SET revoked_at = NOW()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        id,
+        auth_guard.user.id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    if query_result.rows_affected() == 0 {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "API key not found".to_string(),
+        };
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    }
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "API key revoked".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
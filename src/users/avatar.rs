@@ -0,0 +1,83 @@
+use crate::AppState;
+use crate::service::image_processing::{decode_image, encode_jpeg, encode_webp};
+use crate::service::storage::version_prefix;
+use crate::users::response::AvatarVariantResponse;
+use image::ImageFormat;
+use image::imageops::FilterType;
+use std::sync::Arc;
+use tracing::error;
+
+/// Photo every account starts with. It's shared, so it's never exported or
+/// removed.
+pub const DEFAULT_USER_FILE: &str = "uploads/photo-user/default.png";
+
+const AVATAR_DIR: &str = "photo-user";
+
+pub struct AvatarVariant {
+    pub size: u32,
+    pub format: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+/// Crops the middle square of the image and encodes it in every size as
+/// JPEG and WebP.
+pub fn render_avatar(
+    bytes: &[u8],
+    format: ImageFormat,
+    sizes: &[u32],
+) -> Result<Vec<AvatarVariant>, image::ImageError> {
+    let image = decode_image(bytes, format)?;
+
+    let side = image.width().min(image.height());
+    let square = image.crop_imm(
+        (image.width() - side) / 2,
+        (image.height() - side) / 2,
+        side,
+        side,
+    );
+
+    let mut variants = Vec::new();
+    for &size in sizes {
+        let resized = square.resize_exact(size, size, FilterType::Lanczos3);
+        variants.push(AvatarVariant {
+            size,
+            format: "jpg",
+            bytes: encode_jpeg(&resized)?,
+        });
+        variants.push(AvatarVariant {
+            size,
+            format: "webp",
+            bytes: encode_webp(&resized)?,
+        });
+    }
+    Ok(variants)
+}
+
+fn avatar_dir(user_id: uuid::Uuid) -> String {
+    format!("{}/{}", AVATAR_DIR, user_id)
+}
+
+/// Path kept in `users.file`: the largest JPEG.
+pub fn avatar_file(variants: &[AvatarVariantResponse]) -> String {
+    variants
+        .iter()
+        .filter(|variant| variant.format == "jpg")
+        .max_by_key(|variant| variant.size)
+        .map(|variant| variant.file.clone())
+        .unwrap_or_else(|| DEFAULT_USER_FILE.to_string())
+}
+
+/// Removes an avatar uploaded before avatars became blobs, when each upload
+/// had its own directory. Failures are only logged: the database no longer
+/// points at the files.
+pub async fn remove_avatar(data: &Arc<AppState>, user_id: uuid::Uuid, file: &str) {
+    let Some(prefix) = version_prefix(file, &avatar_dir(user_id)) else {
+        return;
+    };
+    if let Err(e) = data.storage.delete_prefix(&prefix).await {
+        error!(
+            "Failed to remove avatar {} of user {}: {}",
+            prefix, user_id, e
+        );
+    }
+}
@@ -0,0 +1,431 @@
+use crate::AppState;
+use crate::books::model::Books;
+use crate::books::response::BookResponse;
+use crate::service::storage::storage_key;
+use crate::users::avatar::DEFAULT_USER_FILE;
+use crate::users::model::{ApiKeyScope, User, UserRole};
+use crate::users::response::{ApiKeyResponse, PasskeyResponse, SuspensionResponse, UserResponse};
+use crate::users::session::user_sessions_key;
+use redis::AsyncCommands;
+use serde::Serialize;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+type ExportError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Jobs interrupted by a restart are run again only if they were requested
+/// this recently, older ones are failed so a new export can be requested.
+const EXPORT_RESUME_HOURS: i32 = 1;
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct IdentityExport {
+    provider: String,
+    subject: String,
+    email: Option<String>,
+    last_login_at: Option<chrono::DateTime<chrono::Utc>>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct TwoFactorExport {
+    enabled: bool,
+    confirmed_at: Option<chrono::DateTime<chrono::Utc>>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct SecurityExport {
+    two_factor: Option<TwoFactorExport>,
+    passkeys: Vec<PasskeyResponse>,
+    identities: Vec<IdentityExport>,
+    api_keys: Vec<ApiKeyResponse>,
+}
+
+#[derive(Debug, Serialize)]
+struct SessionExport {
+    id: String,
+    expires_in_seconds: i64,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct AccountEventExport {
+    action: String,
+    details: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+struct LibraryExport {
+    book_id: uuid::Uuid,
+    title: String,
+    granted_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportManifest {
+    user_id: uuid::Uuid,
+    generated_at: chrono::DateTime<chrono::Utc>,
+    files: Vec<String>,
+}
+
+fn export_path(data: &Arc<AppState>, export_id: uuid::Uuid) -> PathBuf {
+    Path::new(&data.env.data_export_dir).join(format!("{}.zip", export_id))
+}
+
+fn add_json<T: Serialize>(
+    zip: &mut ZipWriter<std::io::Cursor<Vec<u8>>>,
+    files: &mut Vec<String>,
+    name: &str,
+    value: &T,
+) -> Result<(), ExportError> {
+    zip.start_file(name, SimpleFileOptions::default())?;
+    zip.write_all(&serde_json::to_vec_pretty(value)?)?;
+    files.push(name.to_string());
+    Ok(())
+}
+
+async fn user_sessions(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<Vec<SessionExport>, ExportError> {
+    let mut redis_client = data.redis.get_multiplexed_async_connection().await?;
+    let token_uuids: Vec<String> = redis_client.smembers(user_sessions_key(user_id)).await?;
+
+    let mut sessions = Vec::new();
+    for token_uuid in token_uuids {
+        let ttl: i64 = redis_client.ttl(&token_uuid).await?;
+        // Logged out and expired tokens stay in the index until it expires.
+        if ttl > 0 {
+            sessions.push(SessionExport {
+                id: token_uuid,
+                expires_in_seconds: ttl,
+            });
+        }
+    }
+    Ok(sessions)
+}
+
+/// Builds the ZIP archive with everything stored about the user: one JSON
+/// file per section and the uploaded photo. The store keeps no reviews, so
+/// the archive has no section for them.
+async fn build_archive(data: &Arc<AppState>, user_id: uuid::Uuid) -> Result<Vec<u8>, ExportError> {
+    let user = sqlx::query_as!(
+        User,
+        r#"SELECT id, first_name, last_name, middle_name, age, email, password, biography, file, verified, role as "role: UserRole", locale, balance, rating, created_at, updated_at FROM users WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(&data.db)
+    .await?;
+
+    let books = sqlx::query_as!(
+        Books,
+        "SELECT * FROM books WHERE author_id = $1 ORDER BY created_at",
+        user_id
+    )
+    .fetch_all(&data.db)
+    .await?
+    .into_iter()
+    .map(BookResponse::from_book)
+    .collect::<Vec<_>>();
+
+    let security = SecurityExport {
+        two_factor: sqlx::query_as!(
+            TwoFactorExport,
+            "SELECT enabled, confirmed_at, created_at FROM user_two_factor WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(&data.db)
+        .await?,
+        passkeys: sqlx::query_as!(
+            PasskeyResponse,
+            "SELECT id, name, last_used_at, created_at FROM user_passkeys WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?,
+        identities: sqlx::query_as!(
+            IdentityExport,
+            "SELECT provider, subject, email, last_login_at, created_at FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?,
+        api_keys: sqlx::query_as!(
+            ApiKeyResponse,
+            r#"
+            SELECT id, name, prefix, scopes as "scopes: Vec<ApiKeyScope>", expires_at, last_used_at, revoked_at, created_at
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY created_at
+            "#,
+            user_id
+        )
+        .fetch_all(&data.db)
+        .await?,
+    };
+
+    let suspensions = sqlx::query_as!(
+        SuspensionResponse,
+        r#"
+        SELECT id, reason, expires_at, created_by, created_at
+        FROM user_suspensions
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let account_events = sqlx::query_as!(
+        AccountEventExport,
+        r#"
+        SELECT action, details, created_at
+        FROM admin_audit_log
+        WHERE target_user_id = $1
+        ORDER BY created_at
+        "#,
+        user_id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let library = sqlx::query_as!(
+        LibraryExport,
+        r#"
+        SELECT books.id as book_id, books.title, book_entitlements.created_at as granted_at
+        FROM book_entitlements
+        JOIN books ON books.id = book_entitlements.book_id
+        WHERE book_entitlements.user_id = $1
+        ORDER BY book_entitlements.created_at
+        "#,
+        user_id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let sessions = user_sessions(data, user_id).await?;
+
+    let photo_key = storage_key(&user.file).filter(|_| user.file != DEFAULT_USER_FILE);
+    let photo = match photo_key {
+        Some(key) => match data.storage.get_bytes(key).await {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                warn!(
+                    "Photo {} of user {} is not readable: {}",
+                    user.file, user_id, e
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut files = Vec::new();
+    add_json(
+        &mut zip,
+        &mut files,
+        "profile.json",
+        &UserResponse::new(&user),
+    )?;
+    add_json(&mut zip, &mut files, "books.json", &books)?;
+    add_json(&mut zip, &mut files, "library.json", &library)?;
+    add_json(&mut zip, &mut files, "security.json", &security)?;
+    add_json(&mut zip, &mut files, "sessions.json", &sessions)?;
+    add_json(&mut zip, &mut files, "suspensions.json", &suspensions)?;
+    add_json(&mut zip, &mut files, "account_events.json", &account_events)?;
+
+    if let Some(photo) = photo {
+        let name = Path::new(&user.file)
+            .file_name()
+            .map(|name| format!("files/{}", name.to_string_lossy()))
+            .unwrap_or_else(|| "files/photo".to_string());
+        zip.start_file(name.as_str(), SimpleFileOptions::default())?;
+        zip.write_all(&photo)?;
+        files.push(name);
+    }
+
+    let manifest = ExportManifest {
+        user_id,
+        generated_at: chrono::Utc::now(),
+        files,
+    };
+    zip.start_file("manifest.json", SimpleFileOptions::default())?;
+    zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+async fn write_archive(
+    data: &Arc<AppState>,
+    export_id: uuid::Uuid,
+    user_id: uuid::Uuid,
+) -> Result<String, ExportError> {
+    let archive = build_archive(data, user_id).await?;
+    let path = export_path(data, export_id);
+    // A resumed job may still be written by the instance it was started on,
+    // so the archive is only ever replaced whole.
+    let temp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+
+    tokio::fs::create_dir_all(&data.env.data_export_dir).await?;
+    tokio::fs::write(&temp_path, archive).await?;
+    if let Err(e) = tokio::fs::rename(&temp_path, &path).await {
+        remove_file(&temp_path.to_string_lossy()).await;
+        return Err(e.into());
+    }
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Runs an export job queued by the export endpoint. The archive is kept
+/// for `data_export_max_age` hours.
+pub async fn process_data_export(data: Arc<AppState>, export_id: uuid::Uuid, user_id: uuid::Uuid) {
+    let result = match sqlx::query!(
+        "UPDATE data_exports SET status = 'processing' WHERE id = $1",
+        export_id
+    )
+    .execute(&data.db)
+    .await
+    {
+        Ok(_) => write_archive(&data, export_id, user_id).await,
+        Err(e) => Err(e.into()),
+    };
+
+    let update = match result {
+        Ok(file_path) => {
+            info!("Data export {} of user {} is ready", export_id, user_id);
+            let updated = sqlx::query!(
+                r#"
+                UPDATE data_exports
+                SET status = 'ready',
+                    file_path = $1,
+                    completed_at = NOW(),
+                    expires_at = NOW() + make_interval(hours => $2)
+                WHERE id = $3
+                "#,
+                file_path,
+                data.env.data_export_max_age as i32,
+                export_id
+            )
+            .execute(&data.db)
+            .await;
+            // The account was deleted while the archive was being built.
+            if updated
+                .as_ref()
+                .is_ok_and(|result| result.rows_affected() == 0)
+            {
+                remove_file(&file_path).await;
+            }
+            updated
+        }
+        Err(e) => {
+            error!(
+                "Data export {} of user {} failed: {}",
+                export_id, user_id, e
+            );
+            sqlx::query!(
+                r#"
+                UPDATE data_exports
+                SET status = 'failed', last_error = $1, completed_at = NOW()
+                WHERE id = $2
+                "#,
+                e.to_string(),
+                export_id
+            )
+            .execute(&data.db)
+            .await
+        }
+    };
+
+    if let Err(e) = update {
+        error!("Failed to update data export {}: {:?}", export_id, e);
+    }
+}
+
+async fn remove_file(path: &str) {
+    if let Err(e) = tokio::fs::remove_file(path).await
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        error!("Failed to remove {}: {}", path, e);
+    }
+}
+
+/// Deletes the user's export archives and their jobs.
+pub async fn remove_data_exports(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<(), sqlx::Error> {
+    let file_paths = sqlx::query_scalar!(
+        r#"
+        DELETE FROM data_exports
+        WHERE user_id = $1
+        RETURNING file_path
+        "#,
+        user_id
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    for file_path in file_paths.into_iter().flatten() {
+        remove_file(&file_path).await;
+    }
+    Ok(())
+}
+
+/// Restarts the export jobs left unfinished by the previous run of the
+/// server. Jobs older than `EXPORT_RESUME_HOURS` are failed instead.
+pub async fn resume_data_exports(data: &Arc<AppState>) -> Result<(), sqlx::Error> {
+    let failed = sqlx::query!(
+        r#"
+        UPDATE data_exports
+        SET status = 'failed', last_error = 'Interrupted by a server restart', completed_at = NOW()
+        WHERE status IN ('pending', 'processing')
+            AND created_at < NOW() - make_interval(hours => $1)
+        "#,
+        EXPORT_RESUME_HOURS
+    )
+    .execute(&data.db)
+    .await?
+    .rows_affected();
+    if failed > 0 {
+        warn!("Failed {} data exports interrupted by a restart", failed);
+    }
+
+    let jobs = sqlx::query!(
+        r#"
+        SELECT id, user_id FROM data_exports
+        WHERE status IN ('pending', 'processing')
+        ORDER BY created_at
+        "#
+    )
+    .fetch_all(&data.db)
+    .await?;
+    for job in jobs {
+        info!("Resuming data export {} of user {}", job.id, job.user_id);
+        tokio::spawn(process_data_export(data.clone(), job.id, job.user_id));
+    }
+    Ok(())
+}
+
+/// Deletes the archives kept past `data_export_max_age`, with their jobs.
+pub async fn remove_expired_data_exports(data: &Arc<AppState>) -> Result<u64, sqlx::Error> {
+    let file_paths = sqlx::query_scalar!(
+        r#"
+        DELETE FROM data_exports
+        WHERE expires_at < NOW()
+        RETURNING file_path
+        "#
+    )
+    .fetch_all(&data.db)
+    .await?;
+
+    let removed = file_paths.len() as u64;
+    for file_path in file_paths.into_iter().flatten() {
+        remove_file(&file_path).await;
+    }
+    Ok(removed)
+}
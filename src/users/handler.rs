@@ -1,20 +1,29 @@
 use crate::AppState;
+use crate::middleware::csrf::generate_csrf_token;
 use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::mail_template::Locale;
 use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
-use crate::users::model::{User, UserRole};
-use crate::users::response::UserResponse;
-use crate::users::schema::{LoginUserSchema, RegisterUserSchema};
-use crate::users::token::{TokenDetails, generate_jwt_token, verify_jwt_token};
-use argon2::password_hash::SaltString;
-use argon2::password_hash::rand_core::OsRng;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use axum::extract::State;
-use axum::http::{HeaderMap, Response, StatusCode, header};
+use crate::users::admin_handler::{check_not_suspended, record_admin_action};
+use crate::users::login_throttle::{
+    check_login_allowed, clear_login_failures, client_ip, register_login_failure, unlock_login,
+};
+use crate::users::model::{AuditAction, User, UserRole};
+use crate::users::password::{hash_password, needs_rehash, verify_dummy_password, verify_password};
+use crate::users::password_policy::check_password;
+use crate::users::response::{CsrfTokenResponse, UserResponse};
+use crate::users::schema::{LoginUserSchema, RegisterUserSchema, UnlockLoginSchema};
+use crate::users::session::{generate_token, remove_sessions, save_token_data_to_redis};
+use crate::users::token::verify_jwt_token;
+use crate::users::two_factor_handler::{start_two_factor_challenge, two_factor_status};
+use crate::users::verification_handler::send_verification_email;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{Extensions, HeaderMap, Response, StatusCode, header};
 use axum::{Extension, Json};
 use axum_extra::extract::CookieJar;
 use axum_extra::extract::cookie::{Cookie, SameSite};
-use redis::AsyncCommands;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use tracing::error;
 use validator::Validate;
 
 #[utoipa::path(
@@ -23,7 +32,7 @@ use validator::Validate;
     request_body = RegisterUserSchema,
     responses(
         (status = 201, description = "Успешно создано", body = UserResponse),
-        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 400, description = "Ошибка валидации данных или пароль не соответствует политике", body = ErrorResponse),
         (status = 409, description = "Ошибка такие данные уже есть", body = ErrorResponse),
         (status = 500, description = "Ошибка сервера", body = ErrorResponse)
     ),
@@ -61,32 +70,23 @@ pub async fn register_user_handler(
         return Err((StatusCode::CONFLICT, Json(error_response)));
     }
 
-    let salt = SaltString::generate(&mut OsRng);
-
-    let hashed_password = Argon2::default()
-        .hash_password(body.password.as_bytes(), &salt)
-        .map_err(|e| {
-            let error_response = ErrorResponse {
-                error: format!("Error while hashing password: {}", e),
-                message: "Password hashing failed".to_string(),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-        })
-        .map(|hash| hash.to_string())?;
+    check_password(&data.env, &body.password).await?;
+    let hashed_password = hash_password(&data.env, &body.password)?;
 
     let user = sqlx::query_as!(
         User,
         r#"
-        INSERT INTO users (first_name, last_name, middle_name, age, email, password)
-        VALUES ($1, $2, $3, $4, $5, $6)
-        RETURNING id, first_name, last_name, middle_name, age, email, password, biography, file, verified, role as "role: UserRole", balance, rating, created_at, updated_at
+        INSERT INTO users (first_name, last_name, middle_name, age, email, password, locale)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, first_name, last_name, middle_name, age, email, password, biography, file, verified, role as "role: UserRole", locale, balance, rating, created_at, updated_at
         "#,
         body.first_name,
         body.last_name,
         body.middle_name,
         body.age,
         body.email,
-        hashed_password
+        hashed_password,
+        body.locale.unwrap_or_else(|| Locale::default().to_string())
     )
         .fetch_one(&data.db)
         .await
@@ -98,6 +98,8 @@ pub async fn register_user_handler(
             (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
         })?;
 
+    send_verification_email(&data, &user).await;
+
     let response = SuccessResponse {
         data: UserResponse::new(&user),
         message: "Registration successful".to_string(),
@@ -112,14 +114,16 @@ pub async fn register_user_handler(
     request_body = LoginUserSchema,
     responses(
         (status = 200, description = "Успешно авторизирован", body = UserResponse),
-        (status = 400, description = "Ошибка валидации данных или ошибка хеширования пароля", body = ErrorResponse),
-        (status = 409, description = "Ошибка такие данные уже есть", body = ErrorResponse),
+        (status = 400, description = "Ошибка валидации данных или неверный email или пароль", body = ErrorResponse),
+        (status = 429, description = "Слишком много неудачных попыток входа", body = ErrorResponse),
         (status = 500, description = "Ошибка сервера", body = ErrorResponse)
     ),
     tag = "Users"
 )]
 pub async fn login_user_handler(
     State(data): State<Arc<AppState>>,
+    headers: HeaderMap,
+    extensions: Extensions,
     Json(body): Json<LoginUserSchema>,
 ) -> APIResult<serde_json::Value> {
     if body.validate().is_err() {
@@ -130,7 +134,17 @@ pub async fn login_user_handler(
         return Err((StatusCode::BAD_REQUEST, Json(error)));
     }
 
-    let user: User = sqlx::query_as!(
+    let ip = client_ip(
+        &data,
+        &headers,
+        // Missing when the router isn't served with connect info, as in tests.
+        extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr),
+    );
+    check_login_allowed(&data, &body.email, ip.as_deref()).await?;
+
+    let user = sqlx::query_as!(
         User,
         r#"
         SELECT
@@ -145,6 +159,7 @@ pub async fn login_user_handler(
         file,
         verified,
         role as "role: UserRole",
+        locale,
         balance,
         rating,
         created_at,
@@ -162,49 +177,90 @@ pub async fn login_user_handler(
             message: "Request failed".to_string(),
         };
         (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-    })?
-    .ok_or_else(|| {
-        let error_response = ErrorResponse {
-            error: "".to_string(),
-            message: "Password hashing failed".to_string(),
-        };
-        (StatusCode::BAD_REQUEST, Json(error_response))
     })?;
 
-    let valid_password = match PasswordHash::new(&user.password) {
-        Ok(hash) => Argon2::default()
-            .verify_password(body.password.as_bytes(), &hash)
-            .is_ok(),
-        Err(_) => false,
+    // Unknown emails and wrong passwords get the same response after the
+    // same amount of hashing work.
+    let password_valid = match &user {
+        Some(user) => verify_password(&body.password, &user.password),
+        None => verify_dummy_password(&data.env, &body.password),
     };
+    let user = match user {
+        Some(user) if password_valid => user,
+        _ => {
+            register_login_failure(&data, &body.email, ip.as_deref()).await?;
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "Invalid email or password".to_string(),
+            };
+            return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+        }
+    };
+    clear_login_failures(&data, &body.email).await?;
+    if needs_rehash(&data.env, &user.password) {
+        upgrade_password_hash(&data, &user, &body.password).await;
+    }
 
-    if !valid_password {
-        let error_response = ErrorResponse {
-            error: "".to_string(),
-            message: "Invalid email or password".to_string(),
-        };
-        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    let two_factor = two_factor_status(&data, &user).await?;
+    if two_factor.enabled {
+        return start_two_factor_challenge(&data, user.id).await;
     }
 
-    let access_token_details = generate_token(
+    issue_user_tokens(&data, &user, two_factor.required).await
+}
+
+/// Re-hashes the password with the current Argon2 parameters. Failures are
+/// only logged: the old hash still works, the upgrade is retried next login.
+async fn upgrade_password_hash(data: &Arc<AppState>, user: &User, password: &str) {
+    let hashed_password = match hash_password(&data.env, password) {
+        Ok(hashed_password) => hashed_password,
+        Err((_, Json(e))) => {
+            error!("Failed to rehash password of user {}: {}", user.id, e.error);
+            return;
+        }
+    };
+    // Compare with the old hash so a concurrent password change wins.
+    let result = sqlx::query!(
+        r#"UPDATE users SET password = $1 WHERE id = $2 AND password = $3"#,
+        hashed_password,
         user.id,
+        user.password
+    )
+    .execute(&data.db)
+    .await;
+    if let Err(e) = result {
+        error!(
+            "Failed to store rehashed password of user {}: {}",
+            user.id, e
+        );
+    }
+}
+
+/// Creates an access/refresh token pair for the user and returns it the same
+/// way for every login method. `setup_required` flags a user whose role
+/// requires 2FA they haven't turned on yet.
+pub async fn issue_user_tokens(
+    data: &Arc<AppState>,
+    user: &User,
+    setup_required: bool,
+) -> APIResult<serde_json::Value> {
+    let user_id = user.id;
+    check_not_suspended(data, user_id).await?;
+
+    let access_token_details = generate_token(
+        user_id,
         data.env.access_token_max_age,
         data.env.access_token_private_key.to_owned(),
     )?;
 
     let refresh_token_details = generate_token(
-        user.id,
+        user_id,
         data.env.refresh_token_max_age,
         data.env.refresh_token_private_key.to_owned(),
     )?;
 
-    save_token_data_to_redis(&data, &access_token_details, data.env.access_token_max_age).await?;
-    save_token_data_to_redis(
-        &data,
-        &refresh_token_details,
-        data.env.refresh_token_max_age,
-    )
-    .await?;
+    save_token_data_to_redis(data, &access_token_details, data.env.access_token_max_age).await?;
+    save_token_data_to_redis(data, &refresh_token_details, data.env.refresh_token_max_age).await?;
 
     let access_cookie = Cookie::build((
         "access_token",
@@ -230,8 +286,11 @@ pub async fn login_user_handler(
         .same_site(SameSite::Lax)
         .http_only(false);
 
-    let messages = serde_json::json!({"type_token":"Bearer".to_string(),"access_token":access_token_details.token.unwrap_or_default(),
+    let mut messages = serde_json::json!({"type_token":"Bearer".to_string(),"access_token":access_token_details.token.unwrap_or_default(),
     "refresh_token": refresh_token_details.token.unwrap_or_default()});
+    if setup_required {
+        messages["two_factor_setup_required"] = serde_json::Value::Bool(true);
+    }
 
     let json_response = SuccessResponse {
         data: messages,
@@ -301,31 +360,15 @@ pub async fn logout_user_handler(
             }
         };
 
-    let mut redis_client = data
-        .redis
-        .get_multiplexed_async_connection()
-        .await
-        .map_err(|e| {
-            let error_response = ErrorResponse {
-                error: format!("Redis error: {}", e),
-                message: "Redis error".to_string(),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-        })?;
-
-    redis_client
-        .del::<_, ()>(&[
+    remove_sessions(
+        &data,
+        auth_guard.user.id,
+        &[
             refresh_token_details.token_uuid.to_string(),
             auth_guard.accesses_token_uuid.to_string(),
-        ])
-        .await
-        .map_err(|e| {
-            let error_response = ErrorResponse {
-                error: format!("Redis Error 2: {:?}", e),
-                message: "Redis Error".to_string(),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-        })?;
+        ],
+    )
+    .await?;
 
     let access_cookie = Cookie::build(("access_token", ""))
         .path("/")
@@ -368,49 +411,89 @@ pub async fn logout_user_handler(
     Ok((StatusCode::OK, Json(response_success)))
 }
 
-fn generate_token(
-    user_id: uuid::Uuid,
-    max_age: i64,
-    private_key: String,
-) -> Result<TokenDetails, (StatusCode, Json<ErrorResponse>)> {
-    generate_jwt_token(user_id, max_age, private_key).map_err(|e| {
-        let error_response = ErrorResponse {
-            error: format!("error generating token: {}", e),
-            message: "Error token".to_string(),
-        };
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-    })
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/csrf/",
+    responses(
+        (status = 200, description = "CSRF токен для заголовка X-CSRF-Token", body = CsrfTokenResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn csrf_token_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<CsrfTokenResponse> {
+    let csrf_token = generate_csrf_token(&data, auth_guard.accesses_token_uuid)?;
+
+    let response = SuccessResponse {
+        data: CsrfTokenResponse { csrf_token },
+        message: "success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
 }
 
-async fn save_token_data_to_redis(
-    data: &Arc<AppState>,
-    token_details: &TokenDetails,
-    max_age: i64,
-) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
-    let mut redis_client = data
-        .redis
-        .get_multiplexed_async_connection()
-        .await
-        .map_err(|e| {
-            let error_response = ErrorResponse {
-                error: format!("Redis error Save Token: {}", e),
-                message: "Redis error".to_string(),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-        })?;
-    redis_client
-        .set_ex::<_, _, ()>(
-            token_details.token_uuid.to_string(),
-            token_details.user_id.to_string(),
-            max_age as u64,
-        )
-        .await
-        .map_err(|e| {
-            let error_response = ErrorResponse {
-                error: format!("Redis error Save Token 2: {}", e),
-                message: "Redis error".to_string(),
-            };
-            (StatusCode::UNPROCESSABLE_ENTITY, Json(error_response))
-        })?;
-    Ok(())
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/login/unlock/",
+    request_body = UnlockLoginSchema,
+    responses(
+        (status = 200, description = "Блокировка входа снята", body = String),
+        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 403, description = "Недостаточно прав", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "Users"
+)]
+pub async fn unlock_login_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<UnlockLoginSchema>,
+) -> APIResult<String> {
+    let ip = match &body.ip {
+        Some(ip) => match ip.trim().parse::<IpAddr>() {
+            Ok(ip) => Some(ip.to_string()),
+            Err(_) => None,
+        },
+        None => None,
+    };
+    if body.validate().is_err()
+        || (body.email.is_none() && body.ip.is_none())
+        || (body.ip.is_some() && ip.is_none())
+    {
+        let error = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error)));
+    }
+
+    unlock_login(&data, body.email.as_deref(), ip.as_deref()).await?;
+    let details = [body.email.as_deref(), ip.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ");
+    record_admin_action(
+        &data,
+        auth_guard.user.id,
+        AuditAction::UnlockLogin,
+        None,
+        Some(details),
+    )
+    .await?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Login unlocked".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
 }
@@ -0,0 +1,197 @@
+use crate::AppState;
+use crate::service::response_server::ErrorResponse;
+use crate::users::session::{redis_connection, redis_error};
+use crate::users::token::hash_token;
+use axum::Json;
+use axum::http::{HeaderMap, StatusCode};
+use redis::AsyncCommands;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Longest pause forced between two attempts before the lockout kicks in.
+const MAX_DELAY_SECONDS: u64 = 30;
+
+// Accounts are keyed by the submitted email, whether it exists or not, so
+// throttling behaves the same for unknown emails.
+fn account_id(email: &str) -> String {
+    hash_token(&email.trim().to_lowercase())
+}
+
+fn failures_key(scope: &str, id: &str) -> String {
+    format!("login-failures-{}-{}", scope, id)
+}
+
+fn lock_key(scope: &str, id: &str) -> String {
+    format!("login-lock-{}-{}", scope, id)
+}
+
+fn delay_key(id: &str) -> String {
+    format!("login-delay-account-{}", id)
+}
+
+/// Client address for per-IP counters. Proxy headers are used only when
+/// `TRUST_PROXY_HEADERS` is on; `None` disables the per-IP limit.
+pub fn client_ip(
+    data: &Arc<AppState>,
+    headers: &HeaderMap,
+    remote_addr: Option<SocketAddr>,
+) -> Option<String> {
+    if data.env.trust_proxy_headers {
+        let forwarded = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .or_else(|| {
+                headers
+                    .get("x-real-ip")
+                    .and_then(|value| value.to_str().ok())
+            })
+            .and_then(|value| value.trim().parse::<IpAddr>().ok());
+        if let Some(ip) = forwarded {
+            return Some(ip.to_string());
+        }
+    }
+    remote_addr.map(|addr| addr.ip().to_string())
+}
+
+fn too_many_attempts(retry_after: i64) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Retry after {} seconds", retry_after),
+        message: "Too many failed login attempts, try again later".to_string(),
+    };
+    (StatusCode::TOO_MANY_REQUESTS, Json(error_response))
+}
+
+/// Rejects the attempt while the account or IP is locked out, or while the
+/// pause after the previous failure hasn't passed yet.
+pub async fn check_login_allowed(
+    data: &Arc<AppState>,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let account = account_id(email);
+    let mut keys = vec![lock_key("account", &account), delay_key(&account)];
+    if let Some(ip) = ip {
+        keys.push(lock_key("ip", ip));
+    }
+
+    let mut redis_client = redis_connection(data).await?;
+    let mut pipe = redis::pipe();
+    for key in &keys {
+        pipe.ttl(key);
+    }
+    let ttls: Vec<i64> = pipe
+        .query_async(&mut redis_client)
+        .await
+        .map_err(redis_error)?;
+
+    match ttls.into_iter().max() {
+        Some(retry_after) if retry_after > 0 => Err(too_many_attempts(retry_after)),
+        _ => Ok(()),
+    }
+}
+
+async fn count_failure(
+    redis_client: &mut redis::aio::MultiplexedConnection,
+    scope: &str,
+    id: &str,
+    window: i64,
+) -> Result<i64, redis::RedisError> {
+    let key = failures_key(scope, id);
+    let failures: i64 = redis_client.incr(&key, 1).await?;
+    if failures == 1 {
+        redis_client.expire::<_, ()>(&key, window).await?;
+    }
+    Ok(failures)
+}
+
+/// Counts a failed attempt. Every failure after the first doubles the pause
+/// before the next attempt; reaching the limit locks the account (or IP)
+/// for `LOGIN_LOCKOUT_DURATION`.
+pub async fn register_login_failure(
+    data: &Arc<AppState>,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let account = account_id(email);
+    let window = data.env.login_failure_window;
+    let lockout = data.env.login_lockout_duration as u64;
+    let mut redis_client = redis_connection(data).await?;
+
+    let failures = count_failure(&mut redis_client, "account", &account, window)
+        .await
+        .map_err(redis_error)?;
+    if failures >= data.env.login_max_failed_attempts {
+        redis::pipe()
+            .set_ex(lock_key("account", &account), 1, lockout)
+            .ignore()
+            .del(&[failures_key("account", &account), delay_key(&account)])
+            .ignore()
+            .query_async::<()>(&mut redis_client)
+            .await
+            .map_err(redis_error)?;
+    } else if failures >= 2 {
+        let delay = 2_u64
+            .pow((failures - 2).min(10) as u32)
+            .min(MAX_DELAY_SECONDS);
+        redis_client
+            .set_ex::<_, _, ()>(delay_key(&account), 1, delay)
+            .await
+            .map_err(redis_error)?;
+    }
+
+    if let Some(ip) = ip {
+        let failures = count_failure(&mut redis_client, "ip", ip, window)
+            .await
+            .map_err(redis_error)?;
+        if failures >= data.env.login_max_failed_attempts_per_ip {
+            redis::pipe()
+                .set_ex(lock_key("ip", ip), 1, lockout)
+                .ignore()
+                .del(failures_key("ip", ip))
+                .ignore()
+                .query_async::<()>(&mut redis_client)
+                .await
+                .map_err(redis_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Resets the account counters after a successful login. The IP counter is
+/// kept, so one valid account can't be used to reset it.
+pub async fn clear_login_failures(
+    data: &Arc<AppState>,
+    email: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let account = account_id(email);
+    let mut redis_client = redis_connection(data).await?;
+    redis_client
+        .del::<_, ()>(&[failures_key("account", &account), delay_key(&account)])
+        .await
+        .map_err(redis_error)
+}
+
+pub async fn unlock_login(
+    data: &Arc<AppState>,
+    email: Option<&str>,
+    ip: Option<&str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let mut keys = Vec::new();
+    if let Some(email) = email {
+        let account = account_id(email);
+        keys.push(lock_key("account", &account));
+        keys.push(failures_key("account", &account));
+        keys.push(delay_key(&account));
+    }
+    if let Some(ip) = ip {
+        keys.push(lock_key("ip", ip));
+        keys.push(failures_key("ip", ip));
+    }
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let mut redis_client = redis_connection(data).await?;
+    redis_client.del::<_, ()>(keys).await.map_err(redis_error)
+}
@@ -1,6 +1,23 @@
+pub mod account_handler;
+pub mod admin_handler;
+pub mod api_key_handler;
+mod avatar;
+pub mod data_export;
 pub mod handler;
+mod login_throttle;
 pub mod model;
+mod oidc;
+pub mod oidc_handler;
+mod passkey;
+pub mod passkey_handler;
+mod password;
+mod password_policy;
+pub mod password_handler;
+pub mod profile_handler;
 pub mod response;
 pub mod route;
 pub mod schema;
+pub mod session;
 pub mod token;
+pub mod two_factor_handler;
+pub mod verification_handler;
@@ -33,8 +33,39 @@ pub struct User {
     pub file: String,
     pub verified: bool,
     pub role: UserRole,
+    pub locale: String,
     pub balance: Decimal,
     pub rating: Decimal,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
+
+/// What a personal API key may do: `read` allows safe (GET/HEAD) requests,
+/// `write` allows the rest.
+#[derive(
+    Debug, Clone, Copy, Display, Serialize, Deserialize, sqlx::Type, PartialEq, utoipa::ToSchema,
+)]
+#[sqlx(type_name = "api_key_scope", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ApiKeyScope {
+    Read,
+    Write,
+}
+
+/// Admin actions recorded in `admin_audit_log`.
+#[derive(Debug, Clone, Copy, Display, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditAction {
+    ChangeRole,
+    Suspend,
+    LiftSuspension,
+    Verify,
+    Delete,
+    UnlockLogin,
+    StartImpersonation,
+    StopImpersonation,
+    ImpersonatedRequest,
+    GrantBookAccess,
+    RevokeBookAccess,
+}
@@ -0,0 +1,192 @@
+use crate::settings::OidcProvider;
+use base64::Engine;
+use base64::engine::general_purpose;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Separates these signatures from anything else signed with the same key.
+const STATE_COOKIE_PURPOSE: &str = "oidc-state";
+
+/// Subset of the OpenID Provider metadata used by the authorization code flow.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default, deserialize_with = "bool_or_string")]
+    pub email_verified: bool,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub nonce: Option<String>,
+}
+
+// Some providers send `email_verified` as the string "true".
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Bool(value) => value,
+        serde_json::Value::String(value) => value == "true",
+        _ => false,
+    })
+}
+
+fn state_mac(secret: &str, state: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}", STATE_COOKIE_PURPOSE, state).as_bytes());
+    mac
+}
+
+/// Cookie value binding `state` to the browser that started the login.
+pub fn sign_state(secret: &str, state: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(state_mac(secret, state).finalize().into_bytes())
+}
+
+/// Whether the cookie was set for this `state`, compared in constant time.
+pub fn verify_state(secret: &str, state: &str, cookie: &str) -> bool {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(cookie)
+        .is_ok_and(|signature| state_mac(secret, state).verify_slice(&signature).is_ok())
+}
+
+/// S256 code challenge for the PKCE `code_verifier`.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+pub async fn discover(
+    client: &reqwest::Client,
+    provider: &OidcProvider,
+) -> Result<ProviderMetadata, String> {
+    let metadata: ProviderMetadata = client
+        .get(format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer
+        ))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Discovery failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid provider metadata: {}", e))?;
+
+    if metadata.issuer.trim_end_matches('/') != provider.issuer {
+        return Err(format!("Unexpected issuer {}", metadata.issuer));
+    }
+    Ok(metadata)
+}
+
+pub fn authorization_url(
+    metadata: &ProviderMetadata,
+    provider: &OidcProvider,
+    redirect_uri: &str,
+    state: &str,
+    nonce: &str,
+    code_challenge: &str,
+) -> Result<String, String> {
+    let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+        .map_err(|e| format!("Invalid authorization endpoint: {}", e))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("scope", &provider.scopes)
+        .append_pair("state", state)
+        .append_pair("nonce", nonce)
+        .append_pair("code_challenge", code_challenge)
+        .append_pair("code_challenge_method", "S256");
+    Ok(url.to_string())
+}
+
+/// Exchanges the authorization code for tokens and returns the raw ID token.
+pub async fn exchange_code(
+    client: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    provider: &OidcProvider,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+) -> Result<String, String> {
+    let mut request = client.post(&metadata.token_endpoint).form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri),
+        ("client_id", &provider.client_id),
+        ("code_verifier", code_verifier),
+    ]);
+    if let Some(client_secret) = &provider.client_secret {
+        request = request.basic_auth(&provider.client_id, Some(client_secret));
+    }
+
+    let token_response: TokenResponse = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Token exchange failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid token response: {}", e))?;
+    Ok(token_response.id_token)
+}
+
+/// Verifies the ID token signature against the provider's JWKS, then its
+/// issuer, audience, expiry and nonce.
+pub async fn verify_id_token(
+    client: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    provider: &OidcProvider,
+    id_token: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, String> {
+    let header = decode_header(id_token).map_err(|e| format!("Invalid ID token: {}", e))?;
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err("Symmetric ID token algorithms are not supported".to_string());
+    }
+
+    let jwks: JwkSet = client
+        .get(&metadata.jwks_uri)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("JWKS request failed: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid JWKS: {}", e))?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or("Signing key not found in JWKS")?;
+    let decoding_key =
+        DecodingKey::from_jwk(jwk).map_err(|e| format!("Invalid signing key: {}", e))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_audience(&[&provider.client_id]);
+    let claims = decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+        .map_err(|e| format!("ID token validation failed: {}", e))?
+        .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err("Nonce mismatch".to_string());
+    }
+    Ok(claims)
+}
@@ -0,0 +1,466 @@
+use crate::AppState;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::settings::OidcProvider;
+use crate::users::handler::issue_user_tokens;
+use crate::users::model::{User, UserRole};
+use crate::users::oidc::{
+    IdTokenClaims, ProviderMetadata, authorization_url, discover, exchange_code, pkce_challenge,
+    sign_state, verify_id_token, verify_state,
+};
+use crate::users::password::hash_password;
+use crate::users::schema::OidcCallbackQuery;
+use crate::users::session::{redis_connection, redis_error};
+use crate::users::token::{generate_random_token, hash_token};
+use crate::users::two_factor_handler::{start_two_factor_challenge, two_factor_status};
+use crate::users::verification_handler::send_verification_email;
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Redirect;
+use axum_extra::extract::CookieJar;
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const STATE_MAX_AGE: u64 = 600;
+
+/// Provider metadata rarely changes, it is fetched again after an hour.
+const METADATA_MAX_AGE: u64 = 3600;
+
+const STATE_COOKIE: &str = "oidc_state";
+
+/// What the callback needs to finish the flow started by the authorize step.
+#[derive(Debug, Serialize, Deserialize)]
+struct OidcLoginState {
+    provider: String,
+    nonce: String,
+    code_verifier: String,
+}
+
+fn state_key(state: &str) -> String {
+    format!("oidc-state-{}", hash_token(state))
+}
+
+fn metadata_key(provider: &OidcProvider) -> String {
+    format!("oidc-metadata-{}", provider.name)
+}
+
+fn redirect_uri(data: &Arc<AppState>, provider: &OidcProvider) -> String {
+    format!(
+        "{}/api/v1/user/oidc/{}/callback/",
+        data.env.app_url, provider.name
+    )
+}
+
+fn database_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Database error: {}", e),
+        message: "Request failed".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn provider_error(e: String) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: e,
+        message: "Identity provider error".to_string(),
+    };
+    (StatusCode::BAD_GATEWAY, Json(error_response))
+}
+
+fn find_provider<'a>(
+    data: &'a Arc<AppState>,
+    name: &str,
+) -> Result<&'a OidcProvider, (StatusCode, Json<ErrorResponse>)> {
+    data.env
+        .oidc_providers
+        .iter()
+        .find(|provider| provider.name == name)
+        .ok_or_else(|| {
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "OIDC provider not found".to_string(),
+            };
+            (StatusCode::NOT_FOUND, Json(error_response))
+        })
+}
+
+/// Discovery is needed by both steps of a login, so the document is kept
+/// in Redis for `METADATA_MAX_AGE`.
+async fn provider_metadata(
+    data: &Arc<AppState>,
+    provider: &OidcProvider,
+) -> Result<ProviderMetadata, (StatusCode, Json<ErrorResponse>)> {
+    let mut redis_client = redis_connection(data).await?;
+    let cached: Option<String> = redis_client
+        .get(metadata_key(provider))
+        .await
+        .map_err(redis_error)?;
+    if let Some(metadata) = cached.and_then(|value| serde_json::from_str(&value).ok()) {
+        return Ok(metadata);
+    }
+
+    let metadata = discover(&data.http, provider)
+        .await
+        .map_err(provider_error)?;
+    redis_client
+        .set_ex::<_, _, ()>(
+            metadata_key(provider),
+            serde_json::to_string(&metadata).unwrap(),
+            METADATA_MAX_AGE,
+        )
+        .await
+        .map_err(redis_error)?;
+    Ok(metadata)
+}
+
+async fn find_user_by_identity(
+    data: &Arc<AppState>,
+    provider: &str,
+    subject: &str,
+) -> Result<Option<User>, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+        u.id,
+        u.first_name,
+        u.last_name,
+        u.middle_name,
+        u.age,
+        u.email,
+        u.password,
+        u.biography,
+        u.file,
+        u.verified,
+        u.role as "role: UserRole",
+        u.locale,
+        u.balance,
+        u.rating,
+        u.created_at,
+        u.updated_at
+        FROM users u
+        JOIN user_identities i ON i.user_id = u.id
+        WHERE i.provider = $1 AND i.subject = $2
+        "#,
+        provider,
+        subject
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)
+}
+
+/// Resolves the local account for an external identity: an already linked
+/// one, an existing account with the same verified email, or a new account.
+async fn find_or_create_user(
+    data: &Arc<AppState>,
+    provider: &OidcProvider,
+    claims: &IdTokenClaims,
+) -> Result<User, (StatusCode, Json<ErrorResponse>)> {
+    if let Some(user) = find_user_by_identity(data, &provider.name, &claims.sub).await? {
+        return Ok(user);
+    }
+
+    let email = claims.email.clone().ok_or_else(|| {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Identity provider did not return an email".to_string(),
+        };
+        (StatusCode::BAD_REQUEST, Json(error_response))
+    })?;
+
+    let existing_user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+        id,
+        first_name,
+        last_name,
+        middle_name,
+        age,
+        email,
+        password,
+        biography,
+        file,
+        verified,
+        role as "role: UserRole",
+        locale,
+        balance,
+        rating,
+        created_at,
+        updated_at
+        FROM users
+        WHERE email = $1
+        "#,
+        email
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let user = match existing_user {
+        // Linking by an unverified email would let anyone who controls the
+        // provider account take over the local one.
+        Some(_) if !claims.email_verified => {
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "An account with this email already exists".to_string(),
+            };
+            return Err((StatusCode::CONFLICT, Json(error_response)));
+        }
+        Some(user) => user,
+        None => {
+            // The account has no usable password until the user resets it.
+            let password = hash_password(&data.env, &generate_random_token())?;
+            let first_name = claims
+                .given_name
+                .clone()
+                .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+            let user = sqlx::query_as!(
+                User,
+                r#"
+                INSERT INTO users (first_name, last_name, age, email, password, verified)
+                VALUES ($1, $2, 0, $3, $4, $5)
+                RETURNING id, first_name, last_name, middle_name, age, email, password, biography, file, verified, role as "role: UserRole", locale, balance, rating, created_at, updated_at
+                "#,
+                first_name.chars().take(100).collect::<String>(),
+                claims
+                    .family_name
+                    .clone()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(100)
+                    .collect::<String>(),
+                email,
+                password,
+                claims.email_verified
+            )
+            .fetch_one(&data.db)
+            .await
+            .map_err(database_error)?;
+
+            if !user.verified {
+                send_verification_email(data, &user).await;
+            }
+            user
+        }
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_identities (user_id, provider, subject, email)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (provider, subject) DO NOTHING
+        "#,
+        user.id,
+        provider.name,
+        claims.sub,
+        email
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    Ok(user)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/oidc/providers/",
+    responses(
+        (status = 200, description = "Настроенные OIDC провайдеры", body = [String])
+    ),
+    tag = "Users"
+)]
+pub async fn get_oidc_providers_handler(
+    State(data): State<Arc<AppState>>,
+) -> APIResult<Vec<String>> {
+    let providers = data
+        .env
+        .oidc_providers
+        .iter()
+        .map(|provider| provider.name.clone())
+        .collect();
+
+    let response = SuccessResponse {
+        data: providers,
+        message: "success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/oidc/{provider}/authorize/",
+    params(
+        ("provider" = String, Path, description = "Имя OIDC провайдера")
+    ),
+    responses(
+        (status = 303, description = "Перенаправление на страницу входа провайдера, state привязывается к браузеру cookie oidc_state"),
+        (status = 404, description = "Провайдер не найден", body = ErrorResponse),
+        (status = 502, description = "Ошибка провайдера", body = ErrorResponse)
+    ),
+    tag = "Users"
+)]
+pub async fn oidc_authorize_handler(
+    State(data): State<Arc<AppState>>,
+    Path(provider): Path<String>,
+    cookie_jar: CookieJar,
+) -> Result<(CookieJar, Redirect), (StatusCode, Json<ErrorResponse>)> {
+    let provider = find_provider(&data, &provider)?;
+    let metadata = provider_metadata(&data, provider).await?;
+
+    let state = generate_random_token();
+    let login_state = OidcLoginState {
+        provider: provider.name.clone(),
+        nonce: generate_random_token(),
+        code_verifier: generate_random_token(),
+    };
+    let url = authorization_url(
+        &metadata,
+        provider,
+        &redirect_uri(&data, provider),
+        &state,
+        &login_state.nonce,
+        &pkce_challenge(&login_state.code_verifier),
+    )
+    .map_err(provider_error)?;
+
+    let mut redis_client = redis_connection(&data).await?;
+    redis_client
+        .set_ex::<_, _, ()>(
+            state_key(&state),
+            serde_json::to_string(&login_state).unwrap(),
+            STATE_MAX_AGE,
+        )
+        .await
+        .map_err(redis_error)?;
+
+    // Lax, the provider sends the browser back with a top-level redirect.
+    let state_cookie = Cookie::build((STATE_COOKIE, sign_state(&data.env.secret_key, &state)))
+        .path("/api/v1/user/oidc/")
+        .max_age(time::Duration::seconds(STATE_MAX_AGE as i64))
+        .same_site(SameSite::Lax)
+        .http_only(true);
+
+    Ok((cookie_jar.add(state_cookie), Redirect::to(&url)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/oidc/{provider}/callback/",
+    params(
+        ("provider" = String, Path, description = "Имя OIDC провайдера"),
+        OidcCallbackQuery
+    ),
+    responses(
+        (status = 200, description = "Успешно авторизирован", body = String),
+        (status = 400, description = "Недействительный state, state начат в другом браузере или отказ провайдера", body = ErrorResponse),
+        (status = 404, description = "Провайдер не найден", body = ErrorResponse),
+        (status = 401, description = "ID токен провайдера не прошел проверку", body = ErrorResponse),
+        (status = 409, description = "Аккаунт с таким email уже существует", body = ErrorResponse),
+        (status = 502, description = "Ошибка провайдера", body = ErrorResponse)
+    ),
+    tag = "Users"
+)]
+pub async fn oidc_callback_handler(
+    State(data): State<Arc<AppState>>,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcCallbackQuery>,
+    cookie_jar: CookieJar,
+) -> APIResult<serde_json::Value> {
+    let provider = find_provider(&data, &provider)?;
+    let invalid_state = || {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "OIDC state is invalid or has expired".to_string(),
+        };
+        (StatusCode::BAD_REQUEST, Json(error_response))
+    };
+
+    // Without the cookie, a victim could be logged into the account of
+    // whoever started the login and sent them the callback link.
+    let bound = cookie_jar
+        .get(STATE_COOKIE)
+        .is_some_and(|cookie| verify_state(&data.env.secret_key, &query.state, cookie.value()));
+    if !bound {
+        return Err(invalid_state());
+    }
+
+    // The state is single-use whatever the outcome of the callback.
+    let mut redis_client = redis_connection(&data).await?;
+    let login_state: Option<String> = redis::cmd("GETDEL")
+        .arg(state_key(&query.state))
+        .query_async(&mut redis_client)
+        .await
+        .map_err(redis_error)?;
+    let login_state = login_state
+        .and_then(|value| serde_json::from_str::<OidcLoginState>(&value).ok())
+        .filter(|login_state| login_state.provider == provider.name)
+        .ok_or_else(invalid_state)?;
+
+    let code = match (query.code, query.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            let error_response = ErrorResponse {
+                error: format!(
+                    "{}: {}",
+                    error.unwrap_or_default(),
+                    query.error_description.unwrap_or_default()
+                ),
+                message: "Login was rejected by the identity provider".to_string(),
+            };
+            return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+        }
+    };
+
+    let metadata = provider_metadata(&data, provider).await?;
+    let id_token = exchange_code(
+        &data.http,
+        &metadata,
+        provider,
+        &code,
+        &redirect_uri(&data, provider),
+        &login_state.code_verifier,
+    )
+    .await
+    .map_err(provider_error)?;
+    let claims = verify_id_token(
+        &data.http,
+        &metadata,
+        provider,
+        &id_token,
+        &login_state.nonce,
+    )
+    .await
+    .map_err(|e| {
+        let error_response = ErrorResponse {
+            error: e,
+            message: "Invalid ID token".to_string(),
+        };
+        (StatusCode::UNAUTHORIZED, Json(error_response))
+    })?;
+
+    let user = find_or_create_user(&data, provider, &claims).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE user_identities SET last_login_at = NOW()
+        WHERE provider = $1 AND subject = $2
+        "#,
+        provider.name,
+        claims.sub
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let two_factor = two_factor_status(&data, &user).await?;
+    if two_factor.enabled {
+        return start_two_factor_challenge(&data, user.id).await;
+    }
+
+    issue_user_tokens(&data, &user, two_factor.required).await
+}
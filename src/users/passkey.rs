@@ -0,0 +1,223 @@
+use base64::Engine;
+use base64::engine::general_purpose;
+use ciborium::Value;
+use hmac::{Hmac, Mac};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// COSE identifier of ECDSA with P-256 and SHA-256, the only algorithm we accept.
+pub const COSE_ALG_ES256: i64 = -7;
+
+/// Separates these ids from anything else signed with the same key.
+const DUMMY_CREDENTIAL_PURPOSE: &str = "passkey-dummy-credential";
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+#[derive(Debug, Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub challenge: String,
+    pub origin: String,
+}
+
+#[derive(Debug)]
+pub struct AttestedCredential {
+    pub credential_id: Vec<u8>,
+    /// Uncompressed SEC1 point of the credential's P-256 public key.
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: Vec<u8>,
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    pub fn user_present(&self) -> bool {
+        self.flags & FLAG_USER_PRESENT != 0
+    }
+}
+
+pub fn decode_base64url(value: &str) -> Result<Vec<u8>, String> {
+    general_purpose::URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|e| format!("Invalid base64url: {}", e))
+}
+
+pub fn encode_base64url(value: &[u8]) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(value)
+}
+
+/// Credential id offered for an email without passkeys, so the login
+/// options look the same whether the email is registered or not. The same
+/// email always gets the same id.
+pub fn dummy_credential_id(secret: &str, email: &str) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}", DUMMY_CREDENTIAL_PURPOSE, email).as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Decodes `clientDataJSON` and checks the ceremony type, challenge and origin.
+pub fn verify_client_data(
+    raw: &[u8],
+    expected_type: &str,
+    expected_challenge: &str,
+    expected_origin: &str,
+) -> Result<ClientData, String> {
+    let client_data: ClientData =
+        serde_json::from_slice(raw).map_err(|e| format!("Invalid client data: {}", e))?;
+
+    if client_data.kind != expected_type {
+        return Err(format!("Unexpected ceremony type {}", client_data.kind));
+    }
+    if client_data.challenge != expected_challenge {
+        return Err("Challenge mismatch".to_string());
+    }
+    if client_data.origin != expected_origin {
+        return Err(format!("Unexpected origin {}", client_data.origin));
+    }
+    Ok(client_data)
+}
+
+/// Reads only the challenge, to find the ceremony state before the full check.
+pub fn client_data_challenge(raw: &[u8]) -> Result<String, String> {
+    serde_json::from_slice::<ClientData>(raw)
+        .map(|client_data| client_data.challenge)
+        .map_err(|e| format!("Invalid client data: {}", e))
+}
+
+/// Checks the RP ID hash and the user presence flag shared by both ceremonies.
+pub fn verify_authenticator_data(
+    authenticator_data: &AuthenticatorData,
+    rp_id: &str,
+) -> Result<(), String> {
+    if authenticator_data.rp_id_hash != Sha256::digest(rp_id.as_bytes()).as_slice() {
+        return Err("RP ID mismatch".to_string());
+    }
+    if !authenticator_data.user_present() {
+        return Err("User presence is required".to_string());
+    }
+    Ok(())
+}
+
+pub fn parse_authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData, String> {
+    if bytes.len() < 37 {
+        return Err("Authenticator data is too short".to_string());
+    }
+    let flags = bytes[32];
+    let sign_count = u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]);
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // AAGUID (16 bytes) followed by the big-endian credential ID length.
+        let rest = bytes
+            .get(37 + 16..)
+            .ok_or("Attested credential data is truncated")?;
+        if rest.len() < 2 {
+            return Err("Attested credential data is truncated".to_string());
+        }
+        let id_len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let credential_id = rest
+            .get(2..2 + id_len)
+            .ok_or("Credential ID is truncated")?
+            .to_vec();
+        let mut cose_key = &rest[2 + id_len..];
+        let cose_key: Value = ciborium::from_reader(&mut cose_key)
+            .map_err(|e| format!("Invalid credential public key: {}", e))?;
+
+        Some(AttestedCredential {
+            credential_id,
+            public_key: cose_key_to_sec1(&cose_key)?,
+        })
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: bytes[..32].to_vec(),
+        flags,
+        sign_count,
+        attested_credential,
+    })
+}
+
+/// Extracts the authenticator data from an attestation object. The
+/// attestation statement itself is not verified: registration asks for
+/// `"attestation": "none"`, so only the credential key matters.
+pub fn parse_attestation_object(bytes: &[u8]) -> Result<AuthenticatorData, String> {
+    let attestation: Value =
+        ciborium::from_reader(bytes).map_err(|e| format!("Invalid attestation object: {}", e))?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|entries| {
+            entries
+                .iter()
+                .find(|(key, _)| key.as_text() == Some("authData"))
+        })
+        .and_then(|(_, value)| value.as_bytes())
+        .ok_or("Attestation object has no authData")?;
+
+    let authenticator_data = parse_authenticator_data(auth_data)?;
+    if authenticator_data.attested_credential.is_none() {
+        return Err("Attestation object has no credential".to_string());
+    }
+    Ok(authenticator_data)
+}
+
+fn cose_key_to_sec1(cose_key: &Value) -> Result<Vec<u8>, String> {
+    let entries = cose_key
+        .as_map()
+        .ok_or("Credential public key is not a map")?;
+    let field = |label: i64| {
+        entries.iter().find_map(|(key, value)| {
+            let key: i128 = key.as_integer()?.into();
+            (key == label as i128).then_some(value)
+        })
+    };
+    let integer = |label: i64| -> Option<i128> { field(label)?.as_integer().map(Into::into) };
+
+    // kty = EC2, alg = ES256, crv = P-256
+    if integer(1) != Some(2) || integer(3) != Some(COSE_ALG_ES256 as i128) || integer(-1) != Some(1)
+    {
+        return Err("Only ES256 credentials are supported".to_string());
+    }
+    let x = field(-2)
+        .and_then(Value::as_bytes)
+        .ok_or("Missing x coordinate")?;
+    let y = field(-3)
+        .and_then(Value::as_bytes)
+        .ok_or("Missing y coordinate")?;
+
+    let mut point = Vec::with_capacity(65);
+    point.push(0x04);
+    point.extend_from_slice(x);
+    point.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&point).map_err(|_| "Invalid P-256 public key".to_string())?;
+    Ok(point)
+}
+
+/// Verifies an assertion signature over `authenticatorData || SHA-256(clientDataJSON)`.
+pub fn verify_assertion_signature(
+    public_key: &[u8],
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+) -> Result<(), String> {
+    let verifying_key = VerifyingKey::from_sec1_bytes(public_key)
+        .map_err(|_| "Invalid stored public key".to_string())?;
+    let signature =
+        Signature::from_der(signature).map_err(|_| "Invalid signature encoding".to_string())?;
+
+    let mut signed_data = authenticator_data.to_vec();
+    signed_data.extend_from_slice(&Sha256::digest(client_data_json));
+    verifying_key
+        .verify(&signed_data, &signature)
+        .map_err(|_| "Signature verification failed".to_string())
+}
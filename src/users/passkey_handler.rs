@@ -0,0 +1,488 @@
+use crate::AppState;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::users::handler::issue_user_tokens;
+use crate::users::model::{User, UserRole};
+use crate::users::passkey::{
+    COSE_ALG_ES256, client_data_challenge, decode_base64url, dummy_credential_id, encode_base64url,
+    parse_attestation_object, parse_authenticator_data, verify_assertion_signature,
+    verify_authenticator_data, verify_client_data,
+};
+use crate::users::response::PasskeyResponse;
+use crate::users::schema::{PasskeyLoginSchema, PasskeyLoginStartSchema, PasskeyRegisterSchema};
+use crate::users::session::{redis_connection, redis_error};
+use crate::users::token::{generate_random_token, hash_token};
+use crate::users::two_factor_handler::two_factor_status;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use redis::AsyncCommands;
+use serde_json::json;
+use std::sync::Arc;
+use validator::Validate;
+
+const CHALLENGE_MAX_AGE: u64 = 300;
+
+fn registration_key(user_id: uuid::Uuid) -> String {
+    format!("passkey-registration-{}", user_id)
+}
+
+fn authentication_key(challenge: &str) -> String {
+    format!("passkey-authentication-{}", hash_token(challenge))
+}
+
+fn database_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Database error: {}", e),
+        message: "Request failed".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn invalid_input() -> (StatusCode, Json<ErrorResponse>) {
+    let error = ErrorResponse {
+        error: "".to_string(),
+        message: "Invalid input data".to_string(),
+    };
+    (StatusCode::BAD_REQUEST, Json(error))
+}
+
+fn invalid_credential(e: String) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: e,
+        message: "Invalid passkey credential".to_string(),
+    };
+    (StatusCode::BAD_REQUEST, Json(error_response))
+}
+
+fn authentication_failed() -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: "".to_string(),
+        message: "Passkey authentication failed".to_string(),
+    };
+    (StatusCode::UNAUTHORIZED, Json(error_response))
+}
+
+async fn user_credential_ids(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<Vec<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let credential_ids = sqlx::query_scalar!(
+        r#"SELECT credential_id FROM user_passkeys WHERE user_id = $1 ORDER BY created_at"#,
+        user_id
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    Ok(credential_ids
+        .iter()
+        .map(|id| json!({"type": "public-key", "id": encode_base64url(id)}))
+        .collect())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/passkeys/register/start/",
+    responses(
+        (status = 200, description = "Параметры PublicKeyCredentialCreationOptions для navigator.credentials.create()", body = String),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Passkeys"
+)]
+pub async fn start_passkey_registration_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<serde_json::Value> {
+    let user = &auth_guard.user;
+    let challenge = generate_random_token();
+    let exclude_credentials = user_credential_ids(&data, user.id).await?;
+
+    let mut redis_client = redis_connection(&data).await?;
+    redis_client
+        .set_ex::<_, _, ()>(registration_key(user.id), &challenge, CHALLENGE_MAX_AGE)
+        .await
+        .map_err(redis_error)?;
+
+    let response = SuccessResponse {
+        data: json!({
+            "challenge": challenge,
+            "rp": {"id": data.env.webauthn_rp_id, "name": data.env.webauthn_rp_name},
+            "user": {
+                "id": encode_base64url(user.id.as_bytes()),
+                "name": user.email,
+                "displayName": format!("{} {}", user.first_name, user.last_name)
+            },
+            "pubKeyCredParams": [{"type": "public-key", "alg": COSE_ALG_ES256}],
+            "timeout": CHALLENGE_MAX_AGE * 1000,
+            "attestation": "none",
+            "excludeCredentials": exclude_credentials,
+            "authenticatorSelection": {
+                "residentKey": "preferred",
+                "userVerification": "preferred"
+            }
+        }),
+        message: "Passkey registration started".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/passkeys/register/finish/",
+    request_body = PasskeyRegisterSchema,
+    responses(
+        (status = 201, description = "Ключ доступа зарегистрирован", body = PasskeyResponse),
+        (status = 400, description = "Ошибка валидации данных или неверные данные ключа", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 409, description = "Ключ доступа уже зарегистрирован", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Passkeys"
+)]
+pub async fn finish_passkey_registration_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<PasskeyRegisterSchema>,
+) -> APIResult<PasskeyResponse> {
+    if body.validate().is_err() {
+        return Err(invalid_input());
+    }
+
+    let mut redis_client = redis_connection(&data).await?;
+    let challenge: Option<String> = redis::cmd("GETDEL")
+        .arg(registration_key(auth_guard.user.id))
+        .query_async(&mut redis_client)
+        .await
+        .map_err(redis_error)?;
+    let challenge = challenge
+        .ok_or_else(|| invalid_credential("Registration has not been started".to_string()))?;
+
+    let response = &body.credential.response;
+    let client_data_json =
+        decode_base64url(&response.client_data_json).map_err(invalid_credential)?;
+    verify_client_data(
+        &client_data_json,
+        "webauthn.create",
+        &challenge,
+        &data.env.webauthn_origin,
+    )
+    .map_err(invalid_credential)?;
+
+    let attestation_object =
+        decode_base64url(&response.attestation_object).map_err(invalid_credential)?;
+    let authenticator_data =
+        parse_attestation_object(&attestation_object).map_err(invalid_credential)?;
+    verify_authenticator_data(&authenticator_data, &data.env.webauthn_rp_id)
+        .map_err(invalid_credential)?;
+    let credential = authenticator_data
+        .attested_credential
+        .ok_or_else(|| invalid_credential("Attestation object has no credential".to_string()))?;
+    if decode_base64url(&body.credential.raw_id).map_err(invalid_credential)?
+        != credential.credential_id
+    {
+        return Err(invalid_credential("Credential ID mismatch".to_string()));
+    }
+
+    let passkey = sqlx::query_as!(
+        PasskeyResponse,
+        r#"
+        INSERT INTO user_passkeys (user_id, credential_id, public_key, sign_count, name)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, name, last_used_at, created_at
+        "#,
+        auth_guard.user.id,
+        credential.credential_id,
+        credential.public_key,
+        authenticator_data.sign_count as i64,
+        body.name
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(|e| {
+        if e.to_string()
+            .contains("duplicate key value violates unique constraint")
+        {
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "Passkey is already registered".to_string(),
+            };
+            return (StatusCode::CONFLICT, Json(error_response));
+        }
+        database_error(e)
+    })?;
+
+    let response = SuccessResponse {
+        data: passkey,
+        message: "Passkey registered".to_string(),
+    };
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/passkeys/",
+    responses(
+        (status = 200, description = "Ключи доступа пользователя", body = [PasskeyResponse]),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Passkeys"
+)]
+pub async fn get_passkeys_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<Vec<PasskeyResponse>> {
+    let passkeys = sqlx::query_as!(
+        PasskeyResponse,
+        r#"
+        SELECT id, name, last_used_at, created_at
+        FROM user_passkeys
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        auth_guard.user.id
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: passkeys,
+        message: "success".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/passkeys/{id}/",
+    params(
+        ("id" = uuid::Uuid, Path, description = "ID ключа доступа")
+    ),
+    responses(
+        (status = 200, description = "Ключ доступа удален", body = String),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 404, description = "Ключ доступа не найден", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Passkeys"
+)]
+pub async fn delete_passkey_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Path(id): Path<uuid::Uuid>,
+) -> APIResult<String> {
+    let query_result = sqlx::query!(
+        r#"DELETE FROM user_passkeys WHERE id = $1 AND user_id = $2"#,
+        id,
+        auth_guard.user.id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    if query_result.rows_affected() == 0 {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Passkey not found".to_string(),
+        };
+        return Err((StatusCode::NOT_FOUND, Json(error_response)));
+    }
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Passkey deleted".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/login/passkey/start/",
+    request_body = PasskeyLoginStartSchema,
+    responses(
+        (status = 200, description = "Параметры PublicKeyCredentialRequestOptions для navigator.credentials.get()", body = String),
+        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    tag = "Passkeys"
+)]
+pub async fn start_passkey_login_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<PasskeyLoginStartSchema>,
+) -> APIResult<serde_json::Value> {
+    if body.validate().is_err() {
+        return Err(invalid_input());
+    }
+
+    let mut allow_credentials = Vec::new();
+    if let Some(email) = &body.email {
+        let user_id = sqlx::query_scalar!(r#"SELECT id FROM users WHERE email = $1"#, email)
+            .fetch_optional(&data.db)
+            .await
+            .map_err(database_error)?;
+        if let Some(user_id) = user_id {
+            allow_credentials = user_credential_ids(&data, user_id).await?;
+        }
+        // An empty list would tell that the email has no passkeys or no
+        // account at all, a made-up credential hides it.
+        if allow_credentials.is_empty() {
+            let id = dummy_credential_id(&data.env.secret_key, email);
+            allow_credentials.push(json!({"type": "public-key", "id": encode_base64url(&id)}));
+        }
+    }
+
+    let challenge = generate_random_token();
+    let mut redis_client = redis_connection(&data).await?;
+    redis_client
+        .set_ex::<_, _, ()>(authentication_key(&challenge), 1, CHALLENGE_MAX_AGE)
+        .await
+        .map_err(redis_error)?;
+
+    let response = SuccessResponse {
+        data: json!({
+            "challenge": challenge,
+            "rpId": data.env.webauthn_rp_id,
+            "timeout": CHALLENGE_MAX_AGE * 1000,
+            "allowCredentials": allow_credentials,
+            "userVerification": "preferred"
+        }),
+        message: "Passkey authentication started".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/login/passkey/finish/",
+    request_body = PasskeyLoginSchema,
+    responses(
+        (status = 200, description = "Успешно авторизирован", body = String),
+        (status = 400, description = "Неверные данные ключа", body = ErrorResponse),
+        (status = 401, description = "Ключ доступа не принят", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    tag = "Passkeys"
+)]
+pub async fn finish_passkey_login_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<PasskeyLoginSchema>,
+) -> APIResult<serde_json::Value> {
+    let response = &body.response;
+    let client_data_json =
+        decode_base64url(&response.client_data_json).map_err(invalid_credential)?;
+    let challenge = client_data_challenge(&client_data_json).map_err(invalid_credential)?;
+
+    // GETDEL makes every challenge single-use, even when verification fails.
+    let mut redis_client = redis_connection(&data).await?;
+    let challenge_exists: Option<String> = redis::cmd("GETDEL")
+        .arg(authentication_key(&challenge))
+        .query_async(&mut redis_client)
+        .await
+        .map_err(redis_error)?;
+    if challenge_exists.is_none() {
+        return Err(authentication_failed());
+    }
+
+    verify_client_data(
+        &client_data_json,
+        "webauthn.get",
+        &challenge,
+        &data.env.webauthn_origin,
+    )
+    .map_err(invalid_credential)?;
+
+    let credential_id = decode_base64url(&body.raw_id).map_err(invalid_credential)?;
+    let passkey = sqlx::query!(
+        r#"SELECT id, user_id, public_key, sign_count FROM user_passkeys WHERE credential_id = $1"#,
+        credential_id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(authentication_failed)?;
+
+    if let Some(user_handle) = response.user_handle.as_deref().filter(|h| !h.is_empty()) {
+        let user_handle = decode_base64url(user_handle).map_err(invalid_credential)?;
+        if user_handle != passkey.user_id.as_bytes() {
+            return Err(authentication_failed());
+        }
+    }
+
+    let authenticator_data_raw =
+        decode_base64url(&response.authenticator_data).map_err(invalid_credential)?;
+    let authenticator_data =
+        parse_authenticator_data(&authenticator_data_raw).map_err(invalid_credential)?;
+    verify_authenticator_data(&authenticator_data, &data.env.webauthn_rp_id)
+        .map_err(invalid_credential)?;
+
+    let signature = decode_base64url(&response.signature).map_err(invalid_credential)?;
+    verify_assertion_signature(
+        &passkey.public_key,
+        &authenticator_data_raw,
+        &client_data_json,
+        &signature,
+    )
+    .map_err(|_| authentication_failed())?;
+
+    // Authenticators without a counter always report zero; otherwise the
+    // counter must grow, or the credential may have been cloned.
+    let sign_count = authenticator_data.sign_count as i64;
+    if (sign_count != 0 || passkey.sign_count != 0) && sign_count <= passkey.sign_count {
+        return Err(authentication_failed());
+    }
+
+    sqlx::query!(
+        r#"UPDATE user_passkeys SET sign_count = $1, last_used_at = NOW() WHERE id = $2"#,
+        sign_count,
+        passkey.id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+        id,
+        first_name,
+        last_name,
+        middle_name,
+        age,
+        email,
+        password,
+        biography,
+        file,
+        verified,
+        role as "role: UserRole",
+        locale,
+        balance,
+        rating,
+        created_at,
+        updated_at
+        FROM users WHERE id = $1
+        "#,
+        passkey.user_id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let two_factor = two_factor_status(&data, &user).await?;
+    issue_user_tokens(&data, &user, two_factor.required && !two_factor.enabled).await
+}
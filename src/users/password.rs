@@ -0,0 +1,77 @@
+use crate::Settings;
+use crate::service::response_server::ErrorResponse;
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use axum::Json;
+use axum::http::StatusCode;
+use std::sync::OnceLock;
+
+/// Hash checked when the account doesn't exist, so unknown emails take as
+/// long to reject as wrong passwords. Created with the configured parameters
+/// on first use.
+static DUMMY_PASSWORD_HASH: OnceLock<String> = OnceLock::new();
+
+fn hashing_error(e: impl std::fmt::Display) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Error while hashing password: {}", e),
+        message: "Password hashing failed".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn argon2_params(settings: &Settings) -> Result<Params, argon2::Error> {
+    Params::new(
+        settings.argon2_memory_cost,
+        settings.argon2_time_cost,
+        settings.argon2_parallelism,
+        None,
+    )
+}
+
+pub fn hash_password(
+    settings: &Settings,
+    password: &str,
+) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    let salt = SaltString::generate(&mut OsRng);
+    let params = argon2_params(settings).map_err(hashing_error)?;
+
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(hashing_error)
+        .map(|hash| hash.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Runs a full password verification against a dummy hash and fails.
+pub fn verify_dummy_password(settings: &Settings, password: &str) -> bool {
+    let dummy_hash = DUMMY_PASSWORD_HASH
+        .get_or_init(|| hash_password(settings, "dummy-password").unwrap_or_default());
+    verify_password(password, dummy_hash);
+    false
+}
+
+/// Whether the stored hash was made with another algorithm or with
+/// parameters other than the configured ones.
+pub fn needs_rehash(settings: &Settings, password_hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+    let Ok(params) = Params::try_from(&hash) else {
+        return true;
+    };
+
+    hash.algorithm != Algorithm::Argon2id.ident()
+        || hash.version != Some(Version::V0x13.into())
+        || params.m_cost() != settings.argon2_memory_cost
+        || params.t_cost() != settings.argon2_time_cost
+        || params.p_cost() != settings.argon2_parallelism
+}
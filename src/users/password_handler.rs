@@ -0,0 +1,245 @@
+use crate::AppState;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::mail_template::MailTemplate;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::users::password::{hash_password, verify_password};
+use crate::users::password_policy::check_password;
+use crate::users::schema::{ChangePasswordSchema, ForgotPasswordSchema, ResetPasswordSchema};
+use crate::users::session::revoke_user_sessions;
+use crate::users::token::{generate_random_token, hash_token};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use redis::AsyncCommands;
+use std::sync::Arc;
+use tracing::error;
+use validator::Validate;
+
+fn password_reset_key(token: &str) -> String {
+    format!("password-reset-{}", hash_token(token))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/password/change/",
+    request_body = ChangePasswordSchema,
+    responses(
+        (status = 200, description = "Пароль изменён", body = String),
+        (status = 400, description = "Ошибка валидации данных, неверный пароль или новый пароль не соответствует политике", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn change_password_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<ChangePasswordSchema>,
+) -> APIResult<String> {
+    if body.validate().is_err() {
+        let error = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error)));
+    }
+
+    if !verify_password(&body.old_password, &auth_guard.user.password) {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid old password".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    check_password(&data.env, &body.new_password).await?;
+    update_password(&data, auth_guard.user.id, &body.new_password).await?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Password changed, please log in again".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/password/forgot/",
+    request_body = ForgotPasswordSchema,
+    responses(
+        (status = 200, description = "Письмо со ссылкой отправлено, если email зарегистрирован", body = String),
+        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    tag = "Users"
+)]
+pub async fn forgot_password_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<ForgotPasswordSchema>,
+) -> APIResult<String> {
+    if body.validate().is_err() {
+        let error = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error)));
+    }
+
+    let user: Option<(uuid::Uuid, String)> =
+        sqlx::query_as("SELECT id, locale FROM users WHERE email = $1")
+            .bind(body.email.to_owned())
+            .fetch_optional(&data.db)
+            .await
+            .map_err(|e| {
+                let error_response = ErrorResponse {
+                    error: format!("Database error: {}", e),
+                    message: "Request failed".to_string(),
+                };
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+            })?;
+
+    // The response is the same whether or not the email exists, so the
+    // endpoint can't be used to find out who is registered.
+    if let Some((user_id, locale)) = user {
+        let token = generate_random_token();
+        let mut redis_client = data
+            .redis
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| {
+                let error_response = ErrorResponse {
+                    error: format!("Redis error: {}", e),
+                    message: "Redis error".to_string(),
+                };
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+            })?;
+        redis_client
+            .set_ex::<_, _, ()>(
+                password_reset_key(&token),
+                user_id.to_string(),
+                (data.env.password_reset_token_max_age * 60) as u64,
+            )
+            .await
+            .map_err(|e| {
+                let error_response = ErrorResponse {
+                    error: format!("Redis error: {}", e),
+                    message: "Redis error".to_string(),
+                };
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+            })?;
+
+        let message = MailTemplate::PasswordReset {
+            link: format!("{}/reset-password?token={}", data.env.app_url, token),
+            valid_minutes: data.env.password_reset_token_max_age,
+        }
+        .render(&body.email, &locale);
+        if let Err(e) = data.mailer.send(message).await {
+            error!("Failed to send password reset email: {}", e);
+        }
+    }
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "If the email is registered, a reset link has been sent".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/password/reset/",
+    request_body = ResetPasswordSchema,
+    responses(
+        (status = 200, description = "Пароль изменён", body = String),
+        (status = 400, description = "Ошибка валидации данных, токен недействителен или пароль не соответствует политике", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    tag = "Users"
+)]
+pub async fn reset_password_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<ResetPasswordSchema>,
+) -> APIResult<String> {
+    if body.validate().is_err() {
+        let error = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error)));
+    }
+
+    // Checked before the token is consumed, so the user can retry with
+    // another password.
+    check_password(&data.env, &body.password).await?;
+
+    let mut redis_client = data
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Redis error: {}", e),
+                message: "Redis error".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })?;
+
+    // GETDEL makes the token single-use even with concurrent requests.
+    let user_id: Option<String> = redis_client
+        .get_del(password_reset_key(&body.token))
+        .await
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Redis error: {}", e),
+                message: "Redis error".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })?;
+
+    let user_id = user_id
+        .and_then(|id| uuid::Uuid::parse_str(&id).ok())
+        .ok_or_else(|| {
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "Reset token is invalid or has expired".to_string(),
+            };
+            (StatusCode::BAD_REQUEST, Json(error_response))
+        })?;
+
+    update_password(&data, user_id, &body.password).await?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Password has been reset".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// Stores the new password hash and logs the user out everywhere.
+async fn update_password(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+    password: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let hashed_password = hash_password(&data.env, password)?;
+
+    sqlx::query!(
+        r#"UPDATE users SET password = $1, updated_at = NOW() WHERE id = $2"#,
+        hashed_password,
+        user_id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(|e| {
+        let error_response = ErrorResponse {
+            error: format!("Database error: {}", e),
+            message: "Error when updating password".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    revoke_user_sessions(data, user_id).await
+}
@@ -0,0 +1,98 @@
+use crate::Settings;
+use crate::service::response_server::ErrorResponse;
+use axum::Json;
+use axum::http::StatusCode;
+use sha1::{Digest, Sha1};
+use std::path::Path;
+use tracing::error;
+
+/// Length of the SHA-1 prefix naming each file of the breached-password list.
+const BREACHED_PREFIX_LENGTH: usize = 5;
+
+/// Lists the policy rules the password breaks.
+pub fn policy_violations(settings: &Settings, password: &str) -> Vec<String> {
+    let mut violations = Vec::new();
+    let length = password.chars().count();
+
+    if length < settings.password_min_length {
+        violations.push(format!(
+            "must be at least {} characters long",
+            settings.password_min_length
+        ));
+    }
+    if length > settings.password_max_length {
+        violations.push(format!(
+            "must be at most {} characters long",
+            settings.password_max_length
+        ));
+    }
+    if settings.password_require_uppercase && !password.chars().any(char::is_uppercase) {
+        violations.push("must contain an uppercase letter".to_string());
+    }
+    if settings.password_require_lowercase && !password.chars().any(char::is_lowercase) {
+        violations.push("must contain a lowercase letter".to_string());
+    }
+    if settings.password_require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        violations.push("must contain a digit".to_string());
+    }
+    if settings.password_require_symbol && password.chars().all(char::is_alphanumeric) {
+        violations.push("must contain a symbol".to_string());
+    }
+    violations
+}
+
+/// Looks the password up in the local breached-password list.
+///
+/// The list uses the layout of the Have I Been Pwned range files: one file
+/// per upper-case SHA-1 prefix of five characters, with `SUFFIX:COUNT`
+/// lines. Only the matching range file is read.
+pub async fn is_breached(dir: &str, password: &str) -> std::io::Result<bool> {
+    let digest = format!("{:X}", Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = digest.split_at(BREACHED_PREFIX_LENGTH);
+
+    let range = match tokio::fs::read_to_string(Path::new(dir).join(prefix)).await {
+        Ok(range) => range,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    Ok(range.lines().any(|line| {
+        line.split(':')
+            .next()
+            .is_some_and(|hash| hash.trim().eq_ignore_ascii_case(suffix))
+    }))
+}
+
+/// Checks a new password against the policy and the breached-password list.
+pub async fn check_password(
+    settings: &Settings,
+    password: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let violations = policy_violations(settings, password);
+    if !violations.is_empty() {
+        let error_response = ErrorResponse {
+            error: format!("Password {}", violations.join(", ")),
+            message: "Password does not meet the password policy".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let Some(dir) = &settings.breached_passwords_dir else {
+        return Ok(());
+    };
+    match is_breached(dir, password).await {
+        Ok(false) => Ok(()),
+        Ok(true) => {
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "This password has appeared in a data breach, choose another one"
+                    .to_string(),
+            };
+            Err((StatusCode::BAD_REQUEST, Json(error_response)))
+        }
+        // An unreadable list shouldn't block registrations.
+        Err(e) => {
+            error!("Failed to read the breached-password list: {}", e);
+            Ok(())
+        }
+    }
+}
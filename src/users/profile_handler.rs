@@ -0,0 +1,253 @@
+use crate::AppState;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::blob::{BlobOwner, set_blob_references, store_blob_bytes};
+use crate::service::image_processing::sniff_image_format;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::service::upload::read_file_field;
+use crate::users::avatar::{DEFAULT_USER_FILE, avatar_file, remove_avatar, render_avatar};
+use crate::users::model::{User, UserRole};
+use crate::users::response::{AvatarResponse, AvatarVariantResponse, UserResponse};
+use crate::users::schema::UpdateUserSchema;
+use axum::extract::{Multipart, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use std::sync::Arc;
+use validator::Validate;
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/me/",
+    responses(
+        (status = 200, description = "Текущий пользователь", body = UserResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn get_me_handler(
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<UserResponse> {
+    // The middleware has just loaded the user, a cache would only go stale.
+    let response = SuccessResponse {
+        data: UserResponse::new(&auth_guard.user),
+        message: "User fetched successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/user/me/",
+    request_body = UpdateUserSchema,
+    responses(
+        (status = 200, description = "Успешно изменено", body = UserResponse),
+        (status = 400, description = "Ошибка валидации данных", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn update_me_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<UpdateUserSchema>,
+) -> APIResult<UserResponse> {
+    if body.validate().is_err() {
+        let error = ErrorResponse {
+            error: "Invalid input data".to_string(),
+            message: "Invalid input data".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error)));
+    }
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        UPDATE users
+        SET
+            first_name = COALESCE($1, first_name),
+            last_name = COALESCE($2, last_name),
+            middle_name = COALESCE($3, middle_name),
+            biography = COALESCE($4, biography),
+            age = COALESCE($5, age),
+            locale = COALESCE($6, locale),
+            updated_at = NOW()
+        WHERE id = $7
+        RETURNING id, first_name, last_name, middle_name, age, email, password, biography, file, verified, role as "role: UserRole", locale, balance, rating, created_at, updated_at
+        "#,
+        body.first_name,
+        body.last_name,
+        body.middle_name,
+        body.biography,
+        body.age,
+        body.locale,
+        auth_guard.user.id
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(|e| {
+        let error_response = ErrorResponse {
+            error: format!("Database error: {}", e),
+            message: "Error when updating user in database".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    let response = SuccessResponse {
+        data: UserResponse::new(&user),
+        message: "User updated successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// Points the user at the new avatar and moves the blob references to its
+/// variants.
+async fn set_user_file(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+    file: &str,
+    hashes: &[String],
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let database_error = |e: sqlx::Error| {
+        let error_response = ErrorResponse {
+            error: format!("Database error: {}", e),
+            message: "Error when updating user in database".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    };
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    sqlx::query!(
+        r#"UPDATE users SET file = $1, updated_at = NOW() WHERE id = $2"#,
+        file,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    set_blob_references(&mut tx, BlobOwner::Avatar, user_id, hashes)
+        .await
+        .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    Ok(())
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/user/me/avatar/",
+    request_body(content = String, content_type = "multipart/form-data", description = "Изображение JPEG, PNG или WebP в поле `file`"),
+    responses(
+        (status = 200, description = "Аватар загружен", body = AvatarResponse),
+        (status = 400, description = "Изображение не удалось прочитать", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 413, description = "Файл слишком большой", body = ErrorResponse),
+        (status = 415, description = "Неподдерживаемый формат изображения", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn upload_avatar_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    mut multipart: Multipart,
+) -> APIResult<AvatarResponse> {
+    let upload = read_file_field(&mut multipart, "file", data.env.avatar_max_size).await?;
+
+    let Some(format) = sniff_image_format(&upload) else {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Unsupported image format, use JPEG, PNG or WebP".to_string(),
+        };
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(error_response)));
+    };
+
+    let sizes = data.env.avatar_sizes.clone();
+    let variants = tokio::task::spawn_blocking(move || render_avatar(&upload, format, &sizes))
+        .await
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Task error: {}", e),
+                message: "Error when processing avatar".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })?
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: e.to_string(),
+                message: "Image can't be decoded".to_string(),
+            };
+            (StatusCode::BAD_REQUEST, Json(error_response))
+        })?;
+
+    let user = auth_guard.user;
+    let mut hashes = Vec::new();
+    let mut variant_responses = Vec::new();
+    for variant in &variants {
+        let blob = store_blob_bytes(&data, BlobOwner::Avatar, &variant.bytes, variant.format)
+            .await
+            .map_err(|e| {
+                let error_response = ErrorResponse {
+                    error: format!("Storage error: {}", e),
+                    message: "Error when saving avatar".to_string(),
+                };
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+            })?;
+        variant_responses.push(AvatarVariantResponse {
+            size: variant.size,
+            format: variant.format.to_string(),
+            file: blob.path(),
+        });
+        hashes.push(blob.hash);
+    }
+
+    let file = avatar_file(&variant_responses);
+    set_user_file(&data, user.id, &file, &hashes).await?;
+    remove_avatar(&data, user.id, &user.file).await;
+
+    let response = SuccessResponse {
+        data: AvatarResponse {
+            file,
+            variants: variant_responses,
+        },
+        message: "Avatar uploaded successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/me/avatar/",
+    responses(
+        (status = 200, description = "Аватар удален, установлено изображение по умолчанию", body = String),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn delete_avatar_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<String> {
+    let user = auth_guard.user;
+    set_user_file(&data, user.id, DEFAULT_USER_FILE, &[]).await?;
+    remove_avatar(&data, user.id, &user.file).await;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Avatar removed".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
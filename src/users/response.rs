@@ -1,9 +1,9 @@
-use crate::users::model::{User, UserRole};
+use crate::users::model::{ApiKeyScope, User, UserRole};
 use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct UserResponse {
     pub id: uuid::Uuid,
     pub first_name: String,
@@ -15,6 +15,7 @@ pub struct UserResponse {
     pub file: String,
     pub verified: bool,
     pub role: UserRole,
+    pub locale: String,
     pub balance: Decimal,
     pub rating: Decimal,
     pub created_at: chrono::DateTime<chrono::Utc>,
@@ -34,6 +35,7 @@ impl UserResponse {
             biography: user.biography.clone(),
             verified: user.verified,
             role: user.role.to_owned(),
+            locale: user.locale.to_owned(),
             balance: user.balance,
             rating: user.rating,
             created_at: user.created_at,
@@ -41,3 +43,149 @@ impl UserResponse {
         }
     }
 }
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TwoFactorSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct TwoFactorPolicyResponse {
+    pub role: UserRole,
+    pub required: bool,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct PasskeyResponse {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct ApiKeyResponse {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Returned only once, when the key is created.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+/// Token to send in the `X-CSRF-Token` header with cookie-authenticated
+/// requests that change state.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CsrfTokenResponse {
+    pub csrf_token: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserListResponse {
+    pub users: Vec<UserResponse>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct SuspensionResponse {
+    pub id: uuid::Uuid,
+    pub reason: String,
+    /// Empty for a permanent ban.
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_by: Option<uuid::Uuid>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AdminUserResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub suspension: Option<SuspensionResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct AuditLogEntryResponse {
+    pub id: uuid::Uuid,
+    pub admin_id: Option<uuid::Uuid>,
+    pub action: String,
+    pub target_user_id: Option<uuid::Uuid>,
+    pub details: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditLogResponse {
+    pub entries: Vec<AuditLogEntryResponse>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+/// Uploaded files stored as content-addressed blobs.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BlobStatsResponse {
+    pub blobs: i64,
+    pub total_size: i64,
+    /// Blobs no row uses any more, removed by the next garbage collection
+    /// once the grace period is over.
+    pub unreferenced: i64,
+    pub corrupted: i64,
+}
+
+/// Short-lived access token for acting as another user. There is no refresh
+/// token: support starts a new session when it expires.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImpersonationResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub user_id: uuid::Uuid,
+    pub impersonator_id: uuid::Uuid,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Export job; the archive can be downloaded once `status` is `ready`,
+/// until `expires_at`.
+#[derive(Debug, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct DataExportResponse {
+    pub id: uuid::Uuid,
+    /// `pending`, `processing`, `ready` or `failed`.
+    pub status: String,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AvatarVariantResponse {
+    /// Width and height in pixels.
+    pub size: u32,
+    /// `jpg` or `webp`.
+    pub format: String,
+    pub file: String,
+}
+
+/// `file` is the largest JPEG, the one stored in the profile.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AvatarResponse {
+    pub file: String,
+    pub variants: Vec<AvatarVariantResponse>,
+}
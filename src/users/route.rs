@@ -1,18 +1,206 @@
 use crate::AppState;
-use crate::middleware::jwt_auth::auth;
-use crate::users::handler::{login_user_handler, logout_user_handler, register_user_handler};
-use axum::routing::post;
+use crate::middleware::jwt_auth::{auth, auth_admin, deny_api_key, deny_impersonation};
+use crate::service::upload::MULTIPART_OVERHEAD;
+use crate::users::account_handler::{
+    delete_me_handler, download_data_export_handler, get_data_export_handler,
+    request_data_export_handler,
+};
+use crate::users::admin_handler::{
+    change_user_role_handler, collect_storage_garbage_handler, delete_user_handler,
+    get_audit_log_handler, get_storage_stats_handler, get_user_handler, grant_book_access_handler,
+    impersonate_user_handler, lift_suspension_handler, list_users_handler,
+    revoke_book_access_handler, stop_impersonation_handler, suspend_user_handler,
+    verify_storage_handler, verify_user_handler,
+};
+use crate::users::api_key_handler::{
+    create_api_key_handler, get_api_keys_handler, revoke_api_key_handler,
+};
+use crate::users::handler::{
+    csrf_token_handler, login_user_handler, logout_user_handler, register_user_handler,
+    unlock_login_handler,
+};
+use crate::users::oidc_handler::{
+    get_oidc_providers_handler, oidc_authorize_handler, oidc_callback_handler,
+};
+use crate::users::passkey_handler::{
+    delete_passkey_handler, finish_passkey_login_handler, finish_passkey_registration_handler,
+    get_passkeys_handler, start_passkey_login_handler, start_passkey_registration_handler,
+};
+use crate::users::password_handler::{
+    change_password_handler, forgot_password_handler, reset_password_handler,
+};
+use crate::users::profile_handler::{
+    delete_avatar_handler, get_me_handler, update_me_handler, upload_avatar_handler,
+};
+use crate::users::two_factor_handler::{
+    confirm_two_factor_handler, disable_two_factor_handler, get_two_factor_policies,
+    regenerate_recovery_codes_handler, set_two_factor_policy, setup_two_factor_handler,
+    two_factor_login_handler,
+};
+use crate::users::verification_handler::{resend_verification_handler, verify_email_handler};
+use axum::extract::DefaultBodyLimit;
+use axum::routing::{delete, get, patch, post, put};
 use axum::{Router, middleware};
 use std::sync::Arc;
 
+pub fn two_factor_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/setup/", post(setup_two_factor_handler))
+        .route("/confirm/", post(confirm_two_factor_handler))
+        .route("/disable/", post(disable_two_factor_handler))
+        .route("/recovery-codes/", post(regenerate_recovery_codes_handler))
+        .route_layer(middleware::from_fn(deny_api_key))
+        .route_layer(middleware::from_fn(deny_impersonation))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .route(
+            "/policies/",
+            get(get_two_factor_policies)
+                .put(set_two_factor_policy)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    auth_admin,
+                )),
+        )
+        .with_state(app_state)
+}
+
+pub fn passkey_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(get_passkeys_handler))
+        .route("/register/start/", post(start_passkey_registration_handler))
+        .route(
+            "/register/finish/",
+            post(finish_passkey_registration_handler),
+        )
+        .route("/{id}/", delete(delete_passkey_handler))
+        .route_layer(middleware::from_fn(deny_api_key))
+        .route_layer(middleware::from_fn(deny_impersonation))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .with_state(app_state)
+}
+
+pub fn api_key_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(get_api_keys_handler).post(create_api_key_handler))
+        .route("/{id}/", delete(revoke_api_key_handler))
+        .route_layer(middleware::from_fn(deny_api_key))
+        .route_layer(middleware::from_fn(deny_impersonation))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .with_state(app_state)
+}
+
+pub fn data_export_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", post(request_data_export_handler))
+        .route("/{id}/", get(get_data_export_handler))
+        .route("/{id}/download/", get(download_data_export_handler))
+        .route_layer(middleware::from_fn(deny_api_key))
+        .route_layer(middleware::from_fn(deny_impersonation))
+        .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
+        .with_state(app_state)
+}
+
+pub fn admin_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/users/", get(list_users_handler))
+        .route(
+            "/users/{id}/",
+            get(get_user_handler).delete(delete_user_handler),
+        )
+        .route("/users/{id}/role/", patch(change_user_role_handler))
+        .route(
+            "/users/{id}/suspend/",
+            post(suspend_user_handler).delete(lift_suspension_handler),
+        )
+        .route("/users/{id}/verify/", post(verify_user_handler))
+        .route("/users/{id}/impersonate/", post(impersonate_user_handler))
+        .route(
+            "/users/{id}/books/{book_id}/",
+            put(grant_book_access_handler).delete(revoke_book_access_handler),
+        )
+        .route("/audit-log/", get(get_audit_log_handler))
+        .route("/storage/", get(get_storage_stats_handler))
+        .route("/storage/gc/", post(collect_storage_garbage_handler))
+        .route("/storage/verify/", post(verify_storage_handler))
+        .route_layer(middleware::from_fn(deny_api_key))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth_admin,
+        ))
+        .with_state(app_state)
+}
+
 pub fn user_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .route("/register/", post(register_user_handler))
         .route("/login/", post(login_user_handler))
+        .route("/login/2fa/", post(two_factor_login_handler))
+        .route(
+            "/login/unlock/",
+            post(unlock_login_handler).route_layer(middleware::from_fn_with_state(
+                app_state.clone(),
+                auth_admin,
+            )),
+        )
+        .nest("/2fa", two_factor_routes(app_state.clone()))
+        .route("/login/passkey/start/", post(start_passkey_login_handler))
+        .route("/login/passkey/finish/", post(finish_passkey_login_handler))
+        .nest("/passkeys", passkey_routes(app_state.clone()))
+        .nest("/api-keys", api_key_routes(app_state.clone()))
+        .nest("/admin", admin_routes(app_state.clone()))
+        .route(
+            "/impersonation/",
+            delete(stop_impersonation_handler)
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route("/oidc/providers/", get(get_oidc_providers_handler))
+        .route("/oidc/{provider}/authorize/", get(oidc_authorize_handler))
+        .route("/oidc/{provider}/callback/", get(oidc_callback_handler))
+        .route(
+            "/csrf/",
+            get(csrf_token_handler)
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
         .route(
             "/logout/",
             post(logout_user_handler)
                 .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
         )
+        .route(
+            "/me/",
+            get(get_me_handler)
+                .patch(update_me_handler)
+                .merge(
+                    delete(delete_me_handler)
+                        .route_layer(middleware::from_fn(deny_api_key))
+                        .route_layer(middleware::from_fn(deny_impersonation)),
+                )
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .nest("/me/exports", data_export_routes(app_state.clone()))
+        .route(
+            "/me/avatar/",
+            put(upload_avatar_handler)
+                .delete(delete_avatar_handler)
+                .layer(DefaultBodyLimit::max(
+                    app_state.env.avatar_max_size + MULTIPART_OVERHEAD,
+                ))
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route(
+            "/password/change/",
+            post(change_password_handler)
+                .route_layer(middleware::from_fn(deny_api_key))
+                .route_layer(middleware::from_fn(deny_impersonation))
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
+        .route("/password/forgot/", post(forgot_password_handler))
+        .route("/password/reset/", post(reset_password_handler))
+        .route("/verify-email/", get(verify_email_handler))
+        .route(
+            "/verify-email/resend/",
+            post(resend_verification_handler)
+                .route_layer(middleware::from_fn_with_state(app_state.clone(), auth)),
+        )
         .with_state(app_state)
 }
@@ -1,6 +1,15 @@
+use crate::service::mail_template::Locale;
+use crate::users::model::{ApiKeyScope, UserRole};
 use serde::Deserialize;
-use utoipa::ToSchema;
-use validator::Validate;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+fn validate_locale(locale: &str) -> Result<(), ValidationError> {
+    locale
+        .parse::<Locale>()
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("locale"))
+}
 
 #[derive(Debug, Deserialize, Validate, ToSchema)]
 pub struct RegisterUserSchema {
@@ -19,8 +28,11 @@ pub struct RegisterUserSchema {
     #[validate(email)]
     pub email: String,
 
-    #[validate(length(min = 8))]
+    #[validate(length(min = 1))]
     pub password: String,
+
+    #[validate(custom(function = "validate_locale"))]
+    pub locale: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Validate, ToSchema)]
@@ -28,6 +40,220 @@ pub struct LoginUserSchema {
     #[validate(email)]
     pub email: String,
 
-    #[validate(length(min = 8))]
+    #[validate(length(min = 1))]
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct UpdateUserSchema {
+    #[validate(length(min = 2, max = 100))]
+    pub first_name: Option<String>,
+
+    #[validate(length(min = 2, max = 100))]
+    pub last_name: Option<String>,
+
+    #[validate(length(min = 2, max = 100))]
+    pub middle_name: Option<String>,
+
+    pub biography: Option<String>,
+
+    #[validate(range(min = 0, max = 150))]
+    pub age: Option<i32>,
+
+    #[validate(custom(function = "validate_locale"))]
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ChangePasswordSchema {
+    #[validate(length(min = 1))]
+    pub old_password: String,
+
+    #[validate(length(min = 1))]
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ForgotPasswordSchema {
+    #[validate(email)]
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ResetPasswordSchema {
+    #[validate(length(min = 1))]
+    pub token: String,
+
+    #[validate(length(min = 1))]
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct TwoFactorCodeSchema {
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct DisableTwoFactorSchema {
+    #[validate(length(min = 1))]
+    pub password: String,
+
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct TwoFactorLoginSchema {
+    #[validate(length(min = 1))]
+    pub challenge: String,
+
+    /// TOTP code from the authenticator app or one of the recovery codes.
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct TwoFactorPolicySchema {
+    pub role: UserRole,
+    pub required: bool,
+}
+
+/// `response` of a `PublicKeyCredential` returned by `navigator.credentials.create()`.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyAttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyRegistrationCredential {
+    pub raw_id: String,
+    pub response: PasskeyAttestationResponse,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct PasskeyRegisterSchema {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+
+    pub credential: PasskeyRegistrationCredential,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct PasskeyLoginStartSchema {
+    /// Limits the allowed credentials to the user's passkeys; omit it for
+    /// discoverable credentials.
+    #[validate(email)]
+    pub email: Option<String>,
+}
+
+/// `response` of a `PublicKeyCredential` returned by `navigator.credentials.get()`.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyAssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyLoginSchema {
+    pub raw_id: String,
+    pub response: PasskeyAssertionResponse,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CreateApiKeySchema {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+
+    #[validate(length(min = 1))]
+    pub scopes: Vec<ApiKeyScope>,
+
+    /// Lifetime of the key, 90 days when omitted.
+    #[validate(range(min = 1, max = 365))]
+    pub expires_in_days: Option<i64>,
+}
+
+/// Clears the lockout of an account, an IP address, or both.
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct UnlockLoginSchema {
+    #[validate(email)]
+    pub email: Option<String>,
+
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate, IntoParams)]
+pub struct UserListQuery {
+    /// Part of the email, first or last name.
+    #[validate(length(min = 1, max = 100))]
+    pub search: Option<String>,
+
+    pub role: Option<UserRole>,
+
+    #[validate(range(min = 1))]
+    pub page: Option<i64>,
+
+    #[validate(range(min = 1, max = 100))]
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChangeRoleSchema {
+    pub role: UserRole,
+}
+
+/// Suspends the account until `expires_at`, or bans it when it's omitted.
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct SuspendUserSchema {
+    #[validate(length(min = 1, max = 500))]
+    pub reason: String,
+
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Deserialize, Validate, IntoParams)]
+pub struct AuditLogQuery {
+    pub target_user_id: Option<uuid::Uuid>,
+
+    #[validate(range(min = 1))]
+    pub page: Option<i64>,
+
+    #[validate(range(min = 1, max = 100))]
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct ImpersonateSchema {
+    /// Why support needs the access, e.g. a ticket number.
+    #[validate(length(min = 1, max = 500))]
+    pub reason: String,
+}
+
+/// The current password confirms the deletion.
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct DeleteAccountSchema {
+    #[validate(length(min = 1))]
     pub password: String,
 }
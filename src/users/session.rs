@@ -0,0 +1,135 @@
+use crate::AppState;
+use crate::service::response_server::ErrorResponse;
+use crate::users::token::{TokenDetails, generate_jwt_token};
+use axum::Json;
+use axum::http::StatusCode;
+use redis::AsyncCommands;
+use std::sync::Arc;
+
+pub fn user_sessions_key(user_id: uuid::Uuid) -> String {
+    format!("user-sessions-{}", user_id)
+}
+
+pub fn generate_token(
+    user_id: uuid::Uuid,
+    max_age: i64,
+    private_key: String,
+) -> Result<TokenDetails, (StatusCode, Json<ErrorResponse>)> {
+    generate_jwt_token(user_id, max_age, private_key).map_err(|e| {
+        let error_response = ErrorResponse {
+            error: format!("error generating token: {}", e),
+            message: "Error token".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })
+}
+
+pub fn redis_error(e: redis::RedisError) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Redis error: {}", e),
+        message: "Redis error".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+pub async fn redis_connection(
+    data: &Arc<AppState>,
+) -> Result<redis::aio::MultiplexedConnection, (StatusCode, Json<ErrorResponse>)> {
+    data.redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(redis_error)
+}
+
+/// Stores the token in Redis and remembers it in the user's session index,
+/// so all sessions of a user can be revoked at once.
+pub async fn save_token_data_to_redis(
+    data: &Arc<AppState>,
+    token_details: &TokenDetails,
+    max_age: i64,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let mut redis_client = redis_connection(data).await?;
+    let sessions_key = user_sessions_key(token_details.user_id);
+    let sessions_max_age = data
+        .env
+        .access_token_max_age
+        .max(data.env.refresh_token_max_age);
+
+    redis::pipe()
+        .set_ex(
+            token_details.token_uuid.to_string(),
+            token_details.user_id.to_string(),
+            max_age as u64,
+        )
+        .ignore()
+        .sadd(&sessions_key, token_details.token_uuid.to_string())
+        .ignore()
+        .expire(&sessions_key, sessions_max_age)
+        .ignore()
+        .query_async::<()>(&mut redis_client)
+        .await
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Redis error Save Token: {}", e),
+                message: "Redis error".to_string(),
+            };
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(error_response))
+        })?;
+    Ok(())
+}
+
+pub async fn remove_sessions(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+    token_uuids: &[String],
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let mut redis_client = redis_connection(data).await?;
+
+    redis::pipe()
+        .del(token_uuids)
+        .ignore()
+        .srem(user_sessions_key(user_id), token_uuids)
+        .ignore()
+        .query_async::<()>(&mut redis_client)
+        .await
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Redis Error: {:?}", e),
+                message: "Redis Error".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })
+}
+
+/// Invalidates every access and refresh token issued to the user.
+pub async fn revoke_user_sessions(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let mut redis_client = redis_connection(data).await?;
+    let sessions_key = user_sessions_key(user_id);
+
+    let token_uuids: Vec<String> = redis_client.smembers(&sessions_key).await.map_err(|e| {
+        let error_response = ErrorResponse {
+            error: format!("Redis Error: {:?}", e),
+            message: "Redis Error".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    let mut pipe = redis::pipe();
+    if !token_uuids.is_empty() {
+        pipe.del(&token_uuids).ignore();
+    }
+    pipe.del(&sessions_key)
+        .ignore()
+        .query_async::<()>(&mut redis_client)
+        .await
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Redis Error: {:?}", e),
+                message: "Redis Error".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })
+}
@@ -1,6 +1,8 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use base64::Engine;
 use base64::engine::general_purpose;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
@@ -9,6 +11,18 @@ pub struct TokenClaims {
     pub exp: i64,
     pub iat: i64,
     pub nbf: i64,
+    /// Id of the admin acting as `sub`, set only on impersonation tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<String>,
+}
+
+/// Claims of short-lived HMAC-signed tokens used in emailed links.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedTokenClaims {
+    pub sub: String,
+    pub purpose: String,
+    pub exp: i64,
+    pub iat: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,12 +31,32 @@ pub struct TokenDetails {
     pub token_uuid: uuid::Uuid,
     pub user_id: uuid::Uuid,
     pub expires_in: Option<i64>,
+    pub impersonator_id: Option<uuid::Uuid>,
 }
 
 pub fn generate_jwt_token(
     user_id: uuid::Uuid,
     ttl: i64,
     private_key: String,
+) -> Result<TokenDetails, jsonwebtoken::errors::Error> {
+    encode_jwt_token(user_id, None, chrono::Duration::days(ttl), private_key)
+}
+
+/// Access token that lets `impersonator_id` act as `user_id`.
+pub fn generate_impersonation_jwt_token(
+    user_id: uuid::Uuid,
+    impersonator_id: uuid::Uuid,
+    ttl: chrono::Duration,
+    private_key: String,
+) -> Result<TokenDetails, jsonwebtoken::errors::Error> {
+    encode_jwt_token(user_id, Some(impersonator_id), ttl, private_key)
+}
+
+fn encode_jwt_token(
+    user_id: uuid::Uuid,
+    impersonator_id: Option<uuid::Uuid>,
+    ttl: chrono::Duration,
+    private_key: String,
 ) -> Result<TokenDetails, jsonwebtoken::errors::Error> {
     let bytes_token_key = general_purpose::STANDARD.decode(private_key).unwrap();
     let token_key = String::from_utf8(bytes_token_key).unwrap();
@@ -33,7 +67,8 @@ pub fn generate_jwt_token(
         user_id,
         token_uuid: uuid::Uuid::new_v4(),
         token: None,
-        expires_in: Some((now + chrono::Duration::days(ttl)).timestamp()),
+        expires_in: Some((now + ttl).timestamp()),
+        impersonator_id,
     };
 
     let token_claims = TokenClaims {
@@ -42,6 +77,7 @@ pub fn generate_jwt_token(
         exp: token_details.expires_in.unwrap(),
         iat: now.timestamp(),
         nbf: now.timestamp(),
+        impersonator_id: impersonator_id.map(|id| id.to_string()),
     };
 
     let headers = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
@@ -71,11 +107,72 @@ pub fn verify_jwt_token(
 
     let user_id = uuid::Uuid::parse_str(decoded.claims.sub.as_str()).unwrap();
     let token_uuid = uuid::Uuid::parse_str(decoded.claims.token_uuid.as_str()).unwrap();
+    let impersonator_id = match decoded.claims.impersonator_id {
+        Some(id) => Some(
+            uuid::Uuid::parse_str(&id)
+                .map_err(|_| jsonwebtoken::errors::ErrorKind::InvalidToken)?,
+        ),
+        None => None,
+    };
 
     Ok(TokenDetails {
         token: None,
         token_uuid,
         user_id,
         expires_in: None,
+        impersonator_id,
     })
 }
+
+/// Generates a random URL-safe token for one-time links and keys.
+pub fn generate_random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hashes a random token before it is stored, so a leaked store can't be replayed.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub fn generate_signed_token(
+    sub: &str,
+    purpose: &str,
+    ttl: chrono::Duration,
+    secret: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = chrono::Utc::now();
+    let claims = SignedTokenClaims {
+        sub: sub.to_string(),
+        purpose: purpose.to_string(),
+        exp: (now + ttl).timestamp(),
+        iat: now.timestamp(),
+    };
+
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256),
+        &claims,
+        &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+    )
+}
+
+/// Verifies the signature and expiry and returns the subject if the token
+/// was issued for `purpose`.
+pub fn verify_signed_token(
+    token: &str,
+    purpose: &str,
+    secret: &str,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
+    let decoded = jsonwebtoken::decode::<SignedTokenClaims>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )?;
+
+    if decoded.claims.purpose != purpose {
+        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    }
+    Ok(decoded.claims.sub)
+}
@@ -0,0 +1,678 @@
+use crate::AppState;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::users::handler::issue_user_tokens;
+use crate::users::model::{User, UserRole};
+use crate::users::password::verify_password;
+use crate::users::response::{
+    RecoveryCodesResponse, TwoFactorPolicyResponse, TwoFactorSetupResponse,
+};
+use crate::users::schema::{
+    DisableTwoFactorSchema, TwoFactorCodeSchema, TwoFactorLoginSchema, TwoFactorPolicySchema,
+};
+use crate::users::session::{redis_connection, redis_error};
+use crate::users::token::{generate_random_token, hash_token};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use redis::AsyncCommands;
+use std::sync::Arc;
+use totp_rs::{Algorithm, Secret, TOTP};
+use validator::Validate;
+
+const TOTP_ISSUER: &str = "Books";
+const RECOVERY_CODES_COUNT: usize = 10;
+const CHALLENGE_MAX_AGE: u64 = 300;
+const CHALLENGE_MAX_ATTEMPTS: i64 = 5;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub required: bool,
+}
+
+fn database_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: format!("Database error: {}", e),
+        message: "Request failed".to_string(),
+    };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+}
+
+fn invalid_input() -> (StatusCode, Json<ErrorResponse>) {
+    let error = ErrorResponse {
+        error: "".to_string(),
+        message: "Invalid input data".to_string(),
+    };
+    (StatusCode::BAD_REQUEST, Json(error))
+}
+
+fn invalid_code() -> (StatusCode, Json<ErrorResponse>) {
+    let error_response = ErrorResponse {
+        error: "".to_string(),
+        message: "Invalid two-factor code".to_string(),
+    };
+    (StatusCode::BAD_REQUEST, Json(error_response))
+}
+
+fn challenge_key(challenge: &str) -> String {
+    format!("two-factor-challenge-{}", hash_token(challenge))
+}
+
+fn build_totp(secret: &str, email: &str) -> Result<TOTP, (StatusCode, Json<ErrorResponse>)> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Invalid TOTP secret: {:?}", e),
+                message: "Two-factor configuration error".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(TOTP_ISSUER.to_string()),
+        email.to_string(),
+    )
+    .map_err(|e| {
+        let error_response = ErrorResponse {
+            error: format!("Invalid TOTP parameters: {:?}", e),
+            message: "Two-factor configuration error".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })
+}
+
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    OsRng.fill_bytes(&mut bytes);
+    let code: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase()
+}
+
+/// Checks a TOTP code and remembers it for the length of the skew window,
+/// so the same code can't be replayed.
+async fn verify_totp_code(
+    data: &Arc<AppState>,
+    user: &User,
+    secret: &str,
+    code: &str,
+) -> Result<bool, (StatusCode, Json<ErrorResponse>)> {
+    let totp = build_totp(secret, &user.email)?;
+    if !totp.check_current(code.trim()).unwrap_or(false) {
+        return Ok(false);
+    }
+
+    let mut redis_client = redis_connection(data).await?;
+    let first_use: Option<String> = redis::cmd("SET")
+        .arg(format!("totp-used-{}-{}", user.id, code.trim()))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(90)
+        .query_async(&mut redis_client)
+        .await
+        .map_err(redis_error)?;
+    Ok(first_use.is_some())
+}
+
+/// Consumes a recovery code; each code works only once.
+async fn use_recovery_code(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+    code: &str,
+) -> Result<bool, (StatusCode, Json<ErrorResponse>)> {
+    let query_result = sqlx::query!(
+        r#"
+        UPDATE user_recovery_codes
+        SET used_at = NOW()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_token(&normalize_recovery_code(code))
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+    Ok(query_result.rows_affected() == 1)
+}
+
+/// Accepts either a TOTP code or a recovery code.
+async fn verify_second_factor(
+    data: &Arc<AppState>,
+    user: &User,
+    secret: &str,
+    code: &str,
+) -> Result<bool, (StatusCode, Json<ErrorResponse>)> {
+    let code = code.trim();
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        verify_totp_code(data, user, secret, code).await
+    } else {
+        use_recovery_code(data, user.id, code).await
+    }
+}
+
+async fn enabled_secret(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<Option<String>, (StatusCode, Json<ErrorResponse>)> {
+    sqlx::query_scalar!(
+        r#"SELECT secret FROM user_two_factor WHERE user_id = $1 AND enabled"#,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)
+}
+
+async fn replace_recovery_codes(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> Result<Vec<String>, (StatusCode, Json<ErrorResponse>)> {
+    let codes: Vec<String> = (0..RECOVERY_CODES_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|code| hash_token(code)).collect();
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    sqlx::query!(
+        r#"
+        INSERT INTO user_recovery_codes (user_id, code_hash)
+        SELECT $1, UNNEST($2::VARCHAR[])
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    Ok(codes)
+}
+
+/// Whether the user has 2FA turned on and whether their role requires it.
+pub async fn two_factor_status(
+    data: &Arc<AppState>,
+    user: &User,
+) -> Result<TwoFactorStatus, (StatusCode, Json<ErrorResponse>)> {
+    let status = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE((SELECT enabled FROM user_two_factor WHERE user_id = $1), FALSE) AS "enabled!",
+            COALESCE((SELECT required FROM two_factor_policies WHERE role = $2), FALSE) AS "required!"
+        "#,
+        user.id,
+        user.role.clone() as UserRole
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    Ok(TwoFactorStatus {
+        enabled: status.enabled,
+        required: status.required,
+    })
+}
+
+/// First step of a 2FA login: the password was correct, so a short-lived
+/// challenge is issued instead of the token pair.
+pub async fn start_two_factor_challenge(
+    data: &Arc<AppState>,
+    user_id: uuid::Uuid,
+) -> APIResult<serde_json::Value> {
+    let challenge = generate_random_token();
+    let mut redis_client = redis_connection(data).await?;
+    redis_client
+        .set_ex::<_, _, ()>(
+            challenge_key(&challenge),
+            user_id.to_string(),
+            CHALLENGE_MAX_AGE,
+        )
+        .await
+        .map_err(redis_error)?;
+
+    let response = SuccessResponse {
+        data: serde_json::json!({
+            "two_factor_required": true,
+            "challenge": challenge,
+            "expires_in": CHALLENGE_MAX_AGE
+        }),
+        message: "Two-factor authentication required".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/login/2fa/",
+    request_body = TwoFactorLoginSchema,
+    responses(
+        (status = 200, description = "Успешно авторизирован", body = String),
+        (status = 400, description = "Ошибка валидации данных или неверный код", body = ErrorResponse),
+        (status = 401, description = "Challenge недействителен или устарел", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    tag = "Users"
+)]
+pub async fn two_factor_login_handler(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<TwoFactorLoginSchema>,
+) -> APIResult<serde_json::Value> {
+    if body.validate().is_err() {
+        return Err(invalid_input());
+    }
+
+    let invalid_challenge = || {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Two-factor challenge is invalid or has expired".to_string(),
+        };
+        (StatusCode::UNAUTHORIZED, Json(error_response))
+    };
+
+    let key = challenge_key(&body.challenge);
+    let attempts_key = format!("{}-attempts", key);
+    let mut redis_client = redis_connection(&data).await?;
+    let user_id: Option<String> = redis_client.get(&key).await.map_err(redis_error)?;
+    let user_id = user_id
+        .and_then(|id| uuid::Uuid::parse_str(&id).ok())
+        .ok_or_else(invalid_challenge)?;
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+        id,
+        first_name,
+        last_name,
+        middle_name,
+        age,
+        email,
+        password,
+        biography,
+        file,
+        verified,
+        role as "role: UserRole",
+        locale,
+        balance,
+        rating,
+        created_at,
+        updated_at
+        FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(invalid_challenge)?;
+
+    let secret = enabled_secret(&data, user.id)
+        .await?
+        .ok_or_else(invalid_challenge)?;
+
+    if !verify_second_factor(&data, &user, &secret, &body.code).await? {
+        let attempts: i64 = redis::pipe()
+            .incr(&attempts_key, 1)
+            .expire(&attempts_key, CHALLENGE_MAX_AGE as i64)
+            .ignore()
+            .query_async::<(i64,)>(&mut redis_client)
+            .await
+            .map_err(redis_error)?
+            .0;
+        if attempts >= CHALLENGE_MAX_ATTEMPTS {
+            redis_client
+                .del::<_, ()>(&[&key, &attempts_key])
+                .await
+                .map_err(redis_error)?;
+            return Err(invalid_challenge());
+        }
+        return Err(invalid_code());
+    }
+
+    // Only the request that takes the challenge logs in, so racing requests
+    // can't use the same code twice.
+    let taken: Option<String> = redis::cmd("GETDEL")
+        .arg(&key)
+        .query_async(&mut redis_client)
+        .await
+        .map_err(redis_error)?;
+    if taken.is_none() {
+        return Err(invalid_challenge());
+    }
+    redis_client
+        .del::<_, ()>(&attempts_key)
+        .await
+        .map_err(redis_error)?;
+
+    // The user has 2FA on, nothing left to set up.
+    issue_user_tokens(&data, &user, false).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/2fa/setup/",
+    responses(
+        (status = 200, description = "Секрет и otpauth URI для приложения-аутентификатора", body = TwoFactorSetupResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 409, description = "Двухфакторная аутентификация уже включена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Two-factor authentication"
+)]
+pub async fn setup_two_factor_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<TwoFactorSetupResponse> {
+    if enabled_secret(&data, auth_guard.user.id).await?.is_some() {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Two-factor authentication is already enabled".to_string(),
+        };
+        return Err((StatusCode::CONFLICT, Json(error_response)));
+    }
+
+    let mut secret_bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut secret_bytes);
+    let secret = Secret::Raw(secret_bytes.to_vec()).to_encoded().to_string();
+    let totp = build_totp(&secret, &auth_guard.user.email)?;
+
+    // Until confirmed the secret stays disabled and can be replaced by
+    // calling setup again.
+    sqlx::query!(
+        r#"
+        INSERT INTO user_two_factor (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+        SET secret = EXCLUDED.secret, enabled = FALSE, confirmed_at = NULL, updated_at = NOW()
+        "#,
+        auth_guard.user.id,
+        secret
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: TwoFactorSetupResponse {
+            secret,
+            otpauth_uri: totp.get_url(),
+        },
+        message: "Scan the QR code and confirm it with a code from the app".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/2fa/confirm/",
+    request_body = TwoFactorCodeSchema,
+    responses(
+        (status = 200, description = "Двухфакторная аутентификация включена, коды восстановления", body = RecoveryCodesResponse),
+        (status = 400, description = "Неверный код", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 404, description = "Настройка не начата", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Two-factor authentication"
+)]
+pub async fn confirm_two_factor_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<TwoFactorCodeSchema>,
+) -> APIResult<RecoveryCodesResponse> {
+    if body.validate().is_err() {
+        return Err(invalid_input());
+    }
+
+    let secret = sqlx::query_scalar!(
+        r#"SELECT secret FROM user_two_factor WHERE user_id = $1 AND NOT enabled"#,
+        auth_guard.user.id
+    )
+    .fetch_optional(&data.db)
+    .await
+    .map_err(database_error)?
+    .ok_or_else(|| {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Two-factor setup has not been started".to_string(),
+        };
+        (StatusCode::NOT_FOUND, Json(error_response))
+    })?;
+
+    if !verify_totp_code(&data, &auth_guard.user, &secret, &body.code).await? {
+        return Err(invalid_code());
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE user_two_factor
+        SET enabled = TRUE, confirmed_at = NOW(), updated_at = NOW()
+        WHERE user_id = $1
+        "#,
+        auth_guard.user.id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let recovery_codes = replace_recovery_codes(&data, auth_guard.user.id).await?;
+
+    let response = SuccessResponse {
+        data: RecoveryCodesResponse { recovery_codes },
+        message: "Two-factor authentication enabled, save the recovery codes".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/2fa/disable/",
+    request_body = DisableTwoFactorSchema,
+    responses(
+        (status = 200, description = "Двухфакторная аутентификация отключена", body = String),
+        (status = 400, description = "Неверный пароль или код", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 404, description = "Двухфакторная аутентификация не включена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Two-factor authentication"
+)]
+pub async fn disable_two_factor_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<DisableTwoFactorSchema>,
+) -> APIResult<String> {
+    if body.validate().is_err() {
+        return Err(invalid_input());
+    }
+
+    let secret = enabled_secret(&data, auth_guard.user.id)
+        .await?
+        .ok_or_else(|| {
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "Two-factor authentication is not enabled".to_string(),
+            };
+            (StatusCode::NOT_FOUND, Json(error_response))
+        })?;
+
+    if !verify_password(&body.password, &auth_guard.user.password)
+        || !verify_second_factor(&data, &auth_guard.user, &secret, &body.code).await?
+    {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Invalid password or two-factor code".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let mut tx = data.db.begin().await.map_err(database_error)?;
+    sqlx::query!(
+        r#"DELETE FROM user_recovery_codes WHERE user_id = $1"#,
+        auth_guard.user.id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    sqlx::query!(
+        r#"DELETE FROM user_two_factor WHERE user_id = $1"#,
+        auth_guard.user.id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Two-factor authentication disabled".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/2fa/recovery-codes/",
+    request_body = TwoFactorCodeSchema,
+    responses(
+        (status = 200, description = "Новые коды восстановления", body = RecoveryCodesResponse),
+        (status = 400, description = "Неверный код", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 404, description = "Двухфакторная аутентификация не включена", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Two-factor authentication"
+)]
+pub async fn regenerate_recovery_codes_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+    Json(body): Json<TwoFactorCodeSchema>,
+) -> APIResult<RecoveryCodesResponse> {
+    if body.validate().is_err() {
+        return Err(invalid_input());
+    }
+
+    let secret = enabled_secret(&data, auth_guard.user.id)
+        .await?
+        .ok_or_else(|| {
+            let error_response = ErrorResponse {
+                error: "".to_string(),
+                message: "Two-factor authentication is not enabled".to_string(),
+            };
+            (StatusCode::NOT_FOUND, Json(error_response))
+        })?;
+
+    if !verify_totp_code(&data, &auth_guard.user, &secret, &body.code).await? {
+        return Err(invalid_code());
+    }
+
+    let recovery_codes = replace_recovery_codes(&data, auth_guard.user.id).await?;
+
+    let response = SuccessResponse {
+        data: RecoveryCodesResponse { recovery_codes },
+        message: "Recovery codes regenerated".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/2fa/policies/",
+    responses(
+        (status = 200, description = "Роли, для которых требуется 2FA", body = Vec<TwoFactorPolicyResponse>),
+        (status = 403, description = "Ошибка авторизации", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "Two-factor authentication"
+)]
+pub async fn get_two_factor_policies(
+    State(data): State<Arc<AppState>>,
+) -> APIResult<Vec<TwoFactorPolicyResponse>> {
+    let policies = sqlx::query_as!(
+        TwoFactorPolicyResponse,
+        r#"SELECT role as "role: UserRole", required, updated_at FROM two_factor_policies"#
+    )
+    .fetch_all(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: policies,
+        message: "Policies fetched successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/user/2fa/policies/",
+    request_body = TwoFactorPolicySchema,
+    responses(
+        (status = 200, description = "Политика сохранена", body = TwoFactorPolicyResponse),
+        (status = 403, description = "Ошибка авторизации", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["admin"])
+    ),
+    tag = "Two-factor authentication"
+)]
+pub async fn set_two_factor_policy(
+    State(data): State<Arc<AppState>>,
+    Json(body): Json<TwoFactorPolicySchema>,
+) -> APIResult<TwoFactorPolicyResponse> {
+    let policy = sqlx::query_as!(
+        TwoFactorPolicyResponse,
+        r#"
+        INSERT INTO two_factor_policies (role, required)
+        VALUES ($1, $2)
+        ON CONFLICT (role) DO UPDATE SET required = EXCLUDED.required, updated_at = NOW()
+        RETURNING role as "role: UserRole", required, updated_at
+        "#,
+        body.role as UserRole,
+        body.required
+    )
+    .fetch_one(&data.db)
+    .await
+    .map_err(database_error)?;
+
+    let response = SuccessResponse {
+        data: policy,
+        message: "Policy saved successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
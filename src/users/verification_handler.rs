@@ -0,0 +1,185 @@
+use crate::AppState;
+use crate::middleware::jwt_auth::JWTAuthMiddleware;
+use crate::service::mail_template::MailTemplate;
+use crate::service::response_server::{APIResult, ErrorResponse, SuccessResponse};
+use crate::users::model::User;
+use crate::users::schema::VerifyEmailQuery;
+use crate::users::token::{generate_signed_token, verify_signed_token};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use redis::AsyncCommands;
+use std::sync::Arc;
+use tracing::error;
+
+const EMAIL_VERIFICATION_PURPOSE: &str = "email-verification";
+
+/// Sends a signed verification link. Delivery failures are only logged, so
+/// they never break the flow that triggered the email.
+pub async fn send_verification_email(data: &Arc<AppState>, user: &User) {
+    let token = match generate_signed_token(
+        &user.id.to_string(),
+        EMAIL_VERIFICATION_PURPOSE,
+        chrono::Duration::hours(data.env.email_verification_token_max_age),
+        &data.env.secret_key,
+    ) {
+        Ok(token) => token,
+        Err(e) => {
+            error!("Failed to generate email verification token: {}", e);
+            return;
+        }
+    };
+
+    let message = MailTemplate::EmailVerification {
+        link: format!(
+            "{}/api/v1/user/verify-email/?token={}",
+            data.env.app_url, token
+        ),
+        valid_hours: data.env.email_verification_token_max_age,
+    }
+    .render(&user.email, &user.locale);
+    if let Err(e) = data.mailer.send(message).await {
+        error!("Failed to send verification email: {}", e);
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/verify-email/",
+    params(VerifyEmailQuery),
+    responses(
+        (status = 200, description = "Email подтверждён", body = String),
+        (status = 400, description = "Ссылка недействительна или устарела", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    tag = "Users"
+)]
+pub async fn verify_email_handler(
+    State(data): State<Arc<AppState>>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> APIResult<String> {
+    let user_id = verify_signed_token(
+        &query.token,
+        EMAIL_VERIFICATION_PURPOSE,
+        &data.env.secret_key,
+    )
+    .ok()
+    .and_then(|sub| uuid::Uuid::parse_str(&sub).ok())
+    .ok_or_else(|| {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Verification link is invalid or has expired".to_string(),
+        };
+        (StatusCode::BAD_REQUEST, Json(error_response))
+    })?;
+
+    let query_result = sqlx::query!(
+        r#"UPDATE users SET verified = TRUE, updated_at = NOW() WHERE id = $1"#,
+        user_id
+    )
+    .execute(&data.db)
+    .await
+    .map_err(|e| {
+        let error_response = ErrorResponse {
+            error: format!("Database error: {}", e),
+            message: "Error when verifying email".to_string(),
+        };
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+    })?;
+
+    if query_result.rows_affected() == 0 {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Verification link is invalid or has expired".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Email verified successfully".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/verify-email/resend/",
+    responses(
+        (status = 200, description = "Письмо отправлено повторно", body = String),
+        (status = 400, description = "Email уже подтверждён", body = ErrorResponse),
+        (status = 401, description = "Ошибка проверка токена", body = ErrorResponse),
+        (status = 429, description = "Слишком частые запросы", body = ErrorResponse),
+        (status = 500, description = "Ошибка сервера", body = ErrorResponse)
+    ),
+    security(
+        ("Bearer" = ["user"])
+    ),
+    tag = "Users"
+)]
+pub async fn resend_verification_handler(
+    State(data): State<Arc<AppState>>,
+    Extension(auth_guard): Extension<JWTAuthMiddleware>,
+) -> APIResult<String> {
+    if auth_guard.user.verified {
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: "Email is already verified".to_string(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(error_response)));
+    }
+
+    let mut redis_client = data
+        .redis
+        .get_multiplexed_async_connection()
+        .await
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Redis error: {}", e),
+                message: "Redis error".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })?;
+
+    // SET NX EX succeeds only once per interval, which rate limits resends.
+    let resend_key = format!("verify-email-resend-{}", auth_guard.user.id);
+    let allowed: bool = redis::cmd("SET")
+        .arg(&resend_key)
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(data.env.email_verification_resend_interval)
+        .query_async::<Option<String>>(&mut redis_client)
+        .await
+        .map_err(|e| {
+            let error_response = ErrorResponse {
+                error: format!("Redis error: {}", e),
+                message: "Redis error".to_string(),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
+        })?
+        .is_some();
+
+    if !allowed {
+        let retry_after: i64 = redis_client
+            .ttl(&resend_key)
+            .await
+            .unwrap_or(data.env.email_verification_resend_interval);
+        let error_response = ErrorResponse {
+            error: "".to_string(),
+            message: format!(
+                "Verification email was sent recently, try again in {} seconds",
+                retry_after.max(1)
+            ),
+        };
+        return Err((StatusCode::TOO_MANY_REQUESTS, Json(error_response)));
+    }
+
+    send_verification_email(&data, &auth_guard.user).await;
+
+    let response = SuccessResponse {
+        data: "success".to_string(),
+        message: "Verification email sent".to_string(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
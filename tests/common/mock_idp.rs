@@ -0,0 +1,146 @@
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect};
+use axum::routing::{get, post};
+use axum::{Form, Json, Router};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use books::OidcProvider;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use p256::SecretKey;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::pkcs8::EncodePrivateKey;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub const MOCK_IDP_ADDR: &str = "127.0.0.1:18089";
+const MOCK_IDP_CLIENT_ID: &str = "books-test";
+const MOCK_IDP_KEY_ID: &str = "mock-key";
+
+/// Provider registered in the test settings, pointing at [`start_mock_idp`].
+pub fn mock_idp_provider() -> OidcProvider {
+    OidcProvider {
+        name: "mock".to_string(),
+        issuer: format!("http://{}", MOCK_IDP_ADDR),
+        client_id: MOCK_IDP_CLIENT_ID.to_string(),
+        client_secret: Some("secret".to_string()),
+        scopes: "openid email profile".to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockIdpUser {
+    pub sub: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub given_name: String,
+}
+
+struct MockIdp {
+    user: MockIdpUser,
+    key: SecretKey,
+    /// Issued authorization codes with their nonce and PKCE challenge.
+    codes: Mutex<HashMap<String, (String, String)>>,
+}
+
+fn issuer() -> String {
+    format!("http://{}", MOCK_IDP_ADDR)
+}
+
+async fn discovery() -> Json<serde_json::Value> {
+    Json(json!({
+        "issuer": issuer(),
+        "authorization_endpoint": format!("{}/authorize", issuer()),
+        "token_endpoint": format!("{}/token", issuer()),
+        "jwks_uri": format!("{}/jwks", issuer())
+    }))
+}
+
+async fn authorize(
+    State(idp): State<Arc<MockIdp>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Redirect {
+    let code = uuid::Uuid::new_v4().to_string();
+    idp.codes.lock().unwrap().insert(
+        code.clone(),
+        (params["nonce"].clone(), params["code_challenge"].clone()),
+    );
+    Redirect::to(&format!(
+        "{}?code={}&state={}",
+        params["redirect_uri"], code, params["state"]
+    ))
+}
+
+async fn token(
+    State(idp): State<Arc<MockIdp>>,
+    Form(params): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let Some((nonce, challenge)) = idp.codes.lock().unwrap().remove(&params["code"]) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_grant"})),
+        );
+    };
+    let verifier_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(&params["code_verifier"]));
+    if verifier_challenge != challenge || params["client_id"] != MOCK_IDP_CLIENT_ID {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_grant"})),
+        );
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = json!({
+        "iss": issuer(),
+        "sub": idp.user.sub,
+        "aud": MOCK_IDP_CLIENT_ID,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": nonce,
+        "email": idp.user.email,
+        "email_verified": idp.user.email_verified,
+        "given_name": idp.user.given_name
+    });
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(MOCK_IDP_KEY_ID.to_string());
+    let key = EncodingKey::from_ec_der(idp.key.to_pkcs8_der().unwrap().as_bytes());
+    let id_token = jsonwebtoken::encode(&header, &claims, &key).unwrap();
+
+    (
+        StatusCode::OK,
+        Json(json!({"access_token": "mock", "token_type": "Bearer", "id_token": id_token})),
+    )
+}
+
+async fn jwks(State(idp): State<Arc<MockIdp>>) -> Json<serde_json::Value> {
+    let point = idp.key.public_key().to_encoded_point(false);
+    Json(json!({"keys": [{
+        "kty": "EC",
+        "crv": "P-256",
+        "x": URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+        "y": URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+        "kid": MOCK_IDP_KEY_ID,
+        "alg": "ES256",
+        "use": "sig"
+    }]}))
+}
+
+/// Serves a minimal OpenID provider that signs in `user` on every request.
+pub async fn start_mock_idp(user: MockIdpUser) {
+    let idp = Arc::new(MockIdp {
+        user,
+        key: SecretKey::from_slice(&[3u8; 32]).unwrap(),
+        codes: Mutex::new(HashMap::new()),
+    });
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .route("/jwks", get(jwks))
+        .with_state(idp);
+
+    let listener = tokio::net::TcpListener::bind(MOCK_IDP_ADDR).await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+}
@@ -0,0 +1,179 @@
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use books::{S3Storage, Storage};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+pub const MOCK_S3_ADDR: &str = "127.0.0.1:18090";
+const MOCK_S3_BUCKET: &str = "books-test";
+const MOCK_S3_ACCESS_KEY: &str = "mock-access-key";
+/// Small listing pages, so clients have to follow continuation tokens.
+const MOCK_S3_PAGE_SIZE: usize = 2;
+
+/// Storage backend pointing at [`start_mock_s3`].
+pub fn mock_s3_storage() -> Arc<dyn Storage> {
+    Arc::new(mock_s3_client(MOCK_S3_ACCESS_KEY))
+}
+
+pub fn mock_s3_client(access_key: &str) -> S3Storage {
+    S3Storage::new(
+        &format!("http://{}", MOCK_S3_ADDR),
+        MOCK_S3_BUCKET,
+        "us-east-1",
+        access_key,
+        "mock-secret-key",
+    )
+    .unwrap()
+}
+
+/// Objects of the mock bucket, by key.
+#[derive(Debug, Default)]
+pub struct MockS3 {
+    objects: Mutex<BTreeMap<String, Bytes>>,
+}
+
+impl MockS3 {
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+fn s3_error(status: StatusCode, code: &str) -> Response {
+    let body = format!("<Error><Code>{}</Code></Error>", code);
+    (status, body).into_response()
+}
+
+/// Only checks that requests are signed with the expected access key; the
+/// signature itself is not verified.
+fn authorized(headers: &HeaderMap) -> bool {
+    let credential = format!("AWS4-HMAC-SHA256 Credential={}/", MOCK_S3_ACCESS_KEY);
+    headers.contains_key("x-amz-date")
+        && headers.contains_key("x-amz-content-sha256")
+        && headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(&credential))
+}
+
+async fn list_objects(
+    State(s3): State<Arc<MockS3>>,
+    Path(bucket): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&headers) {
+        return s3_error(StatusCode::FORBIDDEN, "AccessDenied");
+    }
+    if bucket != MOCK_S3_BUCKET {
+        return s3_error(StatusCode::NOT_FOUND, "NoSuchBucket");
+    }
+    let prefix = params.get("prefix").cloned().unwrap_or_default();
+    let after = params
+        .get("continuation-token")
+        .cloned()
+        .unwrap_or_default();
+
+    let objects = s3.objects.lock().unwrap();
+    let keys: Vec<&String> = objects
+        .keys()
+        .filter(|key| key.starts_with(&prefix) && **key > after)
+        .collect();
+    let page = &keys[..keys.len().min(MOCK_S3_PAGE_SIZE)];
+    let truncated = keys.len() > page.len();
+
+    let mut body = format!(
+        "<ListBucketResult><Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><IsTruncated>{}</IsTruncated>",
+        bucket,
+        prefix,
+        page.len(),
+        truncated
+    );
+    for key in page {
+        body.push_str(&format!("<Contents><Key>{}</Key></Contents>", key));
+    }
+    if let Some(last) = page.last().filter(|_| truncated) {
+        body.push_str(&format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            last
+        ));
+    }
+    body.push_str("</ListBucketResult>");
+    body.into_response()
+}
+
+async fn put_object(
+    State(s3): State<Arc<MockS3>>,
+    Path((bucket, key)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !authorized(&headers) {
+        return s3_error(StatusCode::FORBIDDEN, "AccessDenied");
+    }
+    if bucket != MOCK_S3_BUCKET {
+        return s3_error(StatusCode::NOT_FOUND, "NoSuchBucket");
+    }
+    s3.objects.lock().unwrap().insert(key, body);
+    StatusCode::OK.into_response()
+}
+
+async fn get_object(
+    State(s3): State<Arc<MockS3>>,
+    Path((_, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&headers) {
+        return s3_error(StatusCode::FORBIDDEN, "AccessDenied");
+    }
+    let Some(object) = s3.objects.lock().unwrap().get(&key).cloned() else {
+        return s3_error(StatusCode::NOT_FOUND, "NoSuchKey");
+    };
+    // Only `bytes=<start>-<end>`, as sent by `S3Storage`.
+    let range = headers
+        .get("range")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes="))
+        .and_then(|value| value.split_once('-'))
+        .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)));
+    match range {
+        Some((start, end)) if start <= end && end < object.len() => {
+            (StatusCode::PARTIAL_CONTENT, object.slice(start..=end)).into_response()
+        }
+        Some(_) => s3_error(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange"),
+        None => object.into_response(),
+    }
+}
+
+async fn delete_object(
+    State(s3): State<Arc<MockS3>>,
+    Path((_, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&headers) {
+        return s3_error(StatusCode::FORBIDDEN, "AccessDenied");
+    }
+    s3.objects.lock().unwrap().remove(&key);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Serves an in-memory bucket speaking the subset of the S3 API used by
+/// `S3Storage`.
+pub async fn start_mock_s3() -> Arc<MockS3> {
+    let s3 = Arc::new(MockS3::default());
+    let app = Router::new()
+        .route("/{bucket}", get(list_objects))
+        .route("/{bucket}/", get(list_objects))
+        .route(
+            "/{bucket}/{*key}",
+            get(get_object).put(put_object).delete(delete_object),
+        )
+        .with_state(s3.clone());
+
+    let listener = tokio::net::TcpListener::bind(MOCK_S3_ADDR).await.unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    s3
+}
@@ -1,32 +1,60 @@
+pub mod mock_idp;
+pub mod mock_s3;
+
 use axum_test::TestServer;
-use books::Settings;
 use books::{AppState, route::init_router};
+use books::{MemoryMailer, Settings, Storage, process_text_jobs};
 use redis::Client;
 use serde_json::json;
 use sqlx::{PgPool, Pool, Postgres};
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
 
 pub const TEST_DB_NAME: &str = "book_rust_test";
 
+static TEST_MAILER: LazyLock<Arc<MemoryMailer>> =
+    LazyLock::new(|| Arc::new(MemoryMailer::default()));
+
+/// Mailer shared with the test server, so tests can read sent emails.
+pub fn test_mailer() -> Arc<MemoryMailer> {
+    TEST_MAILER.clone()
+}
+
 pub fn run_test<T>(test: T) -> ()
 where
     T: std::panic::UnwindSafe,
     T: FnOnce(TestServer) -> Pin<Box<dyn Future<Output = ()> + 'static>>,
 {
-    let result = std::panic::catch_unwind(|| {
+    run_test_on(None, test)
+}
+
+/// Same as [`run_test`], with uploads kept in `storage`.
+pub fn run_test_with_storage<T>(storage: Arc<dyn Storage>, test: T)
+where
+    T: std::panic::UnwindSafe,
+    T: FnOnce(TestServer) -> Pin<Box<dyn Future<Output = ()> + 'static>>,
+{
+    run_test_on(Some(storage), test)
+}
+
+fn run_test_on<T>(storage: Option<Arc<dyn Storage>>, test: T)
+where
+    T: std::panic::UnwindSafe,
+    T: FnOnce(TestServer) -> Pin<Box<dyn Future<Output = ()> + 'static>>,
+{
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
         tokio::runtime::Builder::new_current_thread()
             .enable_all()
             .build()
             .unwrap()
             .block_on(async {
-                let server = init_test_server().await;
+                let server = init_test_server(storage).await;
 
                 test(server).await;
 
                 drop_test_database().await.unwrap();
             })
-    });
+    }));
     assert!(result.is_ok());
 }
 
@@ -92,9 +120,27 @@ async fn run_migrate(db_url: &str) -> Result<(), String> {
     Ok(())
 }
 
-async fn setup_test_a_state() -> Arc<AppState> {
+fn test_settings() -> Settings {
     dotenv::from_filename(".env.test").ok();
-    let settings = Settings::init();
+    let mut settings = Settings::init();
+    settings.oidc_providers.push(mock_idp::mock_idp_provider());
+    settings.breached_passwords_dir = Some("tests/fixtures/breached-passwords".to_string());
+    settings.csrf_exempt_routes = vec!["/api/v1/user/verify-email/resend/".to_string()];
+    settings.data_export_dir = std::env::temp_dir()
+        .join("books-test-exports")
+        .to_string_lossy()
+        .into_owned();
+    settings.upload_dir = std::env::temp_dir()
+        .join("books-test-uploads")
+        .to_string_lossy()
+        .into_owned();
+    settings.blob_gc_grace_period = 0;
+    settings.search_preview_quota = 3;
+    settings
+}
+
+async fn setup_test_a_state() -> AppState {
+    let settings = test_settings();
     create_db(&settings.database_url).await.unwrap();
     run_migrate(format!("{}{}", &settings.database_url, TEST_DB_NAME).as_str())
         .await
@@ -109,19 +155,24 @@ async fn setup_test_a_state() -> Arc<AppState> {
     let redis_client = Client::open(&*settings.redis_url).unwrap();
 
     // Создаём AppState
-    Arc::new(AppState::new(pool, settings, redis_client))
+    AppState::new(pool, settings, redis_client).with_mailer(test_mailer())
 }
 
 pub async fn cleanup_db(pool: &Pool<Postgres>) {
-    sqlx::query("TRUNCATE TABLE genres, users, books CASCADE")
+    sqlx::query("TRUNCATE TABLE genres, users, books, mail_outbox, two_factor_policies CASCADE")
         .execute(pool)
         .await
         .expect("Failed to clean up database");
 }
 
-pub async fn init_test_server() -> TestServer {
+pub async fn init_test_server(storage: Option<Arc<dyn Storage>>) -> TestServer {
     drop_test_database().await.ok();
-    let app_state = setup_test_a_state().await;
+    test_mailer().clear();
+    let mut app_state = setup_test_a_state().await;
+    if let Some(storage) = storage {
+        app_state = app_state.with_storage(storage);
+    }
+    let app_state = Arc::new(app_state);
     cleanup_db(&app_state.db()).await;
     let app = init_router(app_state);
     let mut server = TestServer::new(app).expect("Failed to start test server");
@@ -129,6 +180,40 @@ pub async fn init_test_server() -> TestServer {
     server
 }
 
+/// Separate connection to the test database, for setting up state the API
+/// doesn't expose.
+pub async fn test_db() -> PgPool {
+    dotenv::from_filename(".env.test").ok();
+    let database_url =
+        std::env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env.test");
+    PgPool::connect(&format!("{}{}", database_url, TEST_DB_NAME))
+        .await
+        .expect("Failed to connect to the database")
+}
+
+/// State for calling the background jobs directly, on the test database.
+pub async fn worker_state() -> Arc<AppState> {
+    let settings = test_settings();
+    let redis_client = Client::open(&*settings.redis_url).unwrap();
+    Arc::new(AppState::new(test_db().await, settings, redis_client))
+}
+
+/// Extracts the text of uploaded book files, as the worker does in the
+/// background.
+pub async fn run_text_jobs() {
+    process_text_jobs(&worker_state().await).await.unwrap();
+}
+
+/// Gives the account the admin role directly in the test database.
+pub async fn make_admin(email: &str) {
+    let pool = test_db().await;
+    sqlx::query("UPDATE users SET role = 'админ'::user_role WHERE email = $1")
+        .bind(email)
+        .execute(&pool)
+        .await
+        .expect("Failed to update user role");
+}
+
 pub async fn login_user_token_get(server: &TestServer) -> (String, String, String) {
     // Регистрация пользователя
     let register_payload = json!({
@@ -185,3 +270,133 @@ pub async fn login_user_token_get(server: &TestServer) -> (String, String, Strin
         .to_string();
     (user_id, access, refresh)
 }
+
+/// PDF with `pages` pages reading "Page N" and the title and author in the
+/// document information.
+pub fn sample_pdf(pages: u32, title: &str, author: &str) -> Vec<u8> {
+    use lopdf::{Document, Object, Stream, dictionary, text_string};
+
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let resources_id = document.add_object(dictionary! {
+        "Font" => dictionary! { "F1" => font_id },
+    });
+
+    let mut kids = Vec::new();
+    for page in 1..=pages {
+        let content = format!("BT /F1 24 Tf 72 720 Td (Page {}) Tj ET", page);
+        let content_id = document.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Resources" => resources_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        kids.push(Object::Reference(page_id));
+    }
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages,
+        }),
+    );
+
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    let info_id = document.add_object(dictionary! {
+        "Title" => text_string(title),
+        "Author" => text_string(author),
+    });
+    document.trailer.set("Root", catalog_id);
+    document.trailer.set("Info", info_id);
+
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+/// EPUB 3 with two chapters, a navigation document, a stylesheet and a
+/// PNG cover. The first chapter carries a script and links to the second.
+pub fn sample_epub(isbn: &str) -> Vec<u8> {
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    let package = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="id">urn:isbn:{}</dc:identifier>
+    <dc:title>Война и мир</dc:title>
+    <dc:creator>Лев Толстой</dc:creator>
+    <dc:language>ru</dc:language>
+    <dc:description>Роман-эпопея</dc:description>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="one" href="text/one.xhtml" media-type="application/xhtml+xml"/>
+    <item id="two" href="text/two%20part.xhtml" media-type="application/xhtml+xml"/>
+    <item id="css" href="style.css" media-type="text/css"/>
+    <item id="cover" href="images/cover.png" media-type="image/png" properties="cover-image"/>
+  </manifest>
+  <spine>
+    <itemref idref="one"/>
+    <itemref idref="two"/>
+  </spine>
+</package>"#,
+        isbn
+    );
+    let nav = r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<body><nav epub:type="toc"><ol>
+  <li><a href="text/one.xhtml">Том первый</a>
+    <ol><li><a href="text/two%20part.xhtml#battle">Сражение</a></li></ol>
+  </li>
+</ol></nav></body></html>"#;
+    let one = r#"<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>One</title><link rel="stylesheet" href="../style.css"/></head>
+<body><h1 id="start">Глава 1</h1><script>alert(1)</script>
+<p onclick="alert(2)">Eh bien, mon prince. <a href="two%20part.xhtml#battle">Дальше</a></p>
+<img src="../images/cover.png" alt="Обложка"/></body></html>"#;
+    let two = r##"<html xmlns="http://www.w3.org/1999/xhtml">
+<body><h1 id="battle">Глава 2</h1><p>Аустерлиц. <a href="#battle">Сюда</a></p></body></html>"##;
+    let container = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#;
+
+    let cover = image::RgbImage::from_pixel(60, 90, image::Rgb([180, 30, 30]));
+    let mut cover_bytes = Vec::new();
+    cover
+        .write_to(&mut Cursor::new(&mut cover_bytes), image::ImageFormat::Png)
+        .unwrap();
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("mimetype", stored).unwrap();
+    zip.write_all(b"application/epub+zip").unwrap();
+    let entries: [(&str, &[u8]); 7] = [
+        ("META-INF/container.xml", container.as_bytes()),
+        ("OEBPS/content.opf", package.as_bytes()),
+        ("OEBPS/nav.xhtml", nav.as_bytes()),
+        ("OEBPS/text/one.xhtml", one.as_bytes()),
+        ("OEBPS/text/two part.xhtml", two.as_bytes()),
+        ("OEBPS/style.css", b"h1 { color: darkred; }"),
+        ("OEBPS/images/cover.png", &cover_bytes),
+    ];
+    for (name, content) in entries {
+        zip.start_file(name, deflated).unwrap();
+        zip.write_all(content).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
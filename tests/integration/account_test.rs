@@ -0,0 +1,257 @@
+use crate::common::{login_user_token_get, run_test, test_db, worker_state};
+use assert2::check;
+use axum_test::TestServer;
+use books::{remove_expired_data_exports, resume_data_exports};
+use serde_json::json;
+use std::io::Read;
+
+/// Polls the export job until it's finished and returns its status.
+async fn wait_for_export(server: &TestServer, token: &str, export_id: &str) -> String {
+    for _ in 0..50 {
+        let response = server
+            .get(&format!("/api/v1/user/me/exports/{}/", export_id))
+            .authorization(format!("Bearer {}", token))
+            .await;
+        let body: serde_json::Value = response.json();
+        let status = body["data"]["status"].as_str().unwrap().to_string();
+        if status != "pending" && status != "processing" {
+            return status;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    }
+    panic!("Data export {} didn't finish", export_id);
+}
+
+#[test]
+fn test_data_export() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .post("/api/v1/user/me/exports/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 202);
+            let body: serde_json::Value = response.json();
+            let export_id = body["data"]["id"].as_str().unwrap().to_string();
+
+            check!(wait_for_export(&server, &token, &export_id).await == "ready");
+
+            let response = server
+                .get(&format!("/api/v1/user/me/exports/{}/download/", export_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.header("content-type") == "application/zip");
+
+            let bytes = response.as_bytes().to_vec();
+            let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+            let mut profile = String::new();
+            archive
+                .by_name("profile.json")
+                .unwrap()
+                .read_to_string(&mut profile)
+                .unwrap();
+            let profile: serde_json::Value = serde_json::from_str(&profile).unwrap();
+            check!(profile["id"] == user_id);
+            check!(profile["email"] == "admin@example.com");
+            check!(profile.get("password").is_none());
+
+            let mut sessions = String::new();
+            archive
+                .by_name("sessions.json")
+                .unwrap()
+                .read_to_string(&mut sessions)
+                .unwrap();
+            let sessions: serde_json::Value = serde_json::from_str(&sessions).unwrap();
+            check!(!sessions.as_array().unwrap().is_empty());
+            check!(archive.by_name("books.json").is_ok());
+            check!(archive.by_name("library.json").is_ok());
+            check!(archive.by_name("manifest.json").is_ok());
+
+            // Another user can't see the export.
+            server
+                .post("/api/v1/user/register/")
+                .json(&json!({
+                    "first_name": "Other",
+                    "last_name": "Reader",
+                    "age": 25,
+                    "email": "other@example.com",
+                    "password": "password123"
+                }))
+                .await;
+            let response = server
+                .post("/api/v1/user/login/")
+                .json(&json!({"email": "other@example.com", "password": "password123"}))
+                .await;
+            let body: serde_json::Value = response.json();
+            let other_token = body["data"]["access_token"].as_str().unwrap().to_string();
+
+            let response = server
+                .get(&format!("/api/v1/user/me/exports/{}/download/", export_id))
+                .authorization(format!("Bearer {}", other_token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
+
+#[test]
+fn test_expired_data_exports_are_removed() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let response = server
+                .post("/api/v1/user/me/exports/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            let export_id = body["data"]["id"].as_str().unwrap().to_string();
+            check!(wait_for_export(&server, &token, &export_id).await == "ready");
+
+            let pool = test_db().await;
+            let state = worker_state().await;
+            check!(remove_expired_data_exports(&state).await.unwrap() == 0);
+
+            let file_path: String = sqlx::query_scalar(
+                "UPDATE data_exports SET expires_at = NOW() - interval '1 minute' RETURNING file_path",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            check!(std::path::Path::new(&file_path).exists());
+            check!(remove_expired_data_exports(&state).await.unwrap() == 1);
+            check!(!std::path::Path::new(&file_path).exists());
+
+            let response = server
+                .get(&format!("/api/v1/user/me/exports/{}/", export_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
+
+#[test]
+fn test_interrupted_data_exports_are_resumed_or_failed() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, token, _) = login_user_token_get(&server).await;
+            let pool = test_db().await;
+            // Jobs left behind by a server that stopped while building them.
+            let recent: uuid::Uuid = sqlx::query_scalar(
+                "INSERT INTO data_exports (user_id, status) VALUES ($1::uuid, 'processing') RETURNING id",
+            )
+            .bind(&user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            let stale: uuid::Uuid = sqlx::query_scalar(
+                r#"
+                INSERT INTO data_exports (user_id, created_at)
+                VALUES ($1::uuid, NOW() - interval '2 hours')
+                RETURNING id
+                "#,
+            )
+            .bind(&user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+            resume_data_exports(&worker_state().await).await.unwrap();
+
+            check!(wait_for_export(&server, &token, &stale.to_string()).await == "failed");
+            check!(wait_for_export(&server, &token, &recent.to_string()).await == "ready");
+            let response = server
+                .get(&format!("/api/v1/user/me/exports/{}/download/", recent))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.header("content-length") == response.as_bytes().len().to_string());
+        })
+    });
+}
+
+#[test]
+fn test_delete_account() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .delete("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"password": "wrong-password"}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .delete("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"password": "password123"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 401);
+
+            let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+                .fetch_one(&test_db().await)
+                .await
+                .unwrap();
+            check!(users == 0);
+        })
+    });
+}
+
+#[test]
+fn test_delete_account_with_books_anonymizes_user() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, token, _) = login_user_token_get(&server).await;
+
+            let pool = test_db().await;
+            let user_uuid = uuid::Uuid::parse_str(&user_id).unwrap();
+            let genre_id: uuid::Uuid =
+                sqlx::query_scalar("INSERT INTO genres (name) VALUES ('Novel') RETURNING id")
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            sqlx::query(
+                r#"INSERT INTO books (title, author_id, genre_id, publication_year, isbn, price)
+                   VALUES ('Book', $1, $2, 2020, '9780000000001', 10)"#,
+            )
+            .bind(user_uuid)
+            .bind(genre_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+            let response = server
+                .delete("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"password": "password123"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let (email, first_name): (String, String) =
+                sqlx::query_as("SELECT email, first_name FROM users WHERE id = $1")
+                    .bind(user_uuid)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            check!(email == format!("deleted-{}@deleted.invalid", user_id));
+            check!(first_name == "Deleted");
+
+            let response = server
+                .post("/api/v1/user/login/")
+                .json(&json!({"email": "admin@example.com", "password": "password123"}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+        })
+    });
+}
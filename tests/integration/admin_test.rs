@@ -0,0 +1,321 @@
+use crate::common::{login_user_token_get, make_admin, run_test};
+use assert2::check;
+use axum_test::TestServer;
+use serde_json::json;
+
+/// Registers a regular user and returns their id and access token.
+async fn register_user(server: &TestServer, email: &str) -> (String, String) {
+    let response = server
+        .post("/api/v1/user/register/")
+        .json(&json!({
+            "first_name": "Regular",
+            "last_name": "Reader",
+            "age": 25,
+            "email": email,
+            "password": "password123"
+        }))
+        .await;
+    let body: serde_json::Value = response.json();
+    let user_id = body["data"]["id"].as_str().unwrap().to_string();
+
+    let response = server
+        .post("/api/v1/user/login/")
+        .json(&json!({"email": email, "password": "password123"}))
+        .await;
+    let body: serde_json::Value = response.json();
+    let token = body["data"]["access_token"].as_str().unwrap().to_string();
+    (user_id, token)
+}
+
+/// Logs in admin@example.com and gives it the admin role.
+async fn login_admin(server: &TestServer) -> (String, String) {
+    let (admin_id, token, _) = login_user_token_get(server).await;
+    make_admin("admin@example.com").await;
+    (admin_id, token)
+}
+
+#[test]
+fn test_admin_list_users() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, reader_token) = register_user(&server, "reader@example.com").await;
+            register_user(&server, "writer@example.com").await;
+            let (_, token) = login_admin(&server).await;
+
+            let response = server
+                .get("/api/v1/user/admin/users/")
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            let response = server
+                .get("/api/v1/user/admin/users/")
+                .authorization(format!("Bearer {}", token))
+                .add_query_param("per_page", 2)
+                .add_query_param("page", 2)
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["total"] == 3);
+            check!(body["data"]["users"].as_array().unwrap().len() == 1);
+
+            let response = server
+                .get("/api/v1/user/admin/users/")
+                .authorization(format!("Bearer {}", token))
+                .add_query_param("search", "READER")
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["total"] == 1);
+            check!(body["data"]["users"][0]["email"] == "reader@example.com");
+
+            let response = server
+                .get("/api/v1/user/admin/users/")
+                .authorization(format!("Bearer {}", token))
+                .add_query_param("role", "Admin")
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["total"] == 1);
+            check!(body["data"]["users"][0]["email"] == "admin@example.com");
+
+            let response = server
+                .get("/api/v1/user/admin/users/")
+                .authorization(format!("Bearer {}", token))
+                .add_query_param("per_page", 500)
+                .await;
+            check!(response.status_code().as_u16() == 400);
+        })
+    })
+}
+
+#[test]
+fn test_admin_suspend_and_lift() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, user_token) = register_user(&server, "reader@example.com").await;
+            let (_, token) = login_admin(&server).await;
+            let expires_at = chrono::Utc::now() + chrono::Duration::days(7);
+
+            let response = server
+                .post(&format!("/api/v1/user/admin/users/{}/suspend/", user_id))
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"reason": "Spam in reviews", "expires_at": expires_at}))
+                .await;
+            check!(response.status_code().as_u16() == 201);
+
+            // Sessions are revoked and new logins are refused.
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", user_token))
+                .await;
+            check!(response.status_code().as_u16() == 401);
+            let response = server
+                .post("/api/v1/user/login/")
+                .json(&json!({"email": "reader@example.com", "password": "password123"}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+            let body: serde_json::Value = response.json();
+            check!(body["error"] == "Spam in reviews");
+
+            let response = server
+                .get(&format!("/api/v1/user/admin/users/{}/", user_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["suspension"]["reason"] == "Spam in reviews");
+
+            let response = server
+                .delete(&format!("/api/v1/user/admin/users/{}/suspend/", user_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            // Nothing left to lift, and nobody to lift it for.
+            let response = server
+                .delete(&format!("/api/v1/user/admin/users/{}/suspend/", user_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+            let body: serde_json::Value = response.json();
+            check!(body["message"] == "User is not suspended");
+            let response = server
+                .delete(&format!(
+                    "/api/v1/user/admin/users/{}/suspend/",
+                    uuid::Uuid::new_v4()
+                ))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+            let body: serde_json::Value = response.json();
+            check!(body["message"] == "User not found");
+
+            let response = server
+                .post("/api/v1/user/login/")
+                .json(&json!({"email": "reader@example.com", "password": "password123"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get("/api/v1/user/admin/audit-log/")
+                .authorization(format!("Bearer {}", token))
+                .add_query_param("target_user_id", &user_id)
+                .await;
+            let body: serde_json::Value = response.json();
+            let actions: Vec<&str> = body["data"]["entries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|entry| entry["action"].as_str().unwrap())
+                .collect();
+            check!(actions == vec!["lift_suspension", "suspend"]);
+        })
+    })
+}
+
+#[test]
+fn test_admin_role_verify_and_delete() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, _) = register_user(&server, "reader@example.com").await;
+            let (admin_id, token) = login_admin(&server).await;
+
+            let response = server
+                .patch(&format!("/api/v1/user/admin/users/{}/role/", user_id))
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"role": "Author"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["role"] == "Author");
+
+            let response = server
+                .post(&format!("/api/v1/user/admin/users/{}/verify/", user_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["verified"] == true);
+
+            let response = server
+                .delete(&format!("/api/v1/user/admin/users/{}/", admin_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .delete(&format!("/api/v1/user/admin/users/{}/", user_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get(&format!("/api/v1/user/admin/users/{}/", user_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+
+            let response = server
+                .get("/api/v1/user/admin/audit-log/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["total"] == 3);
+            check!(body["data"]["entries"][0]["details"] == "reader@example.com");
+        })
+    })
+}
+
+#[test]
+fn test_admin_impersonation() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, _) = register_user(&server, "reader@example.com").await;
+            let (admin_id, token) = login_admin(&server).await;
+
+            let response = server
+                .post(&format!(
+                    "/api/v1/user/admin/users/{}/impersonate/",
+                    admin_id
+                ))
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"reason": "Ticket #42"}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .post(&format!(
+                    "/api/v1/user/admin/users/{}/impersonate/",
+                    user_id
+                ))
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"reason": "Ticket #42"}))
+                .await;
+            check!(response.status_code().as_u16() == 201);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["impersonator_id"] == admin_id.as_str());
+            let impersonation_token = body["data"]["access_token"].as_str().unwrap().to_string();
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", impersonation_token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["id"] == user_id.as_str());
+
+            let response = server
+                .post("/api/v1/user/password/change/")
+                .authorization(format!("Bearer {}", impersonation_token))
+                .json(&json!({"old_password": "password123", "new_password": "newpassword123"}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            let response = server
+                .delete("/api/v1/user/impersonation/")
+                .authorization(format!("Bearer {}", impersonation_token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", impersonation_token))
+                .await;
+            check!(response.status_code().as_u16() == 401);
+
+            let response = server
+                .get("/api/v1/user/admin/audit-log/")
+                .authorization(format!("Bearer {}", token))
+                .add_query_param("target_user_id", &user_id)
+                .await;
+            let body: serde_json::Value = response.json();
+            let entries: Vec<(String, String)> = body["data"]["entries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|entry| {
+                    (
+                        entry["action"].as_str().unwrap().to_string(),
+                        entry["details"].as_str().unwrap_or_default().to_string(),
+                    )
+                })
+                .collect();
+            check!(
+                entries
+                    == vec![
+                        ("stop_impersonation".to_string(), "".to_string()),
+                        (
+                            "impersonated_request".to_string(),
+                            "DELETE /api/v1/user/impersonation/".to_string()
+                        ),
+                        (
+                            "impersonated_request".to_string(),
+                            "POST /api/v1/user/password/change/".to_string()
+                        ),
+                        (
+                            "impersonated_request".to_string(),
+                            "GET /api/v1/user/me/".to_string()
+                        ),
+                        ("start_impersonation".to_string(), "Ticket #42".to_string()),
+                    ]
+            );
+        })
+    })
+}
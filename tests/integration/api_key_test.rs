@@ -0,0 +1,107 @@
+use crate::common::{login_user_token_get, run_test};
+use assert2::check;
+use axum_test::TestServer;
+use serde_json::json;
+
+async fn create_api_key(server: &TestServer, token: &str, scopes: &[&str]) -> serde_json::Value {
+    let response = server
+        .post("/api/v1/user/api-keys/")
+        .authorization(format!("Bearer {}", token))
+        .json(&json!({"name": "Import script", "scopes": scopes, "expires_in_days": 30}))
+        .await;
+    check!(response.status_code().as_u16() == 201);
+    let body: serde_json::Value = response.json();
+    body["data"].clone()
+}
+
+#[test]
+fn test_api_key_scopes() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, token, _) = login_user_token_get(&server).await;
+            let read_key = create_api_key(&server, &token, &["read"]).await;
+            let read_key = read_key["key"].as_str().unwrap();
+            let write_key = create_api_key(&server, &token, &["read", "write"]).await;
+            let write_key = write_key["key"].as_str().unwrap();
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .add_header("X-Api-Key", read_key)
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["id"] == user_id.as_str());
+
+            let response = server
+                .patch("/api/v1/user/me/")
+                .add_header("X-Api-Key", read_key)
+                .json(&json!({"biography": "Imported"}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            let response = server
+                .patch("/api/v1/user/me/")
+                .authorization(format!("ApiKey {}", write_key))
+                .json(&json!({"biography": "Imported"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            // A key can't be used to mint more keys.
+            let response = server
+                .post("/api/v1/user/api-keys/")
+                .authorization(format!("ApiKey {}", write_key))
+                .json(&json!({"name": "Another", "scopes": ["read"]}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .add_header("X-Api-Key", "bk_unknown")
+                .await;
+            check!(response.status_code().as_u16() == 401);
+        })
+    })
+}
+
+#[test]
+fn test_api_key_listing_and_revocation() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let created = create_api_key(&server, &token, &["read"]).await;
+            let key = created["key"].as_str().unwrap();
+            check!(key.starts_with(created["prefix"].as_str().unwrap()));
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .add_header("X-Api-Key", key)
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get("/api/v1/user/api-keys/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            let api_keys = body["data"].as_array().unwrap();
+            check!(api_keys.len() == 1);
+            check!(api_keys[0].get("key").is_none());
+            check!(api_keys[0]["last_used_at"].is_string());
+
+            let response = server
+                .delete(&format!(
+                    "/api/v1/user/api-keys/{}/",
+                    created["id"].as_str().unwrap()
+                ))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .add_header("X-Api-Key", key)
+                .await;
+            check!(response.status_code().as_u16() == 401);
+        })
+    })
+}
@@ -0,0 +1,120 @@
+use crate::common::{login_user_token_get, make_admin, run_test};
+use assert2::check;
+use axum_test::multipart::{MultipartForm, Part};
+use std::io::Cursor;
+
+fn png_image(width: u32, height: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(width, height, image::Rgb([200, 40, 40]));
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .unwrap();
+    bytes
+}
+
+fn avatar_form(bytes: Vec<u8>) -> MultipartForm {
+    MultipartForm::new().add_part(
+        "file",
+        Part::bytes(bytes)
+            .file_name("avatar.png")
+            .mime_type("image/png"),
+    )
+}
+
+#[test]
+fn test_upload_avatar() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .put("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .multipart(avatar_form(png_image(300, 200)))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let file = body["data"]["file"].as_str().unwrap().to_string();
+            check!(file.starts_with("uploads/blobs/"));
+            check!(file.ends_with(".jpg"));
+            check!(body["data"]["variants"].as_array().unwrap().len() == 6);
+
+            let response = server.get(&format!("/{}", file)).await;
+            check!(response.status_code().as_u16() == 200);
+            let avatar = image::load_from_memory(response.as_bytes()).unwrap();
+            check!(avatar.width() == 256);
+            check!(avatar.height() == 256);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["file"] == file.as_str());
+
+            // The previous files are removed once nothing uses them.
+            let response = server
+                .put("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .multipart(avatar_form(png_image(100, 100)))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            make_admin("admin@example.com").await;
+            let response = server
+                .post("/api/v1/user/admin/storage/gc/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["removed"] == 6);
+            let response = server.get(&format!("/{}", file)).await;
+            check!(response.status_code().as_u16() == 404);
+
+            let response = server
+                .delete("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["file"] == "uploads/photo-user/default.png");
+        })
+    });
+}
+
+#[test]
+fn test_upload_avatar_rejects_invalid_files() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            // The content type sent by the client doesn't matter.
+            let response = server
+                .put("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .multipart(avatar_form(
+                    b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>".to_vec(),
+                ))
+                .await;
+            check!(response.status_code().as_u16() == 415);
+
+            let mut truncated = png_image(300, 200);
+            truncated.truncate(64);
+            let response = server
+                .put("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .multipart(avatar_form(truncated))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .put("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .multipart(avatar_form(vec![0; 6 * 1024 * 1024]))
+                .await;
+            check!(response.status_code().as_u16() == 413);
+        })
+    });
+}
@@ -0,0 +1,194 @@
+use crate::common::{login_user_token_get, make_admin, run_test, test_db};
+use assert2::check;
+use axum_test::TestServer;
+use axum_test::multipart::{MultipartForm, Part};
+use serde_json::json;
+use std::io::Cursor;
+
+fn avatar_form() -> MultipartForm {
+    let image = image::RgbImage::from_pixel(120, 120, image::Rgb([20, 60, 220]));
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .unwrap();
+    MultipartForm::new().add_part("file", Part::bytes(bytes).file_name("avatar.png"))
+}
+
+async fn login_reader(server: &TestServer) -> String {
+    server
+        .post("/api/v1/user/register/")
+        .json(&json!({
+            "first_name": "Regular",
+            "last_name": "Reader",
+            "age": 25,
+            "email": "reader@example.com",
+            "password": "password123"
+        }))
+        .await;
+    let response = server
+        .post("/api/v1/user/login/")
+        .json(&json!({"email": "reader@example.com", "password": "password123"}))
+        .await;
+    let body: serde_json::Value = response.json();
+    body["data"]["access_token"].as_str().unwrap().to_string()
+}
+
+async fn upload_avatar(server: &TestServer, token: &str) -> String {
+    let response = server
+        .put("/api/v1/user/me/avatar/")
+        .authorization(format!("Bearer {}", token))
+        .multipart(avatar_form())
+        .await;
+    check!(response.status_code().as_u16() == 200);
+    let body: serde_json::Value = response.json();
+    body["data"]["file"].as_str().unwrap().to_string()
+}
+
+async fn storage_stats(server: &TestServer, token: &str) -> serde_json::Value {
+    let response = server
+        .get("/api/v1/user/admin/storage/")
+        .authorization(format!("Bearer {}", token))
+        .await;
+    check!(response.status_code().as_u16() == 200);
+    let body: serde_json::Value = response.json();
+    body["data"].clone()
+}
+
+async fn collect_garbage(server: &TestServer, token: &str) -> serde_json::Value {
+    let response = server
+        .post("/api/v1/user/admin/storage/gc/")
+        .authorization(format!("Bearer {}", token))
+        .await;
+    check!(response.status_code().as_u16() == 200);
+    let body: serde_json::Value = response.json();
+    body["data"].clone()
+}
+
+#[test]
+fn test_identical_uploads_are_stored_once() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, admin_token, _) = login_user_token_get(&server).await;
+            make_admin("admin@example.com").await;
+            let reader_token = login_reader(&server).await;
+
+            let admin_file = upload_avatar(&server, &admin_token).await;
+            let reader_file = upload_avatar(&server, &reader_token).await;
+            check!(admin_file == reader_file);
+
+            let stats = storage_stats(&server, &admin_token).await;
+            check!(stats["blobs"] == 6);
+            check!(stats["unreferenced"] == 0);
+
+            // The reader still uses the files.
+            let response = server
+                .delete("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", admin_token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(collect_garbage(&server, &admin_token).await["removed"] == 0);
+            let response = server.get(&format!("/{}", reader_file)).await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .delete("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(storage_stats(&server, &admin_token).await["unreferenced"] == 6);
+            check!(collect_garbage(&server, &admin_token).await["removed"] == 6);
+            check!(storage_stats(&server, &admin_token).await["blobs"] == 0);
+            let response = server.get(&format!("/{}", reader_file)).await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
+
+#[test]
+fn test_verify_detects_corrupted_blobs() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let reader_token = login_reader(&server).await;
+            let file = upload_avatar(&server, &token).await;
+
+            let response = server
+                .post("/api/v1/user/admin/storage/verify/")
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            make_admin("admin@example.com").await;
+            let response = server
+                .post("/api/v1/user/admin/storage/verify/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["checked"] == 6);
+            check!(body["data"]["corrupted"].as_array().unwrap().is_empty());
+
+            let path = std::env::temp_dir()
+                .join("books-test-uploads")
+                .join(file.strip_prefix("uploads/").unwrap());
+            tokio::fs::write(&path, b"not an image").await.unwrap();
+
+            let response = server
+                .post("/api/v1/user/admin/storage/verify/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            let corrupted = body["data"]["corrupted"].as_array().unwrap();
+            check!(corrupted.len() == 1);
+            check!(file.contains(corrupted[0].as_str().unwrap()));
+            check!(storage_stats(&server, &token).await["corrupted"] == 1);
+
+            // Uploading the same image again repairs the blob.
+            upload_avatar(&server, &token).await;
+            check!(storage_stats(&server, &token).await["corrupted"] == 0);
+            let response = server.get(&format!("/{}", file)).await;
+            check!(image::load_from_memory(response.as_bytes()).is_ok());
+        })
+    });
+}
+
+#[test]
+fn test_gc_keeps_blobs_referenced_during_the_run() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, token, _) = login_user_token_get(&server).await;
+            make_admin("admin@example.com").await;
+            let file = upload_avatar(&server, &token).await;
+            server
+                .delete("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let hash = file.rsplit('/').next().unwrap().split('.').next().unwrap();
+
+            // An upload referencing the blob again holds its row until it
+            // commits, with the count not updated yet.
+            let pool = test_db().await;
+            let mut tx = pool.begin().await.unwrap();
+            sqlx::query("SELECT hash FROM blobs WHERE hash = $1 FOR UPDATE")
+                .bind(hash)
+                .execute(&mut *tx)
+                .await
+                .unwrap();
+            sqlx::query(
+                "INSERT INTO blob_references (blob_hash, owner_type, owner_id) VALUES ($1, 'avatar', $2)",
+            )
+            .bind(hash)
+            .bind(uuid::Uuid::parse_str(&user_id).unwrap())
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+            check!(collect_garbage(&server, &token).await["removed"] == 5);
+            tx.commit().await.unwrap();
+
+            // The stale count alone doesn't get it removed either.
+            check!(storage_stats(&server, &token).await["unreferenced"] == 1);
+            check!(collect_garbage(&server, &token).await["removed"] == 0);
+            let response = server.get(&format!("/{}", file)).await;
+            check!(response.status_code().as_u16() == 200);
+        })
+    });
+}
@@ -0,0 +1,154 @@
+use crate::common::{login_user_token_get, make_admin, run_test, test_db};
+use assert2::check;
+use axum_test::TestServer;
+use axum_test::multipart::{MultipartForm, Part};
+use serde_json::json;
+use std::io::Cursor;
+
+fn cover_form(width: u32, height: u32) -> MultipartForm {
+    let image = image::RgbImage::from_pixel(width, height, image::Rgb([200, 40, 40]));
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .unwrap();
+    MultipartForm::new().add_part("file", Part::bytes(bytes).file_name("cover.png"))
+}
+
+/// Makes admin@example.com an author of a new book and returns the book id.
+async fn create_authored_book() -> String {
+    let pool = test_db().await;
+    sqlx::query("UPDATE users SET role = 'автор'::user_role WHERE email = 'admin@example.com'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let genre_id: uuid::Uuid =
+        sqlx::query_scalar("INSERT INTO genres (name) VALUES ('Novel') RETURNING id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    let book_id: uuid::Uuid = sqlx::query_scalar(
+        r#"INSERT INTO books (title, author_id, genre_id, publication_year, isbn, price)
+           SELECT 'Book', id, $1, 2020, '9780000000001', 10 FROM users
+           WHERE email = 'admin@example.com'
+           RETURNING id"#,
+    )
+    .bind(genre_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    book_id.to_string()
+}
+
+async fn login_other_author(server: &TestServer) -> String {
+    server
+        .post("/api/v1/user/register/")
+        .json(&json!({
+            "first_name": "Other",
+            "last_name": "Author",
+            "age": 40,
+            "email": "other-author@example.com",
+            "password": "password123"
+        }))
+        .await;
+    sqlx::query(
+        "UPDATE users SET role = 'автор'::user_role WHERE email = 'other-author@example.com'",
+    )
+    .execute(&test_db().await)
+    .await
+    .unwrap();
+    let response = server
+        .post("/api/v1/user/login/")
+        .json(&json!({"email": "other-author@example.com", "password": "password123"}))
+        .await;
+    let body: serde_json::Value = response.json();
+    body["data"]["access_token"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn test_upload_book_cover() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+
+            let response = server
+                .put(&format!("/api/v1/book/{}/cover/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(cover_form(400, 600))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let cover_image = body["data"]["cover_image"].as_str().unwrap().to_string();
+            check!(cover_image.starts_with("uploads/blobs/"));
+            check!(cover_image.ends_with(".jpg"));
+            check!(body["data"]["cover_color"] == "#c82828");
+            check!(body["data"]["cover_blurhash"].is_string());
+
+            // Widths above the original are capped: 160, 320 and 400.
+            let variants = body["data"]["cover_variants"].as_array().unwrap();
+            check!(variants.len() == 6);
+            check!(variants[0]["width"] == 160);
+            check!(variants[0]["height"] == 240);
+            for variant in variants {
+                let response = server
+                    .get(&format!("/{}", variant["file"].as_str().unwrap()))
+                    .await;
+                check!(response.status_code().as_u16() == 200);
+            }
+
+            let response = server.get(&format!("/api/v1/book/{}", book_id)).await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["cover_image"] == cover_image.as_str());
+            check!(body["data"]["cover_variants"].as_array().unwrap().len() == 6);
+
+            let response = server
+                .delete(&format!("/api/v1/book/{}/cover/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["cover_image"] == "uploads/books/default.jpg");
+            check!(
+                body["data"]["cover_variants"]
+                    .as_array()
+                    .unwrap()
+                    .is_empty()
+            );
+
+            make_admin("admin@example.com").await;
+            let response = server
+                .post("/api/v1/user/admin/storage/gc/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["removed"] == 6);
+            let response = server.get(&format!("/{}", cover_image)).await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
+
+#[test]
+fn test_upload_book_cover_of_another_author() {
+    run_test(|server| {
+        Box::pin(async move {
+            login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let other_token = login_other_author(&server).await;
+
+            let response = server
+                .put(&format!("/api/v1/book/{}/cover/", book_id))
+                .authorization(format!("Bearer {}", other_token))
+                .multipart(cover_form(400, 600))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            let response = server
+                .put(&format!("/api/v1/book/{}/cover/", uuid::Uuid::new_v4()))
+                .authorization(format!("Bearer {}", other_token))
+                .multipart(cover_form(400, 600))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
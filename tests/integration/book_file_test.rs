@@ -0,0 +1,1129 @@
+use crate::common::{
+    login_user_token_get, make_admin, run_test, run_text_jobs, sample_epub, sample_pdf, test_db,
+};
+use assert2::check;
+use axum::http::Method;
+use axum_test::TestServer;
+use axum_test::multipart::{MultipartForm, Part};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use books::Settings;
+use serde_json::json;
+
+fn pdf_form(bytes: Vec<u8>) -> MultipartForm {
+    MultipartForm::new().add_part(
+        "file",
+        Part::bytes(bytes)
+            .file_name("book.pdf")
+            .mime_type("application/pdf"),
+    )
+}
+
+fn epub_form(bytes: Vec<u8>) -> MultipartForm {
+    MultipartForm::new().add_part(
+        "file",
+        Part::bytes(bytes)
+            .file_name("book.epub")
+            .mime_type("application/epub+zip"),
+    )
+}
+
+/// Makes admin@example.com an author of a new book and returns the book id.
+async fn create_authored_book() -> String {
+    let pool = test_db().await;
+    sqlx::query("UPDATE users SET role = 'автор'::user_role WHERE email = 'admin@example.com'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let genre_id: uuid::Uuid =
+        sqlx::query_scalar("INSERT INTO genres (name) VALUES ('Novel') RETURNING id")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    let book_id: uuid::Uuid = sqlx::query_scalar(
+        r#"INSERT INTO books (title, author_id, genre_id, publication_year, isbn, price)
+           SELECT 'War and Peace', id, $1, 1869, '9780000000002', 10 FROM users
+           WHERE email = 'admin@example.com'
+           RETURNING id"#,
+    )
+    .bind(genre_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    book_id.to_string()
+}
+
+/// Registers reader@example.com and returns the user id and access token.
+async fn login_reader(server: &TestServer) -> (String, String) {
+    let response = server
+        .post("/api/v1/user/register/")
+        .json(&json!({
+            "first_name": "Regular",
+            "last_name": "Reader",
+            "age": 25,
+            "email": "reader@example.com",
+            "password": "password123"
+        }))
+        .await;
+    let body: serde_json::Value = response.json();
+    let user_id = body["data"]["id"].as_str().unwrap().to_string();
+    let response = server
+        .post("/api/v1/user/login/")
+        .json(&json!({"email": "reader@example.com", "password": "password123"}))
+        .await;
+    let body: serde_json::Value = response.json();
+    let token = body["data"]["access_token"].as_str().unwrap().to_string();
+    (user_id, token)
+}
+
+#[test]
+fn test_upload_and_download_pdf() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let (reader_id, reader_token) = login_reader(&server).await;
+            let pdf = sample_pdf(3, "Война и мир", "Лев Толстой");
+
+            let response = server
+                .put(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(pdf.clone()))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["format"] == "pdf");
+            check!(body["data"]["size"] == pdf.len());
+            check!(body["data"]["page_count"] == 3);
+            check!(body["data"]["title"] == "Война и мир");
+            check!(body["data"]["author"] == "Лев Толстой");
+
+            let response = server.get(&format!("/api/v1/book/{}", book_id)).await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["page_count"] == 3);
+
+            let response = server
+                .get(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.header("content-type") == "application/pdf");
+            check!(response.as_bytes().as_ref() == pdf.as_slice());
+
+            let response = server.get(&format!("/api/v1/book/{}/pdf/", book_id)).await;
+            check!(response.status_code().as_u16() == 401);
+            let response = server
+                .get(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            // The stored file can't be fetched around the access check.
+            let storage_key: String = sqlx::query_scalar("SELECT storage_key FROM blobs")
+                .fetch_one(&test_db().await)
+                .await
+                .unwrap();
+            check!(storage_key.starts_with("private/"));
+            let response = server.get(&format!("/uploads/{}", storage_key)).await;
+            check!(response.status_code().as_u16() == 404);
+
+            make_admin("admin@example.com").await;
+            let access_url = format!("/api/v1/user/admin/users/{}/books/{}/", reader_id, book_id);
+            let response = server
+                .put(&access_url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let response = server
+                .get(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.as_bytes().as_ref() == pdf.as_slice());
+
+            let response = server
+                .delete(&access_url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let response = server
+                .delete(&access_url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+            let response = server
+                .get(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+        })
+    });
+}
+
+/// The PDF with `subject` in its document information.
+fn with_subject(pdf: Vec<u8>, subject: &str) -> Vec<u8> {
+    let mut document = lopdf::Document::load_mem(&pdf).unwrap();
+    let info_id = document
+        .trailer
+        .get(b"Info")
+        .and_then(lopdf::Object::as_reference)
+        .unwrap();
+    document
+        .get_dictionary_mut(info_id)
+        .unwrap()
+        .set("Subject", lopdf::text_string(subject));
+    let mut bytes = Vec::new();
+    document.save_to(&mut bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn test_pdf_metadata_fills_empty_description() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let book_url = format!("/api/v1/book/{}", book_id);
+            let pdf_url = format!("/api/v1/book/{}/pdf/", book_id);
+
+            // The title set by the author is kept, the missing description
+            // is taken from the document.
+            let pdf = with_subject(sample_pdf(2, "Война и мир", "Лев Толстой"), "Роман-эпопея");
+            let response = server
+                .put(&pdf_url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(pdf))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = server.get(&book_url).await.json();
+            check!(body["data"]["title"] == "War and Peace");
+            check!(body["data"]["description"] == "Роман-эпопея");
+
+            // A description is never overwritten, the title and author stay
+            // on the file only.
+            let pdf = with_subject(sample_pdf(3, "Война и мир", "Лев Толстой"), "Другое");
+            let response = server
+                .put(&pdf_url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(pdf))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["title"] == "Война и мир");
+            check!(body["data"]["author"] == "Лев Толстой");
+            let body: serde_json::Value = server.get(&book_url).await.json();
+            check!(body["data"]["title"] == "War and Peace");
+            check!(body["data"]["description"] == "Роман-эпопея");
+            check!(body["data"]["page_count"] == 3);
+        })
+    });
+}
+
+#[test]
+fn test_upload_rejects_invalid_pdf() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let url = format!("/api/v1/book/{}/pdf/", book_id);
+
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(b"GIF89a not a pdf".to_vec()))
+                .await;
+            check!(response.status_code().as_u16() == 415);
+
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(b"%PDF-1.7\nnot really a pdf".to_vec()))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let mut truncated = sample_pdf(2, "Book", "Author");
+            truncated.truncate(truncated.len() / 2);
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(truncated))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
+
+#[test]
+fn test_delete_pdf() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let url = format!("/api/v1/book/{}/pdf/", book_id);
+
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(sample_pdf(2, "Book", "Author")))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .delete(&url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let response = server
+                .delete(&url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+
+            let response = server.get(&format!("/api/v1/book/{}", book_id)).await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["page_count"].is_null());
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+
+            make_admin("admin@example.com").await;
+            let response = server
+                .post("/api/v1/user/admin/storage/gc/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["removed"] == 1);
+        })
+    });
+}
+
+#[test]
+fn test_upload_epub_and_read_chapters() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let (_, reader_token) = login_reader(&server).await;
+            let epub = sample_epub("978-0-00-000000-2");
+
+            let response = server
+                .put(&format!("/api/v1/book/{}/epub/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(epub_form(epub.clone()))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["format"] == "epub");
+            check!(body["data"]["title"] == "Война и мир");
+            check!(body["data"]["author"] == "Лев Толстой");
+            check!(body["data"]["language"] == "ru");
+            check!(body["data"]["identifiers"] == json!(["urn:isbn:978-0-00-000000-2"]));
+
+            // The book had no description nor cover, the EPUB fills them in.
+            let response = server.get(&format!("/api/v1/book/{}", book_id)).await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["description"] == "Роман-эпопея");
+            check!(body["data"]["cover_image"] != "uploads/books/default.jpg");
+
+            let response = server
+                .get(&format!("/api/v1/book/{}/epub/toc/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["chapters"].as_array().unwrap().len() == 2);
+            check!(body["data"]["toc"][0]["title"] == "Том первый");
+            check!(body["data"]["toc"][0]["chapter"] == 1);
+            check!(body["data"]["toc"][0]["children"][0]["chapter"] == 2);
+            check!(body["data"]["toc"][0]["children"][0]["fragment"] == "battle");
+
+            let response = server
+                .get(&format!("/api/v1/book/{}/epub/chapters/1/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let content = body["data"]["content"].as_str().unwrap();
+            check!(!content.contains("script"));
+            check!(!content.contains("onclick"));
+            check!(content.contains(&format!("/api/v1/book/{}/epub/chapters/2/#battle", book_id)));
+            let image_url = format!(
+                "/api/v1/book/{}/epub/resources/OEBPS/images/cover.png",
+                book_id
+            );
+            check!(content.contains(&image_url));
+            check!(body["data"]["previous"].is_null());
+            check!(body["data"]["next"] == 2);
+
+            let response = server
+                .get(&format!("/api/v1/book/{}/epub/chapters/2/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["title"] == "Сражение");
+            check!(body["data"]["previous"] == 1);
+            check!(body["data"]["next"].is_null());
+            let response = server
+                .get(&format!("/api/v1/book/{}/epub/chapters/3/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+
+            let response = server
+                .get(&image_url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.header("content-type") == "image/png");
+            check!(response.maybe_header("content-security-policy").is_some());
+
+            let response = server
+                .get(&format!("/api/v1/book/{}/epub/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.as_bytes().as_ref() == epub.as_slice());
+
+            for url in [
+                format!("/api/v1/book/{}/epub/", book_id),
+                format!("/api/v1/book/{}/epub/toc/", book_id),
+                format!("/api/v1/book/{}/epub/chapters/1/", book_id),
+                image_url.clone(),
+            ] {
+                let response = server
+                    .get(&url)
+                    .authorization(format!("Bearer {}", reader_token))
+                    .await;
+                check!(response.status_code().as_u16() == 403);
+            }
+        })
+    });
+}
+
+#[test]
+fn test_upload_rejects_invalid_epub() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let url = format!("/api/v1/book/{}/epub/", book_id);
+
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(epub_form(sample_pdf(1, "Book", "Author")))
+                .await;
+            check!(response.status_code().as_u16() == 415);
+
+            let mut truncated = sample_epub("9780000000002");
+            truncated.truncate(truncated.len() / 2);
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(epub_form(truncated))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(epub_form(sample_epub("9781234567897")))
+                .await;
+            check!(response.status_code().as_u16() == 422);
+
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
+
+#[test]
+fn test_delete_epub() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let url = format!("/api/v1/book/{}/epub/", book_id);
+
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(epub_form(sample_epub("9780000000002")))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .delete(&url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let response = server
+                .get(&format!("/api/v1/book/{}/epub/chapters/1/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+
+            // The EPUB, its stylesheet and image; the cover made from the
+            // image stays.
+            make_admin("admin@example.com").await;
+            let response = server
+                .post("/api/v1/user/admin/storage/gc/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["removed"] == 3);
+        })
+    });
+}
+
+#[test]
+fn test_download_pdf_in_ranges() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let url = format!("/api/v1/book/{}/pdf/", book_id);
+            let pdf = sample_pdf(2, "Book", "Author");
+            sqlx::query("UPDATE books SET title = 'Война и мир' WHERE id = $1::uuid")
+                .bind(&book_id)
+                .execute(&test_db().await)
+                .await
+                .unwrap();
+
+            let response = server
+                .put(&url)
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(pdf.clone()))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.header("accept-ranges") == "bytes");
+            check!(response.header("content-length") == pdf.len().to_string());
+            let disposition = response.header("content-disposition");
+            let disposition = disposition.to_str().unwrap();
+            check!(disposition.contains("filename=\"Voyna_i_mir.pdf\""));
+            check!(disposition.contains("filename*=UTF-8''%D0%92%D0%BE%D0%B9%D0%BD%D0%B0"));
+            let etag = response.header("etag").to_str().unwrap().to_string();
+
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .add_header("Range", "bytes=10-19")
+                .add_header("If-Range", etag.clone())
+                .await;
+            check!(response.status_code().as_u16() == 206);
+            check!(response.header("content-range") == format!("bytes 10-19/{}", pdf.len()));
+            check!(response.header("content-length") == "10");
+            check!(response.as_bytes().as_ref() == &pdf[10..20]);
+
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .add_header("Range", "bytes=-5")
+                .await;
+            check!(response.status_code().as_u16() == 206);
+            check!(response.as_bytes().as_ref() == &pdf[pdf.len() - 5..]);
+
+            // The file changed since the client got its tag.
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .add_header("Range", "bytes=10-19")
+                .add_header("If-Range", "\"outdated\"")
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.as_bytes().as_ref() == pdf.as_slice());
+
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .add_header("Range", format!("bytes={}-", pdf.len()))
+                .await;
+            check!(response.status_code().as_u16() == 416);
+            check!(response.header("content-range") == format!("bytes */{}", pdf.len()));
+
+            let response = server
+                .get(&url)
+                .authorization(format!("Bearer {}", token))
+                .add_header("If-None-Match", etag)
+                .await;
+            check!(response.status_code().as_u16() == 304);
+        })
+    });
+}
+
+fn tus_metadata(book_id: &str, format: &str) -> String {
+    format!(
+        "book_id {},format {}",
+        STANDARD.encode(book_id),
+        STANDARD.encode(format)
+    )
+}
+
+#[test]
+fn test_resumable_pdf_upload() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let pdf = sample_pdf(2, "Война и мир", "Лев Толстой");
+            let (first, second) = pdf.split_at(pdf.len() / 2);
+
+            let response = server
+                .method(Method::OPTIONS, "/api/v1/book/uploads/")
+                .await;
+            check!(response.status_code().as_u16() == 204);
+            check!(response.header("tus-version") == "1.0.0");
+
+            let response = server
+                .post("/api/v1/book/uploads/")
+                .authorization(format!("Bearer {}", token))
+                .add_header("upload-length", pdf.len().to_string())
+                .add_header("upload-metadata", tus_metadata(&book_id, "pdf"))
+                .await;
+            check!(response.status_code().as_u16() == 412);
+
+            let response = server
+                .post("/api/v1/book/uploads/")
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-length", pdf.len().to_string())
+                .add_header("upload-metadata", tus_metadata(&book_id, "pdf"))
+                .await;
+            check!(response.status_code().as_u16() == 201);
+            check!(response.header("tus-resumable") == "1.0.0");
+            let location = response.header("location").to_str().unwrap().to_string();
+            check!(location.starts_with("/api/v1/book/uploads/"));
+
+            let response = server
+                .patch(&location)
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-offset", "0")
+                .add_header("content-length", first.len().to_string())
+                .content_type("application/offset+octet-stream")
+                .bytes(first.to_vec().into())
+                .await;
+            check!(response.status_code().as_u16() == 204);
+            check!(response.header("upload-offset") == first.len().to_string());
+
+            // A retried chunk lands on an offset that moved on.
+            let response = server
+                .patch(&location)
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-offset", "0")
+                .add_header("content-length", first.len().to_string())
+                .content_type("application/offset+octet-stream")
+                .bytes(first.to_vec().into())
+                .await;
+            check!(response.status_code().as_u16() == 409);
+
+            let response = server
+                .method(Method::HEAD, &location)
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.header("upload-offset") == first.len().to_string());
+            check!(response.header("upload-length") == pdf.len().to_string());
+
+            let response = server
+                .patch(&location)
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-offset", first.len().to_string())
+                .add_header("content-length", second.len().to_string())
+                .content_type("application/offset+octet-stream")
+                .bytes(second.to_vec().into())
+                .await;
+            check!(response.status_code().as_u16() == 204);
+            check!(response.header("upload-offset") == pdf.len().to_string());
+
+            let response = server
+                .get(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.as_bytes().as_ref() == pdf);
+
+            // The finished upload and its chunks are gone.
+            let response = server
+                .method(Method::HEAD, &location)
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .await;
+            check!(response.status_code().as_u16() == 404);
+            let chunks: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tus_upload_chunks")
+                .fetch_one(&test_db().await)
+                .await
+                .unwrap();
+            check!(chunks == 0);
+        })
+    });
+}
+
+#[test]
+fn test_terminate_resumable_upload() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let (_, reader_token) = login_reader(&server).await;
+
+            let response = server
+                .post("/api/v1/book/uploads/")
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-length", "1000000000000")
+                .add_header("upload-metadata", tus_metadata(&book_id, "pdf"))
+                .await;
+            check!(response.status_code().as_u16() == 413);
+            // Files too large for a multipart upload may still be sent over
+            // tus, up to its own limit.
+            let settings = Settings::init();
+            let response = server
+                .method(Method::OPTIONS, "/api/v1/book/uploads/")
+                .await;
+            let max_size: u64 = response
+                .header("tus-max-size")
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            check!(max_size == settings.tus_max_size);
+            check!(max_size > settings.book_file_max_size as u64);
+            let response = server
+                .post("/api/v1/book/uploads/")
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header(
+                    "upload-length",
+                    (settings.book_file_max_size + 1).to_string(),
+                )
+                .add_header("upload-metadata", tus_metadata(&book_id, "pdf"))
+                .await;
+            check!(response.status_code().as_u16() == 201);
+            let response = server
+                .post("/api/v1/book/uploads/")
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-length", (max_size + 1).to_string())
+                .add_header("upload-metadata", tus_metadata(&book_id, "pdf"))
+                .await;
+            check!(response.status_code().as_u16() == 413);
+            let response = server
+                .post("/api/v1/book/uploads/")
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-length", "100")
+                .add_header("upload-metadata", tus_metadata(&book_id, "mobi"))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .post("/api/v1/book/uploads/")
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-length", "100")
+                .add_header("upload-metadata", tus_metadata(&book_id, "epub"))
+                .await;
+            check!(response.status_code().as_u16() == 201);
+            let location = response.header("location").to_str().unwrap().to_string();
+
+            let response = server
+                .patch(&location)
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .add_header("upload-offset", "0")
+                .add_header("content-length", "10")
+                .content_type("application/octet-stream")
+                .bytes(vec![0; 10].into())
+                .await;
+            check!(response.status_code().as_u16() == 415);
+
+            let response = server
+                .delete(&location)
+                .authorization(format!("Bearer {}", reader_token))
+                .add_header("tus-resumable", "1.0.0")
+                .await;
+            check!(response.status_code().as_u16() == 403);
+            let response = server
+                .delete(&location)
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .await;
+            check!(response.status_code().as_u16() == 204);
+            let response = server
+                .method(Method::HEAD, &location)
+                .authorization(format!("Bearer {}", token))
+                .add_header("tus-resumable", "1.0.0")
+                .await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
+
+/// Path and query of a minted link, without the host.
+fn signed_path(body: &serde_json::Value) -> String {
+    let url = body["data"]["url"].as_str().unwrap();
+    url[url.find("/api/").unwrap()..].to_string()
+}
+
+#[test]
+fn test_signed_pdf_url() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let (reader_id, reader_token) = login_reader(&server).await;
+            let pdf = sample_pdf(1, "Война и мир", "Лев Толстой");
+            server
+                .put(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(pdf.clone()))
+                .await;
+            let signed_url = format!("/api/v1/book/{}/signed-url/", book_id);
+
+            let response = server
+                .post(&signed_url)
+                .authorization(format!("Bearer {}", reader_token))
+                .json(&json!({"target": "pdf"}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            make_admin("admin@example.com").await;
+            let access_url = format!("/api/v1/user/admin/users/{}/books/{}/", reader_id, book_id);
+            server
+                .put(&access_url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let response = server
+                .post(&signed_url)
+                .authorization(format!("Bearer {}", reader_token))
+                .json(&json!({"target": "pdf", "expires_in": 60}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let path = signed_path(&response.json());
+
+            // No token needed, the signature names the reader.
+            let response = server.get(&path).await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.as_bytes().as_ref() == pdf.as_slice());
+            let response = server.get(&path).add_header("range", "bytes=0-4").await;
+            check!(response.status_code().as_u16() == 206);
+            check!(response.as_bytes().as_ref() == b"%PDF-");
+
+            let response = server.get(&path.replace("/pdf/", "/epub/")).await;
+            check!(response.status_code().as_u16() == 403);
+            let response = server.get(&path.replace(&reader_id, &book_id)).await;
+            check!(response.status_code().as_u16() == 403);
+
+            // The link stops working while the reader is suspended.
+            let suspend_url = format!("/api/v1/user/admin/users/{}/suspend/", reader_id);
+            let response = server
+                .post(&suspend_url)
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"reason": "Sharing links"}))
+                .await;
+            check!(response.status_code().as_u16() == 201);
+            let response = server.get(&path).await;
+            check!(response.status_code().as_u16() == 403);
+            server
+                .delete(&suspend_url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let response = server.get(&path).await;
+            check!(response.status_code().as_u16() == 200);
+
+            // Taking the book away revokes the links already handed out.
+            server
+                .delete(&access_url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let response = server.get(&path).await;
+            check!(response.status_code().as_u16() == 403);
+        })
+    });
+}
+
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle.as_bytes())
+}
+
+#[test]
+fn test_watermarked_pdf_download() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let (reader_id, reader_token) = login_reader(&server).await;
+            let pdf = sample_pdf(2, "Война и мир", "Лев Толстой");
+            server
+                .put(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(pdf.clone()))
+                .await;
+            make_admin("admin@example.com").await;
+            server
+                .put(&format!(
+                    "/api/v1/user/admin/users/{}/books/{}/",
+                    reader_id, book_id
+                ))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let pdf_url = format!("/api/v1/book/{}/pdf/", book_id);
+
+            let response = server
+                .get("/api/v1/book/watermark/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 404);
+            let response = server
+                .put("/api/v1/book/watermark/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"text": "Licensed to {email}", "position": "corner", "opacity": 0.3}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+            let response = server
+                .put("/api/v1/book/watermark/")
+                .authorization(format!("Bearer {}", token))
+                .json(
+                    &json!({"text": "Licensed to {email}", "position": "diagonal", "opacity": 0.3}),
+                )
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["enabled"] == true);
+            check!(body["data"]["position"] == "diagonal");
+
+            let response = server
+                .get(&pdf_url)
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let stamped = response.as_bytes().to_vec();
+            check!(stamped != pdf);
+            check!(contains(&stamped, "Licensed to reader@example.com"));
+            let etag = response.header("etag").to_str().unwrap().to_string();
+
+            // The copy is kept and served again, ranges included.
+            let response = server
+                .get(&pdf_url)
+                .authorization(format!("Bearer {}", reader_token))
+                .add_header("range", "bytes=0-99")
+                .await;
+            check!(response.status_code().as_u16() == 206);
+            check!(response.as_bytes().as_ref() == &stamped[..100]);
+            check!(response.header("etag") == etag.as_str());
+
+            // The author gets the original, which stays untouched.
+            let response = server
+                .get(&pdf_url)
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.as_bytes().as_ref() == pdf.as_slice());
+
+            let response = server
+                .delete("/api/v1/book/watermark/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let response = server
+                .get(&pdf_url)
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.as_bytes().as_ref() == pdf.as_slice());
+        })
+    });
+}
+
+#[test]
+fn test_encrypted_pdf_is_not_watermarked() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let (reader_id, reader_token) = login_reader(&server).await;
+            // Opens with an empty password, printing and copying are forbidden
+            // by the owner password.
+            let pdf = std::fs::read("tests/fixtures/encrypted.pdf").unwrap();
+            let response = server
+                .put(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(pdf.clone()))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["title"] == "Protected book");
+            make_admin("admin@example.com").await;
+            server
+                .put(&format!(
+                    "/api/v1/user/admin/users/{}/books/{}/",
+                    reader_id, book_id
+                ))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            // The publisher is told which books stay unstamped.
+            let response = server
+                .put("/api/v1/book/watermark/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"text": "Licensed to {email}", "position": "top", "opacity": 0.3}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["unstamped_books"] == json!([book_id]));
+            check!(body["message"] != "Success");
+
+            // The stamped copy would lose the restrictions, the original is
+            // served instead.
+            let response = server
+                .get(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.as_bytes().as_ref() == pdf.as_slice());
+
+            let copies: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pdf_watermarks")
+                .fetch_one(&test_db().await)
+                .await
+                .unwrap();
+            check!(copies == 0);
+
+            // While the watermark is on, new encrypted files are refused.
+            let response = server
+                .put(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(pdf))
+                .await;
+            check!(response.status_code().as_u16() == 422);
+        })
+    });
+}
+
+#[test]
+fn test_search_inside_book() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let book_id = create_authored_book().await;
+            let (_, reader_token) = login_reader(&server).await;
+            server
+                .put(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(pdf_form(sample_pdf(3, "Война и мир", "Лев Толстой")))
+                .await;
+            server
+                .put(&format!("/api/v1/book/{}/epub/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .multipart(epub_form(sample_epub("9780000000002")))
+                .await;
+            let search_url = format!("/api/v1/book/{}/search/", book_id);
+
+            let response = server
+                .get(&search_url)
+                .add_query_param("q", "")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            // Nothing is found until the text is extracted.
+            let response = server
+                .get(&search_url)
+                .add_query_param("q", "page 2")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["pending"] == true);
+            check!(body["data"]["hits"].as_array().unwrap().is_empty());
+
+            run_text_jobs().await;
+            let response = server
+                .get(&search_url)
+                .add_query_param("q", "page 2")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["pending"] == false);
+            let hits = body["data"]["hits"].as_array().unwrap();
+            check!(hits.len() == 1);
+            check!(hits[0]["format"] == "pdf");
+            check!(hits[0]["position"] == 2);
+            check!(hits[0]["snippet"] == "<mark>Page</mark> <mark>2</mark>");
+            check!(hits[0]["content"] == "Page 2");
+
+            // Readers who don't own the book only get the snippet.
+            let response = server
+                .get(&search_url)
+                .add_query_param("q", "prince")
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let hits = body["data"]["hits"].as_array().unwrap();
+            check!(hits.len() == 1);
+            check!(hits[0]["format"] == "epub");
+            check!(hits[0]["position"] == 1);
+            check!(
+                hits[0]["snippet"]
+                    .as_str()
+                    .unwrap()
+                    .contains("<mark>prince</mark>")
+            );
+            check!(hits[0]["content"].is_null());
+
+            // They have a daily quota of searches, owners don't.
+            for _ in 0..2 {
+                let response = server
+                    .get(&search_url)
+                    .add_query_param("q", "prince")
+                    .authorization(format!("Bearer {}", reader_token))
+                    .await;
+                check!(response.status_code().as_u16() == 200);
+            }
+            let response = server
+                .get(&search_url)
+                .add_query_param("q", "prince")
+                .authorization(format!("Bearer {}", reader_token))
+                .await;
+            check!(response.status_code().as_u16() == 429);
+            let response = server
+                .get(&search_url)
+                .add_query_param("q", "prince")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            server
+                .delete(&format!("/api/v1/book/{}/pdf/", book_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let response = server
+                .get(&search_url)
+                .add_query_param("q", "page")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["hits"].as_array().unwrap().is_empty());
+        })
+    });
+}
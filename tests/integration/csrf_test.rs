@@ -0,0 +1,86 @@
+use crate::common::{login_user_token_get, run_test};
+use assert2::check;
+use cookie::Cookie;
+use serde_json::json;
+
+#[test]
+fn test_csrf_required_for_cookie_auth() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let access_cookie = || Cookie::new("access_token", token.clone());
+
+            // Safe methods and Bearer requests don't need a token.
+            let response = server
+                .get("/api/v1/user/me/")
+                .add_cookie(access_cookie())
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .patch("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .add_cookie(access_cookie())
+                .json(&json!({"biography": "Bearer"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .patch("/api/v1/user/me/")
+                .add_cookie(access_cookie())
+                .json(&json!({"biography": "Cookie"}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            let response = server
+                .patch("/api/v1/user/me/")
+                .add_cookie(access_cookie())
+                .add_header("X-CSRF-Token", "forged")
+                .json(&json!({"biography": "Cookie"}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            let response = server
+                .get("/api/v1/user/csrf/")
+                .add_cookie(access_cookie())
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let csrf_token = body["data"]["csrf_token"].as_str().unwrap().to_string();
+
+            let response = server
+                .patch("/api/v1/user/me/")
+                .add_cookie(access_cookie())
+                .add_header("X-CSRF-Token", &csrf_token)
+                .json(&json!({"biography": "Cookie"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            // A token is bound to the session it was issued for.
+            let (_, other_token, _) = login_user_token_get(&server).await;
+            let response = server
+                .patch("/api/v1/user/me/")
+                .add_cookie(Cookie::new("access_token", other_token))
+                .add_header("X-CSRF-Token", &csrf_token)
+                .json(&json!({"biography": "Cookie"}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+        })
+    })
+}
+
+#[test]
+fn test_csrf_exempt_route() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            // Exempted in the test settings.
+            let response = server
+                .post("/api/v1/user/verify-email/resend/")
+                .add_cookie(Cookie::new("access_token", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+        })
+    })
+}
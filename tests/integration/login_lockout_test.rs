@@ -0,0 +1,127 @@
+use crate::common::{login_user_token_get, make_admin, run_test};
+use assert2::check;
+use axum_test::{TestResponse, TestServer};
+use serde_json::json;
+
+// Redis isn't flushed between runs, so every test uses fresh emails.
+fn unique_email(name: &str) -> String {
+    format!("{}-{}@example.com", name, uuid::Uuid::new_v4().simple())
+}
+
+async fn register(server: &TestServer, email: &str) {
+    let response = server
+        .post("/api/v1/user/register/")
+        .json(&json!({
+            "first_name": "Locked",
+            "last_name": "User",
+            "middle_name": "Test",
+            "age": 30,
+            "email": email,
+            "password": "password123"
+        }))
+        .await;
+    check!(response.status_code().as_u16() == 201);
+}
+
+async fn login(server: &TestServer, email: &str, password: &str) -> TestResponse {
+    server
+        .post("/api/v1/user/login/")
+        .json(&json!({"email": email, "password": password}))
+        .await
+}
+
+async fn fail_login(server: &TestServer, email: &str) -> TestResponse {
+    let response = login(server, email, "wrong-password").await;
+    check!(response.status_code().as_u16() == 400);
+    response
+}
+
+async fn wait_for_delay(response: &TestResponse) {
+    let body: serde_json::Value = response.json();
+    let seconds: u64 = body["error"]
+        .as_str()
+        .unwrap()
+        .trim_start_matches("Retry after ")
+        .trim_end_matches(" seconds")
+        .parse()
+        .unwrap();
+    tokio::time::sleep(tokio::time::Duration::from_secs(seconds + 1)).await;
+}
+
+#[test]
+fn test_login_errors_are_uniform() {
+    run_test(|server| {
+        Box::pin(async move {
+            let email = unique_email("uniform");
+            register(&server, &email).await;
+
+            let wrong_password: serde_json::Value = fail_login(&server, &email).await.json();
+            let unknown_email: serde_json::Value =
+                fail_login(&server, &unique_email("unknown")).await.json();
+            check!(wrong_password == unknown_email);
+            check!(wrong_password["message"] == "Invalid email or password");
+        })
+    })
+}
+
+#[test]
+fn test_login_progressive_delay() {
+    run_test(|server| {
+        Box::pin(async move {
+            let email = unique_email("delay");
+            register(&server, &email).await;
+
+            fail_login(&server, &email).await;
+            fail_login(&server, &email).await;
+
+            // Even the right password is refused during the pause.
+            let response = login(&server, &email, "password123").await;
+            check!(response.status_code().as_u16() == 429);
+            wait_for_delay(&response).await;
+
+            let response = login(&server, &email, "password123").await;
+            check!(response.status_code().as_u16() == 200);
+        })
+    })
+}
+
+#[test]
+fn test_login_lockout_and_admin_unlock() {
+    run_test(|server| {
+        Box::pin(async move {
+            let email = unique_email("lockout");
+            register(&server, &email).await;
+
+            for attempt in 1..=4 {
+                fail_login(&server, &email).await;
+                if attempt >= 2 {
+                    let pause = 1 << (attempt - 2);
+                    tokio::time::sleep(tokio::time::Duration::from_secs(pause + 1)).await;
+                }
+            }
+            fail_login(&server, &email).await;
+
+            let response = login(&server, &email, "password123").await;
+            check!(response.status_code().as_u16() == 429);
+
+            let (_, token, _) = login_user_token_get(&server).await;
+            let response = server
+                .post("/api/v1/user/login/unlock/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"email": email}))
+                .await;
+            check!(response.status_code().as_u16() == 403);
+
+            make_admin("admin@example.com").await;
+            let response = server
+                .post("/api/v1/user/login/unlock/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"email": email}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = login(&server, &email, "password123").await;
+            check!(response.status_code().as_u16() == 200);
+        })
+    })
+}
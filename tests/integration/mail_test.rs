@@ -0,0 +1,167 @@
+use crate::common::{run_test, test_db};
+use assert2::check;
+use async_trait::async_trait;
+use books::{FileMailer, MailMessage, Mailer, MemoryMailer, OutboxMailer, process_mail_queue};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Transport failing the first `failures` deliveries.
+#[derive(Debug, Default)]
+struct FlakyMailer {
+    failures: usize,
+    calls: AtomicUsize,
+    delivered: MemoryMailer,
+}
+
+#[async_trait]
+impl Mailer for FlakyMailer {
+    async fn send(&self, message: MailMessage) -> Result<(), String> {
+        if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+            return Err("Connection refused".to_string());
+        }
+        self.delivered.send(message).await
+    }
+}
+
+fn welcome() -> MailMessage {
+    MailMessage {
+        to: "reader@example.com".to_string(),
+        subject: "Welcome".to_string(),
+        body: "Hello".to_string(),
+    }
+}
+
+async fn outbox_row(pool: &PgPool) -> (String, i32, Option<String>) {
+    sqlx::query_as("SELECT status, attempts, last_error FROM mail_outbox")
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+/// Seconds until the next attempt, rounded.
+async fn seconds_to_next_attempt(pool: &PgPool) -> f64 {
+    sqlx::query_scalar(
+        "SELECT ROUND(EXTRACT(EPOCH FROM next_attempt_at - NOW()))::float8 FROM mail_outbox",
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+async fn make_due(pool: &PgPool) {
+    sqlx::query("UPDATE mail_outbox SET next_attempt_at = NOW() - interval '1 second'")
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_file_mailer_writes_messages() {
+    let dir = std::env::temp_dir().join(format!("books-test-outbox-{}", uuid::Uuid::new_v4()));
+    FileMailer::new(&dir).send(welcome()).await.unwrap();
+
+    let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+    let entry = entries.next_entry().await.unwrap().unwrap();
+    check!(entry.file_name().to_string_lossy().ends_with(".eml"));
+    let content = tokio::fs::read_to_string(entry.path()).await.unwrap();
+    check!(content == "To: reader@example.com\nSubject: Welcome\n\nHello\n");
+    check!(entries.next_entry().await.unwrap().is_none());
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[test]
+fn test_outbox_delivers_queued_mail_once() {
+    run_test(|_server| {
+        Box::pin(async move {
+            let pool = test_db().await;
+            OutboxMailer::new(pool.clone())
+                .send(welcome())
+                .await
+                .unwrap();
+            check!(outbox_row(&pool).await.0 == "pending");
+
+            // A message claimed by another worker waits for its claim to end.
+            sqlx::query(
+                "UPDATE mail_outbox SET status = 'sending', next_attempt_at = NOW() + interval '5 minutes'",
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+            let delivered = Arc::new(MemoryMailer::default());
+            let transport: Arc<dyn Mailer> = delivered.clone();
+            check!(process_mail_queue(&pool, &transport, 3).await.unwrap() == 0);
+
+            make_due(&pool).await;
+            check!(process_mail_queue(&pool, &transport, 3).await.unwrap() == 1);
+            check!(delivered.messages() == vec![welcome()]);
+            check!(outbox_row(&pool).await == ("sent".to_string(), 1, None));
+
+            make_due(&pool).await;
+            check!(process_mail_queue(&pool, &transport, 3).await.unwrap() == 0);
+            check!(delivered.messages().len() == 1);
+        })
+    });
+}
+
+#[test]
+fn test_outbox_retries_with_backoff() {
+    run_test(|_server| {
+        Box::pin(async move {
+            let pool = test_db().await;
+            OutboxMailer::new(pool.clone())
+                .send(welcome())
+                .await
+                .unwrap();
+            let flaky = Arc::new(FlakyMailer {
+                failures: 2,
+                ..Default::default()
+            });
+            let transport: Arc<dyn Mailer> = flaky.clone();
+
+            check!(process_mail_queue(&pool, &transport, 5).await.unwrap() == 1);
+            let error = Some("Connection refused".to_string());
+            check!(outbox_row(&pool).await == ("pending".to_string(), 1, error.clone()));
+            check!(seconds_to_next_attempt(&pool).await == 60.0);
+            // Not retried before the backoff ends.
+            check!(process_mail_queue(&pool, &transport, 5).await.unwrap() == 0);
+
+            make_due(&pool).await;
+            check!(process_mail_queue(&pool, &transport, 5).await.unwrap() == 1);
+            check!(outbox_row(&pool).await == ("pending".to_string(), 2, error));
+            check!(seconds_to_next_attempt(&pool).await == 120.0);
+
+            make_due(&pool).await;
+            check!(process_mail_queue(&pool, &transport, 5).await.unwrap() == 1);
+            check!(outbox_row(&pool).await == ("sent".to_string(), 3, None));
+            check!(flaky.delivered.messages() == vec![welcome()]);
+        })
+    });
+}
+
+#[test]
+fn test_outbox_gives_up_after_max_attempts() {
+    run_test(|_server| {
+        Box::pin(async move {
+            let pool = test_db().await;
+            OutboxMailer::new(pool.clone())
+                .send(welcome())
+                .await
+                .unwrap();
+            let transport: Arc<dyn Mailer> = Arc::new(FlakyMailer {
+                failures: usize::MAX,
+                ..Default::default()
+            });
+
+            check!(process_mail_queue(&pool, &transport, 2).await.unwrap() == 1);
+            make_due(&pool).await;
+            check!(process_mail_queue(&pool, &transport, 2).await.unwrap() == 1);
+            let (status, attempts, _) = outbox_row(&pool).await;
+            check!(status == "failed");
+            check!(attempts == 2);
+
+            make_due(&pool).await;
+            check!(process_mail_queue(&pool, &transport, 2).await.unwrap() == 0);
+        })
+    });
+}
@@ -1 +1,17 @@
+mod account_test;
+mod admin_test;
+mod api_key_test;
+mod avatar_test;
+mod blob_test;
+mod book_cover_test;
+mod book_file_test;
+mod csrf_test;
+mod login_lockout_test;
+mod mail_test;
+mod oidc_test;
+mod passkey_test;
+mod password_test;
+mod storage_test;
+mod two_factor_test;
 mod user_test;
+mod verification_test;
@@ -0,0 +1,147 @@
+use crate::common::mock_idp::{MockIdpUser, start_mock_idp};
+use crate::common::{login_user_token_get, run_test};
+use assert2::check;
+use axum_test::{TestResponse, TestServer};
+
+fn corporate_user(email: &str, email_verified: bool) -> MockIdpUser {
+    MockIdpUser {
+        sub: "corp-user-1".to_string(),
+        email: email.to_string(),
+        email_verified,
+        given_name: "Corporate".to_string(),
+    }
+}
+
+/// Walks through the redirects a browser would follow and returns the
+/// callback URL parameters.
+async fn authorize(server: &TestServer) -> (String, String) {
+    let response = server.get("/api/v1/user/oidc/mock/authorize/").await;
+    check!(response.status_code().as_u16() == 303);
+    let location = response.header("location").to_str().unwrap().to_string();
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = client.get(&location).send().await.unwrap();
+    let callback = reqwest::Url::parse(
+        response
+            .headers()
+            .get("location")
+            .unwrap()
+            .to_str()
+            .unwrap(),
+    )
+    .unwrap();
+    let param = |name: &str| {
+        callback
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.to_string())
+            .unwrap()
+    };
+    (param("code"), param("state"))
+}
+
+async fn callback(server: &TestServer, code: &str, state: &str) -> TestResponse {
+    server
+        .get("/api/v1/user/oidc/mock/callback/")
+        .add_query_param("code", code)
+        .add_query_param("state", state)
+        .await
+}
+
+async fn get_me(server: &TestServer, response: TestResponse) -> serde_json::Value {
+    let body: serde_json::Value = response.json();
+    let token = body["data"]["access_token"].as_str().unwrap();
+    let response = server
+        .get("/api/v1/user/me/")
+        .authorization(format!("Bearer {}", token))
+        .await;
+    let body: serde_json::Value = response.json();
+    body["data"].clone()
+}
+
+#[test]
+fn test_oidc_login_creates_account() {
+    run_test(|server| {
+        Box::pin(async move {
+            start_mock_idp(corporate_user("new@corp.example", true)).await;
+
+            let (code, state) = authorize(&server).await;
+            let response = callback(&server, &code, &state).await;
+            check!(response.status_code().as_u16() == 200);
+            let me = get_me(&server, response).await;
+            check!(me["email"] == "new@corp.example");
+            check!(me["first_name"] == "Corporate");
+            check!(me["verified"] == true);
+
+            let (code, state) = authorize(&server).await;
+            let response = callback(&server, &code, &state).await;
+            check!(response.status_code().as_u16() == 200);
+            check!(get_me(&server, response).await["id"] == me["id"]);
+        })
+    })
+}
+
+#[test]
+fn test_oidc_links_existing_account_by_verified_email() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, _, _) = login_user_token_get(&server).await;
+            start_mock_idp(corporate_user("admin@example.com", true)).await;
+
+            let (code, state) = authorize(&server).await;
+            let response = callback(&server, &code, &state).await;
+            check!(response.status_code().as_u16() == 200);
+            check!(get_me(&server, response).await["id"] == user_id.as_str());
+        })
+    })
+}
+
+#[test]
+fn test_oidc_rejects_unverified_email_and_reused_state() {
+    run_test(|server| {
+        Box::pin(async move {
+            login_user_token_get(&server).await;
+            start_mock_idp(corporate_user("admin@example.com", false)).await;
+
+            let (code, state) = authorize(&server).await;
+            let response = callback(&server, &code, &state).await;
+            check!(response.status_code().as_u16() == 409);
+
+            let response = callback(&server, &code, &state).await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server.get("/api/v1/user/oidc/unknown/authorize/").await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    })
+}
+
+#[test]
+fn test_oidc_state_is_bound_to_the_browser() {
+    run_test(|server| {
+        Box::pin(async move {
+            start_mock_idp(corporate_user("new@corp.example", true)).await;
+
+            // A callback link opened in another browser has no state cookie.
+            let (code, state) = authorize(&server).await;
+            let response = server
+                .get("/api/v1/user/oidc/mock/callback/")
+                .add_query_param("code", &code)
+                .add_query_param("state", &state)
+                .clear_cookies()
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            // A newer login replaces the cookie, the older state no longer matches.
+            let (other_code, other_state) = authorize(&server).await;
+            let response = callback(&server, &code, &state).await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = callback(&server, &other_code, &other_state).await;
+            check!(response.status_code().as_u16() == 200);
+        })
+    })
+}
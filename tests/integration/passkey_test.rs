@@ -0,0 +1,251 @@
+use crate::common::{login_user_token_get, run_test};
+use assert2::check;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use books::Settings;
+use ciborium::Value;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Minimal ES256 authenticator producing the same payloads as a browser.
+struct SoftAuthenticator {
+    credential_id: Vec<u8>,
+    signing_key: SigningKey,
+    sign_count: u32,
+    rp_id: String,
+    origin: String,
+}
+
+impl SoftAuthenticator {
+    fn new(seed: u8) -> Self {
+        let settings = Settings::init();
+        Self {
+            credential_id: vec![seed; 16],
+            signing_key: SigningKey::from_slice(&[seed; 32]).unwrap(),
+            sign_count: 0,
+            rp_id: settings.webauthn_rp_id,
+            origin: settings.webauthn_origin,
+        }
+    }
+
+    fn client_data(&self, kind: &str, challenge: &str) -> Vec<u8> {
+        json!({"type": kind, "challenge": challenge, "origin": self.origin})
+            .to_string()
+            .into_bytes()
+    }
+
+    fn authenticator_data(&self, attested: bool) -> Vec<u8> {
+        let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+        data.push(if attested { 0x45 } else { 0x05 });
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        if attested {
+            let point = self.signing_key.verifying_key().to_encoded_point(false);
+            let cose_key = Value::Map(vec![
+                (Value::from(1), Value::from(2)),
+                (Value::from(3), Value::from(-7)),
+                (Value::from(-1), Value::from(1)),
+                (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+            data.extend_from_slice(&[0u8; 16]);
+            data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(&self.credential_id);
+            ciborium::into_writer(&cose_key, &mut data).unwrap();
+        }
+        data
+    }
+
+    fn create(&self, challenge: &str) -> serde_json::Value {
+        let attestation = Value::Map(vec![
+            (Value::from("fmt"), Value::from("none")),
+            (Value::from("attStmt"), Value::Map(vec![])),
+            (
+                Value::from("authData"),
+                Value::Bytes(self.authenticator_data(true)),
+            ),
+        ]);
+        let mut attestation_object = Vec::new();
+        ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+        let id = URL_SAFE_NO_PAD.encode(&self.credential_id);
+        json!({
+            "id": id,
+            "rawId": id,
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(self.client_data("webauthn.create", challenge)),
+                "attestationObject": URL_SAFE_NO_PAD.encode(attestation_object)
+            }
+        })
+    }
+
+    fn get(&mut self, challenge: &str) -> serde_json::Value {
+        self.sign_count += 1;
+        let client_data = self.client_data("webauthn.get", challenge);
+        let authenticator_data = self.authenticator_data(false);
+        let mut signed_data = authenticator_data.clone();
+        signed_data.extend_from_slice(&Sha256::digest(&client_data));
+        let signature: Signature = self.signing_key.sign(&signed_data);
+
+        let id = URL_SAFE_NO_PAD.encode(&self.credential_id);
+        json!({
+            "id": id,
+            "rawId": id,
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(authenticator_data),
+                "signature": URL_SAFE_NO_PAD.encode(signature.to_der().as_bytes())
+            }
+        })
+    }
+}
+
+#[test]
+fn test_passkey_register_and_login() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let mut authenticator = SoftAuthenticator::new(7);
+
+            let response = server
+                .post("/api/v1/user/passkeys/register/start/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let challenge = body["data"]["challenge"].as_str().unwrap().to_string();
+
+            let response = server
+                .post("/api/v1/user/passkeys/register/finish/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"name": "Laptop", "credential": authenticator.create(&challenge)}))
+                .await;
+            check!(response.status_code().as_u16() == 201);
+
+            let response = server
+                .get("/api/v1/user/passkeys/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"].as_array().unwrap().len() == 1);
+            check!(body["data"][0]["name"] == "Laptop");
+
+            let response = server
+                .post("/api/v1/user/login/passkey/start/")
+                .json(&json!({"email": "admin@example.com"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["allowCredentials"].as_array().unwrap().len() == 1);
+            let challenge = body["data"]["challenge"].as_str().unwrap().to_string();
+
+            let assertion = authenticator.get(&challenge);
+            let response = server
+                .post("/api/v1/user/login/passkey/finish/")
+                .json(&assertion)
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["access_token"].is_string());
+            check!(body["data"]["refresh_token"].is_string());
+
+            // The challenge is consumed by the first attempt.
+            let response = server
+                .post("/api/v1/user/login/passkey/finish/")
+                .json(&assertion)
+                .await;
+            check!(response.status_code().as_u16() == 401);
+        })
+    })
+}
+
+#[test]
+fn test_passkey_login_rejects_wrong_key_and_removed_passkey() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let authenticator = SoftAuthenticator::new(7);
+
+            let response = server
+                .post("/api/v1/user/passkeys/register/start/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            let challenge = body["data"]["challenge"].as_str().unwrap().to_string();
+            let response = server
+                .post("/api/v1/user/passkeys/register/finish/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"name": "Phone", "credential": authenticator.create(&challenge)}))
+                .await;
+            let body: serde_json::Value = response.json();
+            let passkey_id = body["data"]["id"].as_str().unwrap().to_string();
+
+            // Same credential ID, different private key.
+            let mut impostor = SoftAuthenticator::new(9);
+            impostor.credential_id = authenticator.credential_id.clone();
+            let response = server
+                .post("/api/v1/user/login/passkey/start/")
+                .json(&json!({}))
+                .await;
+            let body: serde_json::Value = response.json();
+            let challenge = body["data"]["challenge"].as_str().unwrap().to_string();
+            let response = server
+                .post("/api/v1/user/login/passkey/finish/")
+                .json(&impostor.get(&challenge))
+                .await;
+            check!(response.status_code().as_u16() == 401);
+
+            let response = server
+                .delete(&format!("/api/v1/user/passkeys/{}/", passkey_id))
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let mut authenticator = authenticator;
+            let response = server
+                .post("/api/v1/user/login/passkey/start/")
+                .json(&json!({}))
+                .await;
+            let body: serde_json::Value = response.json();
+            let challenge = body["data"]["challenge"].as_str().unwrap().to_string();
+            let response = server
+                .post("/api/v1/user/login/passkey/finish/")
+                .json(&authenticator.get(&challenge))
+                .await;
+            check!(response.status_code().as_u16() == 401);
+        })
+    })
+}
+
+#[test]
+fn test_passkey_login_start_hides_unknown_emails() {
+    run_test(|server| {
+        Box::pin(async move {
+            login_user_token_get(&server).await;
+            let allowed = |email: &'static str| {
+                let server = &server;
+                async move {
+                    let response = server
+                        .post("/api/v1/user/login/passkey/start/")
+                        .json(&json!({"email": email}))
+                        .await;
+                    check!(response.status_code().as_u16() == 200);
+                    let body: serde_json::Value = response.json();
+                    body["data"]["allowCredentials"].clone()
+                }
+            };
+
+            // A user without passkeys and an unknown email look alike, and
+            // asking again gives the same answer.
+            let unknown = allowed("nobody@example.com").await;
+            check!(unknown.as_array().unwrap().len() == 1);
+            check!(allowed("nobody@example.com").await == unknown);
+            let registered = allowed("admin@example.com").await;
+            check!(registered.as_array().unwrap().len() == 1);
+            check!(registered != unknown);
+        })
+    });
+}
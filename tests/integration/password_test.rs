@@ -0,0 +1,182 @@
+use crate::common::{login_user_token_get, run_test, test_db, test_mailer};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use assert2::check;
+use serde_json::json;
+
+#[test]
+fn test_change_password() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .post("/api/v1/user/password/change/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"old_password": "wrongpassword", "new_password": "newpassword123"}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .post("/api/v1/user/password/change/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"old_password": "password123", "new_password": "newpassword123"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 401);
+
+            let response = server
+                .post("/api/v1/user/login/")
+                .json(&json!({"email": "admin@example.com", "password": "newpassword123"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+        })
+    })
+}
+
+#[test]
+fn test_reset_password() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .post("/api/v1/user/password/forgot/")
+                .json(&json!({"email": "admin@example.com"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let mail = test_mailer().last_to("admin@example.com").unwrap();
+            let reset_token = mail
+                .body
+                .split("token=")
+                .nth(1)
+                .unwrap()
+                .split_whitespace()
+                .next()
+                .unwrap()
+                .to_string();
+
+            let response = server
+                .post("/api/v1/user/password/reset/")
+                .json(&json!({"token": reset_token, "password": "resetpassword1"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .post("/api/v1/user/password/reset/")
+                .json(&json!({"token": reset_token, "password": "resetpassword2"}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 401);
+
+            let response = server
+                .post("/api/v1/user/login/")
+                .json(&json!({"email": "admin@example.com", "password": "resetpassword1"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+        })
+    })
+}
+
+#[test]
+fn test_forgot_password_unknown_email() {
+    run_test(|server| {
+        Box::pin(async move {
+            let response = server
+                .post("/api/v1/user/password/forgot/")
+                .json(&json!({"email": "nobody@example.com"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(test_mailer().messages().is_empty());
+        })
+    })
+}
+
+#[test]
+fn test_password_policy_and_breached_passwords() {
+    run_test(|server| {
+        Box::pin(async move {
+            let register = |password: &'static str| {
+                server.post("/api/v1/user/register/").json(&json!({
+                    "first_name": "Policy",
+                    "last_name": "User",
+                    "age": 30,
+                    "email": "policy@example.com",
+                    "password": password
+                }))
+            };
+
+            let response = register("short").await;
+            check!(response.status_code().as_u16() == 400);
+            let body: serde_json::Value = response.json();
+            check!(body["message"] == "Password does not meet the password policy");
+
+            // Listed in tests/fixtures/breached-passwords.
+            let response = register("qwerty123456").await;
+            check!(response.status_code().as_u16() == 400);
+            let body: serde_json::Value = response.json();
+            check!(
+                body["message"]
+                    == "This password has appeared in a data breach, choose another one"
+            );
+
+            let (_, token, _) = login_user_token_get(&server).await;
+            let response = server
+                .post("/api/v1/user/password/change/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"old_password": "password123", "new_password": "qwerty123456"}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = register("long enough passphrase").await;
+            check!(response.status_code().as_u16() == 201);
+        })
+    })
+}
+
+#[test]
+fn test_login_rehashes_outdated_password_hash() {
+    run_test(|server| {
+        Box::pin(async move {
+            login_user_token_get(&server).await;
+
+            let params = Params::new(8 * 1024, 1, 1, None).unwrap();
+            let old_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password(b"password123", &SaltString::generate(&mut OsRng))
+                .unwrap()
+                .to_string();
+            let pool = test_db().await;
+            sqlx::query("UPDATE users SET password = $1 WHERE email = 'admin@example.com'")
+                .bind(&old_hash)
+                .execute(&pool)
+                .await
+                .unwrap();
+
+            let response = server
+                .post("/api/v1/user/login/")
+                .json(&json!({"email": "admin@example.com", "password": "password123"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let new_hash: String =
+                sqlx::query_scalar("SELECT password FROM users WHERE email = 'admin@example.com'")
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap();
+            check!(new_hash != old_hash);
+            check!(new_hash.contains("m=19456,t=2,p=1"));
+        })
+    })
+}
@@ -0,0 +1,153 @@
+use crate::common::mock_s3::{mock_s3_client, mock_s3_storage, start_mock_s3};
+use crate::common::{login_user_token_get, make_admin, run_test_with_storage};
+use assert2::check;
+use axum::body::Bytes;
+use axum_test::multipart::{MultipartForm, Part};
+use books::{ByteStream, LocalStorage, Storage};
+use futures_util::TryStreamExt;
+use std::io::{Cursor, ErrorKind};
+
+fn chunked(chunks: &[&'static [u8]]) -> ByteStream {
+    let chunks: Vec<std::io::Result<Bytes>> = chunks
+        .iter()
+        .map(|chunk| Ok(Bytes::from_static(chunk)))
+        .collect();
+    Box::pin(futures_util::stream::iter(chunks))
+}
+
+async fn check_round_trip(storage: &dyn Storage) {
+    storage
+        .put("books/1/v1/book.txt", chunked(&[b"hello ", b"world"]), 11)
+        .await
+        .unwrap();
+    storage.put_bytes("books/1/v1/a.txt", b"a").await.unwrap();
+    storage.put_bytes("books/1/v1/b.txt", b"b").await.unwrap();
+    storage.put_bytes("books/1/v2/a.txt", b"new").await.unwrap();
+
+    let object = storage.get("books/1/v1/book.txt").await.unwrap();
+    check!(object.length == 11);
+    check!(storage.get_bytes("books/1/v1/book.txt").await.unwrap() == b"hello world");
+    let range = storage
+        .get_range("books/1/v1/book.txt", 6, 5)
+        .await
+        .unwrap();
+    check!(range.length == 5);
+    let body: Vec<Bytes> = range.body.try_collect().await.unwrap();
+    check!(body.concat() == b"world");
+    let empty = storage
+        .get_range("books/1/v1/book.txt", 0, 0)
+        .await
+        .unwrap();
+    check!(empty.length == 0);
+    let body: Vec<Bytes> = empty.body.try_collect().await.unwrap();
+    check!(body.is_empty());
+
+    let missing = storage.get("books/1/v1/missing.txt").await.err().unwrap();
+    check!(missing.kind() == ErrorKind::NotFound);
+    let invalid = storage.get("../etc/passwd").await.err().unwrap();
+    check!(invalid.kind() == ErrorKind::InvalidInput);
+
+    storage.delete("books/1/v1/a.txt").await.unwrap();
+    storage.delete("books/1/v1/a.txt").await.unwrap();
+    check!(storage.get("books/1/v1/a.txt").await.is_err());
+
+    storage.delete_prefix("books/1/v1").await.unwrap();
+    check!(storage.get("books/1/v1/book.txt").await.is_err());
+    check!(storage.get("books/1/v1/b.txt").await.is_err());
+    check!(storage.get_bytes("books/1/v2/a.txt").await.unwrap() == b"new");
+    storage.delete_prefix("books/2").await.unwrap();
+}
+
+#[tokio::test]
+async fn test_local_storage() {
+    let root = std::env::temp_dir().join(format!("books-test-storage-{}", uuid::Uuid::new_v4()));
+    let storage = LocalStorage::new(&root);
+    check_round_trip(&storage).await;
+
+    // A short body never replaces the stored file.
+    let result = storage.put("books/1/v2/a.txt", chunked(&[b"tr"]), 9).await;
+    check!(result.is_err());
+    check!(storage.get_bytes("books/1/v2/a.txt").await.unwrap() == b"new");
+
+    tokio::fs::remove_dir_all(&root).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_s3_storage() {
+    let s3 = start_mock_s3().await;
+    check_round_trip(mock_s3_storage().as_ref()).await;
+    check!(s3.keys() == vec!["books/1/v2/a.txt".to_string()]);
+
+    let result = mock_s3_client("wrong-key")
+        .put_bytes("books/1/v3/a.txt", b"a")
+        .await;
+    let error = result.err().unwrap();
+    check!(error.kind() != ErrorKind::NotFound);
+    check!(error.to_string().contains("AccessDenied"));
+}
+
+fn png_image(width: u32, height: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(width, height, image::Rgb([40, 200, 40]));
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .unwrap();
+    bytes
+}
+
+fn avatar_form(bytes: Vec<u8>) -> MultipartForm {
+    MultipartForm::new().add_part(
+        "file",
+        Part::bytes(bytes)
+            .file_name("avatar.png")
+            .mime_type("image/png"),
+    )
+}
+
+#[test]
+fn test_uploads_served_from_s3() {
+    run_test_with_storage(mock_s3_storage(), |server| {
+        Box::pin(async move {
+            let s3 = start_mock_s3().await;
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .put("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .multipart(avatar_form(png_image(200, 200)))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let file = body["data"]["file"].as_str().unwrap().to_string();
+            let keys = s3.keys();
+            check!(keys.len() == 6);
+            check!(keys.iter().all(|key| key.starts_with("blobs/")));
+
+            let response = server.get(&format!("/{}", file)).await;
+            check!(response.status_code().as_u16() == 200);
+            check!(response.header("content-type") == "image/jpeg");
+            let avatar = image::load_from_memory(response.as_bytes()).unwrap();
+            check!(avatar.width() == 256);
+
+            // The previous upload is removed from the bucket by the garbage
+            // collection.
+            let response = server
+                .put("/api/v1/user/me/avatar/")
+                .authorization(format!("Bearer {}", token))
+                .multipart(avatar_form(png_image(100, 100)))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(s3.keys().len() == 12);
+            make_admin("admin@example.com").await;
+            let response = server
+                .post("/api/v1/user/admin/storage/gc/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            check!(s3.keys().len() == 6);
+            check!(!s3.keys().iter().any(|key| file.ends_with(key.as_str())));
+            let response = server.get(&format!("/{}", file)).await;
+            check!(response.status_code().as_u16() == 404);
+        })
+    });
+}
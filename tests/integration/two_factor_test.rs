@@ -0,0 +1,103 @@
+use crate::common::{login_user_token_get, run_test};
+use assert2::check;
+use serde_json::json;
+use totp_rs::TOTP;
+
+#[test]
+fn test_two_factor_login() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .post("/api/v1/user/2fa/setup/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let totp = TOTP::from_url(body["data"]["otpauth_uri"].as_str().unwrap()).unwrap();
+
+            let response = server
+                .post("/api/v1/user/2fa/confirm/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"code": "000000"}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .post("/api/v1/user/2fa/confirm/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"code": totp.generate_current().unwrap()}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            let recovery_codes = body["data"]["recovery_codes"].as_array().unwrap().clone();
+            check!(recovery_codes.len() == 10);
+
+            let response = server
+                .post("/api/v1/user/login/")
+                .json(&json!({"email": "admin@example.com", "password": "password123"}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["two_factor_required"] == true);
+            check!(body["data"].get("access_token").is_none());
+            let challenge = body["data"]["challenge"].as_str().unwrap().to_string();
+
+            let response = server
+                .post("/api/v1/user/login/2fa/")
+                .json(&json!({"challenge": challenge, "code": recovery_codes[0]}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["access_token"].is_string());
+
+            let response = server
+                .post("/api/v1/user/login/2fa/")
+                .json(&json!({"challenge": challenge, "code": recovery_codes[1]}))
+                .await;
+            check!(response.status_code().as_u16() == 401);
+        })
+    })
+}
+
+#[test]
+fn test_two_factor_recovery_code_single_use() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .post("/api/v1/user/2fa/setup/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            let totp = TOTP::from_url(body["data"]["otpauth_uri"].as_str().unwrap()).unwrap();
+            let response = server
+                .post("/api/v1/user/2fa/confirm/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"code": totp.generate_current().unwrap()}))
+                .await;
+            let body: serde_json::Value = response.json();
+            let recovery_code = body["data"]["recovery_codes"][0]
+                .as_str()
+                .unwrap()
+                .to_string();
+
+            for expected_status in [200, 400] {
+                let response = server
+                    .post("/api/v1/user/login/")
+                    .json(&json!({"email": "admin@example.com", "password": "password123"}))
+                    .await;
+                let body: serde_json::Value = response.json();
+                let challenge = body["data"]["challenge"].as_str().unwrap().to_string();
+
+                let response = server
+                    .post("/api/v1/user/login/2fa/")
+                    .json(&json!({"challenge": challenge, "code": recovery_code}))
+                    .await;
+                check!(response.status_code().as_u16() == expected_status);
+            }
+        })
+    })
+}
@@ -75,3 +75,50 @@ fn test_logout_user() {
         })
     })
 }
+
+#[test]
+fn test_get_me() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (user_id, token, _) = login_user_token_get(&server).await;
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+
+            let body: serde_json::Value = response.json();
+            check!(response.status_code().as_u16() == 200);
+            check!(body["data"]["id"] == user_id.as_str());
+        })
+    })
+}
+
+#[test]
+fn test_update_me() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let response = server
+                .patch("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"first_name": "Updated", "age": 42}))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["first_name"] == "Updated");
+            check!(body["data"]["age"] == 42);
+
+            let response = server
+                .patch("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .json(&json!({"age": 200}))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+        })
+    })
+}
@@ -0,0 +1,116 @@
+use crate::common::{login_user_token_get, run_test, test_mailer};
+use assert2::check;
+use serde_json::json;
+
+fn link_token(body: &str) -> String {
+    body.split("token=")
+        .nth(1)
+        .unwrap()
+        .split_whitespace()
+        .next()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn test_verify_email() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+            let mail = test_mailer().last_to("admin@example.com").unwrap();
+
+            let response = server
+                .get("/api/v1/user/verify-email/")
+                .add_query_param("token", "invalid")
+                .await;
+            check!(response.status_code().as_u16() == 400);
+
+            let response = server
+                .get("/api/v1/user/verify-email/")
+                .add_query_param("token", link_token(&mail.body))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .get("/api/v1/user/me/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            let body: serde_json::Value = response.json();
+            check!(body["data"]["verified"] == true);
+
+            let response = server
+                .post("/api/v1/user/verify-email/resend/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+        })
+    })
+}
+
+#[test]
+fn test_resend_verification_rate_limit() {
+    run_test(|server| {
+        Box::pin(async move {
+            let (_, token, _) = login_user_token_get(&server).await;
+
+            let response = server
+                .post("/api/v1/user/verify-email/resend/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 200);
+
+            let response = server
+                .post("/api/v1/user/verify-email/resend/")
+                .authorization(format!("Bearer {}", token))
+                .await;
+            check!(response.status_code().as_u16() == 429);
+        })
+    })
+}
+
+#[test]
+fn test_verification_email_locale() {
+    run_test(|server| {
+        Box::pin(async move {
+            server
+                .post("/api/v1/user/register/")
+                .json(&json!({
+                    "first_name": "English",
+                    "last_name": "User",
+                    "age": 30,
+                    "email": "en@example.com",
+                    "password": "password123",
+                    "locale": "en"
+                }))
+                .await;
+            server
+                .post("/api/v1/user/register/")
+                .json(&json!({
+                    "first_name": "Russian",
+                    "last_name": "User",
+                    "age": 30,
+                    "email": "ru@example.com",
+                    "password": "password123"
+                }))
+                .await;
+
+            let mail = test_mailer().last_to("en@example.com").unwrap();
+            check!(mail.subject == "Email verification");
+            let mail = test_mailer().last_to("ru@example.com").unwrap();
+            check!(mail.subject == "Подтверждение email");
+
+            let response = server
+                .post("/api/v1/user/register/")
+                .json(&json!({
+                    "first_name": "Unknown",
+                    "last_name": "Locale",
+                    "age": 30,
+                    "email": "de@example.com",
+                    "password": "password123",
+                    "locale": "de"
+                }))
+                .await;
+            check!(response.status_code().as_u16() == 400);
+        })
+    })
+}